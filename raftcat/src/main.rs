@@ -42,8 +42,15 @@ async fn get_all_manifests(c: Data<State>, _req: HttpRequest) -> Result<HttpResp
 }
 async fn get_resource_usage(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
     let name = req.match_info().get("name").unwrap();
+    let cfg = c.get_config().await?;
+    let region = c.get_region().await?;
     if let Some(mf) = c.get_manifest(name).await? {
-        let totals = mf.spec.compute_resource_totals().unwrap(); // TODO: use 'failure' in shipcat_definitions
+        // TODO: use 'failure' in shipcat_definitions
+        let totals = mf
+            .spec
+            .compute_resource_totals()
+            .and_then(|t| t.priced_in(&cfg.cost_models(&region)))
+            .unwrap();
         Ok(HttpResponse::Ok().json(totals))
     } else {
         Ok(HttpResponse::NotFound().finish())
@@ -166,6 +173,9 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
             if let Some(r) = &conds.rolledout {
                 cvec.push(format!("RolledOut: {}", r.html_list_item().unwrap()));
             }
+            if let Some(r) = &conds.rolledback {
+                cvec.push(format!("RolledBack: {}", r.html_list_item().unwrap()));
+            }
             ctx.insert("conditions", &cvec);
         }
 
@@ -187,10 +197,15 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
         }
 
         // stats
-        if let Ok(_usage) = mf.compute_resource_totals() {
+        let costs = cfg.cost_model(&region);
+        let priced = mf
+            .compute_resource_totals()
+            .and_then(|u| u.priced_in(&cfg.cost_models(&region)));
+        if let Ok(_usage) = priced {
             let usagen = _usage.normalise();
             ctx.insert("usage", &serde_json::to_string_pretty(&usagen)?);
-            ctx.insert("cost", &usagen.daily_cost());
+            ctx.insert("cost", &usagen.cost);
+            ctx.insert("currency", &costs.currency);
             ctx.insert("rollouts", &mf.estimate_rollout_iterations());
         }
         if let Some(ru) = mf.rollingUpdate {
@@ -1,17 +1,18 @@
-use std::path::Path;
-use tokio::fs;
-
 use crate::{
-    diff, helm,
-    kubeapi::ShipKube,
-    kubectl, track,
+    diff::{self, Diff},
+    helm,
+    kubeapi::{self, ShipKube},
+    kubectl, registry, slack, track,
     webhooks::{self, UpgradeState},
 };
-use serde_json::json;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 
 use shipcat_definitions::{
-    status::{make_date, Condition},
-    structs::{Metadata, NotificationMode},
+    manifest::ShipcatManifest,
+    status::{make_date, Applier, Condition, ManifestStatus},
+    structs::{BlueGreen, Canary, Metadata, NotificationMode},
     Config, Manifest, PrimaryWorkload, ReconciliationMode, Region,
 };
 
@@ -35,8 +36,14 @@ pub struct UpgradeInfo {
     pub region: String,
     /// Validated namespace inferred from region
     pub namespace: String,
-    /// Computed diff string (if available)
-    pub diff: Option<String>,
+    /// Computed diff (if available)
+    pub diff: Option<Diff>,
+    /// Cluster applied to when the region spans several clusters
+    pub cluster: Option<String>,
+    /// Image digest verified before the upgrade (if pinned)
+    pub digest: Option<String>,
+    /// Reason given for upgrading during a freeze window
+    pub freezeOverride: Option<String>,
 }
 
 impl UpgradeInfo {
@@ -58,6 +65,9 @@ impl UpgradeInfo {
             region: mf.region.clone(),
             namespace: mf.namespace.clone(),
             diff: None,
+            cluster: None,
+            digest: mf.imageDigest.clone(),
+            freezeOverride: mf.freezeOverride.clone(),
         }
     }
 }
@@ -82,6 +92,8 @@ impl UpgradeInfo {
 /// It is also entirely responsible for sending webhooks on errors / successes.
 /// As such, it's entirely responsible for not propagating random errors here with `?`
 /// Every error cases is something that might need to be notified.
+///
+/// Regions in a freeze window are refused unless a `freeze_override` reason is given.
 pub async fn apply(
     svc: String,
     force: bool,
@@ -89,12 +101,252 @@ pub async fn apply(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    freeze_override: Option<&str>,
 ) -> Result<Option<UpgradeInfo>> {
+    let freeze = check_freeze(region, freeze_override)?;
+    let freeze = freeze.as_deref();
+    if !region.activeClusters.is_empty() {
+        return apply_clusters(&svc, force, region, conf, wait, passed_version, freeze).await;
+    }
     match region.reconciliationMode {
-        ReconciliationMode::CrdOwned => apply_kubectl(&svc, force, region, conf, wait, passed_version).await,
+        ReconciliationMode::CrdOwned | ReconciliationMode::Operator => {
+            apply_kubectl(&svc, force, region, conf, wait, passed_version, None, freeze).await
+        }
+    }
+}
+
+/// Refuse to change a region during a freeze window, unless overridden with a reason
+///
+/// Returns the reason if it overrode a freeze, so it can be recorded with the change.
+pub fn check_freeze(region: &Region, reason: Option<&str>) -> Result<Option<String>> {
+    let window = match region.active_freeze(Utc::now())? {
+        Some(w) => w,
+        None => return Ok(None),
+    };
+    match reason {
+        Some(r) if !r.trim().is_empty() => {
+            warn!("{} is frozen for {}, overriding: {}", region.name, window.name, r);
+            Ok(Some(r.to_string()))
+        }
+        _ => Err(ErrorKind::RegionFrozen(region.name.clone(), window.name.clone()).into()),
+    }
+}
+
+/// shipcat apply for a region spanning several clusters
+///
+/// Every cluster in `activeClusters` is applied to in parallel, as if it was its own region.
+/// Audit webhooks fire per cluster, but slack gets a single message once all clusters are done,
+/// and the outcome in every cluster is written to the `clusters` status of each crd.
+async fn apply_clusters(
+    svc: &str,
+    force: bool,
+    region: &Region,
+    conf: &Config,
+    wait: bool,
+    passed_version: Option<String>,
+    freeze: Option<&str>,
+) -> Result<Option<UpgradeInfo>> {
+    let applies = region.activeClusters.iter().map(|c| {
+        let version = passed_version.clone();
+        async move {
+            let (ctx, res) = match kubeapi::cluster_context(conf, c, region) {
+                Ok(ctx) => {
+                    let res =
+                        apply_kubectl(svc, force, region, conf, wait, version, Some(&ctx), freeze).await;
+                    (Some(ctx), res)
+                }
+                Err(e) => (None, Err(e)),
+            };
+            if let Err(e) = &res {
+                error!("Failed to apply {} in {}: {}", svc, c, e);
+            }
+            (c.clone(), ctx, res)
+        }
+    });
+    let results = futures::future::join_all(applies).await;
+
+    let applier = Applier::infer();
+    let mut clusters = BTreeMap::new();
+    let mut failed = vec![];
+    let mut info: Option<UpgradeInfo> = None;
+    let mut contexts = vec![];
+    for (c, ctx, res) in results {
+        contexts.extend(ctx);
+        let cond = match res {
+            Ok(Some(ui)) => {
+                let mut cond = Condition::ok(&applier);
+                cond.message = Some(format!("applied {}", ui.version));
+                info = info.or(Some(ui));
+                cond
+            }
+            Ok(None) => {
+                let mut cond = Condition::ok(&applier);
+                cond.message = Some("no rollout needed".into());
+                cond
+            }
+            Err(e) => {
+                failed.push(c.clone());
+                Condition::bad(&applier, "ApplyFailure", e.to_string())
+            }
+        };
+        clusters.insert(c, cond);
+    }
+
+    for ctx in &contexts {
+        let res = match ShipKube::new_in_context(svc, &region.namespace, Some(ctx)).await {
+            Ok(s) => s.update_clusters(&clusters).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            warn!("Failed to record cluster status of {} in {}: {}", svc, ctx, e);
+        }
+    }
+    // clusters that were up to date have nothing to report
+    if info.is_some() || !failed.is_empty() {
+        notify_clusters(svc, region, conf, &clusters, info.as_ref()).await;
+    }
+
+    if !failed.is_empty() {
+        return Err(ErrorKind::ClusterApplyFailure(svc.to_string(), failed.join(", ")).into());
     }
+    Ok(info.map(|mut ui| {
+        ui.cluster = None;
+        ui
+    }))
 }
 
+/// Send one slack message for an apply across several clusters
+async fn notify_clusters(
+    svc: &str,
+    region: &Region,
+    conf: &Config,
+    clusters: &BTreeMap<String, Condition>,
+    info: Option<&UpgradeInfo>,
+) {
+    let failures = clusters.values().filter(|c| !c.status).count();
+    let mut text = if failures == 0 {
+        format!("applied `{}` in `{}`", svc, region.name)
+    } else {
+        format!(
+            "failed to apply `{}` in `{}` on {}/{} clusters",
+            svc,
+            region.name,
+            failures,
+            clusters.len()
+        )
+    };
+    for (name, c) in clusters {
+        let state = if c.status { "ok" } else { "failed" };
+        text += &format!(
+            "\n- `{}`: {} ({})",
+            name,
+            state,
+            c.message.clone().unwrap_or_default()
+        );
+    }
+    // metadata is needed to find the right channels, so load it if every cluster failed early
+    let (metadata, mode, version, diff) = match info {
+        Some(ui) => (
+            ui.metadata.clone(),
+            ui.slackMode.clone(),
+            Some(ui.version.clone()),
+            ui.diff.clone(),
+        ),
+        None => match shipcat_filebacked::load_manifest(svc, conf, region).await {
+            Ok(Manifest {
+                metadata: Some(md),
+                upgradeNotifications,
+                version,
+                ..
+            }) => (md, upgradeNotifications.unwrap_or_default(), version, None),
+            Ok(_) => {
+                warn!("Failed to notify about {} in {}: no metadata", svc, region.name);
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to notify about {} in {}: {}", svc, region.name, e);
+                return;
+            }
+        },
+    };
+    let color = if failures == 0 { "good" } else { "danger" };
+    let msg = slack::Message {
+        text,
+        diff,
+        color: Some(color.into()),
+        version,
+        mode,
+        metadata,
+    };
+    if let Err(e) = slack::send(msg, &conf.owners).await {
+        warn!("Failed to notify about {} in {}: {}", svc, region.name, e);
+    }
+}
+
+/// shipcat operator apply
+///
+/// Rolls out the spec of a shipcatmanifest crd as it is in the cluster.
+/// The crd has already been updated by `shipcat apply` or a reconcile, and is the source of truth.
+///
+/// Like `apply`, this is responsible for sending webhooks and setting conditions.
+pub async fn apply_crd(
+    crd: ShipcatManifest,
+    s: &ShipKube,
+    region: &Region,
+    conf: &Config,
+) -> Result<Option<UpgradeInfo>> {
+    let mut mfcrd = crd.spec;
+    let version = match &mfcrd.version {
+        Some(v) => v.clone(),
+        None => return Err(ErrorKind::MissingRollingVersion(mfcrd.name.clone()).into()),
+    };
+    region.versioningScheme.verify(&version)?;
+    if !mfcrd.regions.contains(&region.name) {
+        bail!(
+            "Cannot deploy '{}' to a region it's not configured for in its manifest",
+            mfcrd.name
+        );
+    }
+    // Overrides given to `shipcat apply` during a freeze are carried by the crd
+    check_freeze(region, mfcrd.freezeOverride.as_deref())?;
+    // Digests pinned by `shipcat apply` are verified again, signatures included
+    registry::pre_apply(&mut mfcrd, region).await?;
+
+    // Only services that have been applied before can be diffed against
+    let uid = crd.metadata.uid;
+    let status = crd.status.as_ref();
+    let installed = status
+        .and_then(|st| st.conditions.applied.as_ref())
+        .map(|_| Installed::new(uid, status));
+    let last_version = installed
+        .as_ref()
+        .and_then(|i| i.last_good.as_ref())
+        .map(|(v, _)| v.clone());
+    let reason = match (&installed, last_version) {
+        (None, _) => UpgradeReason::NewService,
+        (Some(_), Some(v)) if v != version => UpgradeReason::VersionChange,
+        _ => UpgradeReason::ManifestChange,
+    };
+    upgrade(mfcrd, Some(reason), installed, s, false, true, region, conf).await
+}
+
+/// What a shipcatmanifest crd recorded before an upgrade
+struct Installed {
+    /// Uid of the crd, for ownerReferences
+    uid: Option<String>,
+    /// Last version that rolled out, and the secret versions it used
+    last_good: Option<(String, BTreeMap<String, u32>)>,
+}
+
+impl Installed {
+    fn new(uid: Option<String>, status: Option<&ManifestStatus>) -> Self {
+        let last_good = status.and_then(|s| s.summary.as_ref()).and_then(|s| {
+            let secrets = s.last_successful_secret_versions.clone();
+            s.last_successful_rollout_version.clone().map(|v| (v, secrets))
+        });
+        Installed { uid, last_good }
+    }
+}
 
 /// Reason for an apply being allowed through
 ///
@@ -124,7 +376,7 @@ impl ToString for UpgradeReason {
 /// First version of apply that does not use tiller
 ///
 /// This writes events to uses the shipcatmanifest crd
-#[allow(clippy::cognitive_complexity)] // TODO: refactor this!
+#[allow(clippy::cognitive_complexity, clippy::too_many_arguments)] // TODO: refactor this!
 async fn apply_kubectl(
     svc: &str,
     force: bool,
@@ -132,6 +384,8 @@ async fn apply_kubectl(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    context: Option<&str>,
+    freeze: Option<&str>,
 ) -> Result<Option<UpgradeInfo>> {
     if let Err(e) = webhooks::ensure_requirements(&region) {
         warn!("Could not ensure webhook requirements: {}", e);
@@ -160,7 +414,7 @@ async fn apply_kubectl(
     // - if the service has been installed before (negates the need for a diff)
     // - if we need to apply a new crd (so we have an atomic change)
     // - if we need to interact with secret-manager TODO: do
-    let s = ShipKube::new(&mfbase).await?;
+    let s = ShipKube::new_in_context(&mfbase.name, &mfbase.namespace, context).await?;
 
     // Next large batch is working out the reason for the upgrade (if any)
     let mut reason = None;
@@ -198,13 +452,16 @@ async fn apply_kubectl(
             }
         }
     };
-    let can_diff = crd.is_some();
     debug!("using {}={}", svc, actual_version);
     // no shoehorning in illegal versions in the crd!
     region.versioningScheme.verify(&actual_version)?;
 
+    // Fail early on images missing from the registry, and pin their digest if required
+    let mut mfcrd = mfbase.version(actual_version.clone());
+    registry::pre_apply(&mut mfcrd, region).await?;
+    mfcrd.freezeOverride = freeze.map(String::from);
+
     // Complete and apply the CRD
-    let mfcrd = mfbase.version(actual_version.clone());
     let crd_changed = s.apply(mfcrd.clone()).await?;
     // Cheap reconcile ends here if !changed && !force
     if crd_changed {
@@ -214,14 +471,43 @@ async fn apply_kubectl(
         info!("{} up to date (crd check)", svc);
         return Ok(None);
     }
+    // The operator rolls out crd changes as it sees them
+    if region.reconciliationMode == ReconciliationMode::Operator {
+        info!("{} crd applied, leaving the rollout to the operator", svc);
+        return Ok(None);
+    }
+
+    // Remember what last worked in case we need to roll back to it
+    let installed = crd.map(|o| Installed::new(o.metadata.uid, o.status.as_ref()));
+    upgrade(mfcrd, reason, installed, &s, force, wait, region, conf).await
+}
+
+/// Template, apply and track a manifest whose crd is up to date
+///
+/// `installed` is `None` for services that have not been applied before.
+#[allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
+async fn upgrade(
+    mfcrd: Manifest,
+    mut reason: Option<UpgradeReason>,
+    installed: Option<Installed>,
+    s: &ShipKube,
+    force: bool,
+    wait: bool,
+    region: &Region,
+    conf: &Config,
+) -> Result<Option<UpgradeInfo>> {
+    let svc = mfcrd.name.clone();
+    let actual_version = mfcrd.version.clone().expect("crd must have a version");
+    let can_diff = installed.is_some();
 
     // Prepare for an actual upgrade now..
     let mut ui = UpgradeInfo::new(&mfcrd);
+    ui.cluster = s.context.clone();
     webhooks::apply_event(UpgradeState::Pending, &ui, &region, &conf).await;
 
     // Fetch all the secrets so we can create a completed manifest
     // TODO: check scp.status.secretChecksum against secret-manager instead
-    let mut mf = match mfcrd.complete(&region).await {
+    let mut mf = match mfcrd.clone().complete(&region).await {
         Ok(m) => m,
         Err(e) => {
             // Fire failed events if secrets fail to resolve
@@ -232,8 +518,12 @@ async fn apply_kubectl(
         }
     };
     // Should have a UID for ownerReferences now
-    mf.uid = if let Some(o) = crd {
-        o.metadata.uid
+    let (uid, last_good) = match installed {
+        Some(i) => (Some(i.uid), i.last_good),
+        None => (None, None),
+    };
+    mf.uid = if let Some(uid) = uid {
+        uid
     } else {
         match s.get().await {
             // fallback to the one we just created
@@ -249,21 +539,33 @@ async fn apply_kubectl(
         }
     };
 
-    // Create completed kubernetes yaml (via shipcat values | helm template)
-    let tfile = format!("{}.kube.gen.yml", svc);
-    let tpth = Path::new(".").join(tfile.clone());
-    if let Err(e) = helm::template(&mf, Some(tpth)).await {
-        // Errors here are obscure, and should not happen, but pass them up anyway
-        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
-        s.update_generate_false("ResolveFailure", e.description().to_string())
-            .await?;
-        return Err(e);
-    }
+    // Create completed kubernetes objects (via shipcat values | helm template)
+    let objects = match helm::template(&mf, None)
+        .await
+        .and_then(|tpl| helm::objects(&tpl))
+    {
+        Ok(o) => o,
+        Err(e) => {
+            // Errors here are obscure, and should not happen, but pass them up anyway
+            webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+            s.update_generate_false("ResolveFailure", e.description().to_string())
+                .await?;
+            return Err(e);
+        }
+    };
 
     // Attach diff to UpgradeInfo if diffing is possible
     if can_diff {
         // helm diff only supports diffing if already installed..
-        match diff_kubectl(&mf, &tfile).await {
+        // blue/green objects are compared against the colour that is live
+        let diffable = match &mf.blueGreen {
+            Some(_) => match live_colour(&mf, s, &objects).await {
+                Ok(Some(c)) => coloured_objects(&mf, &objects, &c),
+                _ => objects.clone(),
+            },
+            None => objects.clone(),
+        };
+        match diff_objects(&mf, s, &diffable).await {
             Ok(Some(kdiff)) => {
                 ui.diff = Some(kdiff);
                 reason = reason.or(Some(UpgradeReason::TemplateDiff));
@@ -296,104 +598,479 @@ async fn apply_kubectl(
     webhooks::apply_event(UpgradeState::Started, &ui, &region, &conf).await;
     s.update_generate_true().await?; // if this fails, stop, want .status to be correct
 
-    match upgrade_kubectl(&mf, &tfile).await {
+    // Canaries only make sense when there's something running to compare against
+    let canaried = can_diff && mf.canary.is_some() && mf.blueGreen.is_none();
+    match rollout_objects(&mf, s, &objects, can_diff, force).await {
         Err(e) => {
             error!("{} from {}", e, ui.name);
             webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+            let err = match e.kind() {
+                ErrorKind::CanaryAborted(..) => "CanaryAborted",
+                ErrorKind::ApplyConflict(..) => "ApplyConflict",
+                ErrorKind::UpgradeTimeout(..) => "Timeout",
+                _ => "ApplyFailure",
+            };
             let reason = e.description().to_string();
-            s.update_apply_false(ureason.to_string(), "ApplyFailure", reason)
-                .await?; // TODO: chain
+            s.update_apply_false(ureason.to_string(), err, reason).await?; // TODO: chain
             return Err(e);
         }
         Ok(_) => {
             let _ = s.update_apply_true(ureason.to_string()).await;
-            if !wait {
+            if !wait && mf.blueGreen.is_none() && !canaried {
                 info!("successfully applied {} (without waiting)", ui.name);
             } else {
-                match track::workload_rollout(&mf, &s).await {
+                // blue/green deployments are tracked before switching traffic
+                let rollout = if mf.blueGreen.is_some() {
+                    Ok(true)
+                } else {
+                    track::workload_rollout(&mf, s).await
+                };
+                // the canary keeps serving until the main Deployment is done rolling out
+                if canaried {
+                    if let Err(e) = prune_objects(&mf, s, &objects).await {
+                        warn!("Failed to remove the canary of {}: {}", ui.name, e);
+                    }
+                }
+                match rollout {
                     Ok(true) => {
                         info!("successfully rolled out {}", &ui.name);
                         webhooks::apply_event(UpgradeState::Completed, &ui, &region, &conf).await;
-                        s.update_rollout_true(&actual_version).await?;
+                        s.update_rollout_true(&actual_version, &mf.secretVersions).await?;
                     }
                     Ok(false) => {
                         let time = mf.estimate_wait_time();
                         let reason = format!("timed out waiting {}s for rollout", time);
                         //let _ = kubectl::debug_rollout_status(&mf).await;
-                        let _ = track::debug(&mf, &s).await;
+                        let _ = track::debug(&mf, s).await;
                         // TODO: collect these for .status call ^?
                         warn!("failed to roll out {}", &ui.name);
                         webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
                         s.update_rollout_false("Timeout", reason).await?; // TODO: chain
+                        rollback(&mf, mfcrd, s, last_good, &ui, force, &region, &conf).await;
                         return Err(ErrorKind::UpgradeTimeout(mf.name.clone(), time).into());
                     }
                     Err(e) => {
                         webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
                         s.update_rollout_false("RolloutTrackFailure", e.description().to_string())
                             .await?; // TODO: chain
+                        rollback(&mf, mfcrd, s, last_good, &ui, force, &region, &conf).await;
                         return Err(e);
                     }
                 }
             }
         }
     };
-    // cleanups in non-error cases
-    let _ = fs::remove_file(&tfile).await;
     Ok(Some(ui))
 }
 
-/// Shell out to kubectl apply
+/// Roll back to the last successfully rolled out version after a failed rollout
 ///
-/// Assumes you have written your template file from `helm template`
-async fn upgrade_kubectl(mf: &Manifest, tfile: &str) -> Result<()> {
-    // upgrade it using the same command
-    let applyvec = vec![
-        "apply".into(),
-        format!("-n={}", mf.namespace),
-        "-f".into(),
-        tfile.into(),
-        "--prune".into(),
-        // NB: assumes one deploy per namespace
-        format!("-l=app.kubernetes.io/name={}", mf.name),
-    ];
-    info!("kubectl {}", applyvec.join(" "));
-    kubectl::kexec(applyvec)
-        .await
-        .chain_err(|| ErrorKind::KubectlApplyFailure(mf.name.clone()))?;
+/// Only done when `autoRollback` is enabled for the manifest or its region.
+/// Secrets are pinned to the versions recorded for that rollout, so a secret change
+/// alone can also be rolled back.
+/// Errors are recorded in the `rolledback` condition rather than propagated,
+/// as the caller is already failing the apply.
+#[allow(clippy::too_many_arguments)]
+async fn rollback(
+    mf: &Manifest,
+    mfcrd: Manifest,
+    s: &ShipKube,
+    last_good: Option<(String, BTreeMap<String, u32>)>,
+    ui: &UpgradeInfo,
+    force: bool,
+    region: &Region,
+    conf: &Config,
+) {
+    if !mf.autoRollback.unwrap_or(region.autoRollback) {
+        return;
+    }
+    let (version, secrets) = match last_good {
+        Some((v, sv)) if v != ui.version || sv != mf.secretVersions => (v, sv),
+        _ => {
+            warn!(
+                "Not rolling back {}: no other successful rollout recorded",
+                mf.name
+            );
+            return;
+        }
+    };
+    warn!("Rolling back {} from {} to {}", mf.name, ui.version, version);
+    match rollback_to(mf, mfcrd, s, &version, &secrets, force, region).await {
+        Ok(_) => {
+            info!("successfully rolled back {} to {}", mf.name, version);
+            let mut rbui = ui.clone();
+            rbui.version = version.clone();
+            webhooks::apply_event(UpgradeState::RolledBack, &rbui, &region, &conf).await;
+            let _ = s.update_rollback_true(&ui.version, &version).await;
+        }
+        Err(e) => {
+            error!("Failed to roll back {} to {}: {}", mf.name, version, e);
+            let reason = format!("{} (rolling back to {})", e.description(), version);
+            let _ = s.update_rollback_false("RollbackFailure", reason).await;
+        }
+    }
+}
+
+/// Re-apply and track a previous version of a manifest with pinned secret versions
+///
+/// The CRD is rolled back as well, so that it matches what is running.
+/// The objects go out through the manifest's rollout strategy, so blue/green services
+/// get a new colour rather than an uncoloured Deployment.
+async fn rollback_to(
+    mf: &Manifest,
+    mfcrd: Manifest,
+    s: &ShipKube,
+    version: &str,
+    secrets: &BTreeMap<String, u32>,
+    force: bool,
+    region: &Region,
+) -> Result<()> {
+    let mut prevcrd = mfcrd.version(version.to_string());
+    registry::pre_apply(&mut prevcrd, region).await?;
+    if s.apply(prevcrd.clone()).await? {
+        // the operator must not reconcile the spec change it made itself
+        s.record_generation(version).await?;
+    }
+    prevcrd.pin_secrets(secrets)?;
+    let mut prev = prevcrd.complete(region).await?;
+    prev.uid = mf.uid.clone();
+    let objects = helm::objects(&helm::template(&prev, None).await?)?;
+    // the last good version is not canaried again
+    rollout_objects(&prev, s, &objects, false, force).await?;
+    // blue/green rollouts are tracked before switching traffic
+    if prev.blueGreen.is_none() && !track::workload_rollout(&prev, s).await? {
+        let time = prev.estimate_wait_time();
+        return Err(ErrorKind::UpgradeTimeout(prev.name.clone(), time).into());
+    }
+    Ok(())
+}
+
+/// Apply rendered objects using the manifest's rollout strategy
+///
+/// Blue/green takes precedence, and canaries are only run when `canary` is set.
+/// After a canary, the caller must prune once the main Deployment has rolled out.
+async fn rollout_objects(
+    mf: &Manifest,
+    s: &ShipKube,
+    objects: &[Value],
+    canary: bool,
+    force: bool,
+) -> Result<()> {
+    if let Some(bg) = &mf.blueGreen {
+        blue_green_rollout(mf, s, objects, bg, force).await
+    } else if canary && mf.canary.is_some() {
+        // the canary is pruned once the main Deployment has rolled out
+        canary_rollout(mf, s, objects, force).await?;
+        apply_objects(mf, s, objects, force).await
+    } else {
+        upgrade_objects(mf, s, objects, force).await
+    }
+}
+
+/// Create a canary copy of a rendered Deployment
+///
+/// Pods get a `track: canary` label that the selector also requires,
+/// so the main Deployment and rollout tracking can tell them apart.
+fn canary_deployment(main: &Value) -> Value {
+    let mut obj = main.clone();
+    let name = format!("{}-canary", main["metadata"]["name"].as_str().unwrap_or_default());
+    obj["metadata"]["name"] = json!(name);
+    obj["spec"]["selector"]["matchLabels"]["track"] = json!("canary");
+    obj["spec"]["template"]["metadata"]["labels"]["track"] = json!("canary");
+    obj
+}
+
+/// Point references to the named config objects at their canary copies
+///
+/// Covers volumes, projected volumes, `envFrom` and `valueFrom` references.
+fn canary_refs(v: &mut Value, configmaps: &[String], secrets: &[String]) {
+    match v {
+        Value::Object(o) => {
+            for (k, x) in o.iter_mut() {
+                let (keys, names): (&[&str], _) = match k.as_str() {
+                    "configMap" | "configMapRef" | "configMapKeyRef" => (&["name"], configmaps),
+                    "secret" => (&["secretName", "name"], secrets),
+                    "secretRef" | "secretKeyRef" => (&["name"], secrets),
+                    _ => {
+                        canary_refs(x, configmaps, secrets);
+                        continue;
+                    }
+                };
+                for key in keys {
+                    if let Some(n) = x[*key].as_str().filter(|n| names.iter().any(|c| c == n)) {
+                        x[*key] = json!(format!("{}-canary", n));
+                    }
+                }
+            }
+        }
+        Value::Array(xs) => {
+            for x in xs {
+                canary_refs(x, configmaps, secrets);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Canary copies of a rendered Deployment and the config it runs with
+///
+/// ServiceAccounts, Secrets and ConfigMaps are copied with a `-canary` suffix,
+/// and the canary Deployment uses the copies, so the main Deployment keeps its config
+/// until the canary is promoted. The copies are returned before the Deployment.
+fn canary_objects(main: &Value, objects: &[Value]) -> Vec<Value> {
+    let names = |kind: &str| -> Vec<String> {
+        objects
+            .iter()
+            .filter(|o| o["kind"] == kind)
+            .filter_map(|o| o["metadata"]["name"].as_str().map(String::from))
+            .collect()
+    };
+    let (configmaps, secrets, accounts) = (names("ConfigMap"), names("Secret"), names("ServiceAccount"));
+    let mut res = vec![];
+    for o in objects {
+        if let Some("ServiceAccount") | Some("Secret") | Some("ConfigMap") = o["kind"].as_str() {
+            let mut copy = o.clone();
+            let name = format!("{}-canary", o["metadata"]["name"].as_str().unwrap_or_default());
+            copy["metadata"]["name"] = json!(name);
+            res.push(copy);
+        }
+    }
+    let mut deploy = canary_deployment(main);
+    let pod = &mut deploy["spec"]["template"]["spec"];
+    if let Some(sa) = pod["serviceAccountName"].as_str().filter(|sa| accounts.iter().any(|a| a == sa)) {
+        pod["serviceAccountName"] = json!(format!("{}-canary", sa));
+    }
+    canary_refs(pod, &configmaps, &secrets);
+    res.push(deploy);
+    res
+}
+
+/// Advance a canary Deployment through the manifest's canary steps
+///
+/// The canary shares labels with the main Deployment, so the Service splits traffic
+/// by replica count. Only canary copies are applied, so the main Deployment and its config
+/// are untouched until promotion. None of them are rendered, so they are pruned
+/// once the main Deployment has rolled out.
+/// On abort, the canary objects are deleted and the main Deployment is left untouched.
+async fn canary_rollout(mf: &Manifest, s: &ShipKube, objects: &[Value], force: bool) -> Result<()> {
+    let canary = mf
+        .canary
+        .clone()
+        .expect("canary rollout requires canary settings");
+    let main = objects
+        .iter()
+        .find(|o| o["kind"] == "Deployment" && o["metadata"]["name"] == mf.name.as_str());
+    let mut copies = match main {
+        Some(d) => canary_objects(d, objects),
+        None => bail!("Canary of {} requires a Deployment named {}", mf.name, mf.name),
+    };
+    let mut obj = copies.pop().expect("canary deployment");
+    // the canary pods need their config before they start
+    for o in &copies {
+        apply_object(mf, s, o, force).await?;
+    }
+    let total = mf.min_replicas();
+    for step in &canary.steps {
+        let replicas = canary.replicas_for(step.weight, total);
+        obj["spec"]["replicas"] = json!(replicas);
+        info!(
+            "Canary {} at {}% ({} canary and {} main replicas)",
+            mf.name,
+            Canary::traffic_weight(replicas, total),
+            replicas,
+            total
+        );
+        apply_object(mf, s, &obj, force).await?;
+        let abort = match track::canary_step(mf, s, &canary, replicas, step.pause).await {
+            Ok(a) => a,
+            Err(e) => Some(format!("failed to track canary: {}", e)),
+        };
+        if let Some(reason) = abort {
+            warn!("Aborting canary of {}: {}", mf.name, reason);
+            for o in copies.iter().chain(Some(&obj)) {
+                s.delete_object(o).await?;
+            }
+            return Err(ErrorKind::CanaryAborted(mf.name.clone(), reason).into());
+        }
+    }
+    info!("Canary {} passed all steps; upgrading", mf.name);
     Ok(())
 }
 
-/// Minified kubectl diff shell out
+fn is_main(mf: &Manifest, obj: &Value, kind: &str) -> bool {
+    obj["kind"] == kind && obj["metadata"]["name"] == mf.name.as_str()
+}
+
+/// Rendered objects with the main Deployment replaced by a copy for `colour`
 ///
-/// Requires kubernetes 1.13
-pub async fn diff_kubectl(mf: &Manifest, tfile: &str) -> Result<Option<String>> {
-    let namespace = mf.namespace.clone();
-    let pth = Path::new(tfile);
-    let (kdiffunobfusc, kdifferr, success) = kubectl::diff(pth.to_path_buf(), &namespace).await?;
+/// The Service selects that colour, and autoscalers target the coloured Deployment.
+pub fn coloured_objects(mf: &Manifest, objects: &[Value], colour: &str) -> Vec<Value> {
+    let deploy_name = format!("{}-{}", mf.name, colour);
+    let mut res = vec![];
+    for o in objects {
+        let mut o = o.clone();
+        if is_main(mf, &o, "Deployment") {
+            o["metadata"]["name"] = json!(deploy_name);
+            o["spec"]["selector"]["matchLabels"]["colour"] = json!(colour);
+            o["spec"]["template"]["metadata"]["labels"]["colour"] = json!(colour);
+        } else if is_main(mf, &o, "Service") {
+            o["spec"]["selector"]["colour"] = json!(colour);
+        } else if o["kind"] == "HorizontalPodAutoscaler"
+            && o["spec"]["scaleTargetRef"]["name"] == mf.name.as_str()
+        {
+            o["spec"]["scaleTargetRef"]["name"] = json!(deploy_name);
+        }
+        res.push(o);
+    }
+    res
+}
 
-    let kubediff = diff::obfuscate_secrets(
-        kdiffunobfusc, // move this away quickly..
-        mf.get_secrets(),
+/// The colour the live Service currently selects (if any)
+pub async fn live_colour(mf: &Manifest, s: &ShipKube, objects: &[Value]) -> Result<Option<String>> {
+    let svc = match objects.iter().find(|o| is_main(mf, o, "Service")) {
+        Some(o) => o,
+        None => bail!("Blue/green of {} requires a Service named {}", mf.name, mf.name),
+    };
+    let live = s.get_object(svc).await?;
+    Ok(live.and_then(|o| o["spec"]["selector"]["colour"].as_str().map(String::from)))
+}
+
+/// Bring up a full second Deployment, then switch the Service over to it
+///
+/// The colour in the live Service selector decides which colour to deploy next,
+/// and the old colour is pruned after the soak time.
+/// If the new colour fails to roll out, it is deleted and the Service is left alone.
+async fn blue_green_rollout(
+    mf: &Manifest,
+    s: &ShipKube,
+    objects: &[Value],
+    bg: &BlueGreen,
+    force: bool,
+) -> Result<()> {
+    use futures_timer::Delay;
+    if !objects.iter().any(|o| is_main(mf, o, "Deployment")) {
+        bail!(
+            "Blue/green of {} requires a Deployment named {}",
+            mf.name,
+            mf.name
+        );
+    }
+    let old = live_colour(mf, s, objects).await?;
+    let colour = if old.as_ref().map(String::as_str) == Some("blue") {
+        "green"
+    } else {
+        "blue"
+    };
+    let coloured = coloured_objects(mf, objects, colour);
+    let (svc, rest): (Vec<Value>, Vec<Value>) = coloured.into_iter().partition(|o| is_main(mf, o, "Service"));
+    let deploy_name = format!("{}-{}", mf.name, colour);
+    let deploy = rest
+        .iter()
+        .find(|o| o["kind"] == "Deployment" && o["metadata"]["name"] == deploy_name.as_str())
+        .cloned()
+        .expect("coloured deployment");
+    info!(
+        "Deploying {} as {} (live: {})",
+        mf.name,
+        colour,
+        old.unwrap_or_else(|| "none".into())
     );
-    debug!("Full diff (obfuscated): \n{}", kubediff);
-
-    if !success {
-        let err = kdifferr.trim();
-        if err != "exit status 1" {
-            warn!("diff {} stderr: {}", mf.name, kdifferr.trim());
-            bail!(
-                "kubectl diff {} returned: {}",
-                mf.name,
-                kdifferr.lines().next().unwrap()
-            );
+    apply_objects(mf, s, &rest, force).await?;
+
+    let ck = ShipKube::new_in_context(&mf.name, &mf.namespace, s.context.as_deref())
+        .await?
+        .coloured(colour);
+    let ok = match track::workload_rollout(mf, &ck).await {
+        Ok(ok) => ok,
+        Err(e) => {
+            s.delete_object(&deploy).await?;
+            return Err(e);
+        }
+    };
+    if !ok {
+        warn!(
+            "{} did not roll out; leaving traffic on the old colour",
+            deploy_name
+        );
+        s.delete_object(&deploy).await?;
+        return Err(ErrorKind::UpgradeTimeout(deploy_name, mf.estimate_wait_time()).into());
+    }
+
+    apply_objects(mf, s, &svc, force).await?;
+    info!("Switched {} Service to {}", mf.name, colour);
+
+    if bg.soak > 0 {
+        info!(
+            "Soaking {} for {}s before removing the old colour",
+            mf.name, bg.soak
+        );
+        Delay::new(std::time::Duration::from_secs(bg.soak.into())).await;
+    }
+    let keep = rest.into_iter().chain(svc).collect::<Vec<_>>();
+    prune_objects(mf, s, &keep).await
+}
+
+/// Server side apply all rendered objects as shipcat
+///
+/// Conflicts with other field managers fail the apply unless `force` is set,
+/// and labelled objects that are no longer rendered are pruned afterwards.
+async fn upgrade_objects(mf: &Manifest, s: &ShipKube, objects: &[Value], force: bool) -> Result<()> {
+    apply_objects(mf, s, objects, force).await?;
+    prune_objects(mf, s, objects).await
+}
+
+/// Server side apply rendered objects, creating dependencies before workloads
+async fn apply_objects(mf: &Manifest, s: &ShipKube, objects: &[Value], force: bool) -> Result<()> {
+    let mut ordered = objects.to_vec();
+    // create what workloads mount before the workloads themselves
+    ordered.sort_by_key(|o| match o["kind"].as_str() {
+        Some("ServiceAccount") => 0,
+        Some("Secret") | Some("ConfigMap") => 1,
+        _ => 2,
+    });
+    for obj in &ordered {
+        apply_object(mf, s, obj, force).await?;
+    }
+    Ok(())
+}
+
+/// Server side apply a single rendered object
+///
+/// Conflicts are returned as is so the conflicting fields reach the user.
+/// Taking ownership of them is only done with `force`.
+async fn apply_object(mf: &Manifest, s: &ShipKube, obj: &Value, force: bool) -> Result<()> {
+    let (res, name) = kubeapi::object_ref(obj, &mf.namespace)?;
+    if let Err(e) = s.apply_object(obj, false, force).await {
+        if let ErrorKind::ApplyConflict(..) = e.kind() {
+            warn!("Use --force to take ownership of fields managed by others");
+            return Err(e);
         }
+        return Err(e).chain_err(|| ErrorKind::KubectlApplyFailure(mf.name.clone()));
     }
+    info!("{}/{} serverside-applied", res.kind, name);
+    Ok(())
+}
 
-    let smalldiff = diff::minify(&kubediff);
-    Ok(if !smalldiff.is_empty() {
-        debug!("{}", kubediff); // full diff for logs
-        println!("{}", smalldiff);
-        Some(smalldiff)
+/// Prune labelled objects for a service that are not in `objects`
+async fn prune_objects(mf: &Manifest, s: &ShipKube, objects: &[Value]) -> Result<()> {
+    let pruned = s
+        .prune(objects)
+        .await
+        .chain_err(|| ErrorKind::KubectlApplyFailure(mf.name.clone()))?;
+    for p in pruned {
+        info!("{} pruned", p);
+    }
+    Ok(())
+}
+
+/// Diff of rendered objects against the cluster
+///
+/// Prints the diff and returns it if the objects have changed.
+pub async fn diff_objects(mf: &Manifest, s: &ShipKube, objects: &[Value]) -> Result<Option<Diff>> {
+    let diff = diff::objects_vs_cluster(mf, s, objects).await?;
+    Ok(if !diff.is_empty() {
+        println!("{}", diff);
+        Some(diff)
     } else {
         None
     })
@@ -401,34 +1078,71 @@ pub async fn diff_kubectl(mf: &Manifest, tfile: &str) -> Result<Option<String>>
 
 /// Restart the workloads associated with a shipcatmanifest
 ///
+/// Regions with `activeClusters` are restarted in every cluster in parallel,
+/// and regions in a freeze window are refused unless a `freeze_override` reason is given.
 /// Optionally wait for the main resource
-pub async fn restart(mf: &Manifest, wait: bool) -> Result<()> {
-    for w in &mf.workers {
-        let r = Restartable {
-            name: w.container.name.clone(),
-            namespace: mf.namespace.clone(),
-            workload: PrimaryWorkload::Deployment,
+pub async fn restart(
+    mf: &Manifest,
+    region: &Region,
+    conf: &Config,
+    wait: bool,
+    freeze_override: Option<&str>,
+) -> Result<()> {
+    check_freeze(region, freeze_override)?;
+    if region.activeClusters.is_empty() {
+        return restart_in(mf, None, wait).await;
+    }
+    let restarts = region.activeClusters.iter().map(|c| async move {
+        let res = match kubeapi::cluster_context(conf, c, region) {
+            Ok(ctx) => restart_in(mf, Some(&ctx), wait).await,
+            Err(e) => Err(e),
         };
-        trigger_rollout_restart(r).await?; // fire-and-forget for subresources
+        if let Err(e) = &res {
+            error!("Failed to restart {} in {}: {}", mf.name, c, e);
+        }
+        (c.clone(), res)
+    });
+    let failed = futures::future::join_all(restarts)
+        .await
+        .into_iter()
+        .filter(|(_, res)| res.is_err())
+        .map(|(c, _)| c)
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        return Err(ErrorKind::ClusterApplyFailure(mf.name.clone(), failed.join(", ")).into());
     }
-    let main = Restartable {
-        name: mf.name.clone(),
-        namespace: mf.namespace.clone(),
-        workload: mf.workload.clone(),
+    Ok(())
+}
+
+/// Restart the workloads of a shipcatmanifest in a kube context, or the current one
+///
+/// Blue/green services restart the Deployment of the colour the live Service selects.
+async fn restart_in(mf: &Manifest, context: Option<&str>, wait: bool) -> Result<()> {
+    let s = ShipKube::new_in_context(&mf.name, &mf.namespace, context).await?;
+    let colour = match &mf.blueGreen {
+        Some(_) => match live_colour(mf, &s, &[service_ref(mf)]).await? {
+            Some(c) => Some(c),
+            None => bail!("{} has no live colour to restart", mf.name),
+        },
+        None => None,
     };
-    trigger_rollout_restart(main).await?;
+    let mut targets = restart_targets(mf, colour.as_deref());
+    let main = targets.pop().expect("main workload");
+    for target in targets {
+        trigger_rollout_restart(&mf.namespace, &target, context).await?; // fire-and-forget for subresources
+    }
+    trigger_rollout_restart(&mf.namespace, &main, context).await?;
     if !wait {
-        info!(
-            "successfully triggered a restart of {}/{}",
-            mf.workload.to_string(),
-            mf.name
-        );
+        info!("successfully triggered a restart of {}", main);
         return Ok(());
     }
-    let sk = ShipKube::new(&mf).await?;
+    let sk = match &colour {
+        Some(c) => s.coloured(c),
+        None => s,
+    };
     // wait for primary if we are waiting
     if track::workload_rollout(&mf, &sk).await? {
-        info!("successfully restarted {}/{}", mf.workload.to_string(), &mf.name);
+        info!("successfully restarted {}", main);
         Ok(())
     } else {
         let time = mf.estimate_wait_time();
@@ -441,22 +1155,45 @@ pub async fn restart(mf: &Manifest, wait: bool) -> Result<()> {
     }
 }
 
-struct Restartable {
-    name: String,
-    workload: PrimaryWorkload,
-    namespace: String,
+/// Reference to the main Service of a manifest, enough to look it up
+fn service_ref(mf: &Manifest) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": { "name": mf.name, "namespace": mf.namespace },
+    })
+}
+
+/// Workloads to restart as `kind/name`, ending with the main workload
+///
+/// Blue/green services run their main workload as the Deployment of a `colour`.
+pub fn restart_targets(mf: &Manifest, colour: Option<&str>) -> Vec<String> {
+    let mut targets = mf
+        .workers
+        .iter()
+        .map(|w| format!("{}/{}", PrimaryWorkload::Deployment.to_string(), w.container.name))
+        .collect::<Vec<_>>();
+    targets.push(match colour {
+        Some(c) => format!("{}/{}-{}", PrimaryWorkload::Deployment.to_string(), mf.name, c),
+        None => format!("{}/{}", mf.workload.to_string(), mf.name),
+    });
+    targets
 }
-async fn trigger_rollout_restart(r: Restartable) -> Result<()> {
-    let restartvec = vec![
+
+async fn trigger_rollout_restart(namespace: &str, target: &str, context: Option<&str>) -> Result<()> {
+    let mut restartvec = vec![
         "rollout".into(),
-        format!("-n={}", r.namespace),
+        format!("-n={}", namespace),
         "restart".into(),
-        format!("{}/{}", r.workload.to_string(), r.name),
+        target.to_string(),
     ];
+    if let Some(ctx) = context {
+        restartvec.push(format!("--context={}", ctx));
+    }
     info!("kubectl {}", restartvec.join(" "));
     kubectl::kexec(restartvec)
         .await
-        .chain_err(|| ErrorKind::KubectlApplyFailure(r.name))
+        .chain_err(|| ErrorKind::KubectlApplyFailure(target.to_string()))
 }
 
 /// Uninstall a service
@@ -464,8 +1201,26 @@ async fn trigger_rollout_restart(r: Restartable) -> Result<()> {
 /// Not meant to be called if the manifest is still installed in the region
 /// shipcat::cluster module is responsible for calling this,
 /// when (and only when) a service disappears from disk.
+///
+/// Regions with `activeClusters` delete the service from every cluster.
 pub async fn delete(svc: &str, reg: &Region, conf: &Config) -> Result<()> {
-    let s = ShipKube::new_within(&svc, &reg.namespace).await?;
+    let mut failed = vec![];
+    for (i, ctx) in kubeapi::region_contexts(conf, reg)?.into_iter().enumerate() {
+        if let Err(e) = delete_in(svc, reg, conf, ctx.as_deref()).await {
+            let cluster = reg.activeClusters.get(i).unwrap_or(&reg.name);
+            error!("Failed to delete {} in {}: {}", svc, cluster, e);
+            failed.push(cluster.clone());
+        }
+    }
+    if !failed.is_empty() {
+        return Err(ErrorKind::ClusterApplyFailure(svc.to_string(), failed.join(", ")).into());
+    }
+    Ok(())
+}
+
+/// Uninstall a service from a kube context, or the current one
+pub async fn delete_in(svc: &str, reg: &Region, conf: &Config, context: Option<&str>) -> Result<()> {
+    let s = ShipKube::new_in_context(svc, &reg.namespace, context).await?;
     match s.get().await {
         // audit all events if it's possible to deserialize current crd
         Ok(mfk) => {
@@ -617,7 +1372,91 @@ impl ShipKube {
         self.patch(&data).await
     }
 
-    pub async fn update_rollout_true(&self, version: &str) -> Result<()> {
+    pub async fn update_rollback_false(&self, err: &str, reason: String) -> Result<()> {
+        debug!("Setting rolledback false");
+        let cond = Condition::bad(&self.applier, err, reason.clone());
+        let now = make_date();
+        let data = json!({
+            "status": {
+                "conditions": {
+                    "rolledback": cond
+                },
+                "summary": {
+                    "lastRollback": now,
+                    "lastFailureReason": reason,
+                    "lastAction": "Rollback",
+                }
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_rollback_true(&self, from: &str, to: &str) -> Result<()> {
+        debug!("Setting rolledback true");
+        let now = make_date();
+        let mut cond = Condition::ok(&self.applier);
+        cond.message = Some(format!("rolled back from {} to {}", from, to));
+        let data = json!({
+            "status": {
+                "conditions": {
+                    "rolledback": cond
+                },
+                "summary": {
+                    "lastRollback": now,
+                    "lastAction": "Rollback",
+                }
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_clusters(&self, clusters: &BTreeMap<String, Condition>) -> Result<()> {
+        debug!("Setting cluster conditions");
+        let data = json!({
+            "status": {
+                "clusters": clusters
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_synced_true(&self) -> Result<()> {
+        debug!("Setting synced true");
+        let cond = Condition::ok(&self.applier);
+        let data = json!({
+            "status": {
+                "conditions": {
+                    "synced": cond
+                }
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_synced_false(&self, err: &str, reason: String) -> Result<()> {
+        debug!("Setting synced false");
+        let cond = Condition::bad(&self.applier, err, reason);
+        let data = json!({
+            "status": {
+                "conditions": {
+                    "synced": cond
+                }
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_observed_generation(&self, generation: Option<i64>) -> Result<()> {
+        debug!("Setting observedGeneration {:?}", generation);
+        let data = json!({
+            "status": {
+                "observedGeneration": generation,
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_rollout_true(&self, version: &str, secrets: &BTreeMap<String, u32>) -> Result<()> {
         debug!("Setting rolledout true");
         let now = make_date();
         let cond = Condition::ok(&self.applier);
@@ -632,6 +1471,7 @@ impl ShipKube {
                     "lastFailureReason": null,
                     "lastAction": "Rollout",
                     "lastSuccessfulRolloutVersion": version,
+                    "lastSuccessfulSecretVersions": secrets,
                 }
             }
         });
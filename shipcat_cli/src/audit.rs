@@ -96,6 +96,15 @@ struct DeploymentPayload {
     service: String,
     version: String,
     manifests_revision: String,
+    /// Cluster applied to when the region spans several clusters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster: Option<String>,
+    /// Image digest that was verified before applying
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    /// Reason given for running during a freeze window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    freeze_override: Option<String>,
 }
 impl DeploymentPayload {
     fn new(whc: &WHC, info: &UpgradeInfo) -> Self {
@@ -105,6 +114,9 @@ impl DeploymentPayload {
             service: info.name.clone(),
             version: info.version.clone(),
             manifests_revision: whc["SHIPCAT_AUDIT_REVISION"].clone(),
+            cluster: info.cluster.clone(),
+            digest: info.digest.clone(),
+            freeze_override: info.freezeOverride.clone(),
         }
     }
 }
@@ -115,13 +127,17 @@ struct ReconciliationPayload {
     id: String,
     region: String,
     manifests_revision: String,
+    /// Reason given for running during a freeze window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    freeze_override: Option<String>,
 }
 impl ReconciliationPayload {
-    fn new(whc: &WHC, r: &str) -> Self {
+    fn new(whc: &WHC, r: &str, freeze_override: Option<&str>) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             region: r.into(),
             manifests_revision: whc["SHIPCAT_AUDIT_REVISION"].clone(),
+            freeze_override: freeze_override.map(String::from),
         }
     }
 }
@@ -133,6 +149,9 @@ struct DeletionPayload {
     region: String,
     service: String,
     manifests_revision: String,
+    /// Reason given for running during a freeze window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    freeze_override: Option<String>,
 }
 impl DeletionPayload {
     fn new(whc: &WHC, info: &UpgradeInfo) -> Self {
@@ -141,6 +160,7 @@ impl DeletionPayload {
             manifests_revision: whc["SHIPCAT_AUDIT_REVISION"].clone(),
             region: info.region.clone(),
             service: info.name.clone(),
+            freeze_override: info.freezeOverride.clone(),
         }
     }
 }
@@ -158,8 +178,14 @@ pub async fn apply(us: &UpgradeState, u: &UpgradeInfo, audcfg: &AuditWebhook, wh
 }
 
 /// Apply audit sent by shipcat::cluster
-pub async fn reconciliation(us: &UpgradeState, region: &str, audcfg: &AuditWebhook, whc: WHC) -> Result<()> {
-    let pl = ReconciliationPayload::new(&whc, region);
+pub async fn reconciliation(
+    us: &UpgradeState,
+    region: &str,
+    freeze_override: Option<&str>,
+    audcfg: &AuditWebhook,
+    whc: WHC,
+) -> Result<()> {
+    let pl = ReconciliationPayload::new(&whc, region, freeze_override);
     AuditEvent::new(AuditType::Reconciliation, &whc, &us, pl)
         .send(&audcfg)
         .await
@@ -231,7 +257,7 @@ mod tests {
         whc.insert("SHIPCAT_AUDIT_CONTEXT_LINK".into(), "http://eg.server/".into());
         whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
 
-        let arp = audit::ReconciliationPayload::new(&whc, "region_name");
+        let arp = audit::ReconciliationPayload::new(&whc, "region_name", None);
         let ae = audit::AuditEvent::new(
             audit::AuditType::Reconciliation,
             &whc,
@@ -240,4 +266,39 @@ mod tests {
         );
         assert_eq!(ae.domain_type, "reconciliation");
     }
+
+    #[test]
+    fn audit_reconciliation_has_freeze_override() {
+        let mut whc: BTreeMap<String, String> = BTreeMap::default();
+        whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(), "egcontextid".into());
+        whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
+
+        let arp = audit::ReconciliationPayload::new(&whc, "region_name", None);
+        assert!(serde_json::to_value(&arp)
+            .unwrap()
+            .get("freeze_override")
+            .is_none());
+
+        let arp = audit::ReconciliationPayload::new(&whc, "region_name", Some("hotfix"));
+        assert_eq!(serde_json::to_value(&arp).unwrap()["freeze_override"], "hotfix");
+    }
+
+    #[test]
+    fn audit_deployment_has_digest() {
+        let mut whc: BTreeMap<String, String> = BTreeMap::default();
+        whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(), "egcontextid".into());
+        whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
+
+        let mut mf = Manifest::test("fake-svc");
+        let adp = audit::DeploymentPayload::new(&whc, &UpgradeInfo::new(&mf));
+        assert!(serde_json::to_value(&adp).unwrap().get("digest").is_none());
+
+        mf.imageDigest = Some("sha256:abc123".into());
+        let adp = audit::DeploymentPayload::new(&whc, &UpgradeInfo::new(&mf));
+        assert_eq!(serde_json::to_value(&adp).unwrap()["digest"], "sha256:abc123");
+
+        mf.freezeOverride = Some("hotfix".into());
+        let adp = audit::DeploymentPayload::new(&whc, &UpgradeInfo::new(&mf));
+        assert_eq!(serde_json::to_value(&adp).unwrap()["freeze_override"], "hotfix");
+    }
 }
@@ -5,13 +5,14 @@ use shipcat_filebacked::SimpleManifest;
 use super::{kubectl, Error, ErrorKind, Result};
 use crate::{
     apply, diff, helm,
-    kubeapi::ShipKube,
+    kubeapi::{self, ShipKube},
+    validate,
     webhooks::{self, UpgradeState},
 };
 
 struct DiffResult {
     name: String,
-    diff: Option<String>,
+    diff: diff::Diff,
 }
 async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffResult> {
     let mut mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
@@ -24,16 +25,7 @@ async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffRe
     mf.version = mf.version.or(crd.spec.version);
     mf.uid = crd.metadata.uid;
     info!("diffing {}", mf.name);
-    let d = if let Some(kdiffunobfusc) = diff::template_vs_kubectl(&mf).await? {
-        let kubediff = diff::obfuscate_secrets(
-            kdiffunobfusc, // move this away quickly..
-            mf.get_secrets(),
-        );
-        let smalldiff = diff::minify(&kubediff);
-        Some(smalldiff)
-    } else {
-        None
-    };
+    let d = diff::template_vs_cluster(&mf).await?;
     Ok(DiffResult {
         name: mf.name,
         diff: d,
@@ -42,7 +34,7 @@ async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffRe
 
 /// Diffs all services in a region
 ///
-/// Helper that shells out to kubectl diff in parallel.
+/// Helper that diffs templates against the cluster in parallel.
 pub async fn mass_diff(conf: &Config, reg: &Region) -> Result<()> {
     let svcs = shipcat_filebacked::available(conf, reg).await?;
     assert!(conf.has_secrets());
@@ -60,8 +52,8 @@ pub async fn mass_diff(conf: &Config, reg: &Region) -> Result<()> {
         }
     }
     for dr in diffs {
-        if let Some(diff) = dr.diff {
-            info!("{} diff output:\n{}", dr.name, diff);
+        if !dr.diff.is_empty() {
+            info!("{} diff output:\n{}", dr.name, dr.diff);
         } else {
             info!("{} unchanged", dr.name)
         }
@@ -98,10 +90,11 @@ async fn check_summary(svc: String, skipped: &[String], conf: &Config, reg: &Reg
     info!("verifying template for {}", mf.name);
     let tpl = helm::template(&mf, None).await?;
     helm::template_check(&mf, reg, skipped, &tpl)?;
+    validate::policies(&mf, conf, reg)?;
     Ok(mf.name)
 }
 
-/// Verifies all populated templates for all services in a region
+/// Verifies all populated templates and policies for all services in a region
 ///
 /// Helper that shells out to helm template in parallel.
 pub async fn mass_template_verify(conf: &Config, reg: &Region, skipped: &[String]) -> Result<()> {
@@ -133,10 +126,17 @@ pub async fn mass_template_verify(conf: &Config, reg: &Region, skipped: &[String
 }
 
 /// Apply CRDs in all region
-pub async fn crd_install(reg: &Region) -> Result<()> {
+pub async fn crd_install(reg: &Region, conf: &Config) -> Result<()> {
+    for ctx in kubeapi::region_contexts(conf, reg)? {
+        crd_install_in(reg, ctx.as_deref()).await?;
+    }
+    Ok(())
+}
+
+async fn crd_install_in(reg: &Region, context: Option<&str>) -> Result<()> {
     use shipcat_definitions::gen_all_crds;
     for crdef in gen_all_crds() {
-        kubectl::apply_resource(&reg.name, crdef, &reg.namespace).await?;
+        kubectl::apply_resource(&reg.name, crdef, &reg.namespace, context).await?;
     }
     Ok(())
 }
@@ -144,9 +144,16 @@ pub async fn crd_install(reg: &Region) -> Result<()> {
 /// Apply all services in the region
 ///
 /// Helper that shells out to kubectl apply in parallel.
-pub async fn mass_crd(conf_sec: &Config, conf_base: &Config, reg: &Region, n_workers: usize) -> Result<()> {
+/// Regions in a freeze window are refused unless a `freeze_override` reason is given.
+pub async fn mass_crd(
+    conf_sec: &Config,
+    conf_base: &Config,
+    reg: &Region,
+    n_workers: usize,
+    freeze_override: Option<&str>,
+) -> Result<()> {
     let svcs = shipcat_filebacked::available(conf_base, reg).await?;
-    crd_reconcile(svcs, conf_sec, conf_base, &reg.name, n_workers).await
+    crd_reconcile(svcs, conf_sec, conf_base, &reg.name, n_workers, freeze_override).await
 }
 
 async fn crd_reconcile(
@@ -155,6 +162,7 @@ async fn crd_reconcile(
     config_base: &Config,
     region: &str,
     n_workers: usize,
+    freeze_override: Option<&str>,
 ) -> Result<()> {
     // NB: This needs config_base for base crd application
     // shipcatconfig crd should not have secrets when applied
@@ -174,12 +182,10 @@ async fn crd_reconcile(
         .find(|r| r.name == region)
         .unwrap()
         .clone();
+    let freeze = apply::check_freeze(&region_sec, freeze_override)?;
+    let freeze = freeze.as_deref();
 
-    webhooks::reconcile_event(UpgradeState::Pending, &region_sec).await;
-    // Always reconcile the CRDs (definitions themselves) first
-    crd_install(&region_base).await?;
-
-    // Make sure config can apply first
+    webhooks::reconcile_event(UpgradeState::Pending, &region_sec, freeze).await;
     let applycfg: ShipcatConfig = if let Some(ref crs) = &region_base.customResources {
         // special configtype detected - re-populating config object
         Config::new(crs.shipcatConfig.clone(), &region_base.name).await?.0
@@ -187,17 +193,24 @@ async fn crd_reconcile(
         config_base.clone()
     }
     .into();
-    kubectl::apply_resource(&region_base.name, applycfg, &region_base.namespace).await?;
-
-    // Single instruction kubectl delete shipcat manifests .... of excess ones
     let svc_names = svcs.iter().map(|x| x.base.name.to_string()).collect::<Vec<_>>();
-    let excess = kubectl::find_redundant_manifests(&region_sec.namespace, &svc_names).await?;
-    if !excess.is_empty() {
-        info!("Will remove excess manifests: {:?}", excess);
-    }
-    for svc in excess {
-        // NB: doing deletion sequentially...
-        apply::delete(&svc, &region_sec, &config_sec).await?;
+    for ctx in kubeapi::region_contexts(config_sec, &region_sec)? {
+        let ctx = ctx.as_deref();
+        // Always reconcile the CRDs (definitions themselves) first
+        crd_install_in(&region_base, ctx).await?;
+
+        // Make sure config can apply first
+        kubectl::apply_resource(&region_base.name, applycfg.clone(), &region_base.namespace, ctx).await?;
+
+        // Single instruction kubectl delete shipcat manifests .... of excess ones
+        let excess = kubectl::find_redundant_manifests(&region_sec.namespace, &svc_names, ctx).await?;
+        if !excess.is_empty() {
+            info!("Will remove excess manifests: {:?}", excess);
+        }
+        for svc in excess {
+            // NB: doing deletion sequentially...
+            apply::delete_in(&svc, &region_sec, config_sec, ctx).await?;
+        }
     }
 
     info!(
@@ -206,7 +219,7 @@ async fn crd_reconcile(
         n_workers
     );
 
-    webhooks::reconcile_event(UpgradeState::Started, &region_sec).await;
+    webhooks::reconcile_event(UpgradeState::Started, &region_sec, freeze).await;
     // then parallel apply the remaining ones
     let force = std::env::var("SHIPCAT_MASS_RECONCILE").unwrap_or("0".into()) == "1";
     let wait_for_rollout = true;
@@ -216,7 +229,7 @@ async fn crd_reconcile(
     let mut buffered = stream::iter(svcs)
         .map(|mf| {
             debug!("Running CRD reconcile for {:?}", mf.base.name);
-            apply::apply(mf.base.name, force, &reg, &conf, wait_for_rollout, None)
+            apply::apply(mf.base.name, force, &reg, &conf, wait_for_rollout, None, freeze)
         })
         .buffer_unordered(n_workers);
 
@@ -241,14 +254,14 @@ async fn crd_reconcile(
             }
             // remaining cases not ignorable
             _ => {
-                webhooks::reconcile_event(UpgradeState::Failed, &region_sec).await;
+                webhooks::reconcile_event(UpgradeState::Failed, &region_sec, freeze).await;
                 return Err(e);
             }
         }
     }
 
     // Otherwise we're good
-    webhooks::reconcile_event(UpgradeState::Completed, &region_sec).await;
+    webhooks::reconcile_event(UpgradeState::Completed, &region_sec, freeze).await;
     Ok(())
 }
 
@@ -1,26 +1,339 @@
-use super::{Config, Manifest, Region, Result};
-use crate::{git, helm, kubectl};
-use regex::Regex;
-use shipcat_definitions::ShipcatManifest;
-use std::process::Command;
+use super::{Config, ErrorKind, Manifest, Region, Result};
+use crate::{apply, git, helm, kubeapi::ShipKube};
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Placeholder shown instead of secret values
+const MASKED: &str = "(masked)";
+
+/// How a field or object changed
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A changed field
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    /// Dotted path to the field
+    ///
+    /// List items are identified by their name when every item has one, otherwise by index.
+    pub path: String,
+    pub change: Change,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
 
+/// Changes to a single kube object
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ObjectDiff {
+    pub kind: String,
+    pub name: String,
+    /// Whether the whole object was added or removed, or some of its fields changed
+    pub change: Change,
+    /// Changed fields, only set for changed objects
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldChange>,
+}
 
-/// YAML serialisation of a manifest.
+/// Structured diff of kube objects
 ///
-/// Return an empty string if the manifest fails region-validation,
-/// otherwise YAML serialise the content. For diff purposes, the content
+/// Secret values are masked when the diff is created, so it is safe to print or send.
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Diff {
+    pub objects: Vec<ObjectDiff>,
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Whether list items can be matched by name
+fn named_items(before: &[Value], after: &[Value]) -> bool {
+    let mut items = before.iter().chain(after.iter()).peekable();
+    items.peek().is_some() && items.all(|x| x["name"].is_string())
+}
+
+fn diff_into(path: &str, before: &Value, after: &Value, out: &mut Vec<FieldChange>) {
+    match (before, after) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (k, av) in a {
+                let p = join_path(path, k);
+                match b.get(k) {
+                    Some(bv) => diff_into(&p, av, bv, out),
+                    None => out.push(FieldChange {
+                        path: p,
+                        change: Change::Removed,
+                        before: Some(av.clone()),
+                        after: None,
+                    }),
+                }
+            }
+            for (k, bv) in b {
+                if !a.contains_key(k) {
+                    out.push(FieldChange {
+                        path: join_path(path, k),
+                        change: Change::Added,
+                        before: None,
+                        after: Some(bv.clone()),
+                    });
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if named_items(a, b) => {
+            let find = |xs: &[Value], name: &Value| xs.iter().find(|x| &x["name"] == name).cloned();
+            for av in a {
+                let p = format!("{}[{}]", path, av["name"].as_str().unwrap_or_default());
+                match find(b, &av["name"]) {
+                    Some(bv) => diff_into(&p, av, &bv, out),
+                    None => out.push(FieldChange {
+                        path: p,
+                        change: Change::Removed,
+                        before: Some(av.clone()),
+                        after: None,
+                    }),
+                }
+            }
+            for bv in b {
+                if find(a, &bv["name"]).is_none() {
+                    out.push(FieldChange {
+                        path: format!("{}[{}]", path, bv["name"].as_str().unwrap_or_default()),
+                        change: Change::Added,
+                        before: None,
+                        after: Some(bv.clone()),
+                    });
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for i in 0..std::cmp::max(a.len(), b.len()) {
+                let p = format!("{}[{}]", path, i);
+                match (a.get(i), b.get(i)) {
+                    (Some(av), Some(bv)) => diff_into(&p, av, bv, out),
+                    (Some(av), None) => out.push(FieldChange {
+                        path: p,
+                        change: Change::Removed,
+                        before: Some(av.clone()),
+                        after: None,
+                    }),
+                    (None, Some(bv)) => out.push(FieldChange {
+                        path: p,
+                        change: Change::Added,
+                        before: None,
+                        after: Some(bv.clone()),
+                    }),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        _ => {
+            if before != after {
+                out.push(FieldChange {
+                    path: path.to_string(),
+                    change: Change::Changed,
+                    before: Some(before.clone()),
+                    after: Some(after.clone()),
+                });
+            }
+        }
+    }
+}
+
+/// Field level diff of two values
+pub fn compare_values(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let mut res = vec![];
+    diff_into("", before, after, &mut res);
+    res
+}
+
+fn contains_secret(s: &str, secrets: &[String]) -> bool {
+    // Short secrets are only masked on exact matches, for fear of clashing with other values
+    secrets
+        .iter()
+        .any(|x| !x.is_empty() && (s == x || (x.len() >= 8 && s.contains(x.as_str()))))
+}
+
+/// Mask string values containing secrets, or every string value if `all`
+fn mask(v: &Value, secrets: &[String], all: bool) -> Value {
+    match v {
+        Value::String(s) if all || contains_secret(s, secrets) => json!(MASKED),
+        Value::Object(o) => Value::Object(
+            o.iter()
+                .map(|(k, x)| (k.clone(), mask(x, secrets, all)))
+                .collect(),
+        ),
+        Value::Array(xs) => Value::Array(xs.iter().map(|x| mask(x, secrets, all)).collect()),
+        _ => v.clone(),
+    }
+}
+
+fn object_id(obj: &Value) -> (String, String) {
+    let kind = obj["kind"].as_str().unwrap_or_default().to_string();
+    let name = obj["metadata"]["name"].as_str().unwrap_or_default().to_string();
+    (kind, name)
+}
+
+/// Diff two sets of kube objects, matching objects by kind and name
+///
+/// Values in the data of Secrets, and any value containing one of `secrets`, are masked.
+pub fn compare_objects(before: &[Value], after: &[Value], secrets: &[String]) -> Diff {
+    let mut objects = vec![];
+    for b in before {
+        let id = object_id(b);
+        if !after.iter().any(|a| object_id(a) == id) {
+            objects.push(ObjectDiff {
+                kind: id.0,
+                name: id.1,
+                change: Change::Removed,
+                fields: vec![],
+            });
+        }
+    }
+    for a in after {
+        let (kind, name) = object_id(a);
+        let prev = before
+            .iter()
+            .find(|b| object_id(b) == (kind.clone(), name.clone()));
+        let (change, fields) = match prev {
+            None => (Change::Added, vec![]),
+            Some(b) => {
+                let fields = compare_values(b, a)
+                    .into_iter()
+                    .map(|f| {
+                        let all = kind == "Secret"
+                            && (f.path.starts_with("data") || f.path.starts_with("stringData"));
+                        FieldChange {
+                            before: f.before.map(|v| mask(&v, secrets, all)),
+                            after: f.after.map(|v| mask(&v, secrets, all)),
+                            ..f
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if fields.is_empty() {
+                    continue;
+                }
+                (Change::Changed, fields)
+            }
+        };
+        objects.push(ObjectDiff {
+            kind,
+            name,
+            change,
+            fields,
+        });
+    }
+    Diff { objects }
+}
+
+/// Tag of an image reference, if it has one
+fn image_tag(image: &str) -> Option<&str> {
+    let last = image.rsplit('/').next()?;
+    let mut parts = last.splitn(2, ':');
+    parts.next();
+    parts.next()
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Infer a version change from a changed image tag
+    ///
+    /// Returns the old and the new version.
+    pub fn version_change(&self) -> Option<(String, String)> {
+        for f in self.objects.iter().flat_map(|o| &o.fields) {
+            if !f.path.ends_with("image") {
+                continue;
+            }
+            if let (Some(Value::String(a)), Some(Value::String(b))) = (&f.before, &f.after) {
+                if let (Some(va), Some(vb)) = (image_tag(a), image_tag(b)) {
+                    if va != vb {
+                        return Some((va.to_string(), vb.to_string()));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Check if a diff contains only version related changes
+    ///
+    /// Every changed field must be a string where swapping the versions explains the change.
+    pub fn is_version_only(&self, vers: (&str, &str)) -> bool {
+        self.objects.iter().all(|o| {
+            o.change == Change::Changed
+                && o.fields.iter().all(|f| match (&f.before, &f.after) {
+                    (Some(Value::String(a)), Some(Value::String(b))) => &a.replace(vers.0, vers.1) == b,
+                    _ => false,
+                })
+        })
+    }
+}
+
+fn show_value(v: &Value) -> String {
+    serde_json::to_string(v).unwrap_or_default()
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.before, &self.after) {
+            (Some(a), Some(b)) => write!(f, "~ {}: {} -> {}", self.path, show_value(a), show_value(b)),
+            (None, Some(b)) => write!(f, "+ {}: {}", self.path, show_value(b)),
+            (Some(a), None) => write!(f, "- {}: {}", self.path, show_value(a)),
+            (None, None) => write!(f, "  {}", self.path),
+        }
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = vec![];
+        for o in &self.objects {
+            match o.change {
+                Change::Added => lines.push(format!("{}/{} added", o.kind, o.name)),
+                Change::Removed => lines.push(format!("{}/{} removed", o.kind, o.name)),
+                Change::Changed => {
+                    lines.push(format!("{}/{} changed:", o.kind, o.name));
+                    lines.extend(o.fields.iter().map(|fc| format!("  {}", fc)));
+                }
+            }
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Print field changes, returning whether there were none
+fn print_fields(fields: &[FieldChange]) -> bool {
+    for fc in fields {
+        println!("{}", fc);
+    }
+    fields.is_empty()
+}
+
+/// Serialisation of a manifest as a value.
+///
+/// Return an empty object if the manifest fails region-validation,
+/// otherwise serialise the content. For diff purposes, the content
 /// of a manifest not in a region is a blank, rather than being invalid.
-async fn as_yaml(svc: &str, conf: &Config, region: &Region) -> Result<String> {
+async fn as_value(svc: &str, conf: &Config, region: &Region) -> Result<Value> {
     let mf = shipcat_filebacked::load_manifest(&svc, conf, region).await?;
     if let Ok(m) = mf.verify_region() {
-        let yaml = serde_yaml::to_string(&m)?;
-        Ok(yaml)
+        Ok(serde_json::to_value(&m)?)
     } else {
-        Ok("".to_string())
+        Ok(json!({}))
     }
 }
 
-
 /// Fast local git compare of the crd
 ///
 /// Should be pretty safe. Stashes existing work, checks out master, compares,
@@ -28,7 +341,7 @@ async fn as_yaml(svc: &str, conf: &Config, region: &Region) -> Result<String> {
 ///
 /// Because this does fiddle with git state while running it is not the default implementation.
 pub async fn values_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
-    let after = as_yaml(&svc, conf, region).await?;
+    let after = as_value(&svc, conf, region).await?;
 
     // move git to get before state:
     let merge_base = git::merge_base()?;
@@ -40,7 +353,7 @@ pub async fn values_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<
     }
 
     // compute before state
-    let before = as_yaml(&svc, conf, region).await?;
+    let before = as_value(&svc, conf, region).await?;
 
     // move git back
     if needs_stash {
@@ -49,7 +362,7 @@ pub async fn values_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<
     git::checkout("-")?;
 
     // display diff
-    shell_diff(&before, &after, "before", "after")
+    Ok(print_fields(&compare_values(&before, &after)))
 }
 
 /// Fast local compare of shipcat template for two regions
@@ -59,14 +372,21 @@ pub async fn values_vs_region(
     region: &Region,
     ref_region: &Region,
 ) -> Result<bool> {
-    let before_region = format!("{}.{}", svc, ref_region.name);
-    let before_values = as_yaml(svc, conf, ref_region).await?;
-
-    let after_region = format!("{}.{}", svc, region.name);
-    let after_values = as_yaml(svc, conf, region).await?;
+    let before_values = as_value(svc, conf, ref_region).await?;
+    let after_values = as_value(svc, conf, region).await?;
 
     // display diff
-    shell_diff(&before_values, &after_values, &before_region, &after_region)
+    Ok(print_fields(&compare_values(&before_values, &after_values)))
+}
+
+/// Stubbed kube objects for a service
+async fn stubbed_objects(svc: &str, conf: &Config, region: &Region) -> Result<(Vec<Value>, Vec<String>)> {
+    let mf = shipcat_filebacked::load_manifest(svc, conf, region)
+        .await?
+        .stub(region)
+        .await?;
+    let objects = helm::objects(&helm::template(&mf, None).await?)?;
+    Ok((objects, mf.get_secrets()))
 }
 
 /// Fast local git compare of shipcat template
@@ -74,12 +394,7 @@ pub async fn values_vs_region(
 /// Because this uses the template in master against local state,
 /// we don't resolve secrets for this (would compare equal values anyway).
 pub async fn template_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
-    let afterpth = Path::new(".").join("after.shipcat.gen.yml");
-    let mf_after = shipcat_filebacked::load_manifest(svc, conf, region)
-        .await?
-        .stub(region)
-        .await?;
-    let _after = helm::template(&mf_after, Some(afterpth.clone())).await?;
+    let (after, secrets) = stubbed_objects(svc, conf, region).await?;
 
     // move git to get before state:
     let merge_base = git::merge_base()?;
@@ -91,12 +406,7 @@ pub async fn template_vs_git(svc: &str, conf: &Config, region: &Region) -> Resul
     }
 
     // compute old state:
-    let beforepth = Path::new(".").join("before.shipcat.gen.yml");
-    let mf_before = shipcat_filebacked::load_manifest(svc, conf, region)
-        .await?
-        .stub(region)
-        .await?;
-    let _before = helm::template(&mf_before, Some(beforepth.clone())).await?;
+    let before = stubbed_objects(svc, conf, region).await;
 
     // move git back
     if needs_stash {
@@ -105,347 +415,311 @@ pub async fn template_vs_git(svc: &str, conf: &Config, region: &Region) -> Resul
     git::checkout("-")?;
 
     // display diff
-    // doesn't reuse shell_diff because we already have files from direct::template
-    let args = ["-u", "before.shipcat.gen.yml", "after.shipcat.gen.yml"];
-    debug!("diff {}", args.join(" "));
-    let s = Command::new("diff").args(&args).status()?;
-    // cleanup
-    fs::remove_file(beforepth)?;
-    fs::remove_file(afterpth)?;
-    Ok(s.success())
+    let diff = compare_objects(&before?.0, &after, &secrets);
+    if !diff.is_empty() {
+        println!("{}", diff);
+    }
+    Ok(diff.is_empty())
 }
 
-use std::{
-    fs::{self, File},
-    io::Write,
-    path::Path,
-};
-
-/// Diff values using kubectl diff
+/// Diff the shipcatmanifest crd against the one in the cluster
 ///
-/// Generate crd as we write it and pipe it to `kubectl diff -`
-/// Only works on clusters with kubectl 1.13 on the server side, so not available everywhere
-pub async fn values_vs_kubectl(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
-    // Generate crd in a temp file:
+/// A crd that is not yet in the cluster is compared against an empty one.
+pub async fn values_vs_crd(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
     let mf = shipcat_filebacked::load_manifest(svc, conf, region).await?;
-    let crd = ShipcatManifest::from(mf);
-    let encoded = serde_yaml::to_string(&crd)?;
-    let cfile = format!("{}.shipcat.crd.gen.yml", svc);
-    let pth = Path::new(".").join(cfile);
-    debug!("Writing crd for {} to {}", svc, pth.display());
-    let mut f = File::create(&pth)?;
-    writeln!(f, "{}", encoded)?;
-    // shell out to kubectl:
-    let (out, _err, success) = kubectl::diff(pth.clone(), &region.namespace).await?;
-    println!("{}", out);
-    // cleanup:
-    fs::remove_file(pth)?;
-    Ok(success)
+    let s = ShipKube::new(&mf).await?;
+    let before = match s.get().await {
+        Ok(crd) => serde_json::to_value(&crd.spec)?,
+        Err(e) => {
+            debug!("No crd for {}: {}", svc, e);
+            json!({})
+        }
+    };
+    let after = serde_json::to_value(&mf)?;
+    Ok(print_fields(&compare_values(&before, &after)))
 }
 
-/// Diff using template kubectl diff
-///
-/// Generate template as we write it and pipe it to `kubectl diff -`
-/// Only works on clusters with kubectl 1.13 on the server side, so not available everywhere
-pub async fn template_vs_kubectl(mf: &Manifest) -> Result<Option<String>> {
-    // Generate template in a temp file:
-    let tfile = format!("{}.shipcat.tpl.gen.yml", mf.name);
-    let pth = Path::new(".").join(tfile);
-
-    let _tpl = helm::template(&mf, Some(pth.clone())).await?;
-
-    let (out, err, success) = kubectl::diff(pth.clone(), &mf.namespace).await?;
-    // cleanup:
-    fs::remove_file(pth)?;
-    if !success && !err.is_empty() && err.trim() != "exit status 1" {
-        println!("kubectl diff stderr: {}", err.trim());
-    }
-    if !out.is_empty() {
-        Ok(Some(out))
-    } else {
-        Ok(None)
+/// Metadata the apiserver manages that should not show up in diffs
+const SERVER_METADATA: &[&str] = &[
+    "creationTimestamp",
+    "generation",
+    "managedFields",
+    "resourceVersion",
+    "selfLink",
+    "uid",
+];
+
+fn strip_server_fields(obj: &mut Value) {
+    if let Some(o) = obj.as_object_mut() {
+        o.remove("status");
     }
-}
-
-// Compare using diff(1)
-// difference libraries all seemed to be lacking somewhat
-fn shell_diff(before: &str, after: &str, before_name: &str, after_name: &str) -> Result<bool> {
-    let beforefilename = format!("{}.shipcat.gen.yml", before_name);
-    let beforepth = Path::new(".").join(&beforefilename);
-    debug!("Writing before to {}", beforepth.display());
-    let mut f = File::create(&beforepth)?;
-    writeln!(f, "{}", before)?;
-
-    let afterfilename = format!("{}.shipcat.gen.yml", after_name);
-    let afterpth = Path::new(".").join(&afterfilename);
-    debug!("Writing after to {}", afterpth.display());
-    let mut f = File::create(&afterpth)?;
-    writeln!(f, "{}", after)?;
-
-    let args = ["-u", &beforefilename, &afterfilename];
-    debug!("diff {}", args.join(" "));
-    let s = Command::new("diff").args(&args).status()?;
-    // cleanup
-    fs::remove_file(beforepth)?;
-    fs::remove_file(afterpth)?;
-
-    Ok(s.success())
-}
-
-/// Minify diff output from kubectl diff
-pub fn minify(diff: &str) -> String {
-    let minusplus = Regex::new(r"^\- |^\+ ").unwrap();
-    let generation = Regex::new(r"generation[:]{1}").unwrap();
-    let kind_line = Regex::new(r"--- /tmp/LIVE-[a-zA-Z0-9]+/([\w\.]+)").unwrap();
-    // Find the +++/--- header and extract the type from it.
-    // Then trim everything that doesn't start with `- ` or `+ `
-    // and additionally ignore `generation` integer updates
-
-    let mut res = vec![];
-    let mut in_secret = false;
-    for l in diff.lines() {
-        if let Some(cap) = kind_line.captures(l) {
-            in_secret = cap[1].contains("Secret");
-            if in_secret {
-                res.push(format!("Change to {} elided for security", &cap[1]));
-            } else {
-                res.push(format!("{} has changed:", &cap[1]));
-            }
-        } else if !in_secret && !l.starts_with("+++") && !generation.is_match(l) && minusplus.is_match(l) {
-            res.push(l.to_string());
+    if let Some(md) = obj["metadata"].as_object_mut() {
+        for k in SERVER_METADATA {
+            md.remove(*k);
+        }
+        if let Some(annots) = md.get_mut("annotations").and_then(Value::as_object_mut) {
+            annots.remove("deployment.kubernetes.io/revision");
+            annots.remove("kubectl.kubernetes.io/last-applied-configuration");
         }
     }
-    res.join("\n")
 }
 
-/// Check if a diff contains only version related changes
-pub fn is_version_only(diff: &str, vers: (&str, &str)) -> bool {
-    let smalldiff = minify(diff);
-    trace!("Checking diff for {:?}", vers);
-    for l in smalldiff.lines() {
-        // ignore headline for resource type (no actual changes)
-        if !l.starts_with('+') && !l.starts_with('-') && l.contains("has changed:") {
-            continue;
-        }
-        // ignore all lines that contain one of the versions
-        if l.contains(vers.0) || l.contains(vers.1) {
-            continue;
+/// Diff rendered objects against their live versions in the cluster
+///
+/// Every object is dry-run applied server side, so defaulted fields do not show up.
+/// Requires kubernetes 1.16
+pub async fn objects_vs_cluster(mf: &Manifest, s: &ShipKube, objects: &[Value]) -> Result<Diff> {
+    let (mut before, mut after) = (vec![], vec![]);
+    for obj in objects {
+        if let Some(mut live) = s.get_object(obj).await? {
+            strip_server_fields(&mut live);
+            before.push(live);
         }
-        // any other lines found => not just a version change
-        return false;
+        let mut merged = match s.apply_object(obj, true, false).await {
+            Err(e) => match e.kind() {
+                ErrorKind::ApplyConflict(id, msg) => {
+                    warn!("Apply of {} needs --force to take conflicting fields: {}", id, msg);
+                    s.apply_object(obj, true, true).await?
+                }
+                _ => return Err(e),
+            },
+            Ok(o) => o,
+        };
+        strip_server_fields(&mut merged);
+        after.push(merged);
     }
-    true
-}
-
-/// Infer a version change diff and extract old version and new version
-pub fn infer_version_change(diff: &str) -> Option<(String, String)> {
-    let img_re = Regex::new(r"[^:]+:(?P<version>[a-z0-9\.\-]+)").unwrap();
-    let res = img_re
-        .captures_iter(diff)
-        .map(|cap| cap["version"].to_string())
-        .collect::<Vec<String>>();
-    if res.len() >= 2 {
-        return Some((res[0].clone(), res[1].clone()));
-    }
-    None
+    Ok(compare_objects(&before, &after, &mf.get_secrets()))
 }
 
-/// Obfuscate a set of secrets from an input string
-pub fn obfuscate_secrets(input: String, secrets: Vec<String>) -> String {
-    let mut out = input;
-    for s in secrets {
-        // If your secret is less than 8 characters, we won't obfuscate it
-        // Mostly for fear of clashing with other parts of the output,
-        // but also because it's an insecure secret anyway
-        if s.len() >= 8 {
-            out = out.replace(&s, "************");
+/// Diff a completed manifest's templates against the cluster
+///
+/// Blue/green services are diffed against the objects of the colour their Service selects.
+pub async fn template_vs_cluster(mf: &Manifest) -> Result<Diff> {
+    let s = ShipKube::new(mf).await?;
+    let mut objects = helm::objects(&helm::template(mf, None).await?)?;
+    if mf.blueGreen.is_some() {
+        if let Some(colour) = apply::live_colour(mf, &s, &objects).await? {
+            objects = apply::coloured_objects(mf, &objects, &colour);
         }
     }
-    out
+    objects_vs_cluster(mf, &s, &objects).await
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{infer_version_change, is_version_only, minify};
+    use super::{compare_objects, compare_values, strip_server_fields, Change, Diff, MASKED};
+    use serde_json::{json, Value};
+
+    fn deploy(image: &str, env: Value) -> Value {
+        json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": { "name": "raftcat", "labels": { "app.kubernetes.io/version": image } },
+            "spec": { "template": { "spec": { "containers": [{
+                "name": "raftcat",
+                "image": format!("quay.io/babylonhealth/raftcat:{}", image),
+                "env": env,
+            }]}}}
+        })
+    }
+
+    fn version_diff(before: &str, after: &str) -> Diff {
+        let env = |v: &str| json!([{ "name": "VERSION", "value": v }]);
+        compare_objects(&[deploy(before, env(before))], &[deploy(after, env(after))], &[])
+    }
+
+    #[test]
+    fn compare_values_test() {
+        let before = json!({ "a": 1, "b": { "c": 2 }, "d": [1, 2], "e": [{ "name": "x", "v": 1 }] });
+        let after = json!({ "a": 1, "b": { "c": 4 }, "d": [1], "e": [{ "name": "y", "v": 1 }], "f": 5 });
+        let paths = compare_values(&before, &after)
+            .into_iter()
+            .map(|f| (f.path, f.change))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![
+            ("b.c".to_string(), Change::Changed),
+            ("d[1]".to_string(), Change::Removed),
+            ("e[x]".to_string(), Change::Removed),
+            ("e[y]".to_string(), Change::Added),
+            ("f".to_string(), Change::Added),
+        ]);
+        assert!(compare_values(&before, &before).is_empty());
+    }
 
     #[test]
     fn version_change_test() {
-        let input = "pa-aggregator, Deployment (apps/v1) has changed:
--         image: \"quay.io/babylonhealth/pa-aggregator-python:e7c1e5dd5de74b2b5da5eef76eb5bf12bdc2ac19\"
-+         image: \"quay.io/babylonhealth/pa-aggregator-python:d4f01f5143643e75d9cc2d5e3221e82a9e1c12e5\"";
-        let res = infer_version_change(input);
-        assert!(res.is_some());
-        let (old, new) = res.unwrap();
+        let diff = version_diff(
+            "e7c1e5dd5de74b2b5da5eef76eb5bf12bdc2ac19",
+            "d4f01f5143643e75d9cc2d5e3221e82a9e1c12e5",
+        );
+        let (old, new) = diff.version_change().unwrap();
         assert_eq!(old, "e7c1e5dd5de74b2b5da5eef76eb5bf12bdc2ac19");
         assert_eq!(new, "d4f01f5143643e75d9cc2d5e3221e82a9e1c12e5");
+        assert!(diff.is_version_only((&old, &new)));
     }
 
     #[test]
     fn version_change_semver() {
-        let input = "pa-aggregator, Deployment (apps/v1) has changed:
--         image: \"quay.io/babylonhealth/pa-aggregator-python:1.2.3\"
-+         image: \"quay.io/babylonhealth/pa-aggregator-python:1.3.0-alpine\"";
-        let res = infer_version_change(input);
-        assert!(res.is_some());
-        let (old, new) = res.unwrap();
+        let diff = version_diff("1.2.3", "1.3.0-alpine");
+        let (old, new) = diff.version_change().unwrap();
         assert_eq!(old, "1.2.3");
         assert_eq!(new, "1.3.0-alpine");
+        assert!(diff.is_version_only((&old, &new)));
     }
 
     #[test]
-    fn version_diff_test() {
-        // simple version change with versions referenced more than once
-        let input = "react-ask-frontend, Deployment (apps/v1) has changed:
--         image: \"quay.io/babylonhealth/react-ask-frontend:6418d7cacb7438ddd4e533d78b38902bc7f79e7b\"
-+         image: \"quay.io/babylonhealth/react-ask-frontend:d27b5c6f96f05436b236dae112c7c8fcedca4c71\"
--           value: 6418d7cacb7438ddd4e533d78b38902bc7f79e7b
-+           value: d27b5c6f96f05436b236dae112c7c8fcedca4c71";
-
-        let res = infer_version_change(input);
-        assert!(res.is_some());
-        let (old, new) = res.unwrap();
-        assert_eq!(old, "6418d7cacb7438ddd4e533d78b38902bc7f79e7b");
-        assert_eq!(new, "d27b5c6f96f05436b236dae112c7c8fcedca4c71");
-        assert!(is_version_only(input, (&new, &old)));
+    fn version_diff_semver_only() {
+        // semver version change, also referenced in an evar
+        let diff = version_diff("1.0.6", "1.0.7");
+        let (old, new) = diff.version_change().unwrap();
+        assert_eq!(old, "1.0.6");
+        assert_eq!(new, "1.0.7");
+        assert!(diff.is_version_only((&old, &new)));
     }
 
     #[test]
-    fn version_diff_test2() {
-        // not just a simple version change
-        let input = "react-ask-frontend, Deployment (apps/v1) has changed:
--         image: \"quay.io/babylonhealth/react-ask-frontend:6418d7cacb7438ddd4e533d78b38902bc7f79e7b\"
-+         image: \"quay.io/babylonhealth/react-ask-frontend:d27b5c6f96f05436b236dae112c7c8fcedca4c71\"
--           blast: keyremoval";
-        let res = infer_version_change(input);
-        assert!(res.is_some());
-        let (old, new) = res.unwrap();
-        assert_eq!(old, "6418d7cacb7438ddd4e533d78b38902bc7f79e7b");
-        assert_eq!(new, "d27b5c6f96f05436b236dae112c7c8fcedca4c71");
-        assert!(!is_version_only(input, (&new, &old)));
+    fn version_only_across_objects() {
+        let labelled = |kind: &str, v: &str| {
+            json!({
+                "kind": kind,
+                "metadata": { "name": "aim-dashboard", "labels": { "app.kubernetes.io/version": v } }
+            })
+        };
+        let (old, new) = (
+            "a844d0db93216b25d22a482ab80029d4a552f285",
+            "203894776eed17f00b9dd0bc25a09dcef644ea67",
+        );
+        let env = |v: &str| json!([{ "name": "VERSION", "value": v }]);
+        let before = vec![
+            deploy(old, env(old)),
+            labelled("ServiceAccount", old),
+            labelled("Service", old),
+        ];
+        let after = vec![
+            deploy(new, env(new)),
+            labelled("ServiceAccount", new),
+            labelled("Service", new),
+        ];
+        let diff = compare_objects(&before, &after, &[]);
+        assert_eq!(diff.objects.len(), 3);
+        assert_eq!(diff.version_change(), Some((old.to_string(), new.to_string())));
+        assert!(diff.is_version_only((old, new)));
     }
 
     #[test]
-    fn version_diff_test3() {
-        // semver version change
-        let input = "knowledge-base2-search, Deployment (apps/v1) has changed:
--         image: \"quay.io/babylonhealth/knowledgebase2:1.0.6\"
-+         image: \"quay.io/babylonhealth/knowledgebase2:1.0.7\"
--           value: 1.0.6
-+           value: 1.0.7";
-        let res = infer_version_change(input);
-        assert!(res.is_some());
-        let (old, new) = res.unwrap();
-        assert_eq!(old, "1.0.6");
-        assert_eq!(new, "1.0.7");
-        assert!(is_version_only(input, (&new, &old)));
+    fn server_fields_ignored() {
+        let env =
+            |v: &str| json!([{ "name": "BLAAA", "value": v }, { "name": "LOG_LEVEL", "value": "DEBUG" }]);
+        let mut live = deploy("0.3.0", env("eirik4"));
+        live["metadata"]["creationTimestamp"] = json!("2019-09-11T14:49:14Z");
+        live["metadata"]["generation"] = json!(5);
+        live["metadata"]["annotations"] = json!({
+            "kubectl.kubernetes.io/last-applied-configuration": "{\"kind\":\"Deployment\"}"
+        });
+        live["status"] = json!({ "replicas": 1 });
+        strip_server_fields(&mut live);
+        let mut rendered = deploy("0.3.0", env("eirik5"));
+        rendered["metadata"]["annotations"] = json!({});
+        let diff = compare_objects(&[live], &[rendered], &[]);
+        assert_eq!(
+            diff.to_string(),
+            "Deployment/raftcat changed:
+  ~ spec.template.spec.containers[raftcat].env[BLAAA].value: \"eirik4\" -> \"eirik5\""
+        );
     }
 
     #[test]
-    fn kubectl_diff_minify_test() {
-        let input = "--- /tmp/LIVE-A9/apps.v1.Deployment.dev.raftcat   2019-09-11 16:12:26.819641578 +0100
-+++ /tmp/MERGED-B0/apps.v1.Deployment.dev.raftcat 2019-09-11 16:12:26.852974183 +0100
-@@ -6,7 +6,7 @@
-     kubectl.kubernetes.io/last-applied-configuration: |
-       {\"apiVersion\":\"apps/v1\",\"kind\":\"Deployment\"}
-   creationTimestamp: \"2019-09-11T14:49:14Z\"
--  generation: 5
-+  generation: 6
-   labels:
-     app: raftcat
-     chart: raftcat-0.3.0
-@@ -66,7 +66,7 @@
-       containers:
-       - env:
-         - name: BLAAA
--          value: eirik4
-+          value: eirik5
-         - name: LOG_LEVEL
-           value: DEBUG
-         - name: NAMESPACE";
-
+    fn secrets_masked_among_changes() {
+        // masking secrets does not hide the changes to the objects around them
+        let mut before = deploy("0.3.0", json!([]));
+        let mut after = before.clone();
+        let checksum = "spec.template.metadata.annotations.checksum/secrets";
+        before["spec"]["template"]["metadata"] = json!({ "annotations": { "checksum/secrets": "3e6c8c20" } });
+        after["spec"]["template"]["metadata"] = json!({ "annotations": { "checksum/secrets": "fd7f339f" } });
+        let secret = |data: Value| {
+            json!({ "kind": "Secret", "metadata": { "name": "raftcat-secrets" }, "data": data })
+        };
+        let hpa = |max: u32| {
+            json!({
+                "kind": "HorizontalPodAutoscaler",
+                "metadata": { "name": "raftcat" },
+                "spec": { "maxReplicas": max }
+            })
+        };
+        let before = vec![
+            before,
+            secret(json!({ "SENTRY_DSN": "aGVsbG8gd29ybGQK==" })),
+            hpa(3),
+        ];
+        let after = vec![
+            after,
+            secret(json!({ "SENTRY_DSN": "YUdWc2JHOGdkMjl5YkdRPQ==", "WOOT": "aGk=" })),
+            hpa(4),
+        ];
+        let diff = compare_objects(&before, &after, &[]);
         assert_eq!(
-            minify(input),
-            "apps.v1.Deployment.dev.raftcat has changed:
--          value: eirik4
-+          value: eirik5"
+            diff.to_string(),
+            format!(
+                "Deployment/raftcat changed:
+  ~ {}: \"3e6c8c20\" -> \"fd7f339f\"
+Secret/raftcat-secrets changed:
+  ~ data.SENTRY_DSN: \"(masked)\" -> \"(masked)\"
+  + data.WOOT: \"(masked)\"
+HorizontalPodAutoscaler/raftcat changed:
+  ~ spec.maxReplicas: 3 -> 4",
+                checksum
+            )
         );
     }
 
     #[test]
-    fn kubectl_diff_version_only() {
-        let min_input = "extensions.v1beta1.Deployment.dev has changed:
--    app.kubernetes.io/version: a844d0db93216b25d22a482ab80029d4a552f285
-+    app.kubernetes.io/version: 203894776eed17f00b9dd0bc25a09dcef644ea67
--          value: a844d0db93216b25d22a482ab80029d4a552f285
--        image: quay.io/babylonhealth/aim-dashboard:a844d0db93216b25d22a482ab80029d4a552f285
-+          value: 203894776eed17f00b9dd0bc25a09dcef644ea67
-+        image: quay.io/babylonhealth/aim-dashboard:203894776eed17f00b9dd0bc25a09dcef644ea67
-v1.ServiceAccount.dev has changed:
--    app.kubernetes.io/version: a844d0db93216b25d22a482ab80029d4a552f285
-+    app.kubernetes.io/version: 203894776eed17f00b9dd0bc25a09dcef644ea67
-v1.Service.dev has changed:
--    app.kubernetes.io/version: a844d0db93216b25d22a482ab80029d4a552f285
-+    app.kubernetes.io/version: 203894776eed17f00b9dd0bc25a09dcef644ea67";
-
-        let res = infer_version_change(min_input);
-        assert!(res.is_some());
-        let (old, new) = res.unwrap();
-        assert!(is_version_only(min_input, (&old, &new)));
+    fn version_diff_not_only() {
+        // not just a simple version change
+        let before = deploy("1.0.6", json!([{ "name": "BLAST", "value": "keyremoval" }]));
+        let after = deploy("1.0.7", json!([]));
+        let diff = compare_objects(&[before], &[after], &[]);
+        let (old, new) = diff.version_change().unwrap();
+        assert!(!diff.is_version_only((&old, &new)));
+        // nor are added objects
+        let svc = json!({ "kind": "Service", "metadata": { "name": "raftcat" } });
+        let mut diff = version_diff("1.0.6", "1.0.7");
+        diff.objects.extend(compare_objects(&[], &[svc], &[]).objects);
+        assert!(!diff.is_version_only(("1.0.6", "1.0.7")));
     }
 
     #[test]
-    fn kubectl_diff_secret_squash_test() {
-        // A pretty large diff input that checks that secrets are filtered out
-        // ..and that it doesn't filter out objects surrounding the secrets
-        let input = r#"diff -u -N /tmp/LIVE-547038353/apps.v1.Deployment.dev.raftcat /tmp/MERGED-191875772/apps.v1.Deployment.dev.raftcat
---- /tmp/LIVE-547038353/apps.v1.Deployment.dev.raftcat   2020-04-07 12:25:26.255493075 +0100
-+++ /tmp/MERGED-191875772/apps.v1.Deployment.dev.raftcat 2020-04-07 12:25:26.312159054 +0100
-@@ -6,7 +6,7 @@
-   creationTimestamp: "2019-11-18T22:47:05Z"
--  generation: 227
-+  generation: 228
-   labels:
-     app: raftcat
-     app.kubernetes.io/managed-by: shipcat
-@@ -45,7 +45,7 @@
-     metadata:
-       annotations:
-         checksum/config: 01ba4719c80b6fe911b091a7c05124b64eeece964e09c058ef8f9805daca546b
--        checksum/secrets: 3e6c8c2098228c15554026bef845a1e121c6d32a315ecbba953d92e8b4d26bed
-+        checksum/secrets: fd7f339fc814c7067c66213705ff7bfc78a81723e50c1083bd3a29df272d34e7
-         kubectl.kubernetes.io/restartedAt: "2020-03-18T18:40:01Z"
-       creationTimestamp: null
-diff -u -N /tmp/LIVE-422759316/v1.Secret.dev.raftcat-secrets /tmp/MERGED-101659107/v1.Secret.dev.raftcat-secrets
---- /tmp/LIVE-422759316/v1.Secret.dev.raftcat-secrets   2020-04-07 12:26:32.618020569 +0100
-+++ /tmp/MERGED-101659107/v1.Secret.dev.raftcat-secrets 2020-04-07 12:26:32.664686669 +0100
-@@ -1,9 +1,10 @@
- apiVersion: v1
- data:
--  SENTRY_DSN: aGVsbG8gd29ybGQK==
-+  SENTRY_DSN: YUdWc2JHOGdkMjl5YkdRPQ==
-+  WOOT: aGk=
- kind: Secret
- metadata:
-diff -u -N /tmp/LIVE-167159470/autoscaling.v2beta2.HorizontalPodAutoscaler.dev.raftcat /tmp/MERGED-264369205/autoscaling.v2beta2.HorizontalPodAutoscaler.dev.raftcat
---- /tmp/LIVE-167159470/autoscaling.v2beta2.HorizontalPodAutoscaler.dev.raftcat 2020-04-07 13:02:15.788649534 +0100
-+++ /tmp/MERGED-264369205/autoscaling.v2beta2.HorizontalPodAutoscaler.dev.raftcat   2020-04-07 13:02:15.875315149 +0100
-@@ -22,7 +22,7 @@
- spec:
--  maxReplicas: 3
-+  maxReplicas: 4
-   metrics:
-   - resource:
-       name: cpu"#;
-
-        assert_eq!(
-            minify(input),
-            "apps.v1.Deployment.dev.raftcat has changed:
--        checksum/secrets: 3e6c8c2098228c15554026bef845a1e121c6d32a315ecbba953d92e8b4d26bed
-+        checksum/secrets: fd7f339fc814c7067c66213705ff7bfc78a81723e50c1083bd3a29df272d34e7
-Change to v1.Secret.dev.raftcat elided for security
-autoscaling.v2beta2.HorizontalPodAutoscaler.dev.raftcat has changed:
--  maxReplicas: 3
-+  maxReplicas: 4"
-        );
+    fn secrets_masked() {
+        let secret = |v: &str| {
+            json!({
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "metadata": { "name": "raftcat-secrets" },
+                "data": { "SENTRY_DSN": v }
+            })
+        };
+        let cm = |v: &str| {
+            json!({
+                "apiVersion": "v1",
+                "kind": "ConfigMap",
+                "metadata": { "name": "raftcat-config" },
+                "data": { "config.yml": format!("dsn: {}\nlevel: info", v), "level": "info" }
+            })
+        };
+        let before = vec![secret("aGVsbG8gd29ybGQK"), cm("hunter2hunter2")];
+        let after = vec![secret("YUdWc2JHOGdkMjl5YkdRPQ=="), cm("correcthorsebattery")];
+        let secrets = vec!["hunter2hunter2".to_string(), "correcthorsebattery".to_string()];
+        let diff = compare_objects(&before, &after, &secrets);
+        assert_eq!(diff.objects.len(), 2);
+        for o in &diff.objects {
+            assert_eq!(o.change, Change::Changed);
+            assert_eq!(o.fields.len(), 1);
+            assert_eq!(o.fields[0].before, Some(json!(MASKED)));
+            assert_eq!(o.fields[0].after, Some(json!(MASKED)));
+        }
+        let out = diff.to_string();
+        assert!(out.contains("Secret/raftcat-secrets changed:"));
+        assert!(out.contains("~ data.config.yml: \"(masked)\" -> \"(masked)\""));
+        assert!(!out.contains("hunter2"));
+        assert!(!out.contains("aGVsbG8"));
     }
 }
@@ -0,0 +1,190 @@
+use futures::stream::{self, StreamExt};
+use futures_timer::Delay;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use kube::api::{Api, ListParams, Meta};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use shipcat_definitions::{manifest::ShipcatManifest, status::Applier};
+
+use super::{
+    diff::{self, Diff},
+    kubeapi::{self, ShipKube},
+    ErrorKind, Region, Result,
+};
+
+/// Whether each service has drifted, as last checked
+type DriftState = Arc<Mutex<BTreeMap<String, bool>>>;
+
+/// Objects that changed in a diff
+fn changed_objects(diff: &Diff) -> Vec<String> {
+    diff.objects
+        .iter()
+        .map(|o| format!("{}/{}", o.kind, o.name))
+        .collect()
+}
+
+/// Render drift state in the prometheus text exposition format
+fn render_metrics(region: &str, state: &BTreeMap<String, bool>) -> String {
+    let mut out = String::new();
+    out += "# HELP shipcat_drifted Whether objects for a service differ from its shipcatmanifest\n";
+    out += "# TYPE shipcat_drifted gauge\n";
+    for (svc, drifted) in state {
+        out += &format!(
+            "shipcat_drifted{{service=\"{}\",region=\"{}\"}} {}\n",
+            svc,
+            region,
+            if *drifted { 1 } else { 0 }
+        );
+    }
+    out
+}
+
+/// Serve drift state to prometheus on any path
+async fn serve_metrics(addr: SocketAddr, region: String, state: DriftState) -> Result<()> {
+    let make_svc = make_service_fn(move |_conn| {
+        let region = region.clone();
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_req| {
+                let body = render_metrics(&region, &state.lock().unwrap());
+                async move { Ok::<_, Infallible>(Response::new(Body::from(body))) }
+            }))
+        }
+    });
+    info!("Serving drift metrics on {}", addr);
+    Server::bind(&addr)
+        .serve(make_svc)
+        .await
+        .map_err(|e| format!("metrics server failed: {}", e))?;
+    Ok(())
+}
+
+/// Diff the objects of a service against what its shipcatmanifest crd templates to
+///
+/// Returns the diff, which is empty unless the objects have been changed outside of shipcat.
+pub async fn check(crd: ShipcatManifest, reg: &Region) -> Result<Diff> {
+    let mut mf = crd.spec.complete(reg).await?;
+    mf.uid = crd.metadata.uid;
+    diff::template_vs_cluster(&mf).await
+}
+
+/// Check a service for drift, and record the result if it changed
+async fn record(crd: ShipcatManifest, reg: &Region, state: &DriftState) -> Result<()> {
+    let name = Meta::name(&crd);
+    let previous = crd.status.as_ref().and_then(|s| s.conditions.synced.clone());
+    let mut s = ShipKube::new_within(&name, &reg.namespace).await?;
+    s.applier = Applier {
+        name: "shipcat drift".into(),
+        url: None,
+    };
+    let d = check(crd, reg).await?;
+    if !d.is_empty() {
+        warn!("{} has drifted:\n{}", name, d);
+        state.lock().unwrap().insert(name, true);
+        let msg = format!("changed outside shipcat: {}", changed_objects(&d).join(", "));
+        let unchanged = previous.map_or(false, |c| !c.status && c.message.as_ref() == Some(&msg));
+        if !unchanged {
+            s.update_synced_false("Drift", msg).await?;
+        }
+    } else {
+        debug!("{} has not drifted", name);
+        state.lock().unwrap().insert(name, false);
+        if !previous.map_or(false, |c| c.status) {
+            s.update_synced_true().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Check every shipcatmanifest crd in a region once
+async fn check_all(crds: Vec<ShipcatManifest>, reg: &Region, n_workers: usize, state: &DriftState) {
+    {
+        // forget services that have been deleted
+        let names = crds.iter().map(Meta::name).collect::<Vec<_>>();
+        let mut st = state.lock().unwrap();
+        let gone = st
+            .keys()
+            .filter(|k| !names.contains(k))
+            .cloned()
+            .collect::<Vec<_>>();
+        for k in gone {
+            st.remove(&k);
+        }
+    }
+    let mut buffered = stream::iter(crds)
+        .map(|crd| async move {
+            let name = Meta::name(&crd);
+            (name, record(crd, reg, state).await)
+        })
+        .buffer_unordered(n_workers);
+    while let Some((name, res)) = buffered.next().await {
+        if let Err(e) = res {
+            warn!("Failed to check {} for drift: {}", name, e);
+        }
+    }
+}
+
+/// Periodically check all services in a region for drift
+///
+/// Every `interval` seconds, each shipcatmanifest crd is templated and diffed against the cluster,
+/// the same way as `shipcat cluster diff`. Drift is recorded in the `synced` condition of the crd,
+/// and exposed as the `shipcat_drifted` gauge for prometheus on `addr`.
+/// Blue/green services are checked against the objects of their live colour.
+pub async fn run(reg: &Region, interval: u64, n_workers: usize, addr: SocketAddr) -> Result<()> {
+    let state: DriftState = Default::default();
+    let metrics = serve_metrics(addr, reg.name.clone(), state.clone());
+    tokio::spawn(async move {
+        if let Err(e) = metrics.await {
+            error!("{}", e);
+        }
+    });
+
+    let client = kubeapi::make_client().await?;
+    let api: Api<ShipcatManifest> = Api::namespaced(client, &reg.namespace);
+    loop {
+        match api.list(&ListParams::default()).await {
+            Ok(crds) => check_all(crds.items, reg, n_workers, &state).await,
+            Err(e) => warn!("Failed to list shipcatmanifests: {}", ErrorKind::KubeError(e)),
+        }
+        Delay::new(Duration::from_secs(interval)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_objects, render_metrics};
+    use crate::diff::compare_objects;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn drift_changed_objects() {
+        let deploy = |n: u32| json!({ "kind": "Deployment", "metadata": { "name": "fake-ask" }, "spec": { "replicas": n } });
+        let secret = json!({ "kind": "Secret", "metadata": { "name": "fake-ask-secrets" } });
+        let diff = compare_objects(&[deploy(2)], &[deploy(3), secret], &[]);
+        assert_eq!(changed_objects(&diff), vec![
+            "Deployment/fake-ask",
+            "Secret/fake-ask-secrets",
+        ]);
+    }
+
+    #[test]
+    fn drift_metrics() {
+        let mut state = BTreeMap::new();
+        state.insert("fake-ask".to_string(), true);
+        state.insert("fake-storage".to_string(), false);
+        let out = render_metrics("dev-uk", &state);
+        assert!(out.contains("# TYPE shipcat_drifted gauge\n"));
+        assert!(out.contains("shipcat_drifted{service=\"fake-ask\",region=\"dev-uk\"} 1\n"));
+        assert!(out.contains("shipcat_drifted{service=\"fake-storage\",region=\"dev-uk\"} 0\n"));
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Config, Region, Result};
+use super::{Config, OutputFormat, Region, Result};
 use semver::Version;
 use shipcat_definitions::Environment;
 /// This file contains the `shipcat get` subcommand
@@ -10,7 +10,11 @@ use std::collections::BTreeMap;
 /// Find the hardcoded versions of services in a region
 ///
 /// Services without a hardcoded version are not returned.
-pub async fn versions(conf: &Config, region: &Region) -> Result<BTreeMap<String, Version>> {
+pub async fn versions(
+    conf: &Config,
+    region: &Region,
+    fmt: OutputFormat,
+) -> Result<BTreeMap<String, Version>> {
     let mut output = BTreeMap::new();
     for mf in shipcat_filebacked::available(conf, region).await? {
         if let Some(v) = mf.version {
@@ -19,21 +23,31 @@ pub async fn versions(conf: &Config, region: &Region) -> Result<BTreeMap<String,
             }
         }
     }
-    println!("{}", serde_json::to_string_pretty(&output)?);
+    fmt.print(&output, |vers| {
+        for (svc, v) in vers {
+            println!("{:<50} {}", svc, v);
+        }
+        Ok(())
+    })?;
     Ok(output)
 }
 
 /// Find the hardcoded images of services in a region
 ///
 /// Services without a hardcoded image will assume the shipcat.conf specific default
-pub async fn images(conf: &Config, region: &Region) -> Result<BTreeMap<String, String>> {
+pub async fn images(conf: &Config, region: &Region, fmt: OutputFormat) -> Result<BTreeMap<String, String>> {
     let mut output = BTreeMap::new();
     for mf in shipcat_filebacked::available(conf, region).await? {
         if let Some(i) = mf.image {
             output.insert(mf.base.name, i);
         }
     }
-    println!("{}", serde_json::to_string_pretty(&output)?);
+    fmt.print(&output, |imgs| {
+        for (svc, i) in imgs {
+            println!("{:<50} {}", svc, i);
+        }
+        Ok(())
+    })?;
     Ok(output)
 }
 
@@ -41,7 +55,7 @@ pub async fn images(conf: &Config, region: &Region) -> Result<BTreeMap<String, S
 ///
 /// Cross references config.teams with manifest.metadata.team
 /// Each returned string is Github CODEOWNER syntax
-pub async fn codeowners(conf: &Config) -> Result<Vec<String>> {
+pub async fn codeowners(conf: &Config, fmt: OutputFormat) -> Result<Vec<String>> {
     let mut output = vec![];
     let org = &conf.github.organisation;
     for mf in shipcat_filebacked::all(conf).await? {
@@ -73,7 +87,7 @@ pub async fn codeowners(conf: &Config) -> Result<Vec<String>> {
             output.push(format!("services/{}/* {}", mf.name, ghids.join(" ")));
         }
     }
-    println!("{}", output.join("\n"));
+    fmt.print_lines(&output)?;
     Ok(output)
 }
 
@@ -89,7 +103,12 @@ pub async fn codeowners(conf: &Config) -> Result<Vec<String>> {
 ///
 /// Assumes you have setup github provider using right organisation.
 /// vault write auth/github/config organization={GithubOrganisation}
-pub async fn vaultpolicy(conf: &Config, region: &Region, team_name: &str) -> Result<String> {
+pub async fn vaultpolicy(
+    conf: &Config,
+    region: &Region,
+    team_name: &str,
+    fmt: OutputFormat,
+) -> Result<String> {
     let mfs = shipcat_filebacked::all(conf).await?;
     let team = if let Some(s) = conf.owners.squads.get(team_name) {
         if s.github.admins.is_none() {
@@ -106,7 +125,10 @@ pub async fn vaultpolicy(conf: &Config, region: &Region, team_name: &str) -> Res
         .vault
         .make_policy(mfs, &team, region.environment.clone())
         .await?;
-    println!("{}", output);
+    fmt.print(&output, |hcl| {
+        println!("{}", hcl);
+        Ok(())
+    })?;
     Ok(output)
 }
 
@@ -129,7 +151,12 @@ pub struct ClusterInfo {
 /// Entry point for clusterinfo
 ///
 /// Need explicit region: shipcat get -r preprodca-green clusterinfo
-pub fn clusterinfo(conf: &Config, ctx: &str, cluster: Option<&str>) -> Result<ClusterInfo> {
+pub fn clusterinfo(
+    conf: &Config,
+    ctx: &str,
+    cluster: Option<&str>,
+    fmt: OutputFormat,
+) -> Result<ClusterInfo> {
     assert!(conf.has_all_regions()); // can't work with reduced configs
     let (clust, reg) = conf.resolve_cluster(ctx, cluster.map(String::from))?;
     let ci = ClusterInfo {
@@ -141,7 +168,7 @@ pub fn clusterinfo(conf: &Config, ctx: &str, cluster: Option<&str>) -> Result<Cl
         vault: reg.vault.url.clone(),
         kong: reg.kong.map(|k| k.config_url),
     };
-    println!("{}", serde_json::to_string_pretty(&ci)?);
+    fmt.print_data(&ci)?;
     Ok(ci)
 }
 
@@ -149,9 +176,12 @@ pub fn clusterinfo(conf: &Config, ctx: &str, cluster: Option<&str>) -> Result<Cl
 ///
 /// Prints just the vault url for a region
 /// Because this is invariant over a region
-pub fn vault_url(region: &Region) -> Result<String> {
+pub fn vault_url(region: &Region, fmt: OutputFormat) -> Result<String> {
     let out = region.vault.url.clone();
-    println!("{}", out);
+    fmt.print(&out, |url| {
+        println!("{}", url);
+        Ok(())
+    })?;
     Ok(out)
 }
 
@@ -180,7 +210,7 @@ struct RegionInfo {
     base_urls: BTreeMap<String, String>,
     ip_whitelist: Vec<String>,
 }
-pub async fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
+pub async fn apistatus(conf: &Config, reg: &Region, fmt: OutputFormat) -> Result<()> {
     let mut services = BTreeMap::new();
 
     // Get Environment Config
@@ -233,8 +263,7 @@ pub async fn apistatus(conf: &Config, reg: &Region) -> Result<()> {
     }
 
     let output = APIStatusOutput { region, services };
-    println!("{}", serde_json::to_string_pretty(&output)?);
-    Ok(())
+    fmt.print_data(&output)
 }
 
 // ----------------------------------------------------------------------------
@@ -247,7 +276,7 @@ struct EventStreamsOutput {
     eventstreams: BTreeMap<String, EventStream>,
 }
 
-pub async fn eventstreams(conf: &Config, reg: &Region) -> Result<()> {
+pub async fn eventstreams(conf: &Config, reg: &Region, fmt: OutputFormat) -> Result<()> {
     let mut eventstreams = BTreeMap::new();
 
     // Get eventstream Info from Manifests
@@ -260,8 +289,7 @@ pub async fn eventstreams(conf: &Config, reg: &Region) -> Result<()> {
 
     let region = reg.name.clone();
     let output = EventStreamsOutput { region, eventstreams };
-    println!("{}", serde_json::to_string_pretty(&output)?);
-    Ok(())
+    fmt.print_data(&output)
 }
 
 // get Kafka Users
@@ -312,7 +340,7 @@ fn transformEventstreamUsers(input: KafkaUsersInput) -> BTreeMap<String, EventSt
     output
 }
 
-pub async fn kafkausers(conf: &Config, reg: &Region) -> Result<()> {
+pub async fn kafkausers(conf: &Config, reg: &Region, fmt: OutputFormat) -> Result<()> {
     let mut eventStreamsUsers = BTreeMap::new();
     let mut krusers = BTreeMap::new();
 
@@ -344,8 +372,7 @@ pub async fn kafkausers(conf: &Config, reg: &Region) -> Result<()> {
         es_kafka_users: transformEventstreamUsers(KafkaUsersInput { eventStreamsUsers }),
         kr_kafka_users: krusers,
     };
-    println!("{}", serde_json::to_string_pretty(&output)?);
-    Ok(())
+    fmt.print_data(&output)
 }
 
 // get kafka topics
@@ -364,7 +391,7 @@ pub struct KafkaTopicParams {
     config: BTreeMap<String, String>,
 }
 
-pub async fn kafkatopics(conf: &Config, reg: &Region) -> Result<()> {
+pub async fn kafkatopics(conf: &Config, reg: &Region, fmt: OutputFormat) -> Result<()> {
     let mut kafkaTopics = BTreeMap::new();
 
     // Get eventstream Info from Manifests
@@ -402,6 +429,5 @@ pub async fn kafkatopics(conf: &Config, reg: &Region) -> Result<()> {
     }
     let region = reg.name.clone();
     let output = KafkaTopics { region, kafkaTopics };
-    println!("{}", serde_json::to_string_pretty(&output)?);
-    Ok(())
+    fmt.print_data(&output)
 }
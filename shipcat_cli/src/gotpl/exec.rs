@@ -0,0 +1,624 @@
+use serde_json::{Map, Number, Value};
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use super::{
+    super::Result,
+    parse::{Node, Operand, Pipeline},
+};
+
+/// Maximum nesting of `template` / `include` calls
+const MAX_DEPTH: usize = 100;
+
+/// Go template truthiness: false, 0, nil and empty collections are false
+pub fn truthy(v: &Value) -> bool {
+    match v {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map_or(false, |f| f != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+/// String conversion matching what go's fmt would print (nil prints nothing as in helm)
+pub fn strval(v: &Value) -> String {
+    match v {
+        Value::Null => "".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(a) => format!("[{}]", a.iter().map(strval).collect::<Vec<_>>().join(" ")),
+        Value::Object(o) => format!(
+            "map[{}]",
+            o.iter()
+                .map(|(k, v)| format!("{}:{}", k, strval(v)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    }
+}
+
+fn int(v: &Value) -> i64 {
+    match v {
+        Value::Number(n) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)).unwrap_or(0),
+        Value::String(s) => s.trim().parse::<i64>().unwrap_or(0),
+        Value::Bool(b) => *b as i64,
+        _ => 0,
+    }
+}
+
+fn float(v: &Value) -> f64 {
+    match v {
+        Value::Number(n) => n.as_f64().unwrap_or(0.0),
+        Value::String(s) => s.trim().parse::<f64>().unwrap_or(0.0),
+        Value::Bool(b) => *b as i64 as f64,
+        _ => 0.0,
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Result<Ordering> {
+    match (a, b) {
+        (Value::Number(_), Value::Number(_)) => {
+            Ok(float(a).partial_cmp(&float(b)).unwrap_or(Ordering::Equal))
+        }
+        (Value::String(x), Value::String(y)) => Ok(x.cmp(y)),
+        _ => bail!("incompatible types for comparison"),
+    }
+}
+
+/// Deep merge `src` into `dst` where existing non-empty values in `dst` win
+pub fn merge_into(dst: &mut Map<String, Value>, src: &Map<String, Value>) {
+    for (k, v) in src {
+        match dst.get_mut(k) {
+            Some(Value::Object(d)) => {
+                if let Value::Object(s) = v {
+                    merge_into(d, s);
+                }
+            }
+            Some(Value::Null) | None => {
+                dst.insert(k.clone(), v.clone());
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_else(|_| format!("\"{}\"", s))
+}
+
+fn indent(n: i64, s: &str) -> String {
+    let pad = " ".repeat(n.max(0) as usize);
+    format!("{}{}", pad, s.replace('\n', &format!("\n{}", pad)))
+}
+
+fn to_yaml(v: &Value) -> Result<String> {
+    if v.is_null() {
+        return Ok("null".into());
+    }
+    let s = serde_yaml::to_string(v)?;
+    Ok(s.trim_start_matches("---\n").trim_end_matches('\n').to_string())
+}
+
+/// Minimal go `fmt.Sprintf` supporting the common verbs
+fn sprintf(fmt: &str, args: &[Value]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => out.push('%'),
+            Some(verb) => {
+                let arg = args.next().cloned().unwrap_or(Value::Null);
+                match verb {
+                    'd' => out.push_str(&int(&arg).to_string()),
+                    'q' => out.push_str(&quote(&strval(&arg))),
+                    'f' => out.push_str(&format!("{:.6}", float(&arg))),
+                    _ => out.push_str(&strval(&arg)),
+                }
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+/// Go `fmt.Sprint`: spaces are added between operands when neither is a string
+fn sprint(args: &[Value]) -> String {
+    let mut out = String::new();
+    for (i, a) in args.iter().enumerate() {
+        if i > 0 && !a.is_string() && !args[i - 1].is_string() {
+            out.push(' ');
+        }
+        out.push_str(&strval(a));
+    }
+    out
+}
+
+fn arg<'v>(args: &'v [Value], i: usize, fname: &str) -> Result<&'v Value> {
+    match args.get(i) {
+        Some(v) => Ok(v),
+        None => bail!(
+            "wrong number of args for {}: want at least {} got {}",
+            fname,
+            i + 1,
+            args.len()
+        ),
+    }
+}
+
+fn sarg(args: &[Value], i: usize, fname: &str) -> Result<String> {
+    Ok(strval(arg(args, i, fname)?))
+}
+
+/// Template evaluation state for a single template invocation
+pub struct Exec<'a> {
+    defines: &'a BTreeMap<String, Vec<Node>>,
+    name: String,
+    vars: Vec<(String, Value)>,
+    depth: usize,
+}
+
+impl<'a> Exec<'a> {
+    pub fn new(defines: &'a BTreeMap<String, Vec<Node>>, name: &str, data: Value, depth: usize) -> Self {
+        Exec {
+            defines,
+            name: name.to_string(),
+            vars: vec![("$".into(), data)],
+            depth,
+        }
+    }
+
+    /// Render a named template from the shared namespace
+    pub fn render_named(
+        defines: &'a BTreeMap<String, Vec<Node>>,
+        name: &str,
+        data: Value,
+        depth: usize,
+    ) -> Result<String> {
+        if depth > MAX_DEPTH {
+            bail!(
+                "template: {}: exceeded maximum template depth ({})",
+                name,
+                MAX_DEPTH
+            );
+        }
+        let nodes = match defines.get(name) {
+            Some(n) => n,
+            None => bail!("template: no template {:?} associated with template", name),
+        };
+        let mut exec = Exec::new(defines, name, data.clone(), depth);
+        let mut out = String::new();
+        exec.walk(nodes, &data, &mut out)?;
+        Ok(out)
+    }
+
+    fn err(&self, line: usize, msg: impl std::fmt::Display) -> String {
+        format!("template: {}:{}: {}", self.name, line, msg)
+    }
+
+    /// Error for a failing action, pointing at its source text like go's `executing .. at <..>`
+    fn err_at(&self, line: usize, pipe: &Pipeline, msg: impl std::fmt::Display) -> String {
+        self.err(line, format!("executing {:?} at <{}>: {}", self.name, pipe, msg))
+    }
+
+    pub fn walk(&mut self, nodes: &[Node], dot: &Value, out: &mut String) -> Result<()> {
+        for n in nodes {
+            match n {
+                Node::Text(t) => out.push_str(t),
+                Node::Action { line, pipe } => {
+                    let v = self.pipeline(pipe, dot).map_err(|e| self.err_at(*line, pipe, e))?;
+                    if pipe.decl.is_empty() {
+                        out.push_str(&strval(&v));
+                    }
+                }
+                Node::If {
+                    line,
+                    branches,
+                    otherwise,
+                } => {
+                    let scope = self.vars.len();
+                    let mut taken = false;
+                    for (cond, body) in branches {
+                        let v = self.pipeline(cond, dot).map_err(|e| self.err_at(*line, cond, e))?;
+                        if truthy(&v) {
+                            self.walk(body, dot, out)?;
+                            taken = true;
+                            break;
+                        }
+                    }
+                    if !taken {
+                        self.walk(otherwise, dot, out)?;
+                    }
+                    self.vars.truncate(scope);
+                }
+                Node::With {
+                    line,
+                    pipe,
+                    body,
+                    otherwise,
+                } => {
+                    let scope = self.vars.len();
+                    let v = self.pipeline(pipe, dot).map_err(|e| self.err_at(*line, pipe, e))?;
+                    if truthy(&v) {
+                        self.walk(body, &v, out)?;
+                    } else {
+                        self.walk(otherwise, dot, out)?;
+                    }
+                    self.vars.truncate(scope);
+                }
+                Node::Range {
+                    line,
+                    pipe,
+                    body,
+                    otherwise,
+                } => {
+                    let scope = self.vars.len();
+                    let v = self.commands(pipe, dot).map_err(|e| self.err_at(*line, pipe, e))?;
+                    let elems: Vec<(Value, Value)> = match v {
+                        Value::Array(xs) => xs
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, x)| (Value::Number(Number::from(i as u64)), x))
+                            .collect(),
+                        Value::Object(m) => m.into_iter().map(|(k, x)| (Value::String(k), x)).collect(),
+                        Value::Null => vec![],
+                        x => bail!(self.err(*line, format!("range can't iterate over {}", strval(&x)))),
+                    };
+                    if elems.is_empty() {
+                        self.walk(otherwise, dot, out)?;
+                    }
+                    for (k, x) in elems {
+                        match pipe.decl.as_slice() {
+                            [] => {}
+                            [v] => self.vars.push((v.clone(), x.clone())),
+                            [i, v] => {
+                                self.vars.push((i.clone(), k));
+                                self.vars.push((v.clone(), x.clone()));
+                            }
+                            _ => bail!(self.err(*line, "too many declarations in range")),
+                        }
+                        self.walk(body, &x, out)?;
+                        self.vars.truncate(scope);
+                    }
+                }
+                Node::Template { line, name, pipe } => {
+                    let data = match pipe {
+                        Some(p) => self.pipeline(p, dot).map_err(|e| self.err_at(*line, p, e))?,
+                        None => Value::Null,
+                    };
+                    let res = Exec::render_named(self.defines, name, data, self.depth + 1)?;
+                    out.push_str(&res);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate a pipeline, binding any declared variables
+    fn pipeline(&mut self, pipe: &Pipeline, dot: &Value) -> Result<Value> {
+        let v = self.commands(pipe, dot)?;
+        for name in &pipe.decl {
+            if pipe.assign {
+                match self.vars.iter_mut().rev().find(|(n, _)| n == name) {
+                    Some(slot) => slot.1 = v.clone(),
+                    None => bail!("undefined variable: {}", name),
+                }
+            } else {
+                self.vars.push((name.clone(), v.clone()));
+            }
+        }
+        Ok(v)
+    }
+
+    /// Evaluate the commands of a pipeline without binding declarations
+    fn commands(&mut self, pipe: &Pipeline, dot: &Value) -> Result<Value> {
+        let mut piped: Option<Value> = None;
+        for cmd in &pipe.cmds {
+            piped = Some(self.command(cmd, dot, piped)?);
+        }
+        Ok(piped.unwrap_or(Value::Null))
+    }
+
+    fn command(&mut self, cmd: &[Operand], dot: &Value, piped: Option<Value>) -> Result<Value> {
+        if let Some(Operand::Func(name)) = cmd.first() {
+            let mut args = vec![];
+            for op in &cmd[1..] {
+                args.push(self.operand(op, dot)?);
+            }
+            if let Some(p) = piped {
+                args.push(p);
+            }
+            return self.call(name, args);
+        }
+        if cmd.len() > 1 || piped.is_some() {
+            bail!("can't give argument to non-function {}", cmd[0]);
+        }
+        self.operand(&cmd[0], dot)
+    }
+
+    fn operand(&mut self, op: &Operand, dot: &Value) -> Result<Value> {
+        let v = match op {
+            Operand::Dot => dot.clone(),
+            Operand::Field(fields) => fields_of(dot, fields)?,
+            Operand::Var(name, fields) => match self.vars.iter().rev().find(|(n, _)| n == name) {
+                Some((_, v)) => fields_of(v, fields)?,
+                None => bail!("undefined variable: {}", name),
+            },
+            Operand::Func(name) => self.call(name, vec![])?,
+            Operand::Str(s) => Value::String(s.clone()),
+            Operand::Num(n) => Value::Number(n.clone()),
+            Operand::Bool(b) => Value::Bool(*b),
+            Operand::Nil => Value::Null,
+            Operand::Sub(pipe, fields) => {
+                let v = self.pipeline(pipe, dot)?;
+                fields_of(&v, fields)?
+            }
+        };
+        Ok(v)
+    }
+
+    /// Builtin go template functions and the commonly used sprig/helm subset
+    fn call(&mut self, fname: &str, args: Vec<Value>) -> Result<Value> {
+        let a = &args;
+        let v = match fname {
+            // helm specifics
+            "include" => {
+                let name = sarg(a, 0, fname)?;
+                let data = a.get(1).cloned().unwrap_or(Value::Null);
+                Value::String(Exec::render_named(self.defines, &name, data, self.depth + 1)?)
+            }
+            "required" => {
+                let v = arg(a, 1, fname)?;
+                if v.is_null() || v.as_str() == Some("") {
+                    bail!("{}", sarg(a, 0, fname)?);
+                }
+                v.clone()
+            }
+            "fail" => bail!("{}", sarg(a, 0, fname)?),
+            "toYaml" => Value::String(to_yaml(arg(a, 0, fname)?)?),
+            "toJson" => Value::String(serde_json::to_string(arg(a, 0, fname)?)?),
+            "fromYaml" => serde_yaml::from_str(&sarg(a, 0, fname)?)?,
+            "fromJson" => serde_json::from_str(&sarg(a, 0, fname)?)?,
+
+            // go builtins
+            "and" => {
+                let mut res = Value::Null;
+                for x in a {
+                    res = x.clone();
+                    if !truthy(x) {
+                        break;
+                    }
+                }
+                res
+            }
+            "or" => {
+                let mut res = Value::Null;
+                for x in a {
+                    res = x.clone();
+                    if truthy(x) {
+                        break;
+                    }
+                }
+                res
+            }
+            "not" => Value::Bool(!truthy(arg(a, 0, fname)?)),
+            "eq" => {
+                let first = arg(a, 0, fname)?;
+                Value::Bool(a[1..].iter().any(|x| equal(first, x)))
+            }
+            "ne" => Value::Bool(!equal(arg(a, 0, fname)?, arg(a, 1, fname)?)),
+            "lt" => Value::Bool(compare(arg(a, 0, fname)?, arg(a, 1, fname)?)? == Ordering::Less),
+            "le" => Value::Bool(compare(arg(a, 0, fname)?, arg(a, 1, fname)?)? != Ordering::Greater),
+            "gt" => Value::Bool(compare(arg(a, 0, fname)?, arg(a, 1, fname)?)? == Ordering::Greater),
+            "ge" => Value::Bool(compare(arg(a, 0, fname)?, arg(a, 1, fname)?)? != Ordering::Less),
+            "len" => {
+                let len = match arg(a, 0, fname)? {
+                    Value::String(s) => s.len(),
+                    Value::Array(xs) => xs.len(),
+                    Value::Object(m) => m.len(),
+                    Value::Null => 0,
+                    x => bail!("len of {}", strval(x)),
+                };
+                Value::from(len as u64)
+            }
+            "index" => {
+                let mut v = arg(a, 0, fname)?.clone();
+                for k in &a[1..] {
+                    v = match (&v, k) {
+                        (Value::Array(xs), _) => xs.get(int(k) as usize).cloned().unwrap_or(Value::Null),
+                        (Value::Object(m), _) => m.get(&strval(k)).cloned().unwrap_or(Value::Null),
+                        _ => Value::Null,
+                    };
+                }
+                v
+            }
+            "print" => Value::String(sprint(a)),
+            "println" => Value::String(format!(
+                "{}\n",
+                a.iter().map(strval).collect::<Vec<_>>().join(" ")
+            )),
+            "printf" => Value::String(sprintf(&sarg(a, 0, fname)?, &a[1..])),
+
+            // sprig defaults
+            "default" => {
+                let given = a.get(1).cloned().unwrap_or(Value::Null);
+                if truthy(&given) {
+                    given
+                } else {
+                    arg(a, 0, fname)?.clone()
+                }
+            }
+            "empty" => Value::Bool(!truthy(arg(a, 0, fname)?)),
+            "coalesce" => a.iter().find(|x| truthy(x)).cloned().unwrap_or(Value::Null),
+            "ternary" => {
+                if truthy(arg(a, 2, fname)?) {
+                    a[0].clone()
+                } else {
+                    a[1].clone()
+                }
+            }
+
+            // sprig strings
+            "quote" => Value::String(
+                a.iter()
+                    .filter(|x| !x.is_null())
+                    .map(|x| quote(&strval(x)))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            "squote" => Value::String(
+                a.iter()
+                    .filter(|x| !x.is_null())
+                    .map(|x| format!("'{}'", strval(x)))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            "indent" => Value::String(indent(int(arg(a, 0, fname)?), &sarg(a, 1, fname)?)),
+            "nindent" => Value::String(format!(
+                "\n{}",
+                indent(int(arg(a, 0, fname)?), &sarg(a, 1, fname)?)
+            )),
+            "trim" => Value::String(sarg(a, 0, fname)?.trim().to_string()),
+            "trimSuffix" => {
+                let suffix = sarg(a, 0, fname)?;
+                let s = sarg(a, 1, fname)?;
+                if s.ends_with(&suffix) {
+                    Value::String(s[..s.len() - suffix.len()].to_string())
+                } else {
+                    Value::String(s)
+                }
+            }
+            "trimPrefix" => {
+                let prefix = sarg(a, 0, fname)?;
+                let s = sarg(a, 1, fname)?;
+                if s.starts_with(&prefix) {
+                    Value::String(s[prefix.len()..].to_string())
+                } else {
+                    Value::String(s)
+                }
+            }
+            "trimAll" => {
+                let cutset: Vec<char> = sarg(a, 0, fname)?.chars().collect();
+                Value::String(sarg(a, 1, fname)?.trim_matches(cutset.as_slice()).to_string())
+            }
+            "trunc" => {
+                let n = int(arg(a, 0, fname)?).max(0) as usize;
+                Value::String(sarg(a, 1, fname)?.chars().take(n).collect())
+            }
+            "upper" => Value::String(sarg(a, 0, fname)?.to_uppercase()),
+            "lower" => Value::String(sarg(a, 0, fname)?.to_lowercase()),
+            "replace" => Value::String(sarg(a, 2, fname)?.replace(&sarg(a, 0, fname)?, &sarg(a, 1, fname)?)),
+            "contains" => Value::Bool(sarg(a, 1, fname)?.contains(&sarg(a, 0, fname)?)),
+            "hasPrefix" => Value::Bool(sarg(a, 1, fname)?.starts_with(&sarg(a, 0, fname)?)),
+            "hasSuffix" => Value::Bool(sarg(a, 1, fname)?.ends_with(&sarg(a, 0, fname)?)),
+            "join" => {
+                let sep = sarg(a, 0, fname)?;
+                match arg(a, 1, fname)? {
+                    Value::Array(xs) => Value::String(xs.iter().map(strval).collect::<Vec<_>>().join(&sep)),
+                    x => Value::String(strval(x)),
+                }
+            }
+            "toString" => Value::String(sarg(a, 0, fname)?),
+            "b64enc" => Value::String(base64::encode(&sarg(a, 0, fname)?)),
+            "b64dec" => match base64::decode(&sarg(a, 0, fname)?) {
+                Ok(bytes) => Value::String(String::from_utf8_lossy(&bytes).into()),
+                Err(e) => bail!("b64dec: {}", e),
+            },
+            "sha256sum" => {
+                let digest = ring::digest::digest(&ring::digest::SHA256, sarg(a, 0, fname)?.as_bytes());
+                Value::String(hex::encode(digest.as_ref()))
+            }
+
+            // sprig numbers
+            "int" | "int64" => Value::from(int(arg(a, 0, fname)?)),
+            "float64" => Value::from(float(arg(a, 0, fname)?)),
+            "add" => Value::from(a.iter().map(int).sum::<i64>()),
+            "sub" => Value::from(int(arg(a, 0, fname)?) - int(arg(a, 1, fname)?)),
+            "mul" => Value::from(a.iter().map(int).product::<i64>()),
+            "div" | "mod" => {
+                let d = int(arg(a, 1, fname)?);
+                if d == 0 {
+                    bail!("{}: integer divide by zero", fname);
+                }
+                let n = int(arg(a, 0, fname)?);
+                Value::from(if fname == "div" { n / d } else { n % d })
+            }
+            "max" => Value::from(a.iter().map(int).max().unwrap_or(0)),
+            "min" => Value::from(a.iter().map(int).min().unwrap_or(0)),
+
+            // sprig collections
+            "list" => Value::Array(args.clone()),
+            "dict" => {
+                let mut m = Map::new();
+                for kv in a.chunks(2) {
+                    m.insert(strval(&kv[0]), kv.get(1).cloned().unwrap_or(Value::Null));
+                }
+                Value::Object(m)
+            }
+            "merge" => {
+                let mut dst = match arg(a, 0, fname)? {
+                    Value::Object(m) => m.clone(),
+                    _ => bail!("merge: first argument must be a dict"),
+                };
+                for src in &a[1..] {
+                    if let Value::Object(s) = src {
+                        merge_into(&mut dst, s);
+                    }
+                }
+                Value::Object(dst)
+            }
+            "hasKey" => match arg(a, 0, fname)? {
+                Value::Object(m) => Value::Bool(m.contains_key(&sarg(a, 1, fname)?)),
+                _ => Value::Bool(false),
+            },
+            "keys" => {
+                let mut ks = vec![];
+                for x in a {
+                    if let Value::Object(m) = x {
+                        ks.extend(m.keys().cloned().map(Value::String));
+                    }
+                }
+                Value::Array(ks)
+            }
+            "first" => match arg(a, 0, fname)? {
+                Value::Array(xs) => xs.first().cloned().unwrap_or(Value::Null),
+                _ => Value::Null,
+            },
+            "last" => match arg(a, 0, fname)? {
+                Value::Array(xs) => xs.last().cloned().unwrap_or(Value::Null),
+                _ => Value::Null,
+            },
+            _ => bail!("function {:?} not defined", fname),
+        };
+        Ok(v)
+    }
+}
+
+/// Walk a field chain; missing keys evaluate to nil like helm's rendering
+fn fields_of(v: &Value, fields: &[String]) -> Result<Value> {
+    let mut cur = v;
+    for f in fields {
+        cur = match cur {
+            Value::Object(m) => match m.get(f) {
+                Some(x) => x,
+                None => return Ok(Value::Null),
+            },
+            Value::Null => return Ok(Value::Null),
+            x => bail!("can't evaluate field {} in {}", f, strval(x)),
+        };
+    }
+    Ok(cur.clone())
+}
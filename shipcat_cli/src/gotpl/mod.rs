@@ -0,0 +1,252 @@
+//! This file contains an in-process renderer for helm charts
+//!
+//! It supports the subset of go templates + sprig functions that our charts use,
+//! so that templating does not require a `helm` binary, or a process per service.
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+use super::{Manifest, Result, ResultExt};
+
+mod exec;
+mod parse;
+use exec::{merge_into, Exec};
+use parse::Node;
+
+/// The parts of `Chart.yaml` we expose to templates
+#[derive(Deserialize, Clone)]
+struct ChartMetadata {
+    name: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    appVersion: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// A parsed helm chart
+///
+/// Every file under `templates/` is registered by its helm name (`base/templates/x.yaml`),
+/// along with any `define` blocks, so that `include` and `template` can find them.
+pub struct Chart {
+    metadata: ChartMetadata,
+    /// Default values from the chart's `values.yaml`
+    defaults: Value,
+    /// Names of templates that produce output (non-partials)
+    manifests: Vec<String>,
+    /// Shared template namespace
+    defines: BTreeMap<String, Vec<Node>>,
+}
+
+async fn read_metadata(dir: &Path) -> Result<ChartMetadata> {
+    let chartyml = fs::read_to_string(dir.join("Chart.yaml"))
+        .await
+        .chain_err(|| format!("Failed to read Chart.yaml in {}", dir.display()))?;
+    Ok(serde_yaml::from_str(&chartyml)?)
+}
+
+async fn template_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut res = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(d) = dirs.pop() {
+        let mut entries = fs::read_dir(&d).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let pth = entry.path();
+            if pth.is_dir() {
+                dirs.push(pth);
+            } else {
+                res.push(pth);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+impl Chart {
+    /// Name and version of the chart in a directory, without parsing its templates
+    pub async fn id(dir: &Path) -> Result<(String, String)> {
+        let metadata = read_metadata(dir).await?;
+        Ok((metadata.name, metadata.version))
+    }
+
+    /// Load and parse a chart from a directory like `charts/base`
+    pub async fn load(dir: &Path) -> Result<Chart> {
+        let metadata = read_metadata(dir).await?;
+
+        let valuespth = dir.join("values.yaml");
+        let defaults = if valuespth.is_file() {
+            let data = fs::read_to_string(&valuespth).await?;
+            serde_yaml::from_str::<Option<Value>>(&data)?.unwrap_or(Value::Null)
+        } else {
+            Value::Null
+        };
+
+        let tpldir = dir.join("templates");
+        let mut defines = BTreeMap::new();
+        let mut manifests = vec![];
+        for pth in template_files(&tpldir).await? {
+            let rel = pth.strip_prefix(&tpldir).unwrap().to_string_lossy().to_string();
+            let name = format!("{}/templates/{}", metadata.name, rel);
+            let src = fs::read_to_string(&pth).await?;
+            let nodes = parse::parse(&name, &src, &mut defines)?;
+            defines.insert(name.clone(), nodes);
+            let filename = pth.file_name().unwrap().to_string_lossy();
+            let renderable = ["yaml", "yml", "json"]
+                .iter()
+                .any(|ext| pth.extension().map_or(false, |e| e == *ext));
+            if renderable && !filename.starts_with('_') {
+                manifests.push(name);
+            }
+        }
+        Ok(Chart {
+            metadata,
+            defaults,
+            manifests,
+            defines,
+        })
+    }
+
+    /// Render every manifest in the chart with the given values
+    ///
+    /// The output mimics `helm template`; one `# Source` document per non-empty file.
+    pub fn render(&self, values: Value, release: &str, namespace: &str) -> Result<String> {
+        let values = match (values, &self.defaults) {
+            (Value::Object(mut v), Value::Object(d)) => {
+                merge_into(&mut v, d);
+                Value::Object(v)
+            }
+            (Value::Null, d) => d.clone(),
+            (v, _) => v,
+        };
+        let chart = &self.metadata;
+        let mut out = String::new();
+        for name in &self.manifests {
+            let data = json!({
+                "Values": values,
+                "Release": {
+                    "Name": release,
+                    "Namespace": namespace,
+                    "Service": "Tiller",
+                    "IsInstall": true,
+                    "IsUpgrade": false,
+                    "Revision": 1,
+                },
+                "Chart": {
+                    "Name": chart.name,
+                    "Version": chart.version,
+                    "AppVersion": chart.appVersion,
+                    "Description": chart.description,
+                },
+                "Template": {
+                    "Name": name,
+                    "BasePath": format!("{}/templates", chart.name),
+                },
+                "Capabilities": {},
+            });
+            let res = Exec::render_named(&self.defines, name, data, 0)?;
+            if res.trim().is_empty() {
+                continue;
+            }
+            out += &format!("---\n# Source: {}\n{}\n", name, res);
+        }
+        Ok(out)
+    }
+
+    /// Render a manifest through this chart
+    pub fn render_manifest(&self, mf: &Manifest) -> Result<String> {
+        let values = serde_json::to_value(mf)?;
+        let ns = mf.namespace.clone();
+        self.render(values, &mf.name, &ns)
+    }
+}
+
+/// Render a single template string against some data
+///
+/// Convenience for testing and one-off templates; `define` blocks are local to it.
+pub fn render_str(src: &str, data: Value) -> Result<String> {
+    let mut defines = BTreeMap::new();
+    let nodes = parse::parse("inline", src, &mut defines)?;
+    defines.insert("inline".to_string(), nodes);
+    Exec::render_named(&defines, "inline", data, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_str;
+    use serde_json::json;
+
+    #[test]
+    fn render_values_and_pipes() {
+        let data = json!({"Values": {"name": "fake-ask", "type": null, "replicaCount": 1}});
+        let tpl = "name: {{ .Values.name }}\ntype: {{ .Values.type | default \"service\" | quote }}";
+        let res = render_str(tpl, data.clone()).unwrap();
+        assert_eq!(res, "name: fake-ask\ntype: \"service\"");
+
+        let tpl = "{{- if eq (.Values.replicaCount | int) 1 }}\nmaxUnavailable: 0\n{{- end }}";
+        assert_eq!(render_str(tpl, data).unwrap(), "\nmaxUnavailable: 0");
+    }
+
+    #[test]
+    fn render_range_and_vars() {
+        let data = json!({"Values": {"name": "svc", "env": {"A": "1", "B": "x y"}, "ports": [80, 443]}});
+        let tpl = r#"{{- $svc := .Values.name }}
+{{- range $k, $v := .Values.env }}
+- name: {{ $k }}
+  value: {{ $v | quote }} # {{ $svc }}
+{{- end }}
+{{- range $i, $p := .Values.ports }}
+{{ $i }}={{ $p }}
+{{- else }}
+none
+{{- end }}"#;
+        let res = render_str(tpl, data).unwrap();
+        assert_eq!(
+            res,
+            "\n- name: A\n  value: \"1\" # svc\n- name: B\n  value: \"x y\" # svc\n0=80\n1=443"
+        );
+    }
+
+    #[test]
+    fn render_defines_and_yaml() {
+        let data = json!({"Values": {"name": "svc", "labels": {"team": "a"}}});
+        let tpl = r#"{{- define "refs" }}
+  owner: {{ .Values.name }}
+{{- end }}
+metadata:
+{{ toYaml .Values.labels | indent 2 }}
+{{- template "refs" . }}
+{{- with .Values.missing }}
+never
+{{- else }}
+  hash: {{ include "refs" $ | sha256sum | trunc 8 }}
+{{- end }}"#;
+        let res = render_str(tpl, data).unwrap();
+        assert!(res.starts_with("\nmetadata:\n  team: a\n  owner: svc\n  hash: "));
+        assert_eq!(res.lines().last().unwrap().len(), "  hash: ".len() + 8);
+    }
+
+    #[test]
+    fn render_errors() {
+        assert!(render_str("{{ if .x }}", json!({})).is_err());
+        assert!(render_str("{{ nosuchfn 1 }}", json!({})).is_err());
+        assert!(render_str("{{ $undefined }}", json!({})).is_err());
+        assert!(render_str("{{ required \"need x\" .x }}", json!({})).is_err());
+
+        // errors point at the template and its source rather than the parsed form
+        let err = render_str("{{ (.Values.x) 1 }}", json!({})).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "template: inline:1: executing \"inline\" at <(.Values.x) 1>: \
+             can't give argument to non-function (.Values.x)"
+        );
+        let err = render_str("{{ $x := .a | default \"b\" }}{{ $x | nosuchfn }}", json!({}))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("at <$x | nosuchfn>: function \"nosuchfn\" not defined"));
+    }
+}
@@ -0,0 +1,723 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::super::Result;
+
+/// A parsed template node
+#[derive(Clone, Debug)]
+pub enum Node {
+    /// Raw text copied verbatim to the output
+    Text(String),
+    /// A pipeline whose value is printed (unless it declares variables)
+    Action { line: usize, pipe: Pipeline },
+    /// `if` with optional `else if` chains and an `else`
+    If {
+        line: usize,
+        branches: Vec<(Pipeline, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    /// `range` over a list or a map
+    Range {
+        line: usize,
+        pipe: Pipeline,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `with` rebinding dot when the value is non-empty
+    With {
+        line: usize,
+        pipe: Pipeline,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `template "name" pipeline` invocation
+    Template {
+        line: usize,
+        name: String,
+        pipe: Option<Pipeline>,
+    },
+}
+
+/// A pipeline of commands separated by `|`
+///
+/// Declarations (`$x := ..`) and assignments (`$x = ..`) are recorded in `decl`.
+#[derive(Clone, Debug, Default)]
+pub struct Pipeline {
+    pub decl: Vec<String>,
+    pub assign: bool,
+    pub cmds: Vec<Vec<Operand>>,
+}
+
+/// A single argument inside a command
+#[derive(Clone, Debug)]
+pub enum Operand {
+    /// `.`
+    Dot,
+    /// `.Foo.bar` - field chain evaluated on dot
+    Field(Vec<String>),
+    /// `$` or `$x` optionally followed by a field chain
+    Var(String, Vec<String>),
+    /// A function identifier
+    Func(String),
+    Str(String),
+    Num(serde_json::Number),
+    Bool(bool),
+    Nil,
+    /// A parenthesised pipeline optionally followed by a field chain
+    Sub(Box<Pipeline>, Vec<String>),
+}
+
+/// Print a field chain as it appeared in the template source
+fn write_fields(f: &mut fmt::Formatter<'_>, fields: &[String]) -> fmt::Result {
+    for x in fields {
+        write!(f, ".{}", x)?;
+    }
+    Ok(())
+}
+
+/// Prints the source text of an operand, for error messages
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Dot => write!(f, "."),
+            Operand::Field(fields) => write_fields(f, fields),
+            Operand::Var(name, fields) => {
+                write!(f, "{}", name)?;
+                write_fields(f, fields)
+            }
+            Operand::Func(name) => write!(f, "{}", name),
+            Operand::Str(s) => write!(f, "{:?}", s),
+            Operand::Num(n) => write!(f, "{}", n),
+            Operand::Bool(b) => write!(f, "{}", b),
+            Operand::Nil => write!(f, "nil"),
+            Operand::Sub(pipe, fields) => {
+                write!(f, "({})", pipe)?;
+                write_fields(f, fields)
+            }
+        }
+    }
+}
+
+/// Prints the source text of a pipeline, for error messages
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.decl.is_empty() {
+            let op = if self.assign { "=" } else { ":=" };
+            write!(f, "{} {} ", self.decl.join(", "), op)?;
+        }
+        let cmds = self
+            .cmds
+            .iter()
+            .map(|cmd| cmd.iter().map(|op| op.to_string()).collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>();
+        write!(f, "{}", cmds.join(" | "))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Splitting a template into text and actions
+
+#[derive(Clone, Debug)]
+enum Item {
+    Text(String),
+    Action { line: usize, body: String },
+}
+
+fn is_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || c == '\n'
+}
+
+/// Find the end of an action, ignoring `}}` inside quoted strings
+fn find_close(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut quote: Option<u8> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match quote {
+            Some(q) => {
+                if b == b'\\' && q == b'"' {
+                    i += 1;
+                } else if b == q {
+                    quote = None;
+                }
+            }
+            None => {
+                if b == b'"' || b == b'`' {
+                    quote = Some(b);
+                } else if b == b'/' && s[i..].starts_with("/*") {
+                    // comments may contain anything up until the closing marker
+                    i += s[i..].find("*/")? + 1;
+                } else if s[i..].starts_with("}}") {
+                    return Some(i);
+                }
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+fn split_items(name: &str, src: &str) -> Result<Vec<Item>> {
+    let mut items = vec![];
+    let mut rest = src;
+    let mut line = 1;
+    let mut trim_next = false;
+    while let Some(start) = rest.find("{{") {
+        let mut text = &rest[..start];
+        if trim_next {
+            text = text.trim_start_matches(is_space);
+        }
+        let after = &rest[start + 2..];
+        let trim_left = after.starts_with('-') && after[1..].starts_with(is_space);
+        if trim_left {
+            text = text.trim_end_matches(is_space);
+        }
+        items.push(Item::Text(text.to_string()));
+        line += rest[..start].matches('\n').count();
+
+        let end = match find_close(after) {
+            Some(e) => e,
+            None => bail!("template: {}:{}: unclosed action", name, line),
+        };
+        let mut body = &after[..end];
+        if trim_left {
+            body = &body[1..];
+        }
+        trim_next = body.ends_with('-') && body[..body.len() - 1].ends_with(is_space);
+        if trim_next {
+            body = &body[..body.len() - 1];
+        }
+        let body = body.trim_matches(is_space);
+        if body.starts_with("/*") {
+            if !body.ends_with("*/") {
+                bail!(
+                    "template: {}:{}: comment ends before closing delimiter",
+                    name,
+                    line
+                );
+            }
+        } else {
+            items.push(Item::Action {
+                line,
+                body: body.to_string(),
+            });
+        }
+        line += after[..end].matches('\n').count();
+        rest = &after[end + 2..];
+    }
+    let text = if trim_next {
+        rest.trim_start_matches(is_space)
+    } else {
+        rest
+    };
+    items.push(Item::Text(text.to_string()));
+    Ok(items)
+}
+
+// ----------------------------------------------------------------------------
+// Lexing the inside of an action
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Dot,
+    Field(String),
+    Var(String),
+    Ident(String),
+    Str(String),
+    Num(String),
+    LParen,
+    RParen,
+    Pipe,
+    Declare,
+    Assign,
+    Comma,
+}
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tok::Dot => write!(f, "."),
+            Tok::Field(x) => write!(f, ".{}", x),
+            Tok::Var(x) | Tok::Ident(x) | Tok::Num(x) => write!(f, "{}", x),
+            Tok::Str(x) => write!(f, "{:?}", x),
+            Tok::LParen => write!(f, "("),
+            Tok::RParen => write!(f, ")"),
+            Tok::Pipe => write!(f, "|"),
+            Tok::Declare => write!(f, ":="),
+            Tok::Assign => write!(f, "="),
+            Tok::Comma => write!(f, ","),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    tok: Tok,
+    /// Whether whitespace preceded this token (field chains must be adjacent)
+    spaced: bool,
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn lex(body: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut toks = vec![];
+    let mut i = 0;
+    let mut spaced = true;
+    let take_ident = |i: &mut usize| -> String {
+        let start = *i;
+        while *i < chars.len() && is_ident(chars[*i]) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect()
+    };
+    while i < chars.len() {
+        let c = chars[i];
+        if is_space(c) {
+            spaced = true;
+            i += 1;
+            continue;
+        }
+        let tok = match c {
+            '(' => {
+                i += 1;
+                Tok::LParen
+            }
+            ')' => {
+                i += 1;
+                Tok::RParen
+            }
+            '|' => {
+                i += 1;
+                Tok::Pipe
+            }
+            ',' => {
+                i += 1;
+                Tok::Comma
+            }
+            '=' => {
+                i += 1;
+                Tok::Assign
+            }
+            ':' if chars.get(i + 1) == Some(&'=') => {
+                i += 2;
+                Tok::Declare
+            }
+            '"' => {
+                i += 1;
+                let mut s = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => bail!("unterminated quoted string"),
+                        Some('"') => break,
+                        Some('\\') => {
+                            i += 1;
+                            match chars.get(i) {
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some('r') => s.push('\r'),
+                                Some(x) => s.push(*x),
+                                None => bail!("unterminated quoted string"),
+                            }
+                        }
+                        Some(x) => s.push(*x),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                Tok::Str(s)
+            }
+            '`' => {
+                i += 1;
+                let start = i;
+                while i < chars.len() && chars[i] != '`' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    bail!("unterminated raw quoted string");
+                }
+                let s = chars[start..i].iter().collect();
+                i += 1;
+                Tok::Str(s)
+            }
+            '.' => {
+                i += 1;
+                if i < chars.len() && is_ident(chars[i]) {
+                    Tok::Field(take_ident(&mut i))
+                } else {
+                    Tok::Dot
+                }
+            }
+            '$' => {
+                i += 1;
+                Tok::Var(format!("${}", take_ident(&mut i)))
+            }
+            c if c.is_ascii_digit()
+                || ((c == '-' || c == '+') && chars.get(i + 1).map_or(false, char::is_ascii_digit)) =>
+            {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    // allow exponent signs
+                    let prev = chars[i];
+                    i += 1;
+                    if (prev == 'e' || prev == 'E') && i < chars.len() && (chars[i] == '-' || chars[i] == '+')
+                    {
+                        i += 1;
+                    }
+                }
+                Tok::Num(chars[start..i].iter().collect())
+            }
+            c if is_ident(c) => Tok::Ident(take_ident(&mut i)),
+            c => bail!("unexpected {:?} in command", c),
+        };
+        toks.push(Token { tok, spaced });
+        spaced = false;
+    }
+    Ok(toks)
+}
+
+// ----------------------------------------------------------------------------
+// Parsing pipelines from tokens
+
+struct TokStream {
+    toks: Vec<Token>,
+    pos: usize,
+}
+
+impl TokStream {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos).map(|t| &t.tok)
+    }
+
+    fn next(&mut self) -> Option<Tok> {
+        let t = self.toks.get(self.pos).map(|t| t.tok.clone());
+        self.pos += 1;
+        t
+    }
+
+    /// Next token if it is a field directly attached to the previous operand
+    fn chained_field(&mut self) -> Option<String> {
+        match self.toks.get(self.pos) {
+            Some(Token {
+                tok: Tok::Field(f),
+                spaced: false,
+            }) => {
+                let f = f.clone();
+                self.pos += 1;
+                Some(f)
+            }
+            _ => None,
+        }
+    }
+
+    fn fields(&mut self, mut start: Vec<String>) -> Vec<String> {
+        while let Some(f) = self.chained_field() {
+            start.push(f);
+        }
+        start
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.toks.len()
+    }
+}
+
+fn parse_pipeline(ts: &mut TokStream) -> Result<Pipeline> {
+    let mut pipe = Pipeline::default();
+
+    // look ahead for `$x :=`, `$x =` or `$i, $v :=`
+    let decl_len = match (
+        ts.toks.get(ts.pos),
+        ts.toks.get(ts.pos + 1),
+        ts.toks.get(ts.pos + 2),
+    ) {
+        (
+            Some(Token { tok: Tok::Var(_), .. }),
+            Some(Token {
+                tok: Tok::Declare, ..
+            }),
+            _,
+        )
+        | (Some(Token { tok: Tok::Var(_), .. }), Some(Token { tok: Tok::Assign, .. }), _) => 1,
+        (
+            Some(Token { tok: Tok::Var(_), .. }),
+            Some(Token { tok: Tok::Comma, .. }),
+            Some(Token { tok: Tok::Var(_), .. }),
+        ) => 2,
+        _ => 0,
+    };
+    if decl_len > 0 {
+        for i in 0..decl_len {
+            if let Some(Tok::Var(v)) = ts.next() {
+                pipe.decl.push(v);
+            }
+            if i + 1 < decl_len {
+                ts.next(); // comma
+            }
+        }
+        match ts.next() {
+            Some(Tok::Declare) => {}
+            Some(Tok::Assign) if decl_len == 1 => pipe.assign = true,
+            _ => bail!("expected := in variable declaration"),
+        }
+    }
+
+    loop {
+        let cmd = parse_command(ts)?;
+        if cmd.is_empty() {
+            bail!("missing value for command");
+        }
+        pipe.cmds.push(cmd);
+        match ts.peek() {
+            Some(Tok::Pipe) => {
+                ts.next();
+            }
+            _ => break,
+        }
+    }
+    Ok(pipe)
+}
+
+fn parse_command(ts: &mut TokStream) -> Result<Vec<Operand>> {
+    let mut ops = vec![];
+    loop {
+        match ts.peek() {
+            None | Some(Tok::Pipe) | Some(Tok::RParen) => break,
+            _ => ops.push(parse_operand(ts)?),
+        }
+    }
+    Ok(ops)
+}
+
+fn parse_operand(ts: &mut TokStream) -> Result<Operand> {
+    let op = match ts.next() {
+        Some(Tok::Dot) => {
+            let fields = ts.fields(vec![]);
+            if fields.is_empty() {
+                Operand::Dot
+            } else {
+                Operand::Field(fields)
+            }
+        }
+        Some(Tok::Field(f)) => Operand::Field(ts.fields(vec![f])),
+        Some(Tok::Var(v)) => Operand::Var(v, ts.fields(vec![])),
+        Some(Tok::Ident(i)) => match i.as_str() {
+            "true" => Operand::Bool(true),
+            "false" => Operand::Bool(false),
+            "nil" => Operand::Nil,
+            _ => Operand::Func(i),
+        },
+        Some(Tok::Str(s)) => Operand::Str(s),
+        Some(Tok::Num(n)) => match serde_json::from_str::<serde_json::Number>(n.trim_start_matches('+')) {
+            Ok(num) => Operand::Num(num),
+            Err(_) => bail!("bad number syntax: {}", n),
+        },
+        Some(Tok::LParen) => {
+            let pipe = parse_pipeline(ts)?;
+            match ts.next() {
+                Some(Tok::RParen) => {}
+                _ => bail!("unclosed left paren"),
+            }
+            Operand::Sub(Box::new(pipe), ts.fields(vec![]))
+        }
+        Some(t) => bail!("unexpected \"{}\" in operand", t),
+        None => bail!("missing operand"),
+    };
+    Ok(op)
+}
+
+fn full_pipeline(toks: Vec<Token>) -> Result<Pipeline> {
+    let mut ts = TokStream { toks, pos: 0 };
+    let pipe = parse_pipeline(&mut ts)?;
+    if !ts.is_done() {
+        bail!("unexpected \"{}\" in pipeline", ts.peek().unwrap());
+    }
+    Ok(pipe)
+}
+
+// ----------------------------------------------------------------------------
+// Parsing the node tree
+
+enum Stop {
+    End,
+    Else,
+    ElseIf(Pipeline),
+    Eof,
+}
+
+struct Parser<'a> {
+    name: &'a str,
+    items: Vec<Item>,
+    pos: usize,
+    defines: &'a mut BTreeMap<String, Vec<Node>>,
+}
+
+impl<'a> Parser<'a> {
+    fn err(&self, line: usize, msg: impl std::fmt::Display) -> String {
+        format!("template: {}:{}: {}", self.name, line, msg)
+    }
+
+    fn list(&mut self) -> Result<(Vec<Node>, Stop)> {
+        let mut nodes = vec![];
+        while self.pos < self.items.len() {
+            let item = self.items[self.pos].clone();
+            self.pos += 1;
+            let (line, body) = match item {
+                Item::Text(t) => {
+                    if !t.is_empty() {
+                        nodes.push(Node::Text(t));
+                    }
+                    continue;
+                }
+                Item::Action { line, body } => (line, body),
+            };
+            let mut toks = lex(&body).map_err(|e| self.err(line, e))?;
+            let keyword = match toks.first() {
+                Some(Token {
+                    tok: Tok::Ident(k), ..
+                }) => k.clone(),
+                _ => String::new(),
+            };
+            match keyword.as_str() {
+                "end" => return Ok((nodes, Stop::End)),
+                "else" => {
+                    if let Some(Token {
+                        tok: Tok::Ident(k), ..
+                    }) = toks.get(1)
+                    {
+                        if k == "if" {
+                            let pipe = full_pipeline(toks.split_off(2)).map_err(|e| self.err(line, e))?;
+                            return Ok((nodes, Stop::ElseIf(pipe)));
+                        }
+                    }
+                    return Ok((nodes, Stop::Else));
+                }
+                "if" => {
+                    let pipe = full_pipeline(toks.split_off(1)).map_err(|e| self.err(line, e))?;
+                    nodes.push(self.parse_if(line, pipe)?);
+                }
+                "range" | "with" => {
+                    let pipe = full_pipeline(toks.split_off(1)).map_err(|e| self.err(line, e))?;
+                    let (body, stop) = self.list()?;
+                    let otherwise = match stop {
+                        Stop::End => vec![],
+                        Stop::Else => self.expect_end(line)?,
+                        _ => bail!(self.err(line, format!("unexpected end of {}", keyword))),
+                    };
+                    if keyword == "range" {
+                        nodes.push(Node::Range {
+                            line,
+                            pipe,
+                            body,
+                            otherwise,
+                        });
+                    } else {
+                        nodes.push(Node::With {
+                            line,
+                            pipe,
+                            body,
+                            otherwise,
+                        });
+                    }
+                }
+                "define" | "block" => {
+                    let mut rest = toks.split_off(1);
+                    let name = match rest.first() {
+                        Some(Token { tok: Tok::Str(s), .. }) => s.clone(),
+                        _ => bail!(self.err(line, format!("{} requires a quoted name", keyword))),
+                    };
+                    let rest = rest.split_off(1);
+                    let (body, stop) = self.list()?;
+                    match stop {
+                        Stop::End => {}
+                        _ => bail!(self.err(line, format!("unexpected end of {} {}", keyword, name))),
+                    }
+                    self.defines.insert(name.clone(), body);
+                    if keyword == "block" {
+                        let pipe = if rest.is_empty() {
+                            None
+                        } else {
+                            Some(full_pipeline(rest).map_err(|e| self.err(line, e))?)
+                        };
+                        nodes.push(Node::Template { line, name, pipe });
+                    }
+                }
+                "template" => {
+                    let mut rest = toks.split_off(1);
+                    let name = match rest.first() {
+                        Some(Token { tok: Tok::Str(s), .. }) => s.clone(),
+                        _ => bail!(self.err(line, "template requires a quoted name")),
+                    };
+                    let rest = rest.split_off(1);
+                    let pipe = if rest.is_empty() {
+                        None
+                    } else {
+                        Some(full_pipeline(rest).map_err(|e| self.err(line, e))?)
+                    };
+                    nodes.push(Node::Template { line, name, pipe });
+                }
+                _ => {
+                    let pipe = full_pipeline(toks).map_err(|e| self.err(line, e))?;
+                    nodes.push(Node::Action { line, pipe });
+                }
+            }
+        }
+        Ok((nodes, Stop::Eof))
+    }
+
+    fn expect_end(&mut self, line: usize) -> Result<Vec<Node>> {
+        match self.list()? {
+            (nodes, Stop::End) => Ok(nodes),
+            _ => bail!(self.err(line, "expected end after else")),
+        }
+    }
+
+    fn parse_if(&mut self, line: usize, pipe: Pipeline) -> Result<Node> {
+        let mut branches = vec![];
+        let mut cond = pipe;
+        loop {
+            let (body, stop) = self.list()?;
+            branches.push((cond, body));
+            match stop {
+                Stop::End => {
+                    return Ok(Node::If {
+                        line,
+                        branches,
+                        otherwise: vec![],
+                    })
+                }
+                Stop::Else => {
+                    let otherwise = self.expect_end(line)?;
+                    return Ok(Node::If {
+                        line,
+                        branches,
+                        otherwise,
+                    });
+                }
+                Stop::ElseIf(next) => cond = next,
+                Stop::Eof => bail!(self.err(line, "unexpected EOF in if")),
+            }
+        }
+    }
+}
+
+/// Parse a template source into a node tree
+///
+/// Any `define` blocks encountered are registered in `defines`.
+pub fn parse(name: &str, src: &str, defines: &mut BTreeMap<String, Vec<Node>>) -> Result<Vec<Node>> {
+    let items = split_items(name, src)?;
+    let mut parser = Parser {
+        name,
+        items,
+        pos: 0,
+        defines,
+    };
+    match parser.list()? {
+        (nodes, Stop::Eof) => Ok(nodes),
+        (_, Stop::End) => bail!("template: {}: unexpected {{{{end}}}}", name),
+        (_, _) => bail!("template: {}: unexpected {{{{else}}}}", name),
+    }
+}
@@ -1,12 +1,19 @@
 use petgraph::{
+    algo::tarjan_scc,
     dot,
     graph::{DiGraph, NodeIndex},
+    visit::{Bfs, Reversed},
+};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug},
+    str::FromStr,
 };
-use std::fmt::{self, Debug};
 
 use super::{
     structs::{Dependency, DependencyProtocol},
-    Config, Manifest, Region, Result,
+    Config, Error, ErrorKind, Manifest, OutputFormat, Region, Result,
 };
 
 /// The node type in `CatGraph` representing a `Manifest`
@@ -104,8 +111,205 @@ fn recurse_manifest(
     Ok(())
 }
 
+/// Add a manifest and edges to its dependencies by name
+///
+/// Dependencies get nodes of their own before their manifests are added.
+pub fn add_manifest(graph: &mut CatGraph, mf: &Manifest) -> NodeIndex {
+    let idx = node_for(graph, &mf.name);
+    for dep in &mf.dependencies {
+        let depidx = node_for(graph, &dep.name);
+        graph.update_edge(idx, depidx, DepEdge::new(dep));
+    }
+    idx
+}
+
+fn node_for(graph: &mut CatGraph, name: &str) -> NodeIndex {
+    match nodeidx_from_name(name, graph) {
+        Some(idx) => idx,
+        None => graph.add_node(ManifestNode { name: name.into() }),
+    }
+}
+
+/// Groups of services that depend on each other in a cycle
+///
+/// Names are sorted within and across groups.
+pub fn cycles(graph: &CatGraph) -> Vec<Vec<String>> {
+    let mut res = tarjan_scc(graph)
+        .into_iter()
+        .filter(|scc| scc.len() > 1 || graph.find_edge(scc[0], scc[0]).is_some())
+        .map(|scc| {
+            let mut names = scc.into_iter().map(|i| graph[i].name.clone()).collect::<Vec<_>>();
+            names.sort();
+            names
+        })
+        .collect::<Vec<_>>();
+    res.sort();
+    res
+}
+
+/// Fail if any services depend on each other in a cycle
+pub fn verify_acyclic(graph: &CatGraph) -> Result<()> {
+    let cycles = cycles(graph);
+    if cycles.is_empty() {
+        return Ok(());
+    }
+    let names = cycles.iter().map(|c| c.join(" <-> ")).collect::<Vec<_>>();
+    Err(ErrorKind::DependencyCycle(names.join(", ")).into())
+}
+
+/// Formats a graph can be exported in for other tools
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GraphFormat {
+    /// Graphviz dot
+    Dot,
+    /// GraphML xml for graph editors like yEd or Gephi
+    GraphMl,
+    /// Node-link json with a list of nodes and a list of edges
+    Json,
+    /// Mermaid flowchart for markdown documentation
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "dot" => Ok(Self::Dot),
+            "graphml" => Ok(Self::GraphMl),
+            "json" => Ok(Self::Json),
+            "mermaid" => Ok(Self::Mermaid),
+            _ => bail!("Graph format must be dot, graphml, json or mermaid"),
+        }
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn protocol_name(protocol: &DependencyProtocol) -> String {
+    format!("{:?}", protocol).to_lowercase()
+}
+
+fn to_graphml(graph: &CatGraph) -> String {
+    let mut out = vec![
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#.to_string(),
+    ];
+    for key in &["api", "contract", "protocol", "intent"] {
+        out.push(format!(
+            r#"  <key id="{0}" for="edge" attr.name="{0}" attr.type="string"/>"#,
+            key
+        ));
+    }
+    out.push(r#"  <graph id="shipcat" edgedefault="directed">"#.into());
+    for i in graph.node_indices() {
+        out.push(format!(r#"    <node id="{}"/>"#, xml_escape(&graph[i].name)));
+    }
+    for e in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(e).expect("edge has endpoints");
+        let dep = &graph[e];
+        out.push(format!(
+            r#"    <edge source="{}" target="{}">"#,
+            xml_escape(&graph[a].name),
+            xml_escape(&graph[b].name)
+        ));
+        let data = vec![
+            ("api", Some(dep.api.clone())),
+            ("contract", dep.contract.clone()),
+            ("protocol", Some(protocol_name(&dep.protocol))),
+            ("intent", dep.intent.clone()),
+        ];
+        for (key, val) in data {
+            if let Some(v) = val {
+                out.push(format!(r#"      <data key="{}">{}</data>"#, key, xml_escape(&v)));
+            }
+        }
+        out.push("    </edge>".into());
+    }
+    out.push("  </graph>".into());
+    out.push("</graphml>".into());
+    out.join("\n")
+}
+
+/// A graph as lists of named nodes and of edges between their names
+///
+/// This is the form of the json export, and of json or yaml output.
+pub fn graph_data(graph: &CatGraph) -> serde_json::Value {
+    let nodes = graph
+        .node_indices()
+        .map(|i| json!({ "id": graph[i].name }))
+        .collect::<Vec<_>>();
+    let edges = graph
+        .edge_indices()
+        .map(|e| {
+            let (a, b) = graph.edge_endpoints(e).expect("edge has endpoints");
+            let dep = &graph[e];
+            json!({
+                "source": graph[a].name,
+                "target": graph[b].name,
+                "api": dep.api,
+                "contract": dep.contract,
+                "protocol": dep.protocol,
+                "intent": dep.intent,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "nodes": nodes, "edges": edges })
+}
+
+fn to_mermaid(graph: &CatGraph) -> String {
+    // node ids are indices as service names are not valid mermaid ids
+    let mut out = vec!["graph LR".to_string()];
+    for i in graph.node_indices() {
+        out.push(format!("  n{}[\"{}\"]", i.index(), graph[i].name));
+    }
+    for e in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(e).expect("edge has endpoints");
+        out.push(format!(
+            "  n{} -->|{}| n{}",
+            a.index(),
+            protocol_name(&graph[e].protocol),
+            b.index()
+        ));
+    }
+    out.join("\n")
+}
+
+/// Export a graph in a format for other tools
+pub fn export(graph: &CatGraph, format: GraphFormat) -> Result<String> {
+    Ok(match format {
+        GraphFormat::Dot => format!("{:?}", dot::Dot::with_config(graph, &[dot::Config::EdgeNoLabel])),
+        GraphFormat::GraphMl => to_graphml(graph),
+        GraphFormat::Json => serde_json::to_string_pretty(&graph_data(graph))?,
+        GraphFormat::Mermaid => to_mermaid(graph),
+    })
+}
+
+/// Print a graph in an export format, or as `graph_data` in the requested output format
+fn print_graph(graph: &CatGraph, export_format: Option<GraphFormat>, fmt: OutputFormat) -> Result<()> {
+    match export_format {
+        Some(f) => {
+            println!("{}", export(graph, f)?);
+            Ok(())
+        }
+        None => fmt.print_data(&graph_data(graph)),
+    }
+}
+
 /// Generate dependency graph from an entry point via recursion
-pub async fn generate(service: &str, conf: &Config, reg: &Region, dot: bool) -> Result<CatGraph> {
+pub async fn generate(
+    service: &str,
+    conf: &Config,
+    reg: &Region,
+    export_format: Option<GraphFormat>,
+    fmt: OutputFormat,
+) -> Result<CatGraph> {
     let base = shipcat_filebacked::load_manifest(service, conf, reg).await?;
 
     let mut graph: CatGraph = DiGraph::<_, _>::new();
@@ -114,12 +318,7 @@ pub async fn generate(service: &str, conf: &Config, reg: &Region, dot: bool) ->
 
     recurse_manifest(baseidx, &base, conf, reg, &mut graph)?;
 
-    let out = if dot {
-        format!("{:?}", dot::Dot::with_config(&graph, &[dot::Config::EdgeNoLabel]))
-    } else {
-        serde_yaml::to_string(&graph)?
-    };
-    println!("{}", out);
+    print_graph(&graph, export_format, fmt)?;
     Ok(graph)
 }
 
@@ -129,14 +328,22 @@ pub async fn generate(service: &str, conf: &Config, reg: &Region, dot: bool) ->
 /// one or more services as we could also show grahps reaching into the ecosystem.
 ///
 /// But it would require: TODO: optionally filter edges around node(s)
-pub async fn full(dot: bool, conf: &Config, reg: &Region) -> Result<CatGraph> {
+pub async fn full(
+    export_format: Option<GraphFormat>,
+    fmt: OutputFormat,
+    conf: &Config,
+    reg: &Region,
+) -> Result<CatGraph> {
     let mut graph: CatGraph = DiGraph::<_, _>::new();
     for svc in shipcat_filebacked::available(conf, reg).await? {
         debug!("Scanning service {:?}", svc);
 
         let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
-        let node = ManifestNode::new(&mf);
-        let idx = graph.add_node(node);
+        // services can already be in the graph as dependencies of others
+        let idx = match nodeidx_from_name(&mf.name, &graph) {
+            Some(id) => id,
+            None => graph.add_node(ManifestNode::new(&mf)),
+        };
 
         for dep in &mf.dependencies {
             let subidx = if let Some(id) = nodeidx_from_name(&dep.name, &graph) {
@@ -152,17 +359,12 @@ pub async fn full(dot: bool, conf: &Config, reg: &Region) -> Result<CatGraph> {
         }
     }
 
-    let out = if dot {
-        format!("{:?}", dot::Dot::with_config(&graph, &[dot::Config::EdgeNoLabel]))
-    } else {
-        serde_yaml::to_string(&graph)?
-    };
-    println!("{}", out);
+    print_graph(&graph, export_format, fmt)?;
     Ok(graph)
 }
 
 /// Generate first level reverse dependencies for a service
-pub async fn reverse(service: &str, conf: &Config, reg: &Region) -> Result<Vec<String>> {
+pub async fn reverse(service: &str, conf: &Config, reg: &Region, fmt: OutputFormat) -> Result<Vec<String>> {
     let mut res = vec![];
     for svc in shipcat_filebacked::available(conf, reg).await? {
         let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
@@ -170,7 +372,113 @@ pub async fn reverse(service: &str, conf: &Config, reg: &Region) -> Result<Vec<S
             res.push(svc.base.name)
         }
     }
-    let out = serde_yaml::to_string(&res)?;
-    println!("{}", out);
+    fmt.print_lines(&res)?;
     Ok(res)
 }
+
+/// A squad affected by an outage of a service
+#[derive(Serialize, Clone, Debug)]
+pub struct ImpactedSquad {
+    pub squad: String,
+    /// Services of the squad that depend on the service
+    pub services: Vec<String>,
+    /// Slack channels to reach the squad on
+    pub channels: Vec<String>,
+}
+
+/// Services and squads affected by an outage of a service
+#[derive(Serialize, Clone, Debug)]
+pub struct Impact {
+    pub service: String,
+    /// Services depending on the service directly or transitively
+    pub dependents: Vec<String>,
+    pub squads: Vec<ImpactedSquad>,
+}
+
+/// Services that depend on a service directly or transitively
+///
+/// Walks the dependency edges in reverse, in breadth first order.
+pub fn dependents(service: &str, graph: &CatGraph) -> Vec<String> {
+    let start = match nodeidx_from_name(service, graph) {
+        Some(idx) => idx,
+        None => return vec![],
+    };
+    let reversed = Reversed(graph);
+    let mut bfs = Bfs::new(&reversed, start);
+    let mut res = vec![];
+    while let Some(idx) = bfs.next(&reversed) {
+        if idx != start {
+            res.push(graph[idx].name.clone());
+        }
+    }
+    res
+}
+
+/// Work out the services and squads affected by an outage of a service
+///
+/// Squads are reached on their alerts and support channels from teams.yml,
+/// as well as the support channels of their affected services.
+pub async fn impact(service: &str, conf: &Config, reg: &Region, fmt: OutputFormat) -> Result<Impact> {
+    let mut graph: CatGraph = DiGraph::<_, _>::new();
+    let mut metadata = BTreeMap::new();
+    for svc in shipcat_filebacked::available(conf, reg).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
+        add_manifest(&mut graph, &mf);
+        if let Some(md) = mf.metadata {
+            metadata.insert(mf.name, md);
+        }
+    }
+    if nodeidx_from_name(service, &graph).is_none() {
+        bail!("{} is not a service in {}", service, reg.name);
+    }
+    let dependents = dependents(service, &graph);
+
+    let mut squads: BTreeMap<String, (Vec<String>, BTreeSet<String>)> = BTreeMap::new();
+    for dep in &dependents {
+        let md = match metadata.get(dep) {
+            Some(md) => md,
+            None => continue,
+        };
+        let (services, channels) = squads.entry(md.team.clone()).or_default();
+        services.push(dep.clone());
+        if let Some(s) = &md.support {
+            channels.insert(s.to_string());
+        }
+        match conf.owners.squads.get(&md.team) {
+            Some(squad) => {
+                for c in squad.slack.alerts.iter().chain(squad.slack.support.iter()) {
+                    channels.insert(c.to_string());
+                }
+            }
+            None => warn!("No squad found for {} in teams.yml", md.team),
+        }
+    }
+    let impact = Impact {
+        service: service.into(),
+        dependents,
+        squads: squads
+            .into_iter()
+            .map(|(squad, (services, channels))| ImpactedSquad {
+                squad,
+                services,
+                channels: channels.into_iter().collect(),
+            })
+            .collect(),
+    };
+    fmt.print(&impact, |i| {
+        println!("{} services depend on {}", i.dependents.len(), i.service);
+        if !i.squads.is_empty() {
+            println!("{0:<30} {1:<50} {2}", "SQUAD", "SERVICES", "CHANNELS");
+        }
+        for s in &i.squads {
+            println!(
+                "{0:<30} {1:<50} {2}",
+                s.squad,
+                s.services.join(","),
+                s.channels.join(",")
+            );
+        }
+        Ok(())
+    })?;
+    Ok(impact)
+}
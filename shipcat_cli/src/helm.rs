@@ -1,41 +1,13 @@
 use std::{
     collections::BTreeMap,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
-use tokio::{
-    fs::{self, File},
-    prelude::*,
-    process::Command,
-};
+use tokio::{fs::File, prelude::*};
 
-use super::Result;
+use super::{gotpl::Chart, Result};
 use shipcat_definitions::{Manifest, ReconciliationMode, Region};
 
-pub fn hexists() -> Result<()> {
-    if which::which("helm").is_err() {
-        bail!("helm executable not found!");
-    }
-    Ok(())
-}
-
-pub async fn hexec(args: Vec<String>) -> Result<()> {
-    debug!("helm {}", args.join(" "));
-    hexists()?;
-    let s = Command::new("helm").args(&args).status().await?;
-    if !s.success() {
-        bail!("Subprocess failure from helm: {}", s.code().unwrap_or(1001))
-    }
-    Ok(())
-}
-pub async fn hout(args: Vec<String>) -> Result<(String, String, bool)> {
-    debug!("helm {}", args.join(" "));
-    hexists()?;
-    let s = Command::new("helm").args(&args).output().await?;
-    let out: String = String::from_utf8_lossy(&s.stdout).into();
-    let err: String = String::from_utf8_lossy(&s.stderr).into();
-    Ok((out, err, s.status.success()))
-}
-
 /// Create helm values file for a service
 ///
 /// Requires a completed manifest (with inlined configs)
@@ -55,27 +27,31 @@ pub async fn values(mf: &Manifest, output: &str) -> Result<()> {
     Ok(())
 }
 
+lazy_static! {
+    /// Parsed charts by name and version, so every render does not parse the chart again
+    static ref CHARTS: Mutex<BTreeMap<(String, String), Arc<Chart>>> = Default::default();
+}
+
+/// Load a chart, or reuse it if the same version has been loaded before
+///
+/// Changes to a chart without a version bump are therefore only seen by new processes.
+async fn load_chart(dir: &Path) -> Result<Arc<Chart>> {
+    let id = Chart::id(dir).await?;
+    if let Some(chart) = CHARTS.lock().unwrap().get(&id) {
+        return Ok(chart.clone());
+    }
+    let chart = Arc::new(Chart::load(dir).await?);
+    CHARTS.lock().unwrap().insert(id, chart.clone());
+    Ok(chart)
+}
 
 /// Analogue of helm template
 ///
-/// Generates helm values to disk, then passes it to helm template
+/// Renders the manifest's chart in-process via `gotpl` using the same values as `values`
 pub async fn template(mf: &Manifest, output: Option<PathBuf>) -> Result<String> {
-    let hfile = format!("{}.helm.gen.yml", mf.name);
-    values(&mf, &hfile).await?;
-
-    // helm template with correct params
-    let tplvec = vec![
-        "template".into(),
-        format!("charts/{}", mf.chart.clone().unwrap()),
-        "-f".into(),
-        hfile.clone(),
-    ];
-    // NB: this call does NOT need --tiller-namespace (offline call)
-    let (tpl, tplerr, success) = hout(tplvec.clone()).await?;
-    if !success {
-        warn!("{} stderr: {}", tplvec.join(" "), tplerr);
-        bail!("helm template failed");
-    }
+    let chartdir = Path::new("charts").join(mf.chart.clone().unwrap());
+    let chart = load_chart(&chartdir).await?;
+    let tpl = chart.render_manifest(&mf)?;
     if let Some(o) = &output {
         let pth = Path::new(".").join(o);
         debug!("Writing helm template for {} to {}", mf.name, pth.display());
@@ -88,13 +64,35 @@ pub async fn template(mf: &Manifest, output: Option<PathBuf>) -> Result<String>
             pth.display(),
             tpl
         );
-        if let Err(e) = fs::remove_file(&hfile).await {
-            warn!("Failed to delete file: {} {}", hfile, e);
-        }
     }
     Ok(tpl)
 }
 
+/// Split a rendered template into its kubernetes objects
+///
+/// Documents that render to nothing (comments or whitespace only) are skipped.
+pub fn objects(tpl: &str) -> Result<Vec<serde_json::Value>> {
+    let mut res = vec![];
+    let mut doc = vec![];
+    for l in tpl.lines().chain(std::iter::once("---")) {
+        if l.trim_end() != "---" {
+            doc.push(l);
+            continue;
+        }
+        let empty = doc
+            .iter()
+            .all(|l| l.trim().is_empty() || l.trim_start().starts_with('#'));
+        if !empty {
+            let obj: serde_json::Value = serde_yaml::from_str(&doc.join("\n"))?;
+            if !obj.is_null() {
+                res.push(obj);
+            }
+        }
+        doc.clear();
+    }
+    Ok(res)
+}
+
 /// Helper to validate the assumption of the charts
 ///
 /// This is an addon to checks done through `kubeval`.
@@ -129,7 +127,7 @@ pub fn template_check(mf: &Manifest, reg: &Region, skipped: &[String], tpl: &str
 
         let tiller_ok = check_no_tiller_refs(&kind, &obj)?;
         let ok = match reg.reconciliationMode {
-            ReconciliationMode::CrdOwned => {
+            ReconciliationMode::CrdOwned | ReconciliationMode::Operator => {
                 let owner_ok = check_owner_refs(mf, &kind, &obj)?;
                 let labels_ok = check_labels(mf, &kind, skipped, &obj)?;
                 labels_ok && owner_ok
@@ -1,30 +1,227 @@
-use crate::{ErrorKind, Manifest, Result};
+use crate::{Config, ErrorKind, Manifest, Region, Result};
 use k8s_openapi::api::{
     apps::v1::{Deployment, ReplicaSet, StatefulSet},
     core::v1::Pod,
 };
 use kube::{
-    api::{Api, DeleteParams, ListParams, LogParams, Object, ObjectList, PatchParams, Resource},
+    api::{
+        Api, DeleteParams, ListParams, LogParams, Object, ObjectList, PatchParams, PatchStrategy, Resource,
+    },
     client::APIClient,
+    config::ConfigOptions,
 };
+use serde_json::Value;
 use shipcat_definitions::{
     manifest::ShipcatManifest,
     status::{Applier, ManifestStatus},
+    structs::{parse_cpu, parse_memory, Resources},
 };
+use std::sync::Mutex;
+
+/// Field manager used for server side apply
+const FIELD_MANAGER: &str = "shipcat";
+
+/// Kinds our charts may create that are not namespaced
+const CLUSTER_SCOPED: &[&str] = &[
+    "ClusterRole",
+    "ClusterRoleBinding",
+    "CustomResourceDefinition",
+    "Namespace",
+    "PersistentVolume",
+    "PriorityClass",
+    "StorageClass",
+];
+
+/// Kinds we will prune if they are labelled for a service but no longer rendered
+///
+/// Mirrors the default whitelist of `kubectl apply --prune`.
+const PRUNABLE: &[(&str, &str)] = &[
+    ("v1", "ConfigMap"),
+    ("v1", "PersistentVolumeClaim"),
+    ("v1", "Secret"),
+    ("v1", "Service"),
+    ("apps/v1", "DaemonSet"),
+    ("apps/v1", "Deployment"),
+    ("apps/v1", "StatefulSet"),
+    ("batch/v1", "Job"),
+    ("batch/v1beta1", "CronJob"),
+    ("extensions/v1beta1", "Ingress"),
+];
+
+fn make_resource(api_version: &str, kind: &str, ns: &str) -> Resource {
+    let (group, version) = match api_version.rfind('/') {
+        Some(i) => (&api_version[..i], &api_version[i + 1..]),
+        None => ("", api_version),
+    };
+    let namespace = if CLUSTER_SCOPED.contains(&kind) {
+        None
+    } else {
+        Some(ns.to_string())
+    };
+    Resource {
+        api_version: api_version.to_string(),
+        group: group.to_string(),
+        version: version.to_string(),
+        kind: kind.to_string(),
+        namespace,
+    }
+}
+
+/// Identify a rendered kubernetes object
+///
+/// Returns a `Resource` for its api along with its name.
+pub fn object_ref(obj: &Value, ns: &str) -> Result<(Resource, String)> {
+    let kind = obj["kind"].as_str();
+    let api_version = obj["apiVersion"].as_str();
+    let name = obj["metadata"]["name"].as_str();
+    match (api_version, kind, name) {
+        (Some(av), Some(k), Some(n)) => Ok((make_resource(av, k, ns), n.to_string())),
+        _ => bail!("Object is missing apiVersion, kind or metadata.name"),
+    }
+}
 
 /// Client creator
 ///
 /// TODO: embed inside shipcat::apply when needed for other things
-async fn make_client() -> Result<APIClient> {
-    let config = if let Ok(cfg) = kube::config::incluster_config() {
-        cfg
-    } else {
-        kube::config::load_kube_config()
-            .await
-            .map_err(ErrorKind::KubeError)?
+pub(crate) async fn make_client() -> Result<APIClient> {
+    make_client_for(None).await
+}
+
+/// Client creator for a named kube context
+///
+/// Without a context this prefers the in-cluster config over the current context.
+pub(crate) async fn make_client_for(context: Option<&str>) -> Result<APIClient> {
+    let config = match context {
+        Some(ctx) => kube::config::load_kube_config_with(ConfigOptions {
+            context: Some(ctx.to_string()),
+            ..Default::default()
+        })
+        .await
+        .map_err(ErrorKind::KubeError)?,
+        None => {
+            if let Ok(cfg) = kube::config::incluster_config() {
+                cfg
+            } else {
+                kube::config::load_kube_config()
+                    .await
+                    .map_err(ErrorKind::KubeError)?
+            }
+        }
     };
     Ok(kube::client::APIClient::new(config))
 }
+
+/// The kube context reaching a cluster that serves a region
+///
+/// Contexts are named after the cluster, the region, or one of its `contextAliases`,
+/// and the one pointing at the api server of the cluster in shipcat.conf is used.
+pub fn cluster_context(conf: &Config, cluster: &str, region: &Region) -> Result<String> {
+    let api = match conf.clusters.get(cluster) {
+        Some(c) => c.api.trim_end_matches('/'),
+        None => bail!("Cluster {} is not defined in shipcat.conf", cluster),
+    };
+    let kubeconfig = kube::config::Config::read().map_err(ErrorKind::KubeError)?;
+    let server = |ctx: &str| {
+        let named = kubeconfig.contexts.iter().find(|c| c.name == ctx)?;
+        let cluster = kubeconfig
+            .clusters
+            .iter()
+            .find(|c| c.name == named.context.cluster)?;
+        Some(cluster.cluster.server.trim_end_matches('/'))
+    };
+    let aliases = conf
+        .contextAliases
+        .iter()
+        .filter(|(_, r)| **r == region.name)
+        .map(|(ctx, _)| ctx.as_str());
+    let mut candidates = vec![cluster, region.name.as_str()];
+    candidates.extend(aliases);
+    match candidates.iter().find(|ctx| server(ctx) == Some(api)) {
+        Some(ctx) => Ok(ctx.to_string()),
+        None => bail!(
+            "No kube context for cluster {} ({}) among {}",
+            cluster,
+            api,
+            candidates.join(", ")
+        ),
+    }
+}
+
+/// The kube contexts of every active cluster of a region
+///
+/// Regions without `activeClusters` only have the current context (`None`).
+pub fn region_contexts(conf: &Config, region: &Region) -> Result<Vec<Option<String>>> {
+    if region.activeClusters.is_empty() {
+        return Ok(vec![None]);
+    }
+    region
+        .activeClusters
+        .iter()
+        .map(|c| cluster_context(conf, c, region).map(Some))
+        .collect()
+}
+
+/// Live resource usage of a container
+#[derive(Clone, Debug)]
+pub struct ContainerUsage {
+    /// The `app` label of the pod
+    pub app: String,
+    /// Name of the pod
+    pub pod: String,
+    /// Name of the container
+    pub container: String,
+    /// Cores and Bytes of memory in use
+    pub usage: Resources<f64>,
+}
+
+/// Parse a `PodMetricsList` from the metrics api
+fn parse_pod_metrics(list: &Value) -> Result<Vec<ContainerUsage>> {
+    let mut res = vec![];
+    for item in list["items"].as_array().cloned().unwrap_or_else(Vec::new) {
+        let app = match item["metadata"]["labels"]["app"].as_str() {
+            Some(a) => a.to_string(),
+            None => continue, // not managed by shipcat
+        };
+        let pod = item["metadata"]["name"].as_str().unwrap_or_default().to_string();
+        for c in item["containers"].as_array().cloned().unwrap_or_else(Vec::new) {
+            let usage = Resources {
+                cpu: parse_cpu(c["usage"]["cpu"].as_str().unwrap_or("0"))?,
+                memory: parse_memory(c["usage"]["memory"].as_str().unwrap_or("0"))?,
+            };
+            res.push(ContainerUsage {
+                app: app.clone(),
+                pod: pod.clone(),
+                container: c["name"].as_str().unwrap_or_default().to_string(),
+                usage,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// Live resource usage of every container in a namespace
+///
+/// Read from the metrics api (`pods.metrics.k8s.io`), so needs metrics-server in the cluster.
+/// This is a snapshot averaged over the last scrape window, not a peak.
+pub async fn container_usage(ns: &str) -> Result<Vec<ContainerUsage>> {
+    let client = make_client().await?;
+    // the metrics api serves PodMetrics under the `pods` resource
+    let res = make_resource("metrics.k8s.io/v1beta1", "Pod", ns);
+    let req = res.list(&ListParams::default()).map_err(ErrorKind::KubeError)?;
+    let list = client.request::<Value>(req).await.map_err(ErrorKind::KubeError)?;
+    parse_pod_metrics(&list)
+}
+
+/// Label selector for the pods of a service
+///
+/// Blue/green pods keep the `app` label of the service, and add their `colour`.
+pub fn pod_selector(app: &str, colour: Option<&str>) -> String {
+    match colour {
+        Some(c) => format!("app={},colour={}", app, c),
+        None => format!("app={}", app),
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct MinimalManifest {
     pub name: String,
@@ -40,23 +237,40 @@ pub struct ShipKube {
     api: Api<ShipcatManifest>,
     name: String,
     namespace: String,
+    /// Name of the Deployment or StatefulSet to track
+    workload: String,
+    /// Label selector for the pods of the workload
+    selector: String,
+    /// Kube context when applying to one of several clusters
+    pub(crate) context: Option<String>,
+    /// Latest crd generation from spec changes shipcat made itself
+    produced: Mutex<Option<i64>>,
 }
 
 /// Entry points for shipcat::apply, and shipcat::status
 impl ShipKube {
     pub async fn new_within(svc: &str, ns: &str) -> Result<Self> {
+        Self::new_in_context(svc, ns, None).await
+    }
+
+    /// Interface for a service in the cluster of a named kube context
+    pub async fn new_in_context(svc: &str, ns: &str, context: Option<&str>) -> Result<Self> {
         // hide the client in here -> Api resource for now (not needed elsewhere)
-        let client = make_client().await?;
+        let client = make_client_for(context).await?;
         let mfs = Resource::namespaced::<ShipcatManifest>(ns);
         let api = Api::namespaced(client.clone(), ns);
 
         Ok(Self {
             name: svc.to_string(),
             namespace: ns.to_string(),
+            workload: svc.to_string(),
+            selector: pod_selector(svc, None),
             applier: Applier::infer(),
             api,
             client,
             mfs,
+            context: context.map(String::from),
+            produced: Mutex::new(None),
         })
     }
 
@@ -64,6 +278,15 @@ impl ShipKube {
         Self::new_within(&mf.name, &mf.namespace).await
     }
 
+    /// Track one colour of a blue/green service instead
+    ///
+    /// The workload is the `<svc>-<colour>` Deployment, selecting pods on both labels.
+    pub fn coloured(mut self, colour: &str) -> Self {
+        self.workload = format!("{}-{}", self.name, colour);
+        self.selector = pod_selector(&self.name, Some(colour));
+        self
+    }
+
     /// Apply a Manifest (e.g. it's CRD wrapper)
     pub async fn apply(&self, mf: Manifest) -> Result<bool> {
         assert!(mf.version.is_some()); // ensure crd is in right state w/o secrets
@@ -75,7 +298,23 @@ impl ShipKube {
         // TODO: use server side apply in 1.15
         // for now, shell out to kubectl
         use crate::kubectl;
-        kubectl::apply_resource(&svc, mfcrd, &ns).await
+        kubectl::apply_resource(&svc, mfcrd, &ns, self.context.as_deref()).await
+    }
+
+    /// Remember the crd generation after shipcat itself changed its spec to `version`
+    ///
+    /// Generations from other changes made in the meantime are not recorded.
+    pub async fn record_generation(&self, version: &str) -> Result<()> {
+        let crd = self.get().await?;
+        if crd.spec.version.as_deref() == Some(version) {
+            *self.produced.lock().unwrap() = crd.metadata.generation;
+        }
+        Ok(())
+    }
+
+    /// Latest crd generation from spec changes shipcat made itself
+    pub fn produced_generation(&self) -> Option<i64> {
+        *self.produced.lock().unwrap()
     }
 
     /// Full CRD fetcher
@@ -125,11 +364,118 @@ impl ShipKube {
         Ok(())
     }
 
+    /// Server side apply a rendered object as the shipcat field manager
+    ///
+    /// Conflicts with other field managers are returned as `ApplyConflict` unless `force` is set.
+    pub async fn apply_object(&self, obj: &Value, dry_run: bool, force: bool) -> Result<Value> {
+        let (res, name) = object_ref(obj, &self.namespace)?;
+        let pp = PatchParams {
+            dry_run,
+            force,
+            patch_strategy: PatchStrategy::Apply,
+            field_manager: Some(FIELD_MANAGER.into()),
+        };
+        let req = res
+            .patch(&name, &pp, serde_json::to_vec(obj)?)
+            .map_err(ErrorKind::KubeError)?;
+        match self.client.request::<Value>(req).await {
+            Ok(o) => Ok(o),
+            Err(kube::Error::Api(e)) if e.code == 409 => {
+                Err(ErrorKind::ApplyConflict(format!("{}/{}", res.kind, name), e.message).into())
+            }
+            Err(e) => Err(ErrorKind::KubeError(e).into()),
+        }
+    }
+
+    /// Fetch the live version of a rendered object (if it exists)
+    pub async fn get_object(&self, obj: &Value) -> Result<Option<Value>> {
+        let (res, name) = object_ref(obj, &self.namespace)?;
+        let req = res.get(&name).map_err(ErrorKind::KubeError)?;
+        match self.client.request::<Value>(req).await {
+            Ok(o) => Ok(Some(o)),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+            Err(e) => Err(ErrorKind::KubeError(e).into()),
+        }
+    }
+
+    /// Delete a rendered object (if it exists)
+    pub async fn delete_object(&self, obj: &Value) -> Result<()> {
+        let (res, name) = object_ref(obj, &self.namespace)?;
+        let req = res
+            .delete(&name, &DeleteParams::default())
+            .map_err(ErrorKind::KubeError)?;
+        match self.client.request_status::<Value>(req).await {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+            Err(e) => Err(ErrorKind::KubeError(e).into()),
+        }
+    }
+
+    /// Delete prunable objects labelled for this service that are not in `keep`
+    ///
+    /// Returns the `Kind/name` of every pruned object.
+    pub async fn prune(&self, keep: &[Value]) -> Result<Vec<String>> {
+        let mut kept = vec![];
+        for o in keep {
+            let (res, name) = object_ref(o, &self.namespace)?;
+            kept.push(format!("{}/{}", res.kind, name));
+        }
+        let lp = ListParams {
+            label_selector: Some(format!(
+                "app.kubernetes.io/name={},app.kubernetes.io/managed-by=shipcat",
+                self.name
+            )),
+            ..Default::default()
+        };
+        let mut pruned = vec![];
+        for (av, kind) in PRUNABLE {
+            let res = make_resource(av, kind, &self.namespace);
+            let req = res.list(&lp).map_err(ErrorKind::KubeError)?;
+            let list = match self.client.request::<Value>(req).await {
+                Ok(l) => l,
+                // api group not served by this cluster
+                Err(kube::Error::Api(e)) if e.code == 404 => continue,
+                Err(e) => return Err(ErrorKind::KubeError(e).into()),
+            };
+            let items = list["items"].as_array().cloned().unwrap_or_else(Vec::new);
+            for item in items {
+                let name = match item["metadata"]["name"].as_str() {
+                    Some(n) => n.to_string(),
+                    None => continue,
+                };
+                let id = format!("{}/{}", kind, name);
+                if kept.contains(&id) {
+                    continue;
+                }
+                let req = res
+                    .delete(&name, &DeleteParams::default())
+                    .map_err(ErrorKind::KubeError)?;
+                self.client
+                    .request_status::<Value>(req)
+                    .await
+                    .map_err(ErrorKind::KubeError)?;
+                pruned.push(id);
+            }
+        }
+        Ok(pruned)
+    }
+
     // helper to get pod data
     pub async fn get_pods(&self) -> Result<ObjectList<Pod>> {
         let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
         let lp = ListParams {
-            label_selector: Some(format!("app={}", self.name)),
+            label_selector: Some(self.selector.clone()),
+            ..Default::default()
+        };
+        let pods = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
+        Ok(pods)
+    }
+
+    // helper to get pods of a canary deployment
+    pub async fn get_canary_pods(&self) -> Result<ObjectList<Pod>> {
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
+        let lp = ListParams {
+            label_selector: Some(format!("{},track=canary", self.selector)),
             ..Default::default()
         };
         let pods = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
@@ -140,7 +486,7 @@ impl ShipKube {
     pub async fn get_pods_by_template_hash(&self, hash: &str) -> Result<ObjectList<Pod>> {
         let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
         let lp = ListParams {
-            label_selector: Some(format!("app={},pod-template-hash={}", self.name, hash)),
+            label_selector: Some(format!("{},pod-template-hash={}", self.selector, hash)),
             ..Default::default()
         };
         let pods = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
@@ -163,7 +509,7 @@ impl ShipKube {
     pub async fn get_rs(&self) -> Result<ObjectList<ReplicaSet>> {
         let api: Api<ReplicaSet> = Api::namespaced(self.client.clone(), &self.namespace);
         let lp = ListParams {
-            label_selector: Some(format!("app={}", self.name)),
+            label_selector: Some(format!("{},track!=canary", self.selector)),
             ..Default::default()
         };
         let rs = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
@@ -174,7 +520,7 @@ impl ShipKube {
     pub async fn get_rs_by_template_hash(&self, hash: &str) -> Result<Option<ReplicaSet>> {
         let api: Api<ReplicaSet> = Api::namespaced(self.client.clone(), &self.namespace);
         let lp = ListParams {
-            label_selector: Some(format!("app={},pod-template-hash={}", self.name, hash)),
+            label_selector: Some(format!("{},pod-template-hash={}", self.selector, hash)),
             ..Default::default()
         };
         let rs = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
@@ -187,22 +533,22 @@ impl ShipKube {
         let replicasets: Api<ReplicaSet> = Api::namespaced(self.client.clone(), &self.namespace);
 
         // Get owning deployment and its revision annotation
-        let dep = deps.get(&self.name).await.map_err(ErrorKind::KubeError)?;
+        let dep = deps.get(&self.workload).await.map_err(ErrorKind::KubeError)?;
         let mut rev = None;
         if let Some(meta) = dep.metadata {
             if let Some(annot) = meta.annotations {
                 if let Some(r) = annot.get("deployment.kubernetes.io/revision") {
                     rev = Some(r.clone());
-                    debug!("Desired deployment revision for {} is {}", self.name, r);
+                    debug!("Desired deployment revision for {} is {}", self.workload, r);
                 }
             }
         }
 
         // If that worked, match it up to a replicaset:
         if let Some(desired) = rev {
-            // Find all replicasets with our app label
+            // Find all replicasets with our app label (that are not from a canary)
             let lp = ListParams {
-                label_selector: Some(format!("app={}", self.name)),
+                label_selector: Some(format!("{},track!=canary", self.selector)),
                 ..Default::default()
             };
             let rs = replicasets.list(&lp).await.map_err(ErrorKind::KubeError)?;
@@ -215,7 +561,7 @@ impl ShipKube {
                         if let Some(annot) = &meta.annotations {
                             if let Some(found) = annot.get("deployment.kubernetes.io/revision") {
                                 if found == &desired {
-                                    debug!("Tracking replicaset revision {} for {}", found, self.name);
+                                    debug!("Tracking replicaset revision {} for {}", found, self.workload);
                                     return true;
                                 }
                             }
@@ -237,14 +583,43 @@ impl ShipKube {
     // helper to get deployment data
     pub async fn get_deploy(&self) -> Result<Deployment> {
         let api: Api<Deployment> = Api::namespaced(self.client.clone(), &self.namespace);
-        let deps = api.get(&self.name).await.map_err(ErrorKind::KubeError)?;
+        let deps = api.get(&self.workload).await.map_err(ErrorKind::KubeError)?;
         Ok(deps)
     }
 
     // helper to get statefulset data
     pub async fn get_statefulset(&self) -> Result<StatefulSet> {
         let api: Api<StatefulSet> = Api::namespaced(self.client.clone(), &self.namespace);
-        let ssets = api.get(&self.name).await.map_err(ErrorKind::KubeError)?;
+        let ssets = api.get(&self.workload).await.map_err(ErrorKind::KubeError)?;
         Ok(ssets)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_pod_metrics;
+
+    #[test]
+    fn kubeapi_pod_metrics() {
+        let list = serde_json::json!({
+            "kind": "PodMetricsList",
+            "items": [{
+                "metadata": { "name": "fake-ask-abc", "labels": { "app": "fake-ask" } },
+                "containers": [
+                    { "name": "fake-ask", "usage": { "cpu": "250000000n", "memory": "128Mi" } },
+                    { "name": "envoy", "usage": { "cpu": "5m", "memory": "10240Ki" } }
+                ]
+            }, {
+                "metadata": { "name": "unlabelled" },
+                "containers": [{ "name": "x", "usage": { "cpu": "1", "memory": "1Gi" } }]
+            }]
+        });
+        let usage = parse_pod_metrics(&list).unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].pod, "fake-ask-abc");
+        assert!((usage[0].usage.cpu - 0.25).abs() < 1e-9);
+        assert_eq!(usage[0].usage.memory, 128.0 * 1024.0 * 1024.0);
+        assert_eq!(usage[1].container, "envoy");
+        assert_eq!(usage[1].usage.memory, 10.0 * 1024.0 * 1024.0);
+    }
+}
@@ -253,6 +253,7 @@ pub async fn apply_resource<K: k8s_openapi::Resource + Serialize>(
     name: &str,
     data: K,
     ns: &str,
+    context: Option<&str>,
 ) -> Result<bool> {
     use std::{
         fs::{self, File},
@@ -261,7 +262,11 @@ pub async fn apply_resource<K: k8s_openapi::Resource + Serialize>(
     };
 
     // Write it to a temporary file:
-    let datafile = format!("{}.crd.gen.yml", name);
+    // applies to several contexts happen in parallel, so they need their own files
+    let datafile = match context {
+        Some(ctx) => format!("{}.{}.crd.gen.yml", name, ctx),
+        None => format!("{}.crd.gen.yml", name),
+    };
     let pth = Path::new(".").join(&datafile);
     debug!("Writing {} CRD for {} to {}", K::KIND, name, pth.display());
     let mut f = File::create(&pth)?;
@@ -277,12 +282,15 @@ pub async fn apply_resource<K: k8s_openapi::Resource + Serialize>(
 
     // Apply it using kubectl apply
     debug!("Applying {} CRD for {}", K::KIND, name);
-    let applyargs = vec![
+    let mut applyargs = vec![
         format!("-n={}", ns),
         "apply".into(),
         "-f".into(),
         datafile.clone(),
     ];
+    if let Some(ctx) = context {
+        applyargs.push(format!("--context={}", ctx));
+    }
     debug!("applying {} : {:?}", name, applyargs);
     let (out, status) = kout(applyargs.clone()).await?;
     print!("{}", out); // always print kube output from this
@@ -302,13 +310,16 @@ pub async fn apply_resource<K: k8s_openapi::Resource + Serialize>(
 /// Find all ManifestCrds in a given namespace
 ///
 /// Allows us to purge manifests that are not in Manifest::available()
-async fn find_all_manifest_crds(ns: &str) -> Result<Vec<String>> {
-    let getargs = vec![
+async fn find_all_manifest_crds(ns: &str, context: Option<&str>) -> Result<Vec<String>> {
+    let mut getargs = vec![
         "get".into(),
         format!("-n={}", ns),
         "shipcatmanifests".into(),
         "-ojsonpath='{.items[*].metadata.name}'".into(),
     ];
+    if let Some(ctx) = context {
+        getargs.push(format!("--context={}", ctx));
+    }
     let (out, _) = kout(getargs).await?;
     if out == "''" {
         // stupid kubectl
@@ -317,31 +328,18 @@ async fn find_all_manifest_crds(ns: &str) -> Result<Vec<String>> {
     Ok(out.split(' ').map(String::from).collect())
 }
 
-use std::path::PathBuf;
-// Kubectl diff experiment (ignores secrets)
-pub async fn diff(pth: PathBuf, ns: &str) -> Result<(String, String, bool)> {
-    let args = vec![
-        "diff".into(),
-        format!("-n={}", ns),
-        format!("-f={}", pth.display()),
-    ];
-    // need the error code here so re-implent - and discard stderr
-    debug!("kubectl {}", args.join(" "));
-
-    let s = Command::new("kubectl").args(&args).output().await?;
-    let out: String = String::from_utf8_lossy(&s.stdout).into();
-    let err: String = String::from_utf8_lossy(&s.stderr).into();
-    trace!("out: {}, err: {}", out, err);
-    if err.contains("the dryRun alpha feature is disabled") {
-        bail!("kubectl diff is not supported in your cluster: {}", err.trim());
-    }
-    Ok((out, err, s.status.success()))
-}
-
-pub async fn find_redundant_manifests(ns: &str, svcs: &[String]) -> Result<Vec<String>> {
+pub async fn find_redundant_manifests(
+    ns: &str,
+    svcs: &[String],
+    context: Option<&str>,
+) -> Result<Vec<String>> {
     use std::collections::HashSet;
     let requested: HashSet<_> = svcs.iter().cloned().collect();
-    let found: HashSet<_> = find_all_manifest_crds(ns).await?.iter().cloned().collect();
+    let found: HashSet<_> = find_all_manifest_crds(ns, context)
+        .await?
+        .iter()
+        .cloned()
+        .collect();
     debug!("Found manifests: {:?}", found);
     Ok(found.difference(&requested).cloned().collect())
 }
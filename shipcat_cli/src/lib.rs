@@ -5,6 +5,7 @@
 
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
 
 #[macro_use] extern crate error_chain;
 
@@ -61,6 +62,14 @@ error_chain! {
             description("Kube apply call failed")
             display("Kube apply of {} failed", &svc)
         }
+        ApplyConflict(obj: String, msg: String) {
+            description("server side apply conflict")
+            display("Apply of {} conflicted: {}", &obj, &msg)
+        }
+        CanaryAborted(svc: String, reason: String) {
+            description("canary aborted")
+            display("canary of {} aborted: {}", &svc, &reason)
+        }
         KubectlApiFailure(call: String, svc: String) {
             description("kube call failed")
             display("kube {} of {} failed", &call, &svc)
@@ -69,6 +78,26 @@ error_chain! {
             description("upgrade timed out")
             display("{} upgrade timed out waiting {}s for deployment(s) to come online", &svc, secs)
         }
+        ClusterApplyFailure(svc: String, clusters: String) {
+            description("apply failed in some clusters")
+            display("apply of {} failed in clusters: {}", &svc, &clusters)
+        }
+        ImageNotFound(image: String) {
+            description("image not found in registry")
+            display("{} does not exist in its registry", &image)
+        }
+        UnsignedImage(image: String) {
+            description("image is not signed by a trusted key")
+            display("{} has no valid signature from a trusted key", &image)
+        }
+        DependencyCycle(services: String) {
+            description("services depend on each other in a cycle")
+            display("dependency cycle between {}", &services)
+        }
+        RegionFrozen(region: String, window: String) {
+            description("region is frozen")
+            display("{} is frozen for {} - pass a --freeze-override reason to proceed", &region, &window)
+        }
         SlackSendFailure(hook: String) {
             description("slack message send failed")
             display("Failed to send the slack message to '{}' ", &hook)
@@ -121,9 +150,18 @@ pub mod status;
 /// Apply logic
 pub mod apply;
 
-/// A small CLI helm template interface
+/// Promotion of versions between regions
+pub mod promote;
+
+/// Verification of images against their docker registry
+pub mod registry;
+
+/// Chart templating and template verification
 pub mod helm;
 
+/// An in-process go template renderer for helm charts
+pub mod gotpl;
+
 /// A small CLI kong config generator interface
 pub mod kong;
 
@@ -138,7 +176,8 @@ pub mod get;
 
 /// Top resource use
 pub mod top;
-pub use top::{OutputFormat, ResourceOrder};
+pub use output::OutputFormat;
+pub use top::ResourceOrder;
 
 
 /// Diffing module for values
@@ -150,6 +189,15 @@ pub mod git;
 /// Env module for sourcing secrets
 pub mod env;
 
+/// Secret rotation
+pub mod secret;
+
+/// Continuous reconciliation of shipcatmanifest crds
+pub mod operator;
+
+/// Drift detection between the cluster and shipcatmanifest crds
+pub mod drift;
+
 /// Webhook mux/demux
 pub mod webhooks;
 pub use webhooks::UpgradeState;
@@ -157,6 +205,9 @@ pub use webhooks::UpgradeState;
 /// Simple printers
 pub mod show;
 
+/// Shared output formatting for read-only subcommands
+pub mod output;
+
 /// Cluster auth
 pub mod auth;
 
@@ -1,28 +1,24 @@
 /// This file contains all the hidden `shipcat list-*` subcommands
-use super::{Config, Region, Result};
+use super::{Config, OutputFormat, Region, Result};
 
 /// Print the supported regions
-pub fn regions(conf: &Config) -> Result<()> {
-    for r in conf.list_regions() {
-        println!("{}", r);
-    }
-    Ok(())
+pub fn regions(conf: &Config, fmt: OutputFormat) -> Result<()> {
+    fmt.print_lines(&conf.list_regions())
 }
 
 /// Print the supported locations
-pub fn locations(conf: &Config) -> Result<()> {
-    for r in conf.locations.keys() {
-        println!("{}", r);
-    }
-    Ok(())
+pub fn locations(conf: &Config, fmt: OutputFormat) -> Result<()> {
+    let locations = conf.locations.keys().cloned().collect::<Vec<_>>();
+    fmt.print_lines(&locations)
 }
 
 /// Print supported services in a region
 /// TODO: this one needs to do the guess outside in main!
-pub async fn services(conf: &Config, region: &Region) -> Result<()> {
-    let services = shipcat_filebacked::available(conf, region).await?;
-    for svc in services {
-        println!("{}", &svc.base.name);
-    }
-    Ok(())
+pub async fn services(conf: &Config, region: &Region, fmt: OutputFormat) -> Result<()> {
+    let services = shipcat_filebacked::available(conf, region)
+        .await?
+        .into_iter()
+        .map(|svc| svc.base.name)
+        .collect::<Vec<_>>();
+    fmt.print_lines(&services)
 }
@@ -44,6 +44,17 @@ fn build_cli() -> App<'static, 'static> {
             .long("strict-version-check")
             .global(true)
             .help("Fail on outdated versions"))
+        .arg(Arg::with_name("no-cache")
+            .long("no-cache")
+            .global(true)
+            .help("Always read secrets from vault instead of the local secret cache"))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .possible_values(&["table", "json", "yaml"])
+            .global(true)
+            .help("Output format for read-only subcommands"))
         .arg(Arg::with_name("region")
                 .short("r")
                 .long("region")
@@ -103,6 +114,9 @@ fn build_cli() -> App<'static, 'static> {
                 .short("s")
                 .long("secrets")
                 .help("Verifies secrets exist everywhere"))
+              .arg(Arg::with_name("images")
+                .long("images")
+                .help("Verifies pinned versions exist in the docker registry"))
               .about("Validate the shipcat manifest"))
 
         .subcommand(SubCommand::with_name("verify")
@@ -126,6 +140,27 @@ fn build_cli() -> App<'static, 'static> {
                     .multiple(true)
                     .help("Regions to validate all enabled services for"))
                 .about("Verify existence of secrets for entire regions"))
+            .subcommand(SubCommand::with_name("rotate")
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("Rotated secret path (or folder) under secret/, e.g. dev-uk/webapp/DATABASE_URL"))
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only list the services that would be restarted"))
+                .arg(Arg::with_name("no-wait")
+                    .long("no-wait")
+                    .help("Do not wait for service timeout"))
+                .arg(Arg::with_name("freeze-override")
+                    .long("freeze-override")
+                    .takes_value(true)
+                    .value_name("reason")
+                    .help("Reason for restarting during a freeze window of the region"))
+                .about("Restart all services in a region that read a rotated secret"))
+            .subcommand(SubCommand::with_name("seal")
+                .arg(Arg::with_name("key")
+                    .required(true)
+                    .help("Secret path under secret/, e.g. dev-uk/webapp/DATABASE_URL"))
+                .about("Encrypt a secret from stdin into the secret file of a region"))
             .about("Secret interaction"))
 
         .subcommand(SubCommand::with_name("gdpr")
@@ -181,9 +216,20 @@ fn build_cli() -> App<'static, 'static> {
               .arg(Arg::with_name("dot")
                 .long("dot")
                 .help("Generate dot output for graphviz"))
+              .arg(Arg::with_name("export")
+                .long("export")
+                .takes_value(true)
+                .possible_values(&["dot", "graphml", "json", "mermaid"])
+                .conflicts_with_all(&["dot", "reverse"])
+                .help("Export the graph in a format for other tools (instead of --output)"))
               .arg(Arg::with_name("reverse")
                 .long("reverse")
                 .help("Generate reverse dependencies for a service"))
+              .subcommand(SubCommand::with_name("impact")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service name to assess an outage of"))
+                .about("List the services and squads transitively affected by an outage of a service"))
               .about("Graph the dependencies of a service"))
         // cluster admin operations
         .subcommand(SubCommand::with_name("cluster")
@@ -191,6 +237,23 @@ fn build_cli() -> App<'static, 'static> {
             .about("Perform cluster level recovery / reconcilation commands")
             .subcommand(SubCommand::with_name("diff")
                 .about("Diff all services against the a region"))
+            .subcommand(SubCommand::with_name("drift")
+                .arg(Arg::with_name("interval")
+                    .long("interval")
+                    .takes_value(true)
+                    .default_value("300")
+                    .help("Seconds between checks"))
+                .arg(Arg::with_name("port")
+                    .long("port")
+                    .takes_value(true)
+                    .default_value("9102")
+                    .help("Port to serve prometheus metrics on"))
+                .arg(Arg::with_name("num-jobs")
+                    .short("j")
+                    .long("num-jobs")
+                    .takes_value(true)
+                    .help("Number of worker threads used"))
+                .about("Continuously check all services in a region for drift from their crds"))
             .subcommand(SubCommand::with_name("check")
                 .arg(Arg::with_name("skip-kinds")
                     .long("skip-kinds")
@@ -206,6 +269,11 @@ fn build_cli() -> App<'static, 'static> {
                 .subcommand(SubCommand::with_name("install")
                     .about("Install the Shipcat related CRDs"))
                 .subcommand(SubCommand::with_name("reconcile")
+                    .arg(Arg::with_name("freeze-override")
+                        .long("freeze-override")
+                        .takes_value(true)
+                        .value_name("reason")
+                        .help("Reason for reconciling during a freeze window of the region"))
                     .about("Reconcile shipcat custom resource definitions with local state")))
             .subcommand(SubCommand::with_name("vault-policy")
                 .arg(Arg::with_name("num-jobs")
@@ -254,6 +322,13 @@ fn build_cli() -> App<'static, 'static> {
                 .required(true)
                 .help("Service to generate values for"))
               .about("Generate the completed service manifest that will be passed to the helm chart"))
+        .subcommand(SubCommand::with_name("explain")
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service to explain"))
+              .arg(Arg::with_name("key")
+                .help("Only show keys starting with this, like replicaCount or resources.limits"))
+              .about("Show which file set each value of the completed service manifest"))
         .subcommand(SubCommand::with_name("template")
               .arg(Arg::with_name("secrets")
                 .short("s")
@@ -292,16 +367,54 @@ fn build_cli() -> App<'static, 'static> {
                     .help("Do not wait for service timeout"))
               .arg(Arg::with_name("force")
                     .long("force")
-                    .help("Apply template even if no changes are detected"))
+                    .help("Apply template even if no changes are detected, taking ownership of conflicting fields"))
+              .arg(Arg::with_name("freeze-override")
+                    .long("freeze-override")
+                    .takes_value(true)
+                    .value_name("reason")
+                    .help("Reason for applying during a freeze window of the region"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to apply"))
             .about("Apply a service's configuration in kubernetes (through helm)"))
 
+        .subcommand(SubCommand::with_name("promote")
+              .arg(Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .required(true)
+                .help("Region to take the rolled out version from"))
+              .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .required(true)
+                .help("Region to pin the version in"))
+              .arg(Arg::with_name("apply")
+                .long("apply")
+                .help("Apply the service in the destination region afterwards"))
+              .arg(Arg::with_name("no-wait")
+                    .long("no-wait")
+                    .requires("apply")
+                    .help("Do not wait for service timeout"))
+              .arg(Arg::with_name("freeze-override")
+                    .long("freeze-override")
+                    .takes_value(true)
+                    .value_name("reason")
+                    .help("Reason for applying during a freeze window of the region"))
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service to promote"))
+            .about("Pin the version a service rolled out in one region in the overrides of another"))
+
         .subcommand(SubCommand::with_name("restart")
               .arg(Arg::with_name("no-wait")
                     .long("no-wait")
                     .help("Do not wait for service timeout"))
+              .arg(Arg::with_name("freeze-override")
+                    .long("freeze-override")
+                    .takes_value(true)
+                    .value_name("reason")
+                    .help("Reason for restarting during a freeze window of the region"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to restart"))
@@ -352,11 +465,12 @@ fn build_cli() -> App<'static, 'static> {
               .arg(Arg::with_name("minify")
                 .short("m")
                 .long("minify")
-                .help("Minify the diff context"))
+                .hidden(true)
+                .help("Deprecated: diffs only contain changed fields"))
               .arg(Arg::with_name("obfuscate")
                 .long("obfuscate")
-                .requires("secrets")
-                .help("Obfuscate secrets in the diff"))
+                .hidden(true)
+                .help("Deprecated: secrets are always masked in diffs"))
               .arg(Arg::with_name("secrets")
                 .long("secrets")
                 .short("s")
@@ -376,6 +490,13 @@ fn build_cli() -> App<'static, 'static> {
             .subcommand(SubCommand::with_name("verify")
                 .about("Verify the parsed config")))
 
+        .subcommand(SubCommand::with_name("schema")
+            .about("Print the JSON Schema of manifests or shipcat.conf")
+            .arg(Arg::with_name("kind")
+                .possible_values(&["manifest", "overrides", "config"])
+                .default_value("manifest")
+                .help("manifest.yml, regional override files like dev-uk.yml, or shipcat.conf")))
+
         .subcommand(SubCommand::with_name("login")
             .about("Login to a region (using teleport if possible)")
             .arg(Arg::with_name("force")
@@ -383,22 +504,27 @@ fn build_cli() -> App<'static, 'static> {
                 .short("f")
                 .help("Remove the old tsh state file to force a login")))
 
+        .subcommand(SubCommand::with_name("operator")
+            .about("Continuously roll out changes to shipcatmanifest crds in a region")
+            .arg(Arg::with_name("num-jobs")
+                .short("j")
+                .long("num-jobs")
+                .takes_value(true)
+                .help("Number of services to roll out in parallel")))
+
         .subcommand(SubCommand::with_name("top")
-            .about("Show top requests from manifests on disk")
+            .about("Show top requests and their monthly cost from manifests on disk")
             .arg(Arg::with_name("upper")
                 .short("u")
                 .long("upper-bounds")
                 .help("Use the upper bounds of autoscaling policies"))
-            .arg(Arg::with_name("output")
-                .takes_value(true)
-                .default_value("table")
-                .possible_values(&["table", "yaml"])
-                .long("output")
-                .short("o")
-                .help("Output format to print. Yaml contains machine parseable numbers."))
             .arg(Arg::with_name("world")
                 .long("world")
                 .help("Show resource requests across all regions"))
+            .arg(Arg::with_name("usage")
+                .long("usage")
+                .conflicts_with_all(&["world", "upper"])
+                .help("Compare requests with live usage from the metrics api of the region"))
             .arg(Arg::with_name("squads")
                 .long("squads")
                 .conflicts_with("tribes")
@@ -460,6 +586,14 @@ async fn run(args: &ArgMatches<'static>) -> Result<()> {
         .init()
         .unwrap();
     shipcat::init()?;
+    // long running commands always read secrets from vault
+    let daemon = args.subcommand_matches("operator").is_some()
+        || args
+            .subcommand_matches("cluster")
+            .map_or(false, |a| a.subcommand_matches("drift").is_some());
+    if !daemon && !args.is_present("no-cache") {
+        shipcat_definitions::vault::enable_cache();
+    }
 
     // Ignore SIGPIPE errors to avoid having to use let _ = write! everywhere
     // See https://github.com/rust-lang/rust/issues/46016
@@ -474,7 +608,8 @@ async fn run(args: &ArgMatches<'static>) -> Result<()> {
 /// Create a config for a region
 ///
 /// Resolves an optional "region" Arg or falls back to kube context.
-/// This is the ONLY user of kubectl::current_context for sanity.
+/// Besides promote, which is given both of its regions, this is the ONLY user of
+/// kubectl::current_context for sanity.
 /// If the CLI entrypoint does not need a region-wide config, do not use this.
 async fn resolve_config(args: &ArgMatches<'_>, ct: ConfigState) -> Result<(Config, Region)> {
     let regionguess = if let Some(r) = args.value_of("region") {
@@ -535,6 +670,16 @@ async fn resolve_config(args: &ArgMatches<'_>, ct: ConfigState) -> Result<(Confi
 
 fn void<T>(_x: T) {} // helper so that dispatch_commands can return Result<()>
 
+/// Output format from the global --output flag
+///
+/// Falls back to the format the subcommand printed before the flag existed.
+fn output_format(args: &ArgMatches<'_>, legacy: OutputFormat) -> Result<OutputFormat> {
+    match args.value_of("output") {
+        Some(o) => OutputFormat::from_str(o),
+        None => Ok(legacy),
+    }
+}
+
 /// Dispatch clap arguments to shipcat handlers
 ///
 /// A boring and somewhat error-prone "if-x-then-fnx dance". We are relying on types
@@ -542,15 +687,19 @@ fn void<T>(_x: T) {} // helper so that dispatch_commands can return Result<()>
 #[allow(clippy::cognitive_complexity)] // clap 3 will have typed subcmds..
 async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
     // listers first
-    if let Some(_a) = args.subcommand_matches("list-regions") {
+    if let Some(a) = args.subcommand_matches("list-regions") {
         let rawconf = Config::read().await?;
-        return shipcat::list::regions(&rawconf);
-    } else if args.subcommand_matches("list-locations").is_some() {
+        return shipcat::list::regions(&rawconf, output_format(a, OutputFormat::Table)?);
+    } else if let Some(a) = args.subcommand_matches("list-locations") {
         let rawconf = Config::read().await?;
-        return shipcat::list::locations(&rawconf);
+        return shipcat::list::locations(&rawconf, output_format(a, OutputFormat::Table)?);
     } else if let Some(a) = args.subcommand_matches("list-services") {
+        let fmt = output_format(a, OutputFormat::Table)?;
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
-        return shipcat::list::services(&conf, &region).await;
+        return shipcat::list::services(&conf, &region, fmt).await;
+    } else if let Some(a) = args.subcommand_matches("schema") {
+        let fmt = output_format(a, OutputFormat::Json)?;
+        return shipcat::show::schema(a.value_of("kind").unwrap(), fmt);
     } else if let Some(a) = args.subcommand_matches("login") {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return shipcat::auth::login(&conf, &region, a.is_present("force")).await;
@@ -564,46 +713,58 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
     }
     // getters
     else if let Some(a) = args.subcommand_matches("get") {
+        // getters print json unless told otherwise
+        let fmt = match a.subcommand() {
+            (_, Some(b)) => output_format(b, OutputFormat::Json)?,
+            _ => output_format(a, OutputFormat::Json)?,
+        };
         if let Some(_) = a.subcommand_matches("clusterinfo") {
             let rawconf = Config::read().await?;
             assert!(a.is_present("region"), "explicit context needed for clusterinfo");
-            return shipcat::get::clusterinfo(&rawconf, a.value_of("region").unwrap(), a.value_of("cluster"))
-                .map(void);
+            return shipcat::get::clusterinfo(
+                &rawconf,
+                a.value_of("region").unwrap(),
+                a.value_of("cluster"),
+                fmt,
+            )
+            .map(void);
         }
 
         // resolve region from kube context here if unspecified
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         if let Some(_) = a.subcommand_matches("versions") {
-            return shipcat::get::versions(&conf, &region).await.map(void);
+            return shipcat::get::versions(&conf, &region, fmt).await.map(void);
         }
         if let Some(_) = a.subcommand_matches("vault-url") {
-            return shipcat::get::vault_url(&region).map(void);
+            return shipcat::get::vault_url(&region, fmt).map(void);
         }
         if let Some(_) = a.subcommand_matches("images") {
-            return shipcat::get::images(&conf, &region).await.map(void);
+            return shipcat::get::images(&conf, &region, fmt).await.map(void);
         }
         if let Some(_) = a.subcommand_matches("codeowners") {
-            return shipcat::get::codeowners(&conf).await.map(void);
+            return shipcat::get::codeowners(&conf, fmt).await.map(void);
         }
         if let Some(b) = a.subcommand_matches("vault-policy") {
             let team = b.value_of("team").unwrap(); // required param
-            return shipcat::get::vaultpolicy(&conf, &region, team).await.map(void);
+            return shipcat::get::vaultpolicy(&conf, &region, team, fmt)
+                .await
+                .map(void);
         }
         if let Some(_) = a.subcommand_matches("apistatus") {
-            return shipcat::get::apistatus(&conf, &region).await;
+            return shipcat::get::apistatus(&conf, &region, fmt).await;
         }
         if let Some(_) = a.subcommand_matches("eventstreams") {
-            return shipcat::get::eventstreams(&conf, &region).await;
+            return shipcat::get::eventstreams(&conf, &region, fmt).await;
         }
         if let Some(_) = a.subcommand_matches("kafkausers") {
-            return shipcat::get::kafkausers(&conf, &region).await;
+            return shipcat::get::kafkausers(&conf, &region, fmt).await;
         }
         if let Some(_) = a.subcommand_matches("kafkatopics") {
-            return shipcat::get::kafkatopics(&conf, &region).await;
+            return shipcat::get::kafkatopics(&conf, &region, fmt).await;
         }
     } else if let Some(a) = args.subcommand_matches("top") {
         let sort = top::ResourceOrder::from_str(a.value_of("sort").unwrap())?;
-        let fmt = top::OutputFormat::from_str(a.value_of("output").unwrap())?;
+        let fmt = output_format(a, OutputFormat::Table)?;
         let ub = a.is_present("upper");
         return if a.is_present("world") {
             let rawconf = Config::read().await?;
@@ -620,6 +781,21 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                     .await
                     .map(void)
             }
+        } else if a.is_present("usage") {
+            let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+            if a.is_present("squads") {
+                shipcat::top::region_team_usage("squad", sort, fmt, &conf, &region)
+                    .await
+                    .map(void)
+            } else if a.is_present("tribes") {
+                shipcat::top::region_team_usage("tribe", sort, fmt, &conf, &region)
+                    .await
+                    .map(void)
+            } else {
+                shipcat::top::region_usage(sort, fmt, &conf, &region)
+                    .await
+                    .map(void)
+            }
         } else {
             let (conf, region) = resolve_config(a, ConfigState::Base).await?;
             if a.is_present("squads") {
@@ -676,25 +852,59 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 shipcat::validate::secret_presence_full(&rawconf, regions).await
             };
         }
+        if let Some(b) = a.subcommand_matches("rotate") {
+            let key = b.value_of("key").unwrap();
+            let dry = b.is_present("dry-run");
+            let (conf, region) = resolve_config(b, ConfigState::Base).await?;
+            let wait = !b.is_present("no-wait");
+            let freeze = b.value_of("freeze-override");
+            return shipcat::secret::rotate(key, &conf, &region, dry, wait, freeze).await;
+        }
+        if let Some(b) = a.subcommand_matches("seal") {
+            use std::io::Read;
+            let key = b.value_of("key").unwrap();
+            let (_conf, region) = resolve_config(b, ConfigState::Base).await?;
+            let mut value = String::new();
+            std::io::stdin().read_to_string(&mut value)?;
+            return shipcat::secret::seal(key, value.trim_end_matches('\n'), &region);
+        }
     }
     // ------------------------------------------------------------------------------
     // important dev commands below - they resolve kube context as a fallback
     // otherwise region can be passed in as args
     else if let Some(a) = args.subcommand_matches("status") {
         let svc = a.value_of("service").map(String::from).unwrap();
+        let fmt = output_format(a, OutputFormat::Table)?;
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
-        return shipcat::status::show(&svc, &conf, &region).await;
+        return shipcat::status::show(&svc, &conf, &region, fmt).await;
     } else if let Some(a) = args.subcommand_matches("graph") {
-        let dot = a.is_present("dot");
+        if let Some(b) = a.subcommand_matches("impact") {
+            let svc = b.value_of("service").unwrap();
+            let fmt = output_format(b, OutputFormat::Table)?;
+            let (conf, region) = resolve_config(b, ConfigState::Base).await?;
+            return shipcat::graph::impact(svc, &conf, &region, fmt).await.map(void);
+        }
+        let export = if a.is_present("dot") {
+            Some(shipcat::graph::GraphFormat::Dot)
+        } else {
+            a.value_of("export").map(str::parse).transpose()?
+        };
+        if export.is_some() && a.is_present("output") {
+            let reason = "--export and --dot print their own format, and cannot be combined with --output";
+            return Err(reason.into());
+        }
+        let fmt = output_format(a, OutputFormat::Yaml)?;
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return if let Some(svc) = a.value_of("service") {
             if a.is_present("reverse") {
-                shipcat::graph::reverse(svc, &conf, &region).await.map(void)
+                shipcat::graph::reverse(svc, &conf, &region, fmt).await.map(void)
             } else {
-                shipcat::graph::generate(svc, &conf, &region, dot).await.map(void)
+                shipcat::graph::generate(svc, &conf, &region, export, fmt)
+                    .await
+                    .map(void)
             }
         } else {
-            shipcat::graph::full(dot, &conf, &region).await.map(void)
+            shipcat::graph::full(export, fmt, &conf, &region).await.map(void)
         };
     } else if let Some(a) = args.subcommand_matches("validate") {
         let services = a
@@ -708,8 +918,17 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         } else {
             ConfigState::Base
         };
+        let fmt = output_format(a, OutputFormat::Table)?;
         let (conf, region) = resolve_config(a, ss).await?;
-        return shipcat::validate::manifest(services, &conf, &region, a.is_present("secrets")).await;
+        return shipcat::validate::manifest(
+            services,
+            &conf,
+            &region,
+            a.is_present("secrets"),
+            a.is_present("images"),
+            fmt,
+        )
+        .await;
     } else if let Some(a) = args.subcommand_matches("verify") {
         return if a.value_of("region").is_some() {
             let (conf, region) = resolve_config(a, ConfigState::Base).await?;
@@ -717,6 +936,11 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         } else {
             shipcat::validate::all_manifests().await
         };
+    } else if let Some(a) = args.subcommand_matches("explain") {
+        let svc = a.value_of("service").unwrap();
+        let fmt = output_format(a, OutputFormat::Table)?;
+        let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        return shipcat::show::explain(svc, a.value_of("key"), &conf, &region, fmt).await;
     } else if let Some(a) = args.subcommand_matches("values") {
         let svc = a.value_of("service").map(String::from).unwrap();
 
@@ -805,7 +1029,7 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             if a.is_present("git") {
                 shipcat::diff::values_vs_git(&svc, &conf, &region).await?
             } else {
-                shipcat::diff::values_vs_kubectl(&svc, &conf, &region).await?
+                shipcat::diff::values_vs_crd(&svc, &conf, &region).await?
             }
         } else if a.is_present("git") {
             // special - serial git diff
@@ -849,19 +1073,14 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 mf.uid = Some("FAKE-GUID".to_string());
                 mf.version = mf.version.or(Some("latest".to_string()));
             }
-            let diff = shipcat::diff::template_vs_kubectl(&mf).await?;
-            if let Some(mut out) = diff {
-                if a.is_present("obfuscate") {
-                    out = shipcat::diff::obfuscate_secrets(out, mf.get_secrets())
-                };
-                if a.is_present("minify") {
-                    out = shipcat::diff::minify(&out)
-                };
-                println!("{}", out);
-                false
-            } else {
-                true
-            }
+            let diff = shipcat::diff::template_vs_cluster(&mf).await?;
+            output_format(a, OutputFormat::Table)?.print(&diff, |d| {
+                if !d.is_empty() {
+                    println!("{}", d);
+                }
+                Ok(())
+            })?;
+            diff.is_empty()
         };
         process::exit(if diff_exit { 0 } else { 1 });
     } else if let Some(a) = args.subcommand_matches("kong") {
@@ -890,7 +1109,32 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let force = a.is_present("force");
         let ver = a.value_of("tag").map(String::from); // needed for some subcommands
         assert!(conf.has_secrets()); // sanity on cluster disruptive commands
-        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver)
+        let freeze = a.value_of("freeze-override");
+        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver, freeze)
+            .await
+            .map(void);
+    } else if let Some(a) = args.subcommand_matches("promote") {
+        let svc = a.value_of("service").unwrap();
+        let from = Config::new(ConfigState::Base, a.value_of("from").unwrap()).await?;
+        // applying absolutely needs secrets..
+        let state = if a.is_present("apply") {
+            ConfigState::Filtered
+        } else {
+            ConfigState::Base
+        };
+        let (conf, region) = Config::new(state, a.value_of("to").unwrap()).await?;
+        if a.is_present("apply") {
+            shipcat::promote::verify_apply_context(&conf, &region).await?;
+            // refuse before pinning a version that cannot be applied
+            shipcat::apply::check_freeze(&region, a.value_of("freeze-override"))?;
+        }
+        let version = shipcat::promote::promote(svc, (&from.0, &from.1), (&conf, &region)).await?;
+        if !a.is_present("apply") {
+            return Ok(());
+        }
+        let wait = !a.is_present("no-wait");
+        let freeze = a.value_of("freeze-override");
+        return shipcat::apply::apply(svc.to_string(), false, &region, &conf, wait, Some(version), freeze)
             .await
             .map(void);
     } else if let Some(a) = args.subcommand_matches("restart") {
@@ -898,14 +1142,19 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         let mf = shipcat_filebacked::load_manifest(&svc, &conf, &region).await?;
         let wait = !a.is_present("no-wait");
-        return shipcat::apply::restart(&mf, wait).await.map(void);
+        let freeze = a.value_of("freeze-override");
+        return shipcat::apply::restart(&mf, &region, &conf, wait, freeze).await.map(void);
     } else if let Some(a) = args.subcommand_matches("delete") {
         let svc = a.value_of("service").map(String::from).unwrap();
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return shipcat::apply::delete(&svc, &region, &conf).await.map(void);
     }
     // 4. cluster level commands
-    else if let Some(a) = args.subcommand_matches("cluster") {
+    else if let Some(a) = args.subcommand_matches("operator") {
+        let (conf, region) = resolve_config(a, ConfigState::Filtered).await?;
+        let jobs = a.value_of("num-jobs").unwrap_or("8").parse().unwrap();
+        return shipcat::operator::run(&conf, &region, jobs).await;
+    } else if let Some(a) = args.subcommand_matches("cluster") {
         if let Some(b) = a.subcommand_matches("crd") {
             // This reconcile is special. It needs two config types:
             // - Base (without secrets) for putting config crd in cluster
@@ -914,16 +1163,25 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             let (conf_base, region_base) = resolve_config(args, ConfigState::Base).await?;
             let jobs = b.value_of("num-jobs").unwrap_or("8").parse().unwrap();
             if let Some(_) = b.subcommand_matches("install") {
-                return shipcat::cluster::crd_install(&region_base).await;
+                return shipcat::cluster::crd_install(&region_base, &conf_base).await;
             }
-            if let Some(_) = b.subcommand_matches("reconcile") {
-                return shipcat::cluster::mass_crd(&conf_sec, &conf_base, &region_base, jobs).await;
+            if let Some(c) = b.subcommand_matches("reconcile") {
+                let freeze = c.value_of("freeze-override");
+                return shipcat::cluster::mass_crd(&conf_sec, &conf_base, &region_base, jobs, freeze).await;
             }
         }
         if let Some(_b) = a.subcommand_matches("diff") {
             let (conf, region) = resolve_config(args, ConfigState::Filtered).await?;
             return shipcat::cluster::mass_diff(&conf, &region).await;
         }
+        if let Some(b) = a.subcommand_matches("drift") {
+            let (_conf, region) = resolve_config(args, ConfigState::Filtered).await?;
+            let interval = b.value_of("interval").unwrap().parse()?;
+            let port: u16 = b.value_of("port").unwrap().parse()?;
+            let jobs = b.value_of("num-jobs").unwrap_or("8").parse().unwrap();
+            let addr = ([0, 0, 0, 0], port).into();
+            return shipcat::drift::run(&region, interval, jobs, addr).await;
+        }
         if let Some(b) = a.subcommand_matches("check") {
             let (conf, region) = resolve_config(args, ConfigState::Base).await?;
             let skipped = b
@@ -964,10 +1222,14 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         return shipcat::kubectl::shell(&mf, cmd).await;
     } else if let Some(a) = args.subcommand_matches("version") {
         let svc = a.value_of("service").map(String::from).unwrap();
+        let fmt = output_format(a, OutputFormat::Table)?;
         let (_conf, region) = resolve_config(a, ConfigState::Base).await?;
         let res = shipcat::kubectl::get_running_version(&svc, &region.namespace).await?;
-        println!("{}", res);
-        return Ok(());
+        let data = serde_json::json!({ "service": svc, "version": res });
+        return fmt.print(&data, |_| {
+            println!("{}", res);
+            Ok(())
+        });
     } else if let Some(a) = args.subcommand_matches("port-forward") {
         let (conf, region) = resolve_config(args, ConfigState::Base).await?;
         let service = a.value_of("service").unwrap();
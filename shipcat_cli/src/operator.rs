@@ -0,0 +1,186 @@
+use futures::{stream, StreamExt};
+use futures_timer::Delay;
+use kube::{
+    api::{ListParams, Meta, Resource, WatchEvent},
+    runtime::Informer,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use shipcat_definitions::{manifest::ShipcatManifest, status::Applier, ReconciliationMode};
+
+use super::{
+    apply,
+    kubeapi::{self, ShipKube},
+    Config, ErrorKind, Region, Result,
+};
+
+/// Services currently being rolled out
+type Active = Arc<Mutex<HashSet<String>>>;
+
+/// Services with changes refused by a freeze window
+type Frozen = Arc<Mutex<HashSet<String>>>;
+
+/// How often to check whether a freeze window has ended
+const FREEZE_RETRY: Duration = Duration::from_secs(60);
+
+/// Whether the spec of a crd has changed since the operator last reconciled it
+fn outdated(crd: &ShipcatManifest) -> bool {
+    let observed = crd.status.as_ref().and_then(|s| s.observed_generation);
+    crd.metadata.generation != observed
+}
+
+/// Continuously reconcile shipcatmanifest crds in a region
+///
+/// Watches the shipcatmanifests in the region's namespace, and rolls out every crd whose
+/// `.metadata.generation` differs from the `.status.observedGeneration` it last recorded.
+/// Existing crds are checked on startup, so restarting the operator is safe.
+/// Changes refused by a freeze window are retried once the window has ended.
+///
+/// Requires the `Operator` reconciliationMode, so that `shipcat apply` only updates crds.
+pub async fn run(conf: &Config, reg: &Region, n_workers: usize) -> Result<()> {
+    if reg.reconciliationMode != ReconciliationMode::Operator {
+        bail!("{} does not use the Operator reconciliationMode", reg.name);
+    }
+    let client = kubeapi::make_client().await?;
+    let resource = Resource::namespaced::<ShipcatManifest>(&reg.namespace);
+    let informer: Informer<ShipcatManifest> = Informer::new(client, ListParams::default(), resource);
+    let active: Active = Arc::new(Mutex::new(HashSet::new()));
+    let frozen: Frozen = Arc::new(Mutex::new(HashSet::new()));
+    info!(
+        "Watching shipcatmanifests in {} with {} workers",
+        reg.name, n_workers
+    );
+
+    let watch = async {
+        loop {
+            let events = match informer.poll().await {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Failed to watch shipcatmanifests: {}", e);
+                    Delay::new(Duration::from_secs(10)).await;
+                    continue;
+                }
+            };
+            events
+                .for_each_concurrent(n_workers, |ev| {
+                    let (active, frozen) = (active.clone(), frozen.clone());
+                    async move {
+                        match ev {
+                            Ok(WatchEvent::Added(o)) | Ok(WatchEvent::Modified(o)) => {
+                                if outdated(&o) {
+                                    reconcile(Meta::name(&o), active, frozen, conf, reg).await
+                                }
+                            }
+                            Ok(WatchEvent::Deleted(o)) => {
+                                // kube garbage collects the objects through their ownerReferences
+                                info!("{} was deleted", Meta::name(&o));
+                            }
+                            Ok(WatchEvent::Error(e)) => warn!("Watch error: {:?}", e),
+                            Err(e) => warn!("Watch error: {}", ErrorKind::KubeError(e)),
+                        }
+                    }
+                })
+                .await;
+        }
+    };
+    let requeue = requeue_frozen(active.clone(), frozen.clone(), conf, reg, n_workers);
+    futures::future::join(watch, requeue).await;
+    Ok(())
+}
+
+/// Reconcile the services refused by a freeze window once it has ended
+///
+/// The informer only sees new events, so nothing else would pick these up again.
+async fn requeue_frozen(active: Active, frozen: Frozen, conf: &Config, reg: &Region, n_workers: usize) {
+    loop {
+        Delay::new(FREEZE_RETRY).await;
+        if frozen.lock().unwrap().is_empty() || apply::check_freeze(reg, None).is_err() {
+            continue;
+        }
+        let names = frozen.lock().unwrap().drain().collect::<Vec<_>>();
+        info!("Freeze ended, reconciling {}", names.join(", "));
+        stream::iter(names)
+            .for_each_concurrent(n_workers, |name| {
+                reconcile(name, active.clone(), frozen.clone(), conf, reg)
+            })
+            .await;
+    }
+}
+
+/// Reconcile a service unless it is already being reconciled
+///
+/// The active reconcile picks up any newer generation when it is done,
+/// and services refused by a freeze window are recorded in `frozen`.
+async fn reconcile(name: String, active: Active, frozen: Frozen, conf: &Config, reg: &Region) {
+    if !active.lock().unwrap().insert(name.clone()) {
+        debug!("{} is already being reconciled", name);
+        return;
+    }
+    if let Err(e) = reconcile_latest(&name, conf, reg).await {
+        if let ErrorKind::RegionFrozen(..) = e.kind() {
+            info!("Deferring {} until the freeze ends: {}", name, e);
+            frozen.lock().unwrap().insert(name.clone());
+        } else {
+            warn!("Failed to reconcile {}: {}", name, e);
+        }
+    }
+    active.lock().unwrap().remove(&name);
+}
+
+/// Roll out a crd until its latest generation has been observed
+///
+/// Failed rollouts are also observed; their conditions and webhooks report the failure,
+/// and the next change to the crd triggers a new attempt.
+/// Generations refused by a freeze window are left unobserved, and returned as an error.
+async fn reconcile_latest(name: &str, conf: &Config, reg: &Region) -> Result<()> {
+    let mut s = ShipKube::new_within(name, &reg.namespace).await?;
+    s.applier = Applier {
+        name: "shipcat operator".into(),
+        url: None,
+    };
+    loop {
+        let crd = s.get().await?;
+        if !outdated(&crd) {
+            return Ok(());
+        }
+        let generation = crd.metadata.generation;
+        info!("Reconciling {} at generation {:?}", name, generation);
+        match apply::apply_crd(crd, &s, reg, conf).await {
+            Ok(Some(ui)) => info!("Rolled out {}={}", ui.name, ui.version),
+            Ok(None) => info!("{} up to date", name),
+            Err(e) => {
+                if let ErrorKind::RegionFrozen(..) = e.kind() {
+                    return Err(e);
+                }
+                warn!("Failed to roll out {}: {}", name, e);
+            }
+        }
+        // rollbacks change the spec, but that generation is already rolled out
+        let observed = std::cmp::max(generation, s.produced_generation());
+        s.update_observed_generation(observed).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::outdated;
+    use shipcat_definitions::{manifest::ShipcatManifest, status::ManifestStatus, Manifest};
+
+    #[test]
+    fn operator_outdated() {
+        let mut crd = ShipcatManifest::new("fake-ask", Manifest::default());
+        crd.metadata.generation = Some(2);
+        assert!(outdated(&crd));
+        crd.status = Some(ManifestStatus {
+            observed_generation: Some(1),
+            ..Default::default()
+        });
+        assert!(outdated(&crd));
+        crd.status.as_mut().unwrap().observed_generation = Some(2);
+        assert!(!outdated(&crd));
+    }
+}
@@ -0,0 +1,74 @@
+use serde::Serialize;
+use std::str::FromStr;
+
+use super::{Error, Result};
+
+/// How read-only subcommands print their results
+///
+/// Json and yaml serialize the data returned by the subcommand, and are meant for scripts.
+/// Tables are meant for humans, and may change.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    /// Human readable output, often a table
+    Table,
+    /// Pretty printed json
+    Json,
+    /// Yaml with raw values
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            _ => bail!("Output format must be table, json or yaml"),
+        }
+    }
+}
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self::Table
+    }
+}
+
+impl OutputFormat {
+    /// Print data in this format, using `table` for the human readable form
+    pub fn print<T, F>(self, data: &T, table: F) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+        F: FnOnce(&T) -> Result<()>,
+    {
+        match self {
+            OutputFormat::Table => table(data)?,
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(data)?),
+            OutputFormat::Yaml => println!("{}", serde_yaml::to_string(data)?),
+        }
+        Ok(())
+    }
+
+    /// Print data that has no tabular form
+    ///
+    /// Tables fall back to yaml, which is the most readable for nested data.
+    pub fn print_data<T: Serialize + ?Sized>(self, data: &T) -> Result<()> {
+        let fmt = if self == OutputFormat::Table {
+            OutputFormat::Yaml
+        } else {
+            self
+        };
+        fmt.print(data, |_| Ok(()))
+    }
+
+    /// Print a list of strings, one per line for humans
+    pub fn print_lines(self, data: &[String]) -> Result<()> {
+        self.print(data, |lines| {
+            for l in lines {
+                println!("{}", l);
+            }
+            Ok(())
+        })
+    }
+}
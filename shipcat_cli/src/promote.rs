@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use super::{
+    kubeapi::{self, ShipKube},
+    kubectl, Config, Region, Result,
+};
+
+/// Whether the current kube context belongs to a region
+async fn in_current_context(conf: &Config, region: &Region) -> bool {
+    let current = kubectl::current_context().await.ok();
+    match current.and_then(|c| conf.get_region(&c).ok()) {
+        Some(r) => r.name == region.name,
+        None => false,
+    }
+}
+
+/// Bail unless applying to a region would reach it
+///
+/// Regions with `activeClusters` are applied to through the kube contexts of their clusters,
+/// other regions through the current context, which therefore has to belong to the region.
+pub async fn verify_apply_context(conf: &Config, region: &Region) -> Result<()> {
+    if region.activeClusters.is_empty() && !in_current_context(conf, region).await {
+        bail!(
+            "Current kube context does not belong to {} - switch to it before applying",
+            region.name
+        );
+    }
+    Ok(())
+}
+
+/// Version of a service that last rolled out successfully in a region
+///
+/// Read from the shipcatmanifest crd in the kube context of every cluster of the region,
+/// which all have to agree. Without `activeClusters`, the current context is used if it
+/// belongs to the region, otherwise the context of the region's cluster.
+pub async fn rolled_out_version(svc: &str, conf: &Config, region: &Region) -> Result<String> {
+    let contexts = if !region.activeClusters.is_empty() {
+        kubeapi::region_contexts(conf, region)?
+    } else if in_current_context(conf, region).await {
+        vec![None]
+    } else {
+        vec![Some(kubeapi::cluster_context(conf, &region.cluster, region)?)]
+    };
+    let mut versions = vec![];
+    for ctx in contexts {
+        let crd = ShipKube::new_in_context(svc, &region.namespace, ctx.as_deref())
+            .await?
+            .get()
+            .await?;
+        let summary = crd.status.and_then(|s| s.summary);
+        match summary.and_then(|s| s.last_successful_rollout_version) {
+            Some(v) => versions.push((ctx, v)),
+            None => bail!("{} has not rolled out successfully in {}", svc, region.name),
+        }
+    }
+    let first = versions[0].1.clone();
+    if versions.iter().any(|(_, v)| *v != first) {
+        let found = versions
+            .iter()
+            .map(|(ctx, v)| format!("{}={}", ctx.as_deref().unwrap_or(&region.name), v))
+            .collect::<Vec<_>>();
+        bail!(
+            "{} rolled out different versions in the clusters of {}: {}",
+            svc,
+            region.name,
+            found.join(", ")
+        );
+    }
+    Ok(first)
+}
+
+/// Quote a version if yaml would otherwise not read it as a string
+fn yaml_version(version: &str) -> String {
+    match serde_yaml::from_str::<serde_yaml::Value>(version) {
+        Ok(serde_yaml::Value::String(_)) => version.to_string(),
+        _ => format!("\"{}\"", version),
+    }
+}
+
+/// Set the top level version in a yaml document, keeping everything else as is
+fn set_version(yaml: &str, version: &str) -> String {
+    let line = format!("version: {}", yaml_version(version));
+    let mut found = false;
+    let mut lines = yaml
+        .lines()
+        .map(|l| {
+            if !found && l.starts_with("version:") {
+                found = true;
+                line.clone()
+            } else {
+                l.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    if !found {
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Pin a version in the override file of a region
+///
+/// Region overrides are merged last, so this takes precedence over any other pin.
+pub async fn write_version(svc: &str, region: &Region, version: &str) -> Result<PathBuf> {
+    let pth = Path::new(".")
+        .join("services")
+        .join(svc)
+        .join(format!("{}.yml", region.name));
+    let data = if pth.is_file() {
+        fs::read_to_string(&pth).await?
+    } else {
+        String::new()
+    };
+    fs::write(&pth, set_version(&data, version)).await?;
+    Ok(pth)
+}
+
+/// Promote the version of a service that rolled out in one region to another
+///
+/// The version is validated against the versioning scheme of the destination,
+/// and pinned in the region override file of the destination. Returns the version.
+pub async fn promote(svc: &str, from: (&Config, &Region), to: (&Config, &Region)) -> Result<String> {
+    let (conf, region) = to;
+    let mf = shipcat_filebacked::load_manifest(svc, conf, region).await?;
+    if !mf.regions.contains(&region.name) {
+        bail!(
+            "Cannot promote '{}' to {} as it is not deployed there",
+            svc,
+            region.name
+        );
+    }
+    let version = rolled_out_version(svc, from.0, from.1).await?;
+    region.versioningScheme.verify(&version)?;
+    if mf.version.as_ref() == Some(&version) {
+        info!("{} is already at {} in {}", svc, version, region.name);
+        return Ok(version);
+    }
+    let pth = write_version(svc, region, &version).await?;
+    info!(
+        "Promoted {} from {} to {}: {} in {}",
+        svc,
+        from.1.name,
+        region.name,
+        version,
+        pth.display()
+    );
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::set_version;
+
+    #[test]
+    fn promote_set_version() {
+        let yaml = "# pinned by promote\nversion: 1.5.0\nenv:\n  version: keep\n";
+        assert_eq!(
+            set_version(yaml, "1.6.0"),
+            "# pinned by promote\nversion: 1.6.0\nenv:\n  version: keep\n"
+        );
+        assert_eq!(
+            set_version("env:\n  A: b", "1.6.0"),
+            "env:\n  A: b\nversion: 1.6.0\n"
+        );
+        assert_eq!(set_version("", "1234567"), "version: \"1234567\"\n");
+    }
+}
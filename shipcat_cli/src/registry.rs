@@ -0,0 +1,564 @@
+//! Docker Registry v2 api client for verifying images
+use reqwest::{header, Client, Method, RequestBuilder, Response, StatusCode};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt};
+
+use super::{ErrorKind, Manifest, Region, Result};
+
+/// Manifest media types accepted when resolving a tag
+const MANIFEST_TYPES: &str = "application/vnd.docker.distribution.manifest.list.v2+json, \
+                              application/vnd.docker.distribution.manifest.v2+json, \
+                              application/vnd.oci.image.index.v1+json, \
+                              application/vnd.oci.image.manifest.v1+json";
+
+/// Registry used for images without a registry hostname
+const DOCKER_HUB: &str = "registry-1.docker.io";
+
+/// A tag of an image in a docker registry
+#[derive(Debug, PartialEq)]
+pub struct ImageRef {
+    /// Hostname of the registry, with an optional port
+    pub registry: String,
+    /// Repository within the registry
+    pub repository: String,
+    /// Tag to resolve
+    pub tag: String,
+}
+
+impl ImageRef {
+    /// Split an image name into its registry and repository the way docker does
+    pub fn new(image: &str, tag: &str) -> Self {
+        let (registry, repository) = match image.find('/') {
+            Some(i) if is_hostname(&image[..i]) => (image[..i].to_string(), image[i + 1..].to_string()),
+            Some(_) => (DOCKER_HUB.to_string(), image.to_string()),
+            None => (DOCKER_HUB.to_string(), format!("library/{}", image)),
+        };
+        ImageRef {
+            registry,
+            repository,
+            tag: tag.to_string(),
+        }
+    }
+
+    /// Base url of the repository in the v2 api
+    fn repository_url(&self) -> String {
+        let scheme = if self.registry.starts_with("localhost") {
+            "http"
+        } else {
+            "https"
+        };
+        format!("{}://{}/v2/{}", scheme, self.registry, self.repository)
+    }
+}
+
+impl fmt::Display for ImageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}:{}", self.registry, self.repository, self.tag)
+    }
+}
+
+/// Whether the first component of an image name is a registry hostname
+fn is_hostname(component: &str) -> bool {
+    component.contains('.') || component.contains(':') || component == "localhost"
+}
+
+/// Basic auth credentials for a registry from a docker config
+fn credentials(docker_config: &Value, registry: &str) -> Option<(String, String)> {
+    let auths = docker_config.get("auths")?;
+    let key = if registry == DOCKER_HUB {
+        "https://index.docker.io/v1/".to_string()
+    } else {
+        registry.to_string()
+    };
+    let auth = auths
+        .get(&key)
+        .or_else(|| auths.get(format!("https://{}", registry)))?
+        .get("auth")?
+        .as_str()?;
+    let decoded = String::from_utf8(base64::decode(auth).ok()?).ok()?;
+    let mut split = decoded.splitn(2, ':');
+    Some((split.next()?.to_string(), split.next()?.to_string()))
+}
+
+/// Basic auth credentials for a registry from `~/.docker/config.json`
+fn docker_credentials(registry: &str) -> Option<(String, String)> {
+    let pth = dirs::home_dir()?.join(".docker").join("config.json");
+    let data = std::fs::read_to_string(pth).ok()?;
+    credentials(&serde_json::from_str(&data).ok()?, registry)
+}
+
+/// Parse the scheme and parameters of a `WWW-Authenticate` challenge
+///
+/// E.g. `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`
+fn parse_challenge(challenge: &str) -> Option<(String, BTreeMap<String, String>)> {
+    let mut split = challenge.trim().splitn(2, ' ');
+    let scheme = split.next()?.to_lowercase();
+    let mut params = BTreeMap::new();
+    let mut pairs = vec![String::new()];
+    let mut quoted = false;
+    // commas separate parameters, except inside quotes (scopes can contain them)
+    for c in split.next().unwrap_or_default().chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => pairs.push(String::new()),
+            _ => pairs.last_mut()?.push(c),
+        }
+    }
+    for pair in pairs {
+        let mut kv = pair.splitn(2, '=');
+        if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+            params.insert(k.trim().to_lowercase(), v.trim().to_string());
+        }
+    }
+    Some((scheme, params))
+}
+
+/// Fetch a pull token from the realm of a bearer challenge
+async fn bearer_token(
+    client: &Client,
+    params: &BTreeMap<String, String>,
+    img: &ImageRef,
+    creds: Option<&(String, String)>,
+) -> Result<String> {
+    let realm = match params.get("realm") {
+        Some(r) => r,
+        None => bail!("{} sent a bearer challenge without a realm", img.registry),
+    };
+    let mut query = vec![];
+    if let Some(s) = params.get("service") {
+        query.push(("service", s.clone()));
+    }
+    let scope = params
+        .get("scope")
+        .cloned()
+        .unwrap_or_else(|| format!("repository:{}:pull", img.repository));
+    query.push(("scope", scope));
+    let mut req = client.get(realm).query(&query);
+    if let Some((user, pass)) = creds {
+        req = req.basic_auth(user, Some(pass));
+    }
+    let res = req.send().await?;
+    if !res.status().is_success() {
+        bail!("Failed to authenticate with {}: {}", img.registry, res.status())
+    }
+    let body: Value = serde_json::from_str(&res.text().await?)?;
+    match body.get("token").or_else(|| body.get("access_token")) {
+        Some(Value::String(t)) => Ok(t.clone()),
+        _ => bail!("{} did not return a token", realm),
+    }
+}
+
+/// Authorization for a registry that asked for it
+enum Auth {
+    Bearer(String),
+    Basic(String, String),
+}
+
+/// A session with the repository of an image
+///
+/// Authenticates with credentials from the docker config if the registry asks for them.
+struct Repository<'a> {
+    client: Client,
+    img: &'a ImageRef,
+    auth: Option<Auth>,
+}
+
+impl<'a> Repository<'a> {
+    fn new(img: &'a ImageRef) -> Result<Self> {
+        let client = Client::builder().user_agent("rust-reqwest/shipcat").build()?;
+        Ok(Repository {
+            client,
+            img,
+            auth: None,
+        })
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let req = self
+            .client
+            .request(method, url)
+            .header(header::ACCEPT, MANIFEST_TYPES);
+        match &self.auth {
+            Some(Auth::Bearer(token)) => req.bearer_auth(token),
+            Some(Auth::Basic(user, pass)) => req.basic_auth(user, Some(pass)),
+            None => req,
+        }
+    }
+
+    async fn authenticate(&self, res: &Response) -> Result<Auth> {
+        let creds = docker_credentials(&self.img.registry);
+        let challenge = res
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_challenge);
+        match (challenge, creds) {
+            (Some((scheme, params)), creds) if scheme == "bearer" => Ok(Auth::Bearer(
+                bearer_token(&self.client, &params, self.img, creds.as_ref()).await?,
+            )),
+            (_, Some((user, pass))) => Ok(Auth::Basic(user, pass)),
+            (_, None) => bail!("No credentials for {} in the docker config", self.img.registry),
+        }
+    }
+
+    /// Send a request for a path in the repository, authenticating if asked to
+    async fn send(&mut self, method: Method, path: &str) -> Result<Response> {
+        let url = format!("{}/{}", self.img.repository_url(), path);
+        let res = self.request(method.clone(), &url).send().await?;
+        if res.status() != StatusCode::UNAUTHORIZED || self.auth.is_some() {
+            return Ok(res);
+        }
+        self.auth = Some(self.authenticate(&res).await?);
+        Ok(self.request(method, &url).send().await?)
+    }
+
+    /// Resolve the tag of the image to its digest
+    ///
+    /// Fails with `ImageNotFound` when the tag does not exist.
+    async fn resolve_digest(&mut self) -> Result<String> {
+        debug!("Resolving {}", self.img);
+        let res = self
+            .send(Method::HEAD, &format!("manifests/{}", self.img.tag))
+            .await?;
+        let status = res.status();
+        if status == StatusCode::NOT_FOUND {
+            return Err(ErrorKind::ImageNotFound(self.img.to_string()).into());
+        }
+        if !status.is_success() {
+            bail!("Failed to look up {}: {}", self.img, status);
+        }
+        match res
+            .headers()
+            .get("docker-content-digest")
+            .and_then(|h| h.to_str().ok())
+        {
+            Some(digest) => Ok(digest.to_string()),
+            None => bail!("{} did not return a digest for {}", self.img.registry, self.img),
+        }
+    }
+
+    /// Fetch a manifest or blob that must exist
+    async fn fetch(&mut self, path: &str) -> Result<Vec<u8>> {
+        let res = self.send(Method::GET, path).await?;
+        if !res.status().is_success() {
+            bail!("Failed to fetch {} of {}: {}", path, self.img, res.status());
+        }
+        Ok(res.bytes().await?.to_vec())
+    }
+
+    /// Verify the image digest carries a valid signature from one of the keys
+    ///
+    /// Fails with `UnsignedImage` when no signature verifies.
+    async fn verify_signature(&mut self, digest: &str, keys: &[Vec<u8>]) -> Result<()> {
+        let tag = format!("{}.sig", digest.replace(':', "-"));
+        let res = self.send(Method::GET, &format!("manifests/{}", tag)).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(ErrorKind::UnsignedImage(self.img.to_string()).into());
+        }
+        if !res.status().is_success() {
+            bail!("Failed to look up signatures of {}: {}", self.img, res.status());
+        }
+        let sigs: SignatureManifest = serde_json::from_str(&res.text().await?)?;
+        for layer in sigs.layers {
+            let signature = match layer.annotations.get(SIGNATURE_ANNOTATION) {
+                Some(s) => s,
+                None => continue,
+            };
+            let payload = self.fetch(&format!("blobs/{}", layer.digest)).await?;
+            match verify_payload(&payload, &layer.digest, signature, digest, keys) {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!("Ignoring signature of {}: {}", self.img, e),
+            }
+        }
+        Err(ErrorKind::UnsignedImage(self.img.to_string()).into())
+    }
+}
+
+/// Annotation holding the base64 encoded signature of a cosign payload
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// Manifest of the signatures of an image, as stored by cosign
+#[derive(Deserialize)]
+struct SignatureManifest {
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+/// Signed payload in the red hat simple signing format
+#[derive(Deserialize)]
+struct SimpleSigning {
+    critical: Critical,
+}
+
+#[derive(Deserialize)]
+struct Critical {
+    image: SignedImage,
+}
+
+#[derive(Deserialize)]
+struct SignedImage {
+    #[serde(rename = "docker-manifest-digest")]
+    digest: String,
+}
+
+/// Verify a signed payload covers an image digest, and is signed by one of the keys
+fn verify_payload(
+    payload: &[u8],
+    blob_digest: &str,
+    signature: &str,
+    digest: &str,
+    keys: &[Vec<u8>],
+) -> Result<()> {
+    let hash = ring::digest::digest(&ring::digest::SHA256, payload);
+    if blob_digest != format!("sha256:{}", hex::encode(hash.as_ref())) {
+        bail!("payload does not match its digest {}", blob_digest);
+    }
+    let signed: SimpleSigning = serde_json::from_slice(payload)?;
+    if signed.critical.image.digest != digest {
+        bail!("payload is for {}", signed.critical.image.digest);
+    }
+    let sig = match base64::decode(signature) {
+        Ok(s) => s,
+        Err(_) => bail!("signature is not base64 encoded"),
+    };
+    let trusted = keys.iter().any(|k| {
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, k)
+            .verify(payload, &sig)
+            .is_ok()
+    });
+    if !trusted {
+        bail!("payload is not signed by a trusted key");
+    }
+    Ok(())
+}
+
+/// The image of a manifest at its version
+fn image_ref(mf: &Manifest) -> Result<ImageRef> {
+    match (&mf.image, &mf.version) {
+        (Some(i), Some(v)) => Ok(ImageRef::new(i, v)),
+        _ => bail!("{} needs an image and a version to verify", mf.name),
+    }
+}
+
+/// Images that containers besides the main one run, by container name
+///
+/// Only containers with an image of their own are included, at their version,
+/// or at `latest` like docker does without one, along with any digest pinned for them.
+/// Other containers run the main image, or whatever image the chart gives them.
+fn container_image_refs(mf: &Manifest) -> Vec<(String, ImageRef, Option<String>)> {
+    mf.get_containers()
+        .into_iter()
+        .filter_map(|c| {
+            let image = c.image.as_ref()?;
+            let tag = c.version.as_ref().map_or("latest", String::as_str);
+            Some((c.name.clone(), ImageRef::new(image, tag), c.image_digest.clone()))
+        })
+        .collect()
+}
+
+/// Verify that the image of a manifest exists in its registry
+///
+/// When the region requires signatures, the image must also be signed by a trusted key.
+/// Returns the digest its version resolved to, or the `imageDigest` already pinned.
+pub async fn verify_image(mf: &Manifest, region: &Region) -> Result<String> {
+    verify_image_ref(image_ref(mf)?, mf.imageDigest.as_deref(), region).await
+}
+
+/// Verify the images of the sidecars, workers, cron jobs and init containers of a manifest
+///
+/// Checked like the main image, for containers with an image of their own.
+/// Sidecars without one get their image from the chart, which cannot be verified,
+/// so they are refused when the region requires signatures.
+/// Returns the digests they resolved to by container name.
+pub async fn verify_container_images(mf: &Manifest, region: &Region) -> Result<BTreeMap<String, String>> {
+    let signed = match &region.imageVerification {
+        Some(iv) => !iv.publicKeys.is_empty(),
+        None => false,
+    };
+    if let Some(s) = mf.sidecars.iter().find(|s| signed && s.image.is_none()) {
+        let image = format!("{} sidecar {} (image from the chart)", mf.name, s.name);
+        return Err(ErrorKind::UnsignedImage(image).into());
+    }
+    let mut digests = BTreeMap::new();
+    for (name, img, pinned) in container_image_refs(mf) {
+        digests.insert(name, verify_image_ref(img, pinned.as_deref(), region).await?);
+    }
+    Ok(digests)
+}
+
+/// Verify that an image exists in its registry, and is signed if the region requires it
+///
+/// A pinned digest is verified as it is, rather than what the tag resolves to now.
+async fn verify_image_ref(img: ImageRef, pinned: Option<&str>, region: &Region) -> Result<String> {
+    let img = match pinned {
+        Some(digest) => ImageRef {
+            tag: digest.to_string(),
+            ..img
+        },
+        None => img,
+    };
+    let mut repo = Repository::new(&img)?;
+    let digest = repo.resolve_digest().await?;
+    debug!("{} resolved to {}", img, digest);
+    if let Some(iv) = &region.imageVerification {
+        let keys = iv.public_keys()?;
+        if !keys.is_empty() {
+            repo.verify_signature(&digest, &keys).await?;
+            info!("{}@{} is signed by a trusted key", img, digest);
+        }
+    }
+    Ok(digest)
+}
+
+/// Verify the images of a manifest before applying it, if the region requires it
+///
+/// Digests already pinned are verified as they are, so a crd applied by `shipcat apply`
+/// rolls out exactly what was verified then. Pins the digests into the manifest
+/// and its containers when the region pins digests, and clears them otherwise.
+pub async fn pre_apply(mf: &mut Manifest, region: &Region) -> Result<()> {
+    let (digest, digests) = match &region.imageVerification {
+        Some(iv) => {
+            let digest = verify_image(mf, region).await?;
+            let digests = verify_container_images(mf, region).await?;
+            if iv.pins_digest() {
+                (Some(digest), digests)
+            } else {
+                (None, BTreeMap::new())
+            }
+        }
+        None => (None, BTreeMap::new()),
+    };
+    mf.imageDigest = digest;
+    for c in mf.get_containers_mut() {
+        c.image_digest = digests.get(&c.name).cloned();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{container_image_refs, credentials, parse_challenge, verify_payload, ImageRef};
+    use crate::Manifest;
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+
+    #[test]
+    fn registry_image_refs() {
+        let hub = ImageRef::new("nginx", "1.17");
+        assert_eq!(hub.registry, "registry-1.docker.io");
+        assert_eq!(hub.repository, "library/nginx");
+        assert_eq!(
+            hub.repository_url(),
+            "https://registry-1.docker.io/v2/library/nginx"
+        );
+        assert_eq!(ImageRef::new("circleci/ruby", "2").repository, "circleci/ruby");
+        let quay = ImageRef::new("quay.io/babylonhealth/fake-ask", "1.6.0");
+        assert_eq!(quay.registry, "quay.io");
+        assert_eq!(quay.repository, "babylonhealth/fake-ask");
+        let local = ImageRef::new("localhost:5000/foo", "abc");
+        assert_eq!(local.repository_url(), "http://localhost:5000/v2/foo");
+    }
+
+    #[test]
+    fn registry_container_images() {
+        let mf: Manifest = serde_yaml::from_str(
+            "
+name: fake-ask
+sidecars:
+- name: redis
+  image: redis
+  version: '4'
+- name: proxy
+workers:
+- name: fake-ask-worker
+  image: quay.io/babylonhealth/fake-ask-worker
+  replicaCount: 1
+",
+        )
+        .unwrap();
+        let refs = container_image_refs(&mf);
+        assert_eq!(refs, vec![
+            ("redis".into(), ImageRef::new("redis", "4"), None),
+            (
+                "fake-ask-worker".into(),
+                ImageRef::new("quay.io/babylonhealth/fake-ask-worker", "latest"),
+                None
+            ),
+        ]);
+    }
+
+    #[test]
+    fn registry_challenge() {
+        let (scheme, params) = parse_challenge(
+            "Bearer realm=\"https://quay.io/v2/auth\",service=\"quay.io\",scope=\"repository:a/b:pull,push\"",
+        )
+        .unwrap();
+        assert_eq!(scheme, "bearer");
+        assert_eq!(params["realm"], "https://quay.io/v2/auth");
+        assert_eq!(params["service"], "quay.io");
+        assert_eq!(params["scope"], "repository:a/b:pull,push");
+
+        let (scheme, params) = parse_challenge("Basic realm=\"Registry\"").unwrap();
+        assert_eq!(scheme, "basic");
+        assert_eq!(params["realm"], "Registry");
+    }
+
+    #[test]
+    fn registry_credentials() {
+        let config = serde_json::json!({
+            "auths": {
+                "quay.io": { "auth": base64::encode("robot:s3cr:t") },
+                "https://index.docker.io/v1/": { "auth": base64::encode("hub:pass") },
+            }
+        });
+        let quay = credentials(&config, "quay.io").unwrap();
+        assert_eq!(quay, ("robot".to_string(), "s3cr:t".to_string()));
+        let hub = credentials(&config, "registry-1.docker.io").unwrap();
+        assert_eq!(hub.0, "hub");
+        assert!(credentials(&config, "gcr.io").is_none());
+    }
+
+    #[test]
+    fn registry_signed_payload() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+        let keys = vec![pair.public_key().as_ref().to_vec()];
+
+        let digest = "sha256:abc123";
+        let payload = serde_json::to_vec(&serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": "quay.io/babylonhealth/fake-ask" },
+                "image": { "docker-manifest-digest": digest },
+                "type": "cosign container image signature"
+            },
+            "optional": null
+        }))
+        .unwrap();
+        let blob = format!(
+            "sha256:{}",
+            hex::encode(ring::digest::digest(&ring::digest::SHA256, &payload).as_ref())
+        );
+        let sig = base64::encode(pair.sign(&rng, &payload).unwrap().as_ref());
+
+        verify_payload(&payload, &blob, &sig, digest, &keys).unwrap();
+        // signatures of other images are not accepted
+        assert!(verify_payload(&payload, &blob, &sig, "sha256:def456", &keys).is_err());
+        // nor are tampered payloads
+        assert!(verify_payload(&payload, "sha256:0000", &sig, digest, &keys).is_err());
+        // nor signatures from other keys
+        let other = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let other = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, other.as_ref()).unwrap();
+        let others = vec![other.public_key().as_ref().to_vec()];
+        assert!(verify_payload(&payload, &blob, &sig, digest, &others).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use shipcat_definitions::{region::SecretBackend, vault::EncryptedFile, Config, Manifest, Region};
+
+use super::{apply, ErrorKind, Result};
+
+/// A service referencing a rotated secret
+pub struct Affected {
+    pub manifest: Manifest,
+    /// Evars (as `container/EVAR`) or secret files that read the secret
+    pub names: Vec<String>,
+}
+
+/// Find services in a region that read a secret path, or any secret in a folder
+///
+/// Pinned references are ignored, as they will keep reading the old version.
+pub async fn affected(key: &str, conf: &Config, region: &Region) -> Result<Vec<Affected>> {
+    let key = key.trim_matches('/');
+    let mut res = vec![];
+    for svc in shipcat_filebacked::available(conf, region).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, region).await?;
+        let folder = mf.get_vault_path(&region.vault);
+        let mut names = vec![];
+        for (name, vref) in mf.vault_refs()? {
+            let pth = format!("{}/{}", folder, vref.key);
+            if pth == key || pth.starts_with(&format!("{}/", key)) {
+                if vref.version.is_some() {
+                    info!("{} pins {} to version {:?}; skipping", mf.name, pth, vref.version);
+                } else {
+                    names.push(name);
+                }
+            }
+        }
+        if !names.is_empty() {
+            res.push(Affected { manifest: mf, names });
+        }
+    }
+    Ok(res)
+}
+
+/// Restart every service that reads a rotated secret
+///
+/// Regions in a freeze window are refused unless a `freeze_override` reason is given.
+pub async fn rotate(
+    key: &str,
+    conf: &Config,
+    region: &Region,
+    dry_run: bool,
+    wait: bool,
+    freeze_override: Option<&str>,
+) -> Result<()> {
+    let affected = affected(key, conf, region).await?;
+    if affected.is_empty() {
+        info!("No services in {} reference {}", region.name, key);
+        return Ok(());
+    }
+    if dry_run {
+        for a in &affected {
+            println!("{} ({})", a.manifest.name, a.names.join(", "));
+        }
+        return Ok(());
+    }
+    let mut errs = vec![];
+    for a in affected {
+        let svc = a.manifest.name.clone();
+        info!("Restarting {} for rotated {}", svc, a.names.join(", "));
+        if let Err(e) = apply::restart(&a.manifest, region, conf, wait, freeze_override).await {
+            if let ErrorKind::RegionFrozen(..) = e.kind() {
+                return Err(e);
+            }
+            error!("Failed to restart {}: {}", svc, e);
+            errs.push(svc);
+        }
+    }
+    if !errs.is_empty() {
+        bail!(
+            "Failed to restart {} services after rotating {}: {:?}",
+            errs.len(),
+            key,
+            errs
+        );
+    }
+    Ok(())
+}
+
+/// Seal a secret into the encrypted file backend of a region
+///
+/// The file is created if it does not exist yet, and the key is a full secret path
+/// like `dev-uk/webapp/DATABASE_URL`.
+pub fn seal(key: &str, value: &str, region: &Region) -> Result<()> {
+    let pth = match &region.vault.backend {
+        SecretBackend::File(p) => p,
+        _ => bail!("{} does not keep its secrets in an encrypted file", region.name),
+    };
+    let mut store = if std::path::Path::new(pth).is_file() {
+        EncryptedFile::open(pth)?
+    } else {
+        EncryptedFile::create(pth)?
+    };
+    store.insert(key.trim_matches('/'), value)?;
+    store.save()?;
+    info!("Sealed {} into {}", key, pth);
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use super::{Config, Region, Result};
+use super::{Config, OutputFormat, Region, Result};
 use shipcat_definitions::{ShipcatConfig, ShipcatManifest};
 
 /// Print the config
@@ -28,3 +28,60 @@ pub async fn manifest_crd(svc: &str, conf: &Config, reg: &Region) -> Result<()>
     println!("{}", serde_yaml::to_string(&crd)?);
     Ok(())
 }
+
+/// Print the JSON Schema for manifests or shipcat.conf
+///
+/// Meant for editor autocompletion and validation of yaml files.
+pub fn schema(kind: &str, fmt: OutputFormat) -> Result<()> {
+    match kind {
+        "manifest" => fmt.print_data(&shipcat_filebacked::manifest_schema()),
+        "overrides" => fmt.print_data(&shipcat_filebacked::overrides_schema()),
+        "config" => fmt.print_data(&Config::schema()),
+        _ => bail!("Unknown schema {}", kind),
+    }
+}
+
+/// Print the values of a manifest along with the layer that set them
+///
+/// Later layers override earlier ones: global defaults, regional defaults, `manifest.yml`,
+/// environment overrides like `prod.yml`, then region overrides like `prod-uk.yml`.
+pub async fn explain(
+    svc: &str,
+    key: Option<&str>,
+    conf: &Config,
+    reg: &Region,
+    fmt: OutputFormat,
+) -> Result<()> {
+    let values = shipcat_filebacked::explain(svc, conf, reg)
+        .await?
+        .into_iter()
+        .filter(|p| {
+            key.map_or(true, |k| {
+                p.key == k || p.key.starts_with(&format!("{}.", k)) || p.key.starts_with(&format!("{}[", k))
+            })
+        })
+        .collect::<Vec<_>>();
+    fmt.print(&values, |values| {
+        let lines = values
+            .iter()
+            .map(|p| {
+                // one line per value, so only plain strings are left unquoted
+                let value = match p.value.as_str() {
+                    Some(s) if !s.contains('\n') => s.to_string(),
+                    _ => serde_json::to_string(&p.value)?,
+                };
+                Ok((format!("{}: {}", p.key, value), &p.source))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let width = lines
+            .iter()
+            .map(|(l, _)| l.len())
+            .filter(|w| *w <= 80)
+            .max()
+            .unwrap_or(0);
+        for (line, source) in lines {
+            println!("{:width$}  # {}", line, source, width = width);
+        }
+        Ok(())
+    })
+}
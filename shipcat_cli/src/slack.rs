@@ -7,7 +7,7 @@ use slack_hook2::{
 use std::{collections::BTreeMap, env};
 
 use super::{ErrorKind, Result};
-use crate::diff;
+use crate::diff::Diff;
 use shipcat_definitions::{
     structs::{Contact, Metadata, NotificationMode},
     teams::{Owners, Person},
@@ -31,8 +31,8 @@ pub struct Message {
     /// Optional color for the attachment API
     pub color: Option<String>,
 
-    /// Optional code input
-    pub code: Option<String>,
+    /// Optional diff of the upgrade
+    pub diff: Option<Diff>,
 
     /// Optional version to send when not having code diffs
     pub version: Option<String>,
@@ -165,21 +165,22 @@ async fn send_internal(msg: Message, chan: String, owners: &Owners) -> Result<()
     let mut texts = vec![Text(msg.text.into())];
 
     let mut codeattach = None;
-    if let Some(diff) = msg.code {
+    if let Some(diff) = msg.diff {
         // does the diff contain versions?
-        let is_version_only = if let Some((v1, v2)) = diff::infer_version_change(&diff) {
+        let is_version_only = if let Some((v1, v2)) = diff.version_change() {
             let lnk = create_github_compare_url(&md, (&v1, &v2));
             texts.push(lnk);
-            diff::is_version_only(&diff, (&v1, &v2))
+            diff.is_version_only((&v1, &v2))
         } else {
             false
         };
         // is diff otherwise meaningful?
         if !is_version_only {
+            let code = diff.to_string();
             codeattach = Some(
-                AttachmentBuilder::new(diff.clone())
+                AttachmentBuilder::new(code.clone())
                     .color("#439FE0")
-                    .text(vec![Text(diff.into())].as_slice())
+                    .text(vec![Text(code.into())].as_slice())
                     .build()?,
             )
         }
@@ -1,6 +1,6 @@
-use crate::{kubeapi::ShipKube, track::PodSummary, Result};
+use crate::{kubeapi::ShipKube, track::PodSummary, OutputFormat, Result};
 use k8s_openapi::api::core::v1::Pod;
-use shipcat_definitions::status::Condition;
+use shipcat_definitions::status::{Condition, ManifestStatus};
 use std::convert::TryFrom;
 
 fn format_condition(cond: &Condition) -> Result<String> {
@@ -40,14 +40,67 @@ fn format_pods(pods: Vec<Pod>) -> Result<()> {
     Ok(())
 }
 
+/// Machine readable pod information for `shipcat status`
+#[derive(Serialize)]
+struct PodOutput {
+    name: String,
+    version: String,
+    phase: String,
+    running: i32,
+    containers: u32,
+    restarts: i32,
+    /// Age in seconds
+    age: i64,
+}
+
+impl From<PodSummary> for PodOutput {
+    fn from(p: PodSummary) -> PodOutput {
+        PodOutput {
+            name: p.name,
+            version: p.version,
+            phase: p.phase,
+            running: p.running,
+            containers: p.containers,
+            restarts: p.restarts,
+            age: p.age.num_seconds(),
+        }
+    }
+}
+
+/// Machine readable output of `shipcat status`
+#[derive(Serialize)]
+struct StatusOutput {
+    service: String,
+    version: Option<String>,
+    status: Option<ManifestStatus>,
+    pods: Vec<PodOutput>,
+}
+
 use crate::{Config, Region};
 /// Entry point for `shipcat status`
-pub async fn show(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
+pub async fn show(svc: &str, conf: &Config, reg: &Region, fmt: OutputFormat) -> Result<()> {
     let mf = shipcat_filebacked::load_manifest(svc, conf, reg).await?;
     let api = ShipKube::new(&mf).await?;
     let crd = api.get().await?;
     let pod_res = api.get_pods().await;
 
+    if fmt != OutputFormat::Table {
+        let mut pods = vec![];
+        for p in pod_res
+            .map(|ps| ps.into_iter().collect::<Vec<_>>())
+            .unwrap_or_default()
+        {
+            pods.push(PodSummary::try_from(p)?.into());
+        }
+        let output = StatusOutput {
+            service: mf.name.clone(),
+            version: crd.spec.version,
+            status: crd.status,
+            pods,
+        };
+        return fmt.print_data(&output);
+    }
+
     let md = mf.metadata.clone().expect("need metadata");
     let ver = crd.spec.version.expect("need version");
     let support = md.support.clone().unwrap();
@@ -94,6 +147,9 @@ pub async fn show(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
         if let Some(ro) = &conds.rolledout {
             println!("RolledOut {}", format_condition(ro)?);
         }
+        if let Some(rb) = &conds.rolledback {
+            println!("RolledBack {}", format_condition(rb)?);
+        }
     }
     println!();
 
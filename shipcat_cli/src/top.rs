@@ -1,6 +1,13 @@
-use super::{Config, Error, Manifest, Region, Result};
+use super::{
+    kubeapi::{self, ContainerUsage},
+    Config, Error, Manifest, OutputFormat, Region, Result,
+};
 use futures::stream::{self, StreamExt};
-use shipcat_definitions::{math::ResourceTotals, BaseManifest};
+use shipcat_definitions::{
+    math::ResourceTotals,
+    structs::{ResourceRequirements, Resources},
+    BaseManifest,
+};
 use std::{collections::BTreeMap, str::FromStr};
 
 use generic_array::{typenum::U4, GenericArray};
@@ -43,7 +50,7 @@ async fn load_mf_req(svc: String, conf: &Config, reg: &Region) -> Result<(Manife
         .await?
         .stub(&reg)
         .await?;
-    let res = mf.compute_resource_totals()?;
+    let res = mf.compute_resource_totals()?.priced_in(&conf.cost_models(reg))?;
     Ok((mf, res))
 }
 
@@ -71,13 +78,19 @@ async fn load_mf_req_world(base: BaseManifest, conf: &Config) -> Result<Option<(
                 .stub(&reg)
                 .await?;
             if !mf.disabled && !mf.external {
-                let ResourceTotals { base: rb, extra: se } = mf.compute_resource_totals()?;
+                // priced per cluster as clusters can have different node pricing
+                let ResourceTotals {
+                    base: rb,
+                    extra: se,
+                    cost,
+                } = mf.compute_resource_totals()?.priced_in(&conf.cost_models(reg))?;
                 debug!(
                     "{} in {}: adding reqs: {} {}",
                     mf.name, r, rb.requests.cpu, rb.requests.memory
                 );
                 res.base += rb.clone();
                 res.extra += se.clone();
+                res.cost += cost;
                 first_mf = Some(mf);
             }
         }
@@ -116,7 +129,7 @@ pub async fn world_requests(
     conf: &Config,
 ) -> Result<Vec<(Manifest, ResourceTotals)>> {
     let mfs = calculate_manifest_requests_world(conf).await?;
-    let mfs = sort_and_print_resources(mfs, order, fmt, ub)?;
+    let mfs = sort_and_print_resources(mfs, order, fmt, ub, &conf.costs.currency)?;
     Ok(mfs)
 }
 
@@ -135,40 +148,16 @@ pub async fn region_requests(
     reg: &Region,
 ) -> Result<Vec<(Manifest, ResourceTotals)>> {
     let mfs = calculate_manifest_requests(conf, reg).await?;
-    let mfs = sort_and_print_resources(mfs, order, fmt, ub)?;
+    let mfs = sort_and_print_resources(mfs, order, fmt, ub, &conf.costs.currency)?;
     Ok(mfs)
 }
 
-/// How to format numbers
-pub enum OutputFormat {
-    /// Human readable table using size-formatter
-    Table,
-    /// Yaml output with raw numbers in milli-cores and Bytes
-    Yaml,
-}
-
-impl FromStr for OutputFormat {
-    type Err = Error;
-
-    fn from_str(input: &str) -> Result<Self> {
-        match input {
-            "table" => Ok(Self::Table),
-            "yaml" => Ok(Self::Yaml),
-            _ => bail!("Resource type must be table or yaml"),
-        }
-    }
-}
-impl Default for OutputFormat {
-    fn default() -> Self {
-        Self::Table
-    }
-}
-
 fn sort_and_print_resources(
     mut mfs: Vec<(Manifest, ResourceTotals)>,
     order: ResourceOrder,
     formatting: OutputFormat,
     upper_bounds: bool,
+    currency: &str,
 ) -> Result<Vec<(Manifest, ResourceTotals)>> {
     match order {
         ResourceOrder::Cpu => {
@@ -206,6 +195,9 @@ fn sort_and_print_resources(
         tribe: Option<String>,
         cpu: u64,
         memory: u64,
+        /// Monthly cost
+        cost: f64,
+        currency: String,
     }
     let output = mfs
         .iter()
@@ -223,6 +215,8 @@ fn sort_and_print_resources(
             YamlOutput {
                 memory,
                 cpu,
+                cost: monthly_cost(r, upper_bounds),
+                currency: currency.to_string(),
                 name: mf.name.clone(),
                 squad: mf.metadata.as_ref().unwrap().team.clone(),
                 tribe: mf.metadata.as_ref().unwrap().tribe.clone(),
@@ -230,43 +224,58 @@ fn sort_and_print_resources(
         })
         .collect::<Vec<_>>();
 
-    match formatting {
-        OutputFormat::Table => {
+    // Tables use size-formatter, while json and yaml have raw numbers in milli-cores and Bytes
+    formatting.print(&output, |output| {
+        println!(
+            "{0:<50} {1:<8} {2:<8} {3:<12} {4:40} {5:40}",
+            "SERVICE", "CPU", "MEMORY", "MONTHLY", "SQUAD", "TRIBE"
+        );
+        output.iter().for_each(|o| {
             println!(
-                "{0:<50} {1:<8} {2:<8} {3:40} {4:40}",
-                "SERVICE", "CPU", "MEMORY", "SQUAD", "TRIBE"
+                "{0:<50} {1:width$} {2:width$} {3:<12} {4:<40} {5:<40}",
+                o.name,
+                format!(
+                    "{:.0}",
+                    SizeFormatter::<u64, Millicores, PointSeparated>::new(o.cpu)
+                ),
+                format!("{:.0}", SizeFormatterBinary::new(o.memory)),
+                format!("{:.0} {}", o.cost, o.currency),
+                o.squad,
+                o.tribe.clone().unwrap_or_default(),
+                width = 8,
             );
-            output.into_iter().for_each(|o| {
-                println!(
-                    "{0:<50} {1:width$} {2:width$} {3:<40} {4:<40}",
-                    o.name,
-                    format!(
-                        "{:.0}",
-                        SizeFormatter::<u64, Millicores, PointSeparated>::new(o.cpu)
-                    ),
-                    format!("{:.0}", SizeFormatterBinary::new(o.memory)),
-                    o.squad,
-                    o.tribe.unwrap_or("".to_string()),
-                    width = 8,
-                );
-            });
-        }
-        OutputFormat::Yaml => {
-            println!("{}", serde_yaml::to_string(&output)?);
-        }
-    }
+        });
+        let total: f64 = output.iter().map(|o| o.cost).sum();
+        println!("{0:<68} {1:.0} {2}", "TOTAL", total, currency);
+        Ok(())
+    })?;
     Ok(mfs)
 }
 
+/// Monthly cost of resource totals rounded to cents
+fn monthly_cost(r: &ResourceTotals, upper_bounds: bool) -> f64 {
+    let cost = if upper_bounds {
+        r.cost.base + r.cost.extra
+    } else {
+        r.cost.base
+    };
+    (cost * 100.0).round() / 100.0
+}
+
 fn fold_manifests_by_squad(reqs: Vec<(Manifest, ResourceTotals)>) -> Result<Vec<(String, ResourceTotals)>> {
     let team_requests: Vec<(String, ResourceTotals)> = reqs
         .into_iter()
         .fold(BTreeMap::<String, ResourceTotals>::new(), |mut acc, (mf, res)| {
             acc.entry(mf.metadata.as_ref().unwrap().squad.clone().unwrap())
                 .and_modify(|e| {
-                    let ResourceTotals { base: rb, extra: se } = &res;
+                    let ResourceTotals {
+                        base: rb,
+                        extra: se,
+                        cost,
+                    } = &res;
                     e.base += rb.clone();
                     e.extra += se.clone();
+                    e.cost += cost.clone();
                 })
                 .or_insert(res);
             acc
@@ -285,9 +294,14 @@ fn fold_manifests_by_tribe(reqs: Vec<(Manifest, ResourceTotals)>) -> Result<Vec<
             if let Some(tribe) = &md.tribe {
                 acc.entry(tribe.to_string())
                     .and_modify(|e| {
-                        let ResourceTotals { base: rb, extra: se } = &res;
+                        let ResourceTotals {
+                            base: rb,
+                            extra: se,
+                            cost,
+                        } = &res;
                         e.base += rb.clone();
                         e.extra += se.clone();
+                        e.cost += cost.clone();
                     })
                     .or_insert(res);
             } else {
@@ -314,7 +328,7 @@ pub async fn region_squad_requests(
 ) -> Result<Vec<(String, ResourceTotals)>> {
     let mfs = calculate_manifest_requests(conf, reg).await?;
     let team_requests = fold_manifests_by_squad(mfs)?;
-    let sorted = sort_and_print_team_resources(team_requests, "squad", order, fmt, ub)?;
+    let sorted = sort_and_print_team_resources(team_requests, "squad", order, fmt, ub, &conf.costs.currency)?;
     Ok(sorted)
 }
 
@@ -331,7 +345,7 @@ pub async fn region_tribe_requests(
 ) -> Result<Vec<(String, ResourceTotals)>> {
     let mfs = calculate_manifest_requests(conf, reg).await?;
     let team_requests = fold_manifests_by_tribe(mfs)?;
-    let sorted = sort_and_print_team_resources(team_requests, "tribe", order, fmt, ub)?;
+    let sorted = sort_and_print_team_resources(team_requests, "tribe", order, fmt, ub, &conf.costs.currency)?;
     Ok(sorted)
 }
 
@@ -346,7 +360,7 @@ pub async fn world_squad_requests(
 ) -> Result<Vec<(String, ResourceTotals)>> {
     let mfs = calculate_manifest_requests_world(conf).await?;
     let team_requests = fold_manifests_by_squad(mfs)?;
-    let sorted = sort_and_print_team_resources(team_requests, "squad", order, fmt, ub)?;
+    let sorted = sort_and_print_team_resources(team_requests, "squad", order, fmt, ub, &conf.costs.currency)?;
     Ok(sorted)
 }
 
@@ -362,7 +376,7 @@ pub async fn world_tribe_requests(
 ) -> Result<Vec<(String, ResourceTotals)>> {
     let mfs = calculate_manifest_requests_world(conf).await?;
     let team_requests = fold_manifests_by_tribe(mfs)?;
-    let sorted = sort_and_print_team_resources(team_requests, "tribe", order, fmt, ub)?;
+    let sorted = sort_and_print_team_resources(team_requests, "tribe", order, fmt, ub, &conf.costs.currency)?;
     Ok(sorted)
 }
 
@@ -373,6 +387,7 @@ fn sort_and_print_team_resources(
     order: ResourceOrder,
     formatting: OutputFormat,
     upper_bounds: bool,
+    currency: &str,
 ) -> Result<Vec<(String, ResourceTotals)>> {
     match order {
         ResourceOrder::Cpu => {
@@ -408,6 +423,9 @@ fn sort_and_print_team_resources(
         team: String,
         cpu: u64,
         memory: u64,
+        /// Monthly cost
+        cost: f64,
+        currency: String,
     }
     let output = reqs
         .iter()
@@ -425,30 +443,365 @@ fn sort_and_print_team_resources(
             YamlOutput {
                 memory,
                 cpu,
+                cost: monthly_cost(r, upper_bounds),
+                currency: currency.to_string(),
                 team: team.to_string(),
             }
         })
         .collect::<Vec<_>>();
 
-    match formatting {
-        OutputFormat::Table => {
-            println!("{0:<45} {1:<8} {2:<8}", team_type.to_uppercase(), "CPU", "MEMORY");
-            output.into_iter().for_each(|o| {
-                println!(
-                    "{0:<45} {1:width$} {2:width$}",
-                    o.team,
-                    format!(
-                        "{:.0}",
-                        SizeFormatter::<u64, Millicores, PointSeparated>::new(o.cpu)
-                    ),
-                    format!("{:.0}", SizeFormatterBinary::new(o.memory)),
-                    width = 8,
-                );
-            });
+    formatting.print(&output, |output| {
+        println!(
+            "{0:<45} {1:<8} {2:<8} {3:<12}",
+            team_type.to_uppercase(),
+            "CPU",
+            "MEMORY",
+            "MONTHLY"
+        );
+        output.iter().for_each(|o| {
+            println!(
+                "{0:<45} {1:width$} {2:width$} {3:<12}",
+                o.team,
+                format!(
+                    "{:.0}",
+                    SizeFormatter::<u64, Millicores, PointSeparated>::new(o.cpu)
+                ),
+                format!("{:.0}", SizeFormatterBinary::new(o.memory)),
+                format!("{:.0} {}", o.cost, o.currency),
+                width = 8,
+            );
+        });
+        let total: f64 = output.iter().map(|o| o.cost).sum();
+        println!("{0:<63} {1:.0} {2}", "TOTAL", total, currency);
+        Ok(())
+    })?;
+    Ok(reqs)
+}
+
+// ----------------------------------------------------------------------------------
+// live usage against requests
+// ----------------------------------------------------------------------------------
+
+/// Services are over-provisioned when their busiest pod uses less than this share of requests
+const OVERPROVISIONED: f64 = 0.5;
+
+/// Headroom on top of the busiest pod in suggested requests
+const HEADROOM: f64 = 1.25;
+
+/// Requested, limited and live resource use of the main container of a service
+///
+/// Requests and limits are summed over the running pods, so they compare to actual usage.
+#[derive(Serialize)]
+pub struct ServiceUsage {
+    pub name: String,
+    pub squad: String,
+    pub tribe: Option<String>,
+    /// Number of running pods
+    pub pods: usize,
+    pub requests: Resources<f64>,
+    pub limits: Resources<f64>,
+    pub actual: Resources<f64>,
+    /// Right-sized resources if the service is over-provisioned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested: Option<ResourceRequirements<String>>,
+}
+
+/// Requested, limited and live resource use of a squad or tribe
+#[derive(Serialize)]
+pub struct TeamUsage {
+    pub team: String,
+    pub services: usize,
+    pub requests: Resources<f64>,
+    pub limits: Resources<f64>,
+    pub actual: Resources<f64>,
+    /// Number of over-provisioned services
+    pub overprovisioned: usize,
+}
+
+fn no_resources() -> Resources<f64> {
+    Resources {
+        cpu: 0.0,
+        memory: 0.0,
+    }
+}
+
+fn add_resources(acc: &mut Resources<f64>, r: &Resources<f64>) {
+    acc.cpu += r.cpu;
+    acc.memory += r.memory;
+}
+
+/// Suggest smaller requests for the dimensions the busiest pod uses little of
+///
+/// Limits are kept as they are to leave the same room for bursts.
+fn right_size(
+    current: &ResourceRequirements<String>,
+    peak: &Resources<f64>,
+) -> Result<Option<ResourceRequirements<String>>> {
+    let requests = current.normalised()?.requests;
+    let cpu_over = peak.cpu < requests.cpu * OVERPROVISIONED;
+    let memory_over = peak.memory < requests.memory * OVERPROVISIONED;
+    if !cpu_over && !memory_over {
+        return Ok(None);
+    }
+    let mut suggested = current.clone();
+    if cpu_over {
+        // in steps of 10 millicores
+        let millis = (peak.cpu * HEADROOM * 1000.0).round();
+        let millis = ((millis / 10.0).ceil() * 10.0).max(10.0);
+        suggested.requests.cpu = format!("{}m", millis as u64);
+    }
+    if memory_over {
+        let mebis = (peak.memory * HEADROOM / (1024.0 * 1024.0)).ceil().max(16.0);
+        suggested.requests.memory = format!("{}Mi", mebis as u64);
+    }
+    Ok(Some(suggested))
+}
+
+/// Compare the requests of a service against the usage of its running main containers
+///
+/// Sidecars and workers are not included. Returns None when nothing is running.
+fn service_usage(mf: &Manifest, containers: &[ContainerUsage]) -> Result<Option<ServiceUsage>> {
+    let running = containers
+        .iter()
+        .filter(|c| c.app == mf.name && c.container == mf.name)
+        .collect::<Vec<_>>();
+    let resources = match &mf.resources {
+        Some(r) if !running.is_empty() => r,
+        _ => return Ok(None),
+    };
+    let res = resources.normalised()? * running.len() as u32;
+    let mut actual = no_resources();
+    let mut peak = no_resources();
+    for c in &running {
+        add_resources(&mut actual, &c.usage);
+        peak.cpu = peak.cpu.max(c.usage.cpu);
+        peak.memory = peak.memory.max(c.usage.memory);
+    }
+    let md = mf.metadata.as_ref().unwrap();
+    Ok(Some(ServiceUsage {
+        name: mf.name.clone(),
+        squad: md.team.clone(),
+        tribe: md.tribe.clone(),
+        pods: running.len(),
+        requests: res.requests,
+        limits: res.limits,
+        actual,
+        suggested: right_size(resources, &peak)?,
+    }))
+}
+
+async fn calculate_service_usage(conf: &Config, reg: &Region) -> Result<Vec<ServiceUsage>> {
+    let containers = kubeapi::container_usage(&reg.namespace).await?;
+    let mut usage = vec![];
+    for (mf, _) in calculate_manifest_requests(conf, reg).await? {
+        if mf.disabled || mf.external {
+            continue;
         }
-        OutputFormat::Yaml => {
-            println!("{}", serde_yaml::to_string(&output)?);
+        if let Some(u) = service_usage(&mf, &containers)? {
+            usage.push(u);
         }
     }
-    Ok(reqs)
+    Ok(usage)
+}
+
+fn sort_usage<T, F>(usage: &mut [T], order: &ResourceOrder, requests: F)
+where
+    F: Fn(&T) -> &Resources<f64>,
+{
+    usage.sort_by(|u1, u2| {
+        let (r1, r2) = (requests(u1), requests(u2));
+        match order {
+            ResourceOrder::Cpu => r2.cpu.partial_cmp(&r1.cpu).unwrap(),
+            ResourceOrder::Memory => r2.memory.partial_cmp(&r1.memory).unwrap(),
+        }
+    });
+}
+
+fn format_cpu(cores: f64) -> String {
+    format!(
+        "{:.0}",
+        SizeFormatter::<u64, Millicores, PointSeparated>::new((1000.0 * cores) as u64)
+    )
+}
+
+fn format_memory(bytes: f64) -> String {
+    format!("{:.0}", SizeFormatterBinary::new(bytes as u64))
+}
+
+/// Live usage against requests for a single region
+///
+/// Unlike the other tops, this talks to the metrics api of the cluster for the region,
+/// and flags over-provisioned services along with right-sized resources.
+pub async fn region_usage(
+    order: ResourceOrder,
+    fmt: OutputFormat,
+    conf: &Config,
+    reg: &Region,
+) -> Result<Vec<ServiceUsage>> {
+    let mut usage = calculate_service_usage(conf, reg).await?;
+    sort_usage(&mut usage, &order, |u| &u.requests);
+    fmt.print(&usage, |usage| {
+        println!(
+            "{0:<40} {1:<5} {2:<26} {3:<26} {4}",
+            "SERVICE", "PODS", "CPU REQ/LIM/USED", "MEMORY REQ/LIM/USED", "SUGGESTED"
+        );
+        for u in usage {
+            let suggested = u
+                .suggested
+                .as_ref()
+                .map(|s| format!("cpu: {} memory: {}", s.requests.cpu, s.requests.memory))
+                .unwrap_or_default();
+            println!(
+                "{0:<40} {1:<5} {2:<26} {3:<26} {4}",
+                u.name,
+                u.pods,
+                format!(
+                    "{}/{}/{}",
+                    format_cpu(u.requests.cpu),
+                    format_cpu(u.limits.cpu),
+                    format_cpu(u.actual.cpu)
+                ),
+                format!(
+                    "{}/{}/{}",
+                    format_memory(u.requests.memory),
+                    format_memory(u.limits.memory),
+                    format_memory(u.actual.memory)
+                ),
+                suggested
+            );
+        }
+        Ok(())
+    })?;
+    Ok(usage)
+}
+
+/// Live usage against requests for a single region aggregated by squad or tribe
+pub async fn region_team_usage(
+    team_type: &str,
+    order: ResourceOrder,
+    fmt: OutputFormat,
+    conf: &Config,
+    reg: &Region,
+) -> Result<Vec<TeamUsage>> {
+    let mut teams = BTreeMap::<String, TeamUsage>::new();
+    for u in calculate_service_usage(conf, reg).await? {
+        let team = if team_type == "tribe" {
+            match &u.tribe {
+                Some(t) => t.clone(),
+                None => {
+                    warn!("Could not find a matching tribe for {}", u.name);
+                    continue;
+                }
+            }
+        } else {
+            u.squad.clone()
+        };
+        let tu = teams.entry(team.clone()).or_insert_with(|| TeamUsage {
+            team,
+            services: 0,
+            requests: no_resources(),
+            limits: no_resources(),
+            actual: no_resources(),
+            overprovisioned: 0,
+        });
+        tu.services += 1;
+        add_resources(&mut tu.requests, &u.requests);
+        add_resources(&mut tu.limits, &u.limits);
+        add_resources(&mut tu.actual, &u.actual);
+        if u.suggested.is_some() {
+            tu.overprovisioned += 1;
+        }
+    }
+    let mut usage = teams.into_iter().map(|(_, tu)| tu).collect::<Vec<_>>();
+    sort_usage(&mut usage, &order, |u| &u.requests);
+    fmt.print(&usage, |usage| {
+        println!(
+            "{0:<45} {1:<26} {2:<26} {3}",
+            team_type.to_uppercase(),
+            "CPU REQ/LIM/USED",
+            "MEMORY REQ/LIM/USED",
+            "OVERPROVISIONED"
+        );
+        for u in usage {
+            println!(
+                "{0:<45} {1:<26} {2:<26} {3}/{4}",
+                u.team,
+                format!(
+                    "{}/{}/{}",
+                    format_cpu(u.requests.cpu),
+                    format_cpu(u.limits.cpu),
+                    format_cpu(u.actual.cpu)
+                ),
+                format!(
+                    "{}/{}/{}",
+                    format_memory(u.requests.memory),
+                    format_memory(u.limits.memory),
+                    format_memory(u.actual.memory)
+                ),
+                u.overprovisioned,
+                u.services
+            );
+        }
+        Ok(())
+    })?;
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{right_size, service_usage, ContainerUsage, Manifest, Resources};
+    use shipcat_definitions::structs::ResourceRequirements;
+
+    fn container(pod: &str, cpu: f64, memory: f64) -> ContainerUsage {
+        ContainerUsage {
+            app: "fake-ask".into(),
+            pod: pod.into(),
+            container: "fake-ask".into(),
+            usage: Resources { cpu, memory },
+        }
+    }
+
+    #[test]
+    fn top_usage_right_size() {
+        let mi = 1024.0 * 1024.0;
+        let mut mf = Manifest::test("fake-ask");
+        mf.resources = Some(ResourceRequirements {
+            requests: Resources {
+                cpu: "1".into(),
+                memory: "1Gi".into(),
+            },
+            limits: Resources {
+                cpu: "2".into(),
+                memory: "2Gi".into(),
+            },
+        });
+        let containers = [
+            container("fake-ask-1", 0.1, 800.0 * mi),
+            container("fake-ask-2", 0.2, 600.0 * mi),
+            container("fake-ask-3", 0.3, 700.0 * mi),
+        ];
+        let u = service_usage(&mf, &containers[..2]).unwrap().unwrap();
+        assert_eq!(u.pods, 2);
+        assert_eq!(u.requests.cpu, 2.0);
+        assert_eq!(u.limits.cpu, 4.0);
+        assert!((u.actual.cpu - 0.3).abs() < 1e-9);
+        // only cpu is over-provisioned, sized from the busiest pod
+        let s = u.suggested.unwrap();
+        assert_eq!(s.requests.cpu, "250m");
+        assert_eq!(s.requests.memory, "1Gi");
+        assert_eq!(s.limits.cpu, "2");
+
+        // nothing running
+        assert!(service_usage(&mf, &[]).unwrap().is_none());
+
+        let peak = Resources {
+            cpu: 0.9,
+            memory: 100.0 * mi,
+        };
+        let s = right_size(mf.resources.as_ref().unwrap(), &peak)
+            .unwrap()
+            .unwrap();
+        assert_eq!(s.requests.cpu, "1");
+        assert_eq!(s.requests.memory, "125Mi");
+    }
 }
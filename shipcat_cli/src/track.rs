@@ -6,7 +6,7 @@ use k8s_openapi::api::{
     core::v1::Pod,
 };
 use kube::api::{Meta, ObjectList};
-use shipcat_definitions::{Manifest, PrimaryWorkload};
+use shipcat_definitions::{structs::Canary, Manifest, PrimaryWorkload};
 use std::{
     convert::{TryFrom, TryInto},
     fmt::{self, Debug},
@@ -34,6 +34,8 @@ pub struct PodSummary {
     pub containers: u32,
     pub restarts: i32,
     pub version: String,
+    /// Whether the `Ready` condition of the pod is true
+    pub ready: bool,
 }
 
 impl Debug for PodSummary {
@@ -77,11 +79,17 @@ impl TryFrom<Pod> for PodSummary {
         let mut running = 0;
         let mut containers = 0;
         let mut restarts = 0;
+        let mut ready = false;
         if let Some(status) = pod.status {
             phase = match status.phase {
                 Some(p) => p,
                 None => bail!("missing status.phase on pod {}", name),
             };
+            ready = status
+                .conditions
+                .unwrap_or_default()
+                .iter()
+                .any(|c| c.type_ == "Ready" && c.status == "True");
             for s in status.container_statuses.unwrap_or_default() {
                 running += if s.ready { 1 } else { 0 };
                 containers += 1;
@@ -107,6 +115,7 @@ impl TryFrom<Pod> for PodSummary {
             running,
             containers,
             restarts,
+            ready,
         })
     }
 }
@@ -507,3 +516,122 @@ pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
     }
     Ok(false) // timeout
 }
+
+/// A summary of the pods in a canary deployment
+#[derive(Debug)]
+pub struct CanarySummary {
+    pub pods: u32,
+    pub ready: u32,
+    pub restarts: i32,
+}
+
+async fn canary_status(kube: &ShipKube) -> Result<CanarySummary> {
+    let mut res = CanarySummary {
+        pods: 0,
+        ready: 0,
+        restarts: 0,
+    };
+    for pod in kube.get_canary_pods().await? {
+        let p = PodSummary::try_from(pod)?;
+        res.pods += 1;
+        if p.ready {
+            res.ready += 1;
+        }
+        res.restarts += p.restarts;
+    }
+    Ok(res)
+}
+
+/// Track a canary step until it has been observed healthy for its pause
+///
+/// Waits for `replicas` canary pods to become ready, then watches them for `pause` seconds.
+/// Returns the reason to abort if restarts or unready pods exceed the canary thresholds.
+pub async fn canary_step(
+    mf: &Manifest,
+    kube: &ShipKube,
+    canary: &Canary,
+    replicas: u32,
+    pause: u32,
+) -> Result<Option<String>> {
+    use futures_timer::Delay;
+    let poll = std::time::Duration::from_millis(5000);
+    let waittime = mf.estimate_wait_time();
+
+    // wait for the step to scale up
+    let mut waited = 0;
+    loop {
+        let cs = canary_status(kube).await?;
+        debug!("{} canary: {:?}", mf.name, cs);
+        if cs.restarts > canary.maxRestarts as i32 {
+            return Ok(Some(format!("canary containers restarted {} times", cs.restarts)));
+        }
+        if cs.ready >= replicas {
+            break;
+        }
+        if waited >= waittime {
+            let unready = replicas - cs.ready;
+            if unready > canary.maxUnready {
+                return Ok(Some(format!(
+                    "{}/{} canary pods ready after {}s",
+                    cs.ready, replicas, waited
+                )));
+            }
+            break;
+        }
+        Delay::new(poll).await;
+        waited += 5;
+    }
+
+    // observe it for the pause
+    info!("Observing {} canary pods for {}s", replicas, pause);
+    let mut paused = 0;
+    while paused < pause {
+        Delay::new(poll).await;
+        paused += 5;
+        let cs = canary_status(kube).await?;
+        debug!("{} canary: {:?}", mf.name, cs);
+        if cs.restarts > canary.maxRestarts as i32 {
+            return Ok(Some(format!("canary containers restarted {} times", cs.restarts)));
+        }
+        let unready = cs.pods.saturating_sub(cs.ready);
+        if unready > canary.maxUnready {
+            return Ok(Some(format!("{} canary pods failing readiness", unready)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PodSummary;
+    use k8s_openapi::api::core::v1::Pod;
+    use serde_json::json;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn pod_summary_ready() {
+        let mut pod = json!({
+            "metadata": { "name": "fake-ask-canary-abc", "creationTimestamp": "2020-01-01T00:00:00Z" },
+            "spec": { "containers": [{ "name": "fake-ask", "image": "fake-ask:1.6.0" }] },
+            "status": {
+                "phase": "Running",
+                "conditions": [{ "type": "Ready", "status": "False" }],
+                "containerStatuses": [{
+                    "name": "fake-ask",
+                    "image": "fake-ask:1.6.0",
+                    "imageID": "",
+                    "ready": true,
+                    "restartCount": 0,
+                }],
+            },
+        });
+        // containers can be ready while a readiness gate still holds the pod back
+        let p = PodSummary::try_from(serde_json::from_value::<Pod>(pod.clone()).unwrap()).unwrap();
+        assert_eq!(p.running, 1);
+        assert!(!p.ready);
+
+        pod["status"]["conditions"][0]["status"] = json!("True");
+        let p = PodSummary::try_from(serde_json::from_value::<Pod>(pod).unwrap()).unwrap();
+        assert!(p.ready);
+    }
+}
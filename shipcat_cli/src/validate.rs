@@ -1,6 +1,12 @@
-use super::{Config, Manifest, Region, Result};
-use crate::{error_chain::ChainedError, git};
+use super::{Config, Manifest, OutputFormat, Region, Result};
+use crate::{
+    error_chain::ChainedError,
+    git,
+    graph::{self, CatGraph},
+    registry,
+};
 use futures::stream::{self, StreamExt};
+use shipcat_definitions::policy;
 
 async fn verify_manifest(svc: String, conf: &Config, reg: &Region) -> Result<Manifest> {
     let mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
@@ -8,9 +14,32 @@ async fn verify_manifest(svc: String, conf: &Config, reg: &Region) -> Result<Man
         .stub(&reg)
         .await?;
     mf.verify(&conf, &reg)?;
+    policies(&mf, conf, reg)?;
     Ok(mf)
 }
 
+/// Evaluate the policies in shipcat.conf against a manifest
+///
+/// Warnings and waived violations are logged, while errors fail.
+pub fn policies(mf: &Manifest, conf: &Config, reg: &Region) -> Result<()> {
+    let violations = policy::evaluate(&conf.policies, mf, reg)?;
+    let mut fatal = 0;
+    for v in &violations {
+        if v.is_fatal() {
+            error!("{} violates {}", mf.name, v);
+            fatal += 1;
+        } else if v.waiver.is_some() {
+            info!("{} violates {}", mf.name, v);
+        } else {
+            warn!("{} violates {}", mf.name, v);
+        }
+    }
+    if fatal > 0 {
+        bail!("{} violates {} policies in {}", mf.name, fatal, reg.name);
+    }
+    Ok(())
+}
+
 /// Validate all manifests in a service directory for a region
 ///
 /// This is meant to replace `shipcat validate ..all_services`
@@ -23,6 +52,7 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
         .buffer_unordered(16);
 
     let mut errs = vec![];
+    let mut deps: CatGraph = Default::default();
     let mut used_stream_names = vec![];
     let mut used_topic_names = vec![];
     let mut used_user_names = vec![];
@@ -30,6 +60,7 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
         match r {
             Err(e) => errs.push(e),
             Ok(mf) => {
+                graph::add_manifest(&mut deps, &mf);
                 // uniqueness validation
                 for es in mf.eventStreams {
                     if used_stream_names.contains(&es.name) {
@@ -69,6 +100,7 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
         }
         bail!("Invalid shipcat data in {} files", errs.len());
     }
+    graph::verify_acyclic(&deps)?;
     // TODO: cross reference uniqueness values here
     Ok(())
 }
@@ -110,24 +142,88 @@ pub async fn all_manifests() -> Result<()> {
 /// This will populate the manifest for all supported environments,
 /// and `verify` their parameters.
 /// Optionally, it will also verify that all secrets are found in the corresponding
-/// vault locations serverside (which require vault credentials).
-pub async fn manifest(services: Vec<String>, conf: &Config, reg: &Region, secrets: bool) -> Result<()> {
+/// vault locations serverside (which require vault credentials),
+/// and that pinned versions exist as image tags in their docker registry.
+pub async fn manifest(
+    services: Vec<String>,
+    conf: &Config,
+    reg: &Region,
+    secrets: bool,
+    images: bool,
+    fmt: OutputFormat,
+) -> Result<()> {
     conf.verify()?; // this should work even with a limited config!
+    let mut results = vec![];
     for svc in services {
         debug!("validating {} for {}", svc, reg.name);
-        let mf = if secrets {
-            shipcat_filebacked::load_manifest(&svc, conf, reg)
-                .await?
-                .complete(reg)
-                .await?
+        match validate_manifest(&svc, conf, reg, secrets, images).await {
+            Ok(()) => debug!("validated {} for {}", svc, reg.name),
+            // tables keep failing on the first invalid manifest
+            Err(e) if fmt == OutputFormat::Table => return Err(e),
+            Err(e) => {
+                results.push(Validation {
+                    service: svc,
+                    region: reg.name.clone(),
+                    error: Some(e.display_chain().to_string()),
+                });
+                continue;
+            }
+        }
+        results.push(Validation {
+            service: svc,
+            region: reg.name.clone(),
+            error: None,
+        });
+    }
+    if fmt != OutputFormat::Table {
+        fmt.print_data(&results)?;
+        let failed = results.iter().filter(|v| v.error.is_some()).count();
+        if failed > 0 {
+            bail!("{} manifests failed validation", failed);
+        }
+    }
+    Ok(())
+}
+
+/// Result of validating a single manifest
+#[derive(Serialize)]
+struct Validation {
+    service: String,
+    region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn validate_manifest(
+    svc: &str,
+    conf: &Config,
+    reg: &Region,
+    secrets: bool,
+    images: bool,
+) -> Result<()> {
+    let mf = if secrets {
+        shipcat_filebacked::load_manifest(svc, conf, reg)
+            .await?
+            .complete(reg)
+            .await?
+    } else {
+        shipcat_filebacked::load_manifest(svc, conf, reg)
+            .await?
+            .stub(reg)
+            .await?
+    };
+    mf.verify(conf, reg)?;
+    policies(&mf, conf, reg)?;
+    if images {
+        if mf.version.is_some() {
+            registry::verify_image(&mf, reg).await?;
+            registry::verify_container_images(&mf, reg).await?;
         } else {
-            shipcat_filebacked::load_manifest(&svc, conf, reg)
-                .await?
-                .stub(reg)
-                .await?
-        };
-        mf.verify(conf, reg)?;
-        debug!("validated {} for {}", svc, reg.name);
+            info!(
+                "{} has no pinned version in {}, not verifying its image",
+                svc, reg.name
+            );
+        }
     }
     Ok(())
 }
@@ -15,6 +15,8 @@ pub enum UpgradeState {
     Completed,
     /// Errors
     Failed,
+    /// Errors, but the previous version was restored
+    RolledBack,
 }
 
 pub fn ensure_requirements(reg: &Region) -> Result<()> {
@@ -27,11 +29,11 @@ pub fn ensure_requirements(reg: &Region) -> Result<()> {
 /// Throw events to configured webhooks - warning on delivery errors
 ///
 /// Http errors SHOULD NOT be propagated from here
-pub async fn reconcile_event(us: UpgradeState, reg: &Region) {
+pub async fn reconcile_event(us: UpgradeState, reg: &Region, freeze_override: Option<&str>) {
     for wh in &reg.webhooks {
         if let Ok(whc) = wh.get_configuration() {
             let res = match wh {
-                Webhook::Audit(h) => audit::reconciliation(&us, &reg.name, &h, whc).await,
+                Webhook::Audit(h) => audit::reconciliation(&us, &reg.name, freeze_override, h, whc).await,
             };
             if let Err(e) = res {
                 warn!("Failed to notify about reconciliation event: {}", e)
@@ -50,10 +52,11 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
             let res = match wh {
                 Webhook::Audit(h) => {
                     match us {
-                        UpgradeState::Started | UpgradeState::Completed | UpgradeState::Failed => {
-                            audit::apply(&us, &info, &h, whc).await
-                        }
-                        _ => Ok(()), // audit only sends Started / Failed / Completed
+                        UpgradeState::Started
+                        | UpgradeState::Completed
+                        | UpgradeState::Failed
+                        | UpgradeState::RolledBack => audit::apply(&us, &info, &h, whc).await,
+                        _ => Ok(()), // audit only sends Started / Failed / Completed / RolledBack
                     }
                 }
             };
@@ -62,6 +65,10 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
             }
         }
     }
+    // clusters of an active/active region are summarised in one slack message by apply
+    if info.cluster.is_some() {
+        return;
+    }
     // slack notifications:
     let (color, text) = match us {
         UpgradeState::Completed => ("good", format!("applied `{}` in `{}`", info.name, info.region)),
@@ -69,6 +76,10 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
             "danger",
             format!("failed to apply `{}` in `{}`", info.name, info.region),
         ),
+        UpgradeState::RolledBack => (
+            "warning",
+            format!("rolled back `{}` in `{}` after a failed apply", info.name, info.region),
+        ),
         _ => (
             "good",
             format!(
@@ -78,11 +89,11 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
         ),
     };
     match us {
-        UpgradeState::Completed | UpgradeState::Failed => {
+        UpgradeState::Completed | UpgradeState::Failed | UpgradeState::RolledBack => {
             let _ = slack::send(
                 slack::Message {
                     text,
-                    code: info.diff.clone(),
+                    diff: info.diff.clone(),
                     color: Some(String::from(color)),
                     version: Some(info.version.clone()),
                     mode: info.slackMode.clone(),
@@ -121,7 +132,7 @@ pub async fn delete_event(us: &UpgradeState, info: &UpgradeInfo, reg: &Region, c
             let _ = slack::send(
                 slack::Message {
                     text,
-                    code: info.diff.clone(),
+                    diff: info.diff.clone(),
                     color: Some(String::from(color)),
                     version: Some(info.version.clone()),
                     mode: info.slackMode.clone(),
@@ -1,3 +1,4 @@
+use chrono::{TimeZone, Utc};
 use semver::Version;
 use std::{collections::BTreeSet, env, fs, path::Path, sync::Once};
 
@@ -88,16 +89,16 @@ async fn config_defaults_test() {
     assert_eq!(*metadata.notifications.unwrap(), "#dev-platform-notif-override");
 }
 
-use shipcat::get;
+use shipcat::{get, OutputFormat};
 #[tokio::test]
 async fn getters() {
     setup();
     let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
-    let vers = get::versions(&conf, &reg).await.unwrap();
+    let vers = get::versions(&conf, &reg, OutputFormat::Json).await.unwrap();
     assert_eq!(vers.len(), 1); // only one of the services has a version
     assert_eq!(vers["fake-ask"], Version::new(1, 6, 0));
 
-    let imgs = get::images(&conf, &reg).await.unwrap();
+    let imgs = get::images(&conf, &reg, OutputFormat::Json).await.unwrap();
     assert_eq!(imgs.len(), 2); // every service gets an image
     assert_eq!(imgs["fake-ask"], "quay.io/babylonhealth/fake-ask");
     assert_eq!(imgs["fake-storage"], "nginx");
@@ -111,24 +112,83 @@ async fn clusterinfo() {
     // NB: needs a base config to be able to verify region/cluster constraints
     let conf = Config::read().await.unwrap();
 
-    assert!(get::clusterinfo(&conf, "preproduk-blue", Some("preproduk-blue")).is_ok());
-    assert!(get::clusterinfo(&conf, "preproduk-green", None).is_err()); // ambiguous
-    assert!(get::clusterinfo(&conf, "preprod-uk", None).is_err()); // ambiguous
-
-    let blue = get::clusterinfo(&conf, "preprod-uk", Some("preproduk-blue")).unwrap();
+    assert!(get::clusterinfo(
+        &conf,
+        "preproduk-blue",
+        Some("preproduk-blue"),
+        OutputFormat::Json
+    )
+    .is_ok());
+    assert!(get::clusterinfo(&conf, "preproduk-green", None, OutputFormat::Json).is_err()); // ambiguous
+    assert!(get::clusterinfo(&conf, "preprod-uk", None, OutputFormat::Json).is_err()); // ambiguous
+
+    let blue = get::clusterinfo(&conf, "preprod-uk", Some("preproduk-blue"), OutputFormat::Json).unwrap();
     assert_eq!(blue.region, "preprod-uk"); // correctly resolved
-
-    assert!(get::clusterinfo(&conf, "dev-global", None).is_ok());
-    let devglob = get::clusterinfo(&conf, "dev-global", None).unwrap();
+    // but applies go to both
+    let preprod = conf.get_region("preprod-uk").unwrap();
+    assert_eq!(preprod.activeClusters, vec!["preproduk-blue", "preproduk-green"]);
+    // and are refused during freeze windows
+    let xmas = preprod
+        .active_freeze(Utc.ymd(2019, 12, 25).and_hms(12, 0, 0))
+        .unwrap();
+    assert_eq!(xmas.unwrap().name, "christmas");
+    let tuesday = preprod
+        .active_freeze(Utc.ymd(2020, 6, 9).and_hms(12, 0, 0))
+        .unwrap();
+    assert!(tuesday.is_none());
+    // and pin the digests of verified and signed images
+    let iv = preprod.imageVerification.as_ref().unwrap();
+    assert!(iv.pinDigest);
+    assert_eq!(iv.public_keys().unwrap().len(), 1);
+
+    assert!(get::clusterinfo(&conf, "dev-global", None, OutputFormat::Json).is_ok());
+    let devglob = get::clusterinfo(&conf, "dev-global", None, OutputFormat::Json).unwrap();
     assert_eq!(devglob.region, "dev-global");
     assert_eq!(devglob.cluster, "kops-global")
 }
 
+#[tokio::test]
+async fn cost_model_test() {
+    setup();
+    let conf = Config::read().await.unwrap();
+    let dev = conf.get_region("dev-uk").unwrap();
+    let preprod = conf.get_region("preprod-uk").unwrap();
+    // global pricing unless the serving cluster has its own
+    assert_eq!(conf.cost_model(&dev).nodePools[0].name, "default");
+    assert_eq!(conf.cost_model(&preprod).nodePools[0].name, "general");
+
+    let mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &dev)
+        .await
+        .unwrap();
+    let ondemand = mf
+        .compute_resource_totals()
+        .unwrap()
+        .priced(conf.cost_model(&dev))
+        .unwrap();
+    let spot = mf
+        .compute_resource_totals()
+        .unwrap()
+        .priced(conf.cost_model(&preprod))
+        .unwrap();
+    assert!(ondemand.cost.base > 0.0);
+    assert!(spot.cost.base < ondemand.cost.base);
+
+    // active/active regions pay for every cluster
+    let mut active = preprod.clone();
+    active.activeClusters = vec!["preproduk-blue".into(), "preproduk-green".into()];
+    let both = mf
+        .compute_resource_totals()
+        .unwrap()
+        .priced_in(&conf.cost_models(&active))
+        .unwrap();
+    assert!((both.cost.base - (spot.cost.base + ondemand.cost.base)).abs() < 1e-6);
+}
+
 #[tokio::test]
 async fn get_codeowners() {
     setup();
     let conf = Config::read().await.unwrap();
-    let cos = get::codeowners(&conf).await.unwrap();
+    let cos = get::codeowners(&conf, OutputFormat::Json).await.unwrap();
 
     assert_eq!(cos.len(), 4); // serivces with team admins get a listing
     assert_eq!(cos[1], "services/fake-ask/* @babylonhealth/o11y @clux");
@@ -221,7 +281,7 @@ async fn templating_test() {
 async fn vault_policy_test() {
     setup();
     let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
-    let policy = shipcat::get::vaultpolicy(&conf, &reg, "observability")
+    let policy = shipcat::get::vaultpolicy(&conf, &reg, "observability", OutputFormat::Json)
         .await
         .unwrap();
 
@@ -243,7 +303,7 @@ async fn vault_policy_test() {
     // prod should be stricter:
     let mut fakereg = reg.clone();
     fakereg.environment = Environment::Prod;
-    let strict_policy = shipcat::get::vaultpolicy(&conf, &fakereg, "observability")
+    let strict_policy = shipcat::get::vaultpolicy(&conf, &fakereg, "observability", OutputFormat::Json)
         .await
         .unwrap();
 
@@ -1,13 +1,25 @@
 mod common;
 use crate::common::setup;
-use shipcat::graph::{generate, nodeidx_from_name};
+use shipcat::{
+    graph::{self, generate, impact, nodeidx_from_name, CatGraph, DepEdge, GraphFormat, ManifestNode},
+    structs::DependencyProtocol,
+    OutputFormat,
+};
 use shipcat_definitions::{Config, ConfigState};
 
 #[tokio::test]
 async fn graph_generate() {
     setup();
     let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
-    let graph = generate("fake-ask", &conf, &reg, true).await.unwrap();
+    let graph = generate(
+        "fake-ask",
+        &conf,
+        &reg,
+        Some(GraphFormat::Dot),
+        OutputFormat::Yaml,
+    )
+    .await
+    .unwrap();
     assert!(graph.edge_count() > 0);
     print!("got struct: \n{:?}\n", serde_yaml::to_string(&graph));
     let askidx = nodeidx_from_name("fake-ask", &graph).unwrap();
@@ -20,3 +32,73 @@ async fn graph_generate() {
     println!("edge: {:?}", edge);
     assert_eq!(edge.intent, Some("testing graph module".into()));
 }
+
+#[tokio::test]
+async fn graph_exports() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let graph = generate("fake-ask", &conf, &reg, None, OutputFormat::Json)
+        .await
+        .unwrap();
+
+    let graphml = graph::export(&graph, GraphFormat::GraphMl).unwrap();
+    assert!(graphml.contains(r#"<edge source="fake-ask" target="fake-storage">"#));
+    assert!(graphml.contains(r#"<data key="intent">testing graph module</data>"#));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&graph::export(&graph, GraphFormat::Json).unwrap()).unwrap();
+    assert_eq!(json["nodes"].as_array().unwrap().len(), graph.node_count());
+    assert_eq!(json["edges"][0]["source"], "fake-ask");
+    assert_eq!(json["edges"][0]["target"], "fake-storage");
+    // -o json and yaml print the same form
+    assert_eq!(graph::graph_data(&graph), json);
+
+    let mermaid = graph::export(&graph, GraphFormat::Mermaid).unwrap();
+    assert!(mermaid.starts_with("graph LR"));
+    assert!(mermaid.contains(r#"["fake-storage"]"#));
+    assert!(mermaid.contains("-->|http|"));
+}
+
+#[test]
+fn graph_cycles() {
+    let edge = || DepEdge {
+        api: "v1".into(),
+        contract: None,
+        protocol: DependencyProtocol::Http,
+        intent: None,
+    };
+    let mut graph = CatGraph::default();
+    let node = |name: &str| ManifestNode { name: name.into() };
+    let a = graph.add_node(node("a"));
+    let b = graph.add_node(node("b"));
+    let c = graph.add_node(node("c"));
+    graph.add_edge(a, b, edge());
+    graph.add_edge(b, c, edge());
+    assert!(graph::verify_acyclic(&graph).is_ok());
+    // c depending on a closes the loop
+    graph.add_edge(c, a, edge());
+    assert_eq!(graph::cycles(&graph), vec![vec!["a", "b", "c"]]);
+    assert!(graph::verify_acyclic(&graph).is_err());
+    // outages of any service in a cycle affect the rest of it
+    assert_eq!(graph::dependents("b", &graph), vec!["a", "c"]);
+}
+
+#[tokio::test]
+async fn graph_impact() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let res = impact("fake-storage", &conf, &reg, OutputFormat::Json)
+        .await
+        .unwrap();
+    assert_eq!(res.dependents, vec!["fake-ask"]);
+    assert_eq!(res.squads.len(), 1);
+    assert_eq!(res.squads[0].squad, "observability");
+    assert_eq!(res.squads[0].services, vec!["fake-ask"]);
+    assert_eq!(res.squads[0].channels, vec!["CA04UJ8S0"]);
+
+    let res = impact("fake-ask", &conf, &reg, OutputFormat::Json).await.unwrap();
+    assert!(res.dependents.is_empty());
+    assert!(impact("not-a-service", &conf, &reg, OutputFormat::Json)
+        .await
+        .is_err());
+}
@@ -1,21 +1,95 @@
 mod common;
 use crate::common::setup;
-use shipcat::{helm, Result};
+use shipcat::{apply, helm, kubeapi, Result};
 use shipcat_definitions::{Config, ConfigState};
 
 #[tokio::test]
-#[ignore] // This test requires helm cli - not on circle
 async fn helm_template() -> Result<()> {
     setup();
     let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await?;
-    let mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+    let mf = shipcat_filebacked::load_manifest("fake-ask", &conf, &reg)
         .await?
         .stub(&reg)
         .await?;
 
     let res = helm::template(&mf, None).await?;
 
-    // verify we have deferred to helm for templating
+    // verify we have rendered the chart in-process
     assert!(res.contains("image: \"quay.io/babylonhealth/fake-ask:1.6.0\""));
     Ok(())
 }
+
+#[tokio::test]
+async fn helm_template_image_digest() -> Result<()> {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await?;
+    let mut mf = shipcat_filebacked::load_manifest("fake-ask", &conf, &reg)
+        .await?
+        .stub(&reg)
+        .await?;
+    mf.imageDigest = Some("sha256:abc123".into());
+
+    // pinned digests are pulled by digest
+    let objects = helm::objects(&helm::template(&mf, None).await?)?;
+    let deploy = objects
+        .iter()
+        .find(|o| o["kind"] == "Deployment" && o["metadata"]["name"] == "fake-ask")
+        .expect("deployment");
+    let image = &deploy["spec"]["template"]["spec"]["containers"][0]["image"];
+    assert_eq!(image, "quay.io/babylonhealth/fake-ask:1.6.0@sha256:abc123");
+    Ok(())
+}
+
+#[tokio::test]
+async fn helm_blue_green_selector() -> Result<()> {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await?;
+    let mf = shipcat_filebacked::load_manifest("fake-ask", &conf, &reg)
+        .await?
+        .stub(&reg)
+        .await?;
+    let objects = helm::objects(&helm::template(&mf, None).await?)?;
+    let coloured = apply::coloured_objects(&mf, &objects, "green");
+    let deploy = coloured
+        .iter()
+        .find(|o| o["kind"] == "Deployment" && o["metadata"]["name"] == "fake-ask-green")
+        .expect("coloured deployment");
+
+    // the pods blue/green tracks must be the pods the coloured deployment creates
+    let labels = &deploy["spec"]["template"]["metadata"]["labels"];
+    let selector = kubeapi::pod_selector(&mf.name, Some("green"));
+    assert_eq!(selector, "app=fake-ask,colour=green");
+    for term in selector.split(',') {
+        let kv = term.splitn(2, '=').collect::<Vec<_>>();
+        assert_eq!(labels[kv[0]], kv[1], "pod template is missing {}", term);
+    }
+    // and the deployment itself selects them
+    let matching = deploy["spec"]["selector"]["matchLabels"].as_object().unwrap();
+    for (k, v) in matching {
+        assert_eq!(&labels[k], v);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn helm_blue_green_restart_targets() -> Result<()> {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await?;
+    let mut mf = shipcat_filebacked::load_manifest("fake-ask", &conf, &reg)
+        .await?
+        .stub(&reg)
+        .await?;
+    let targets = apply::restart_targets(&mf, None);
+    assert_eq!(targets, vec!["deployment/worker", "deployment/fake-ask"]);
+
+    // blue/green restarts the deployment of the live colour
+    mf.blueGreen = Some(Default::default());
+    let targets = apply::restart_targets(&mf, Some("green"));
+    assert_eq!(targets, vec!["deployment/worker", "deployment/fake-ask-green"]);
+    let objects = helm::objects(&helm::template(&mf, None).await?)?;
+    let coloured = apply::coloured_objects(&mf, &objects, "green");
+    assert!(coloured
+        .iter()
+        .any(|o| o["kind"] == "Deployment" && o["metadata"]["name"] == "fake-ask-green"));
+    Ok(())
+}
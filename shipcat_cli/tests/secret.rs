@@ -0,0 +1,34 @@
+mod common;
+use crate::common::setup;
+
+use shipcat::secret::affected;
+use shipcat_definitions::{Config, ConfigState};
+
+#[tokio::test]
+async fn secret_rotate_affected() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+
+    // fake-ask reads its secrets from the test-shipcat folder
+    let res = affected("dev-uk/test-shipcat/FAKE_NUMBER", &conf, &reg)
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].manifest.name, "fake-ask");
+    assert_eq!(res[0].names, vec!["redis/FAKE_NUMBER"]);
+
+    // folders match every secret within
+    let res = affected("dev-uk/test-shipcat/", &conf, &reg).await.unwrap();
+    assert_eq!(res.len(), 1);
+    assert!(res[0].names.contains(&"fake-ask/FAKE_SECRET".to_string()));
+    assert!(res[0].names.contains(&"redis/FAKE_SECRET".to_string()));
+
+    assert!(affected("dev-uk/fake-ask/FAKE_NUMBER", &conf, &reg)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(affected("dev-uk/test-shipca", &conf, &reg)
+        .await
+        .unwrap()
+        .is_empty());
+}
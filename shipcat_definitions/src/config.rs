@@ -4,7 +4,7 @@ use kube_derive::CustomResource;
 use semver::Version;
 use std::collections::{BTreeMap, BTreeSet};
 
-use crate::teams;
+use crate::{cost::CostModel, policy::Policy, teams};
 #[allow(unused_imports)] use std::path::{Path, PathBuf};
 
 #[allow(unused_imports)] use super::{Error, Result};
@@ -15,7 +15,7 @@ use crate::{
 
 // ----------------------------------------------------------------------------------
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct ManifestDefaults {
     /// Image prefix string
@@ -38,7 +38,7 @@ impl Default for ManifestDefaults {
 }
 
 /// Kubernetes cluster information
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Cluster {
     /// Name of the cluster
@@ -50,9 +50,12 @@ pub struct Cluster {
     pub teleport: Option<String>,
     /// What regions this cluster control (perhaps not exclusively)
     pub regions: Vec<String>,
+    /// Pricing of the nodes in this cluster, if different from the global one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub costs: Option<CostModel>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Location {
     /// Location name
@@ -65,7 +68,7 @@ pub struct Location {
     pub local_region: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct GithubParameters {
     /// Organisation name
@@ -73,7 +76,7 @@ pub struct GithubParameters {
 }
 
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct SlackParameters {
     /// Team name (T...)
@@ -85,7 +88,7 @@ pub struct SlackParameters {
 
 
 /// Main manifest, serializable from shipcat.conf
-#[derive(CustomResource, Serialize, Deserialize, Debug, Clone)]
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[kube(
     group = "babylontech.co.uk",
     kind = "ShipcatConfig",
@@ -130,6 +133,7 @@ pub struct Config {
     pub allowedCustomMetadata: BTreeSet<String>,
 
     /// Shipcat version pins
+    #[schemars(with = "BTreeMap<Environment, String>")]
     pub versions: BTreeMap<Environment, Version>,
 
     /// Owners of services, squads, tribes
@@ -138,6 +142,14 @@ pub struct Config {
     #[serde(default)]
     pub owners: teams::Owners,
 
+    /// Validation policies for manifests
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<Policy>,
+
+    /// Pricing of the nodes services run on, for cost estimates
+    #[serde(default)]
+    pub costs: CostModel,
+
     // Internal state of the config
     #[serde(default, skip_serializing, skip_deserializing)]
     state: ConfigState,
@@ -195,7 +207,22 @@ impl Config {
             if !self.clusters.keys().any(|c| c == &r.cluster) {
                 bail!("Region {} served by missing cluster '{}'", r.name, r.cluster);
             }
+            for c in &r.activeClusters {
+                match self.clusters.get(c) {
+                    None => bail!("Region {} has missing active cluster '{}'", r.name, c),
+                    Some(clst) if !clst.regions.contains(&r.name) => {
+                        bail!("Active cluster {} does not serve region {}", c, r.name)
+                    }
+                    Some(_) => {}
+                }
+            }
             r.vault.verify(&r.name)?;
+            for w in &r.freezeWindows {
+                w.verify()?;
+            }
+            if let Some(iv) = &r.imageVerification {
+                iv.verify()?;
+            }
             for v in r.base_urls.values() {
                 if v.ends_with('/') {
                     bail!("A base_url must not end with a slash");
@@ -209,6 +236,31 @@ impl Config {
                 used_kong_urls.push(kong.config_url.clone());
             }
         }
+
+        self.costs.verify()?;
+        for c in self.clusters.values() {
+            if let Some(costs) = &c.costs {
+                costs.verify()?;
+                // reports sum costs across clusters
+                if costs.currency != self.costs.currency {
+                    bail!(
+                        "Cluster {} prices in {} rather than {}",
+                        c.name,
+                        costs.currency,
+                        self.costs.currency
+                    );
+                }
+            }
+        }
+
+        let mut policy_names = vec![];
+        for p in &self.policies {
+            p.verify()?;
+            if policy_names.contains(&&p.name) {
+                bail!("Policy names must be unique: {}", p.name);
+            }
+            policy_names.push(&p.name);
+        }
         Ok(())
     }
 
@@ -264,6 +316,11 @@ impl Config {
         Ok(())
     }
 
+    /// JSON Schema for shipcat.conf
+    pub fn schema() -> schemars::schema::RootSchema {
+        schema_for!(Config)
+    }
+
     /// Print Config to stdout
     pub fn print(&self) -> Result<()> {
         println!("{}", serde_yaml::to_string(self)?);
@@ -497,6 +554,35 @@ impl Config {
         self.regions.iter().find(|r| r.name == region)
     }
 
+    /// Pricing of the nodes serving a region
+    ///
+    /// Uses the pricing of the serving cluster if it has one, otherwise the global one.
+    pub fn cost_model(&self, region: &Region) -> &CostModel {
+        self.clusters
+            .get(&region.cluster)
+            .and_then(|c| c.costs.as_ref())
+            .unwrap_or(&self.costs)
+    }
+
+    /// Pricing of the nodes in every cluster serving a region
+    ///
+    /// One per `activeClusters` entry, as each of them runs the full set of services.
+    pub fn cost_models(&self, region: &Region) -> Vec<&CostModel> {
+        if region.activeClusters.is_empty() {
+            return vec![self.cost_model(region)];
+        }
+        region
+            .activeClusters
+            .iter()
+            .map(|c| {
+                self.clusters
+                    .get(c)
+                    .and_then(|c| c.costs.as_ref())
+                    .unwrap_or(&self.costs)
+            })
+            .collect()
+    }
+
     /// Filter a file based config for a known to exist region
     fn remove_redundant_regions(&mut self, region: &str) -> Result<()> {
         assert_eq!(self.state, ConfigState::File);
@@ -0,0 +1,169 @@
+use super::Result;
+use crate::structs::parse_memory;
+
+/// Average number of hours in a month
+pub const HOURS_PER_MONTH: f64 = 730.0;
+
+/// Pricing of the nodes that services are scheduled on
+///
+/// Declared globally in `shipcat.conf`, and optionally overridden per cluster.
+/// Without one, every region is priced as on-demand `m5.2xlarge` nodes.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CostModel {
+    /// Currency of the prices, e.g. `USD`
+    pub currency: String,
+    /// Node pools services are scheduled on
+    ///
+    /// Costs are blended across pools by their `share`.
+    pub nodePools: Vec<NodePool>,
+}
+
+/// A group of identical nodes
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NodePool {
+    /// Name of the node pool
+    pub name: String,
+    /// Instance type of the nodes, e.g. `m5.2xlarge`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instanceType: Option<String>,
+    /// Hourly on-demand price of a node
+    pub hourlyPrice: f64,
+    /// Average hourly price of a node running as a spot instance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spotHourlyPrice: Option<f64>,
+    /// Fraction of the nodes running as spot instances, between 0 and 1
+    #[serde(default)]
+    pub spotFraction: f64,
+    /// Allocatable cpu cores of a node
+    pub cpu: f64,
+    /// Allocatable memory of a node, e.g. `31Gi`
+    pub memory: String,
+    /// Share of the services scheduled on this pool, relative to the other pools
+    #[serde(default = "default_share")]
+    pub share: f64,
+}
+
+fn default_share() -> f64 {
+    1.0
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            currency: "USD".into(),
+            nodePools: vec![NodePool {
+                name: "default".into(),
+                instanceType: Some("m5.2xlarge".into()),
+                hourlyPrice: 0.384,
+                spotHourlyPrice: None,
+                spotFraction: 0.0,
+                cpu: 8.0,
+                memory: "31Gi".into(),
+                share: 1.0,
+            }],
+        }
+    }
+}
+
+impl NodePool {
+    fn verify(&self) -> Result<()> {
+        if self.hourlyPrice < 0.0 || self.spotHourlyPrice.map_or(false, |p| p < 0.0) {
+            bail!("Node pool {} cannot have negative prices", self.name);
+        }
+        if self.spotFraction < 0.0 || self.spotFraction > 1.0 {
+            bail!("Node pool {} needs a spotFraction between 0 and 1", self.name);
+        }
+        if self.spotFraction > 0.0 && self.spotHourlyPrice.is_none() {
+            bail!(
+                "Node pool {} needs a spotHourlyPrice for its spot nodes",
+                self.name
+            );
+        }
+        if self.cpu <= 0.0 || parse_memory(&self.memory)? <= 0.0 {
+            bail!("Node pool {} needs positive cpu and memory", self.name);
+        }
+        if self.share <= 0.0 {
+            bail!("Node pool {} needs a positive share", self.name);
+        }
+        Ok(())
+    }
+
+    /// Average hourly price of a node, accounting for spot instances
+    fn node_price(&self) -> f64 {
+        let spot = self.spotHourlyPrice.unwrap_or(self.hourlyPrice);
+        self.hourlyPrice * (1.0 - self.spotFraction) + spot * self.spotFraction
+    }
+}
+
+impl CostModel {
+    pub fn verify(&self) -> Result<()> {
+        if self.currency.is_empty() {
+            bail!("Cost models need a currency");
+        }
+        if self.nodePools.is_empty() {
+            bail!("Cost models need at least one node pool");
+        }
+        for np in &self.nodePools {
+            np.verify()?;
+        }
+        Ok(())
+    }
+
+    /// Hourly cost of cpu cores and memory bytes
+    ///
+    /// Priced by the share of nodes needed for whichever of cpu or memory is the bottleneck,
+    /// blended across node pools.
+    pub fn hourly_cost(&self, cpu: f64, memory: f64) -> Result<f64> {
+        let mut cost = 0.0;
+        for np in &self.nodePools {
+            let nodes = (cpu / np.cpu).max(memory / parse_memory(&np.memory)?);
+            cost += nodes * np.node_price() * np.share;
+        }
+        let shares: f64 = self.nodePools.iter().map(|np| np.share).sum();
+        Ok(cost / shares)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CostModel;
+
+    #[test]
+    fn cost_blended_pools() {
+        let gi = 1024.0 * 1024.0 * 1024.0;
+        let legacy = CostModel::default();
+        legacy.verify().unwrap();
+        // a full m5.2xlarge in either dimension
+        assert!((legacy.hourly_cost(8.0, 1.0 * gi).unwrap() - 0.384).abs() < 1e-9);
+        assert!((legacy.hourly_cost(1.0, 62.0 * gi).unwrap() - 0.768).abs() < 1e-9);
+
+        let model: CostModel = serde_yaml::from_str(
+            "
+currency: EUR
+nodePools:
+- name: general
+  hourlyPrice: 1.0
+  spotHourlyPrice: 0.5
+  spotFraction: 0.5
+  cpu: 4
+  memory: 16Gi
+  share: 3
+- name: memory
+  hourlyPrice: 2.0
+  cpu: 4
+  memory: 64Gi
+",
+        )
+        .unwrap();
+        model.verify().unwrap();
+        // general node is 0.75/h, memory node is 2.0/h, weighted 3:1
+        let cost = model.hourly_cost(4.0, 16.0 * gi).unwrap();
+        assert!((cost - (0.75 * 3.0 + 2.0) / 4.0).abs() < 1e-9);
+
+        let mut bad = model.clone();
+        bad.nodePools[1].spotFraction = 0.5;
+        assert!(bad.verify().is_err());
+    }
+}
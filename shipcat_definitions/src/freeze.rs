@@ -0,0 +1,296 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset, Timelike, Utc};
+use chrono_tz::Tz;
+
+use super::Result;
+
+/// A period during which changes to a region are frozen
+///
+/// Either a one-off date range (`start` and `end`),
+/// or a recurring window (`cron` and `duration`).
+///
+/// ```yaml
+/// freezeWindows:
+/// - name: christmas
+///   start: 2020-12-21
+///   end: 2021-01-03
+///   timezone: "+00:00"
+/// - name: friday evenings
+///   cron: "0 16 * * 5"
+///   duration: 64h
+///   timezone: Europe/London
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FreezeWindow {
+    /// Name of the freeze shown when refusing changes
+    pub name: String,
+    /// Start of a one-off freeze, e.g. `2020-12-21` or `2020-12-21T18:00`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    /// End of a one-off freeze
+    ///
+    /// A date without a time includes that whole day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    /// Cron schedule starting a recurring freeze
+    ///
+    /// Standard 5 field format: minute, hour, day of month, month, day of week (0 is sunday).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// How long a recurring freeze lasts, e.g. `30m`, `12h` or `2d`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    /// Timezone the times are given in, e.g. `Europe/London`, or a UTC offset like `+01:00`
+    ///
+    /// Named zones follow their daylight saving time. Defaults to UTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// Longest recurring freeze supported
+const MAX_RECURRING_DAYS: i64 = 31;
+
+/// Timezone of a freeze window
+enum Zone {
+    Offset(FixedOffset),
+    Named(Tz),
+}
+
+impl FreezeWindow {
+    pub fn verify(&self) -> Result<()> {
+        self.zone()?;
+        match (&self.start, &self.end, &self.cron, &self.duration) {
+            (Some(s), Some(e), None, None) => {
+                if parse_time(s, false)? >= parse_time(e, true)? {
+                    bail!("Freeze window {} must end after it starts", self.name);
+                }
+            }
+            (None, None, Some(c), Some(d)) => {
+                Schedule::parse(c)?;
+                let dur = parse_duration(d)?;
+                if dur > Duration::days(MAX_RECURRING_DAYS) {
+                    bail!(
+                        "Freeze window {} cannot last more than {} days",
+                        self.name,
+                        MAX_RECURRING_DAYS
+                    );
+                }
+            }
+            _ => bail!(
+                "Freeze window {} needs either start and end, or cron and duration",
+                self.name
+            ),
+        }
+        Ok(())
+    }
+
+    fn zone(&self) -> Result<Zone> {
+        let tz = match &self.timezone {
+            None => return Ok(Zone::Offset(Utc.fix())),
+            Some(tz) if tz == "UTC" || tz == "Z" => return Ok(Zone::Offset(Utc.fix())),
+            Some(tz) => tz,
+        };
+        let (sign, hm) = match tz.chars().next() {
+            Some('+') => (1, &tz[1..]),
+            Some('-') => (-1, &tz[1..]),
+            _ => match tz.parse::<Tz>() {
+                Ok(named) => return Ok(Zone::Named(named)),
+                Err(_) => bail!("Timezone {} must be a name like Europe/London, or an offset", tz),
+            },
+        };
+        let mut parts = hm.splitn(2, ':');
+        let hours: i32 = parts.next().unwrap_or_default().parse()?;
+        let minutes: i32 = parts.next().unwrap_or("0").parse()?;
+        match FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)) {
+            Some(offset) if minutes < 60 => Ok(Zone::Offset(offset)),
+            _ => bail!("Timezone {} is not a valid offset", tz),
+        }
+    }
+
+    /// Whether the freeze is in effect at a given time
+    pub fn is_active(&self, now: DateTime<Utc>) -> Result<bool> {
+        let local = match self.zone()? {
+            Zone::Offset(offset) => now.with_timezone(&offset).naive_local(),
+            Zone::Named(tz) => now.with_timezone(&tz).naive_local(),
+        };
+        if let (Some(s), Some(e)) = (&self.start, &self.end) {
+            return Ok(parse_time(s, false)? <= local && local < parse_time(e, true)?);
+        }
+        if let (Some(c), Some(d)) = (&self.cron, &self.duration) {
+            let schedule = Schedule::parse(c)?;
+            let minutes = parse_duration(d)?
+                .num_minutes()
+                .min(Duration::days(MAX_RECURRING_DAYS).num_minutes());
+            let start = local.with_second(0).unwrap().with_nanosecond(0).unwrap();
+            // active if the schedule fired within the duration
+            return Ok((0..minutes).any(|m| schedule.matches(&(start - Duration::minutes(m)))));
+        }
+        Ok(false)
+    }
+}
+
+/// Parse a date or a date and time, where dates alone cover the whole day
+fn parse_time(s: &str, end: bool) -> Result<NaiveDateTime> {
+    for fmt in &["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, fmt) {
+            return Ok(t);
+        }
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")?;
+    let date = if end { date.succ_opt() } else { Some(date) };
+    match date.and_then(|d| d.and_hms_opt(0, 0, 0)) {
+        Some(t) => Ok(t),
+        None => bail!("Date {} is out of range", s),
+    }
+}
+
+/// Parse a duration like `90m`, `12h` or `2d`
+fn parse_duration(s: &str) -> Result<Duration> {
+    let unit = match s.chars().last() {
+        Some(u) => u,
+        None => bail!("Duration must not be empty"),
+    };
+    let num: i64 = s[..s.len() - unit.len_utf8()].parse()?;
+    let dur = match unit {
+        'm' => Duration::minutes(num),
+        'h' => Duration::hours(num),
+        'd' => Duration::days(num),
+        _ => bail!("Duration {} must end in m, h or d", s),
+    };
+    if num <= 0 {
+        bail!("Duration {} must be positive", s);
+    }
+    Ok(dur)
+}
+
+/// A parsed 5 field cron schedule
+struct Schedule {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    /// Whether day of month and day of week were both restricted
+    either_day: bool,
+}
+
+impl Schedule {
+    fn parse(cron: &str) -> Result<Schedule> {
+        let fields = cron.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            bail!("Cron schedule '{}' needs 5 fields", cron);
+        }
+        let weekdays = parse_field(fields[4], 0, 7)?.into_iter().map(|d| d % 7).collect();
+        Ok(Schedule {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+        })
+    }
+
+    fn matches(&self, t: &NaiveDateTime) -> bool {
+        let day = self.days.contains(&t.day());
+        let weekday = self.weekdays.contains(&t.weekday().num_days_from_sunday());
+        // like cron, a restricted day of month and day of week match either
+        let day_ok = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        day_ok
+            && self.minutes.contains(&t.minute())
+            && self.hours.contains(&t.hour())
+            && self.months.contains(&t.month())
+    }
+}
+
+/// Parse a cron field with lists, ranges and steps, e.g. `*/15` or `1-5,0`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>> {
+    let mut values = vec![];
+    for part in field.split(',') {
+        let mut split = part.splitn(2, '/');
+        let range = split.next().unwrap_or_default();
+        let step: u32 = match split.next() {
+            Some(s) => s.parse()?,
+            None => 1,
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some(i) = range.find('-') {
+            (range[..i].parse()?, range[i + 1..].parse()?)
+        } else {
+            let v = range.parse()?;
+            (v, v)
+        };
+        if lo < min || hi > max || lo > hi || step == 0 {
+            bail!("Cron field '{}' is out of range {}-{}", field, min, max);
+        }
+        values.extend((lo..=hi).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FreezeWindow;
+    use chrono::{TimeZone, Utc};
+
+    fn window(yaml: &str) -> FreezeWindow {
+        let w: FreezeWindow = serde_yaml::from_str(yaml).unwrap();
+        w.verify().unwrap();
+        w
+    }
+
+    #[test]
+    fn freeze_date_range() {
+        let w = window("name: xmas\nstart: 2020-12-21\nend: 2021-01-03\ntimezone: \"+02:00\"");
+        assert!(!w.is_active(Utc.ymd(2020, 12, 20).and_hms(21, 0, 0)).unwrap());
+        // midnight in +02:00
+        assert!(w.is_active(Utc.ymd(2020, 12, 20).and_hms(22, 0, 0)).unwrap());
+        // end date is inclusive
+        assert!(w.is_active(Utc.ymd(2021, 1, 3).and_hms(21, 59, 0)).unwrap());
+        assert!(!w.is_active(Utc.ymd(2021, 1, 3).and_hms(22, 0, 0)).unwrap());
+    }
+
+    #[test]
+    fn freeze_cron() {
+        // friday 16:00 until monday 08:00
+        let w = window("name: weekend\ncron: 0 16 * * 5\nduration: 64h");
+        // 2020-06-05 is a friday
+        assert!(!w.is_active(Utc.ymd(2020, 6, 5).and_hms(15, 59, 0)).unwrap());
+        assert!(w.is_active(Utc.ymd(2020, 6, 5).and_hms(16, 0, 0)).unwrap());
+        assert!(w.is_active(Utc.ymd(2020, 6, 7).and_hms(12, 0, 0)).unwrap());
+        assert!(w.is_active(Utc.ymd(2020, 6, 8).and_hms(7, 59, 30)).unwrap());
+        assert!(!w.is_active(Utc.ymd(2020, 6, 8).and_hms(8, 0, 0)).unwrap());
+        assert!(!w.is_active(Utc.ymd(2020, 6, 10).and_hms(17, 0, 0)).unwrap());
+    }
+
+    #[test]
+    fn freeze_named_timezone() {
+        let w = window("name: evening
+cron: 0 18 * * *
+duration: 1h
+timezone: Europe/London");
+        // 18:00 in London is 17:00 UTC in summer, and 18:00 UTC in winter
+        assert!(w.is_active(Utc.ymd(2020, 6, 9).and_hms(17, 30, 0)).unwrap());
+        assert!(!w.is_active(Utc.ymd(2020, 6, 9).and_hms(18, 30, 0)).unwrap());
+        assert!(!w.is_active(Utc.ymd(2020, 12, 9).and_hms(17, 30, 0)).unwrap());
+        assert!(w.is_active(Utc.ymd(2020, 12, 9).and_hms(18, 30, 0)).unwrap());
+    }
+
+    #[test]
+    fn freeze_invalid() {
+        let parse = |yaml: &str| serde_yaml::from_str::<FreezeWindow>(yaml).unwrap().verify();
+        assert!(parse("name: a\nstart: 2020-12-21").is_err());
+        assert!(parse("name: a\nstart: 2020-12-21\nend: 2020-12-20").is_err());
+        assert!(parse("name: a\ncron: 0 16 * *\nduration: 1h").is_err());
+        assert!(parse("name: a\ncron: 0 24 * * *\nduration: 1h").is_err());
+        assert!(parse("name: a\ncron: 0 16 * * *\nduration: 1w").is_err());
+        assert!(parse("name: a\ncron: 0 16 * * *\nduration: 40d").is_err());
+        assert!(parse("name: a\ncron: 0 16 * * *\nduration: 1h\ntimezone: Europe/Nowhere").is_err());
+        assert!(parse("name: a\ncron: 0 16 * * *\nduration: 1h\ntimezone: \"+01:75\"").is_err());
+    }
+}
@@ -6,6 +6,7 @@
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate log;
 #[macro_use] extern crate maplit;
+#[macro_use] extern crate schemars;
 
 #[macro_use] extern crate error_chain; // bail and error_chain macro
 error_chain! {
@@ -72,12 +73,26 @@ error_chain! {
             description("failed to build manifest")
             display("failed to build manifest for {} in {}", &service_name, &region_name)
         }
+        InvalidManifestFile(position: String, reason: String) {
+            description("manifest file did not parse")
+            display("{}: {}", &position, &reason)
+        }
+        ConflictingManifestKeys(first: String, second: String) {
+            description("manifest properties conflict")
+            display("{} conflicts with {}", &first, &second)
+        }
+        MergedManifestLayers(layers: String) {
+            description("merged manifest is invalid")
+            display("manifest merged from {}", &layers)
+        }
     }
 }
 
 /// Config with regional data
 pub mod region;
-pub use crate::region::{Environment, KongConfig, ReconciliationMode, Region, VaultConfig, VersionScheme};
+pub use crate::region::{
+    Environment, KongConfig, KvVersion, ReconciliationMode, Region, SecretBackend, VaultConfig, VersionScheme,
+};
 /// Master config with cross-region data
 pub mod config;
 pub use crate::config::{Cluster, Config, ConfigFallback, ManifestDefaults, ShipcatConfig};
@@ -95,6 +110,13 @@ pub use crate::base::BaseManifest;
 /// Definitions of teams/squads/tribes (via ewok or otherwise)
 pub mod teams;
 
+/// Declarative validation policies for manifests
+pub mod policy;
+
+/// Deployment freeze windows for regions
+pub mod freeze;
+pub use crate::freeze::FreezeWindow;
+
 /// Crd wrappers
 mod crds;
 pub use crate::crds::gen_all_crds;
@@ -110,6 +132,10 @@ pub use crate::states::{ConfigState, PrimaryWorkload};
 /// Computational helpers
 pub mod math;
 
+/// Pricing of nodes for cost estimates
+pub mod cost;
+pub use crate::cost::CostModel;
+
 /// A renderer of `tera` templates (jinja style)
 ///
 /// Used for small app configs that are inlined in the completed manifests.
@@ -117,6 +143,6 @@ pub mod template;
 
 /// A Hashicorp Vault HTTP client using `reqwest`
 pub mod vault;
-pub use crate::vault::Vault;
+pub use crate::vault::{SecretStore, Vault, VaultRef};
 
 pub mod deserializers;
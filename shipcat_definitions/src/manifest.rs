@@ -1,7 +1,7 @@
-use crate::vault::Vault;
+use crate::vault::{self, SecretStore, VaultRef};
 use kube_derive::CustomResource;
 use regex::Regex;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeMap;
 
 use super::Result;
 use crate::{
@@ -19,13 +19,14 @@ use super::structs::{
     sentry::Sentry,
     tolerations::Tolerations,
     volume::{Volume, VolumeMount},
-    ConfigMap, Container, CronJob, Dependency, DestinationRule, EnvVars, EventStream, Gate, HealthCheck,
-    HostAlias, Kafka, KafkaResources, Kong, LifeCycle, Metadata, NotificationMode, PersistentVolume, Port,
-    Probe, PrometheusAlert, Rbac, ResourceRequirements, RollingUpdate, SecurityContext, VaultOpts, Worker,
+    BlueGreen, Canary, ConfigMap, Container, CronJob, Dependency, DestinationRule, EnvVars, EventStream,
+    Gate, HealthCheck, HostAlias, Kafka, KafkaResources, Kong, LifeCycle, Metadata, NotificationMode,
+    PersistentVolume, Port, Probe, PrometheusAlert, Rbac, ResourceRequirements, RollingUpdate,
+    SecurityContext, VaultOpts, Worker,
 };
 
 /// Main manifest, serializable from manifest.yml or the shipcat CRD.
-#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[kube(
     group = "babylontech.co.uk",
     kind = "ShipcatManifest",
@@ -181,6 +182,25 @@ pub struct Manifest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<String>,
 
+    /// Digest of the image the version resolved to in its registry
+    ///
+    /// Pinned by `shipcat apply` in regions with `imageVerification.pinDigest`,
+    /// so a retagged image cannot slip into a rollout.
+    /// Charts should prefer this over `version` when it is set.
+    ///
+    /// This is an internal property that is exposed as an output only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imageDigest: Option<String>,
+
+    /// Reason given for applying during a freeze window of the region
+    ///
+    /// Set by `shipcat apply --freeze-override` while the region is frozen,
+    /// so the operator can roll out the change as well.
+    ///
+    /// This is an internal property that is exposed as an output only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub freezeOverride: Option<String>,
+
     /// Command to use for the docker image
     ///
     /// This can be left out to use the default image command.
@@ -283,6 +303,7 @@ pub struct Manifest {
     ///
     /// These have the same special "IN_VAULT" behavior as `Manifest::env`:
     /// "IN_VAULT" values is replaced with value from vault/secret/folder/service/key
+    /// and can read a named secret pinned to a version with `vault:key@3` (see `VaultRef`).
     ///
     /// Note the lowercase restriction on keys.
     /// All `secretFiles` are expected to be base64 in vault, and are placed into a
@@ -496,6 +517,48 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub rollingUpdate: Option<RollingUpdate>,
 
+    /// Blue/green Deployment parameters
+    ///
+    /// An alternative to rolling updates for services that cannot run mixed versions.
+    /// A second Deployment is rolled out fully before the `Service` selector is switched to it,
+    /// and the previous one is removed after `soak` seconds.
+    ///
+    /// ```yaml
+    /// blueGreen:
+    ///   soak: 600
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blueGreen: Option<BlueGreen>,
+
+    /// Automatically roll back to the last successfully rolled out version
+    ///
+    /// When a rollout fails during `shipcat apply`, the previous version is re-applied.
+    /// Falls back to the `autoRollback` setting of the region when unset.
+    ///
+    /// ```yaml
+    /// autoRollback: true
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autoRollback: Option<bool>,
+
+    /// Canary rollout steps
+    ///
+    /// When set, upgrades of an installed `Deployment` first run a canary Deployment
+    /// at each step's weight for its pause (in seconds), aborting without touching the main
+    /// Deployment if too many canary pods restart or stay unready.
+    ///
+    /// ```yaml
+    /// canary:
+    ///   maxRestarts: 1
+    ///   steps:
+    ///   - weight: 10
+    ///     pause: 120
+    ///   - weight: 50
+    ///     pause: 300
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<Canary>,
+
     /// `HorizontalPodAutoScaler` parameters for kubernetes
     ///
     /// Passed all parameters directly onto the `spec` of a kube HPA.
@@ -860,6 +923,13 @@ pub struct Manifest {
     #[serde(default, skip_deserializing, skip_serializing_if = "BTreeMap::is_empty")]
     pub secrets: BTreeMap<String, String>,
 
+    /// Versions of the secrets read from a versioned secret store
+    ///
+    /// Keyed by `container/EVAR` for env vars, and by file name for secret files.
+    /// This is an internal property that is recorded in the crd status after a rollout.
+    #[serde(default, skip_deserializing, skip_serializing)]
+    pub secretVersions: BTreeMap<String, u32>,
+
     /// Internal state of the manifest
     ///
     /// A manifest goes through different stages of serialization, templating,
@@ -894,7 +964,12 @@ pub struct Manifest {
 
 impl Manifest {
     /// Set the version field
+    ///
+    /// Clears any `imageDigest` pinned for the previous version.
     pub fn version(mut self, version: String) -> Self {
+        if self.version.as_ref() != Some(&version) {
+            self.imageDigest = None;
+        }
         self.version = Some(version);
         self
     }
@@ -1029,8 +1104,25 @@ impl Manifest {
         if let Some(ref ru) = &self.rollingUpdate {
             ru.verify(self.replicaCount.unwrap())?;
         }
+        if let Some(c) = &self.canary {
+            if let PrimaryWorkload::Statefulset = self.workload {
+                bail!("Canary rollouts are only supported for Deployment workloads");
+            }
+            c.verify()?;
+        }
+        if self.blueGreen.is_some() {
+            if let PrimaryWorkload::Statefulset = self.workload {
+                bail!("Blue/green deployments are only supported for Deployment workloads");
+            }
+            if self.canary.is_some() {
+                bail!("Cannot use both canary and blueGreen");
+            }
+        }
 
         self.env.verify()?;
+        for (k, v) in &self.secretFiles {
+            VaultRef::parse(k, v)?;
+        }
 
         // internal errors - implicits set these!
         if self.image.is_none() {
@@ -1063,7 +1155,8 @@ impl Manifest {
         Ok(())
     }
 
-    fn get_vault_path(&self, vc: &VaultConfig) -> String {
+    /// Folder in the secret store holding this service's secrets
+    pub fn get_vault_path(&self, vc: &VaultConfig) -> String {
         // some services use keys from other services
         let (svc, reg) = if let Some(ref vopts) = self.vault {
             (vopts.name.clone(), vc.folder.clone())
@@ -1073,38 +1166,68 @@ impl Manifest {
         format!("{}/{}", reg, svc)
     }
 
+    // Get all containers besides the main one: sidecars, workers, cron jobs and init containers.
+    pub fn get_containers(&self) -> Vec<&Container> {
+        let mut containers = Vec::new();
+        containers.extend(self.sidecars.iter());
+        containers.extend(self.workers.iter().map(|w| &w.container));
+        containers.extend(self.cronJobs.iter().map(|c| &c.container));
+        containers.extend(self.initContainers.iter());
+        containers
+    }
+
+    // Mutable variant of get_containers.
+    pub fn get_containers_mut(&mut self) -> Vec<&mut Container> {
+        let mut containers = Vec::new();
+        containers.extend(self.sidecars.iter_mut());
+        containers.extend(self.workers.iter_mut().map(|w| &mut w.container));
+        containers.extend(self.cronJobs.iter_mut().map(|c| &mut c.container));
+        containers.extend(self.initContainers.iter_mut());
+        containers
+    }
+
     // Get EnvVars for all containers, workers etc. for this Manifest.
     pub fn get_env_vars(&mut self) -> Vec<&mut EnvVars> {
+        self.get_named_env_vars().into_iter().map(|(_, e)| e).collect()
+    }
+
+    // Get EnvVars for all containers, workers etc. along with their container names.
+    pub fn get_named_env_vars(&mut self) -> Vec<(String, &mut EnvVars)> {
         let mut envs = Vec::new();
-        envs.push(&mut self.env);
+        envs.push((self.name.clone(), &mut self.env));
         for s in &mut self.sidecars {
-            envs.push(&mut s.env);
+            envs.push((s.name.clone(), &mut s.env));
         }
         for w in &mut self.workers {
-            envs.push(&mut w.container.env);
+            envs.push((w.container.name.clone(), &mut w.container.env));
         }
         for c in &mut self.cronJobs {
-            envs.push(&mut c.container.env);
+            envs.push((c.container.name.clone(), &mut c.container.env));
         }
         for i in &mut self.initContainers {
-            envs.push(&mut i.env);
+            envs.push((i.name.clone(), &mut i.env));
         }
         envs
     }
 
     /// Populate placeholder fields with secrets from vault
     ///
-    /// This will use the HTTP api of Vault using the configuration parameters
-    /// in the `Config`.
-    pub async fn secrets(&mut self, client: &Vault, vc: &VaultConfig) -> Result<()> {
+    /// This will use the secret backend configured for the region in the `Config`.
+    pub async fn secrets(&mut self, client: &dyn SecretStore, vc: &VaultConfig) -> Result<()> {
         let pth = self.get_vault_path(vc);
-        debug!("Injecting secrets from vault {} ({:?})", pth, client.mode());
+        debug!("Injecting secrets from {} using {}", pth, client.describe());
 
-        let mut vault_secrets = BTreeSet::new();
+        let mut vault_secrets: BTreeMap<String, (VaultRef, Vec<String>)> = BTreeMap::new();
         let mut template_secrets = BTreeMap::new();
-        for e in &mut self.get_env_vars() {
-            for k in e.vault_secrets() {
-                vault_secrets.insert(k.to_string());
+        for (container, e) in &mut self.get_named_env_vars() {
+            for (k, vref) in e.vault_secrets() {
+                let (existing, containers) = vault_secrets
+                    .entry(k.clone())
+                    .or_insert_with(|| (vref.clone(), vec![]));
+                if existing != &vref {
+                    bail!("Secret {} can not reference different secrets in different containers", k);
+                }
+                containers.push(container.clone());
             }
             for (k, v) in e.template_secrets() {
                 let original = template_secrets.insert(k.to_string(), v.to_string());
@@ -1117,24 +1240,33 @@ impl Manifest {
             }
         }
 
-        let template_keys = template_secrets.keys().map(|x| x.to_string()).collect();
-        if let Some(k) = vault_secrets.intersection(&template_keys).next() {
+        if let Some(k) = vault_secrets.keys().find(|k| template_secrets.contains_key(*k)) {
             bail!("Secret {} can not be both templated and fetched from vault", k);
         }
 
         // Lookup values for each secret in vault.
-        for k in vault_secrets {
-            let vkey = format!("{}/{}", pth, k);
-            self.secrets.insert(k.to_string(), client.read(&vkey).await?);
+        for (k, (vref, containers)) in vault_secrets {
+            let vkey = format!("{}/{}", pth, vref.key);
+            let (value, version) = client.read_version(&vkey, vref.version).await?;
+            if let Some(ver) = version {
+                for c in containers {
+                    self.secretVersions.insert(format!("{}/{}", c, k), ver);
+                }
+            }
+            self.secrets.insert(k, value);
         }
 
         self.secrets.append(&mut template_secrets);
 
         // do the same for secret secrets
         for (k, v) in &mut self.secretFiles {
-            if v == "IN_VAULT" {
-                let vkey = format!("{}/{}", pth, k);
-                *v = client.read(&vkey).await?;
+            if let Some(vref) = VaultRef::parse(k, v)? {
+                let vkey = format!("{}/{}", pth, vref.key);
+                let (value, version) = client.read_version(&vkey, vref.version).await?;
+                if let Some(ver) = version {
+                    self.secretVersions.insert(k.clone(), ver);
+                }
+                *v = value;
             }
             // sanity check; secretFiles are assumed base64 verify we can decode
             if base64::decode(v).is_err() {
@@ -1144,6 +1276,47 @@ impl Manifest {
         Ok(())
     }
 
+    /// Secret references of all containers and secret files
+    ///
+    /// Keyed like `secretVersions`, by `container/EVAR` or file name.
+    /// Only meaningful on a manifest that has not had its secrets resolved.
+    pub fn vault_refs(&self) -> Result<BTreeMap<String, VaultRef>> {
+        let mut mf = self.clone();
+        let mut res = BTreeMap::new();
+        for (k, v) in &mf.secretFiles {
+            if let Some(vref) = VaultRef::parse(k, v)? {
+                res.insert(k.clone(), vref);
+            }
+        }
+        for (container, e) in mf.get_named_env_vars() {
+            for (k, vref) in e.vault_refs() {
+                res.insert(format!("{}/{}", container, k), vref);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Pin unpinned secret references to the given versions
+    ///
+    /// Used to restore the exact secrets of a previous rollout from `secretVersions`.
+    pub fn pin_secrets(&mut self, versions: &BTreeMap<String, u32>) -> Result<()> {
+        let pin = |k: &str, v: &mut String| -> Result<()> {
+            if let (Some(vref), Some(ver)) = (VaultRef::parse(k, v)?, versions.get(k)) {
+                if vref.version.is_none() {
+                    *v = vref.pinned(*ver);
+                }
+            }
+            Ok(())
+        };
+        for (k, v) in self.secretFiles.iter_mut() {
+            pin(k, v)?;
+        }
+        for (container, e) in self.get_named_env_vars() {
+            e.pin_secrets(|k| versions.get(&format!("{}/{}", container, k)).cloned());
+        }
+        Ok(())
+    }
+
     /// Get a list of raw secrets (without associated keys)
     ///
     /// Useful for obfuscation mechanisms so it knows what to obfuscate.
@@ -1157,31 +1330,30 @@ impl Manifest {
     }
 
     pub async fn verify_secrets_exist(&self, vc: &VaultConfig) -> Result<()> {
+        let v = vault::regional_store(vc)?;
+        self.verify_secrets_in(v.as_ref(), vc).await
+    }
+
+    /// Verify secrets exist in a secret store
+    pub async fn verify_secrets_in(&self, v: &dyn SecretStore, vc: &VaultConfig) -> Result<()> {
         use std::collections::HashSet;
         // what are we requesting
         // TODO: Use envvars directly
-        let keys = self
-            .env
-            .plain
-            .clone()
-            .into_iter()
-            .filter(|(_, v)| v == "IN_VAULT")
-            .map(|(k, _)| k)
-            .collect::<HashSet<_>>();
-        let files = self
-            .secretFiles
-            .clone()
-            .into_iter()
-            .filter(|(_, v)| v == "IN_VAULT")
-            .map(|(k, _)| k)
-            .collect::<HashSet<_>>();
-        let expected = keys.union(&files).cloned().collect::<HashSet<_>>();
+        let mut expected = HashSet::new();
+        // (in the form the store lists them)
+        for vref in self.env.vault_refs().values() {
+            expected.insert(v.listed_name(&vref.key));
+        }
+        for (k, val) in &self.secretFiles {
+            if let Some(vref) = VaultRef::parse(k, val)? {
+                expected.insert(v.listed_name(&vref.key));
+            }
+        }
         if expected.is_empty() {
             return Ok(()); // no point trying to cross reference
         }
 
         // what we have
-        let v = Vault::regional(vc)?;
         let secpth = self.get_vault_path(vc);
 
         // list secrets; fail immediately if folder is empty
@@ -1,4 +1,7 @@
+use std::ops::AddAssign;
+
 use super::{
+    cost::{CostModel, HOURS_PER_MONTH},
     structs::{rollingupdate::RollingUpdate, ResourceRequirements},
     Manifest, Result,
 };
@@ -12,6 +15,24 @@ pub struct ResourceTotals {
     pub base: ResourceRequirements<f64>,
     /// Autoscaling Ceilings on top of required
     pub extra: ResourceRequirements<f64>,
+    /// Monthly cost of the requests (zero until priced)
+    pub cost: ResourceCost,
+}
+
+/// Monthly cost of resource requests
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct ResourceCost {
+    /// Cost of the basic resource requests
+    pub base: f64,
+    /// Cost of the autoscaling ceilings on top of the base
+    pub extra: f64,
+}
+
+impl AddAssign for ResourceCost {
+    fn add_assign(&mut self, rhs: Self) {
+        self.base += rhs.base;
+        self.extra += rhs.extra;
+    }
 }
 
 impl ResourceTotals {
@@ -22,30 +43,30 @@ impl ResourceTotals {
         self
     }
 
-    /// Compute daily cost lower + upper bounds based on instance cost
+    /// Compute the monthly cost of the requests based on node pricing
     ///
-    /// Assumes the resource totals have been normalise first!
-    pub fn daily_cost(&self) -> (f64, f64) {
-        // instance cost is hourly cost for a resource.
-        // E.g. m5.2xlarge => $0.384 per Hour, but has 31GB ram, 8vCPU
-        let icost = 0.384 * 24.0;
-        let node_mem = 31.0;
-        let node_cpu = 8.0;
-
-        let memory_cost = (
-            (self.base.requests.memory * icost / node_mem).round(),
-            ((self.base.requests.memory + self.extra.requests.memory) * icost / node_mem).round(),
-        );
-        let cpu_cost = (
-            (self.base.requests.cpu * icost / node_cpu).round(),
-            ((self.base.requests.cpu + self.extra.requests.cpu * icost) * icost / node_cpu).round(),
-        );
-        // quick extimate at what would be more expensive
-        if cpu_cost.1 > memory_cost.1 {
-            cpu_cost
-        } else {
-            memory_cost
+    /// Assumes the resource totals have NOT been normalised yet.
+    pub fn priced(self, model: &CostModel) -> Result<Self> {
+        self.priced_in(&[model])
+    }
+
+    /// Compute the monthly cost of running the requests once in every cluster of `models`
+    ///
+    /// Assumes the resource totals have NOT been normalised yet.
+    pub fn priced_in(mut self, models: &[&CostModel]) -> Result<Self> {
+        let reqs = &self.base.requests;
+        let ceil = &self.extra.requests;
+        let mut cost = ResourceCost::default();
+        for model in models {
+            let base = model.hourly_cost(reqs.cpu, reqs.memory)?;
+            let upper = model.hourly_cost(reqs.cpu + ceil.cpu, reqs.memory + ceil.memory)?;
+            cost += ResourceCost {
+                base: base * HOURS_PER_MONTH,
+                extra: (upper - base) * HOURS_PER_MONTH,
+            };
         }
+        self.cost = cost;
+        Ok(self)
     }
 }
 
@@ -147,7 +168,11 @@ impl Manifest {
                 // TODO: mandatory? sidecar resources when using sidecars?
             }
         }
-        Ok(ResourceTotals { base, extra })
+        Ok(ResourceTotals {
+            base,
+            extra,
+            ..Default::default()
+        })
     }
 }
 
@@ -0,0 +1,385 @@
+use chrono::{NaiveDate, Utc};
+use serde_json::Value;
+use std::fmt;
+
+use super::{Manifest, Result};
+use crate::{
+    region::{Environment, Region},
+    structs::{parse_cpu, parse_memory, Container, ResourceRequirements},
+};
+
+/// How a policy violation is treated
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Reported, but does not fail validation
+    Warning,
+    /// Fails validation
+    Error,
+}
+
+impl Default for Severity {
+    fn default() -> Self {
+        Severity::Error
+    }
+}
+
+/// The check a policy performs on a manifest
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Rule {
+    /// Manifest properties that must be set
+    ///
+    /// Nested properties use dots, e.g. `health.uri`.
+    Require(Vec<String>),
+    /// Maximum memory limit of any container
+    MaxMemory(String),
+    /// Maximum cpu limit of any container
+    MaxCpu(String),
+    /// Image tags that must not be used, e.g. `latest`
+    ForbidTags(Vec<String>),
+}
+
+/// Exemption of a service from a policy
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Waiver {
+    /// Service exempted
+    pub service: String,
+    /// Last day the waiver applies
+    ///
+    /// After this the violation is reported at the policy's severity again.
+    pub expires: NaiveDate,
+    /// Why the service is exempt
+    pub reason: String,
+}
+
+/// A validation rule for manifests declared in shipcat.conf
+///
+/// ```yaml
+/// policies:
+/// - name: prod-liveness
+///   environments: [prod]
+///   rule:
+///     require: [livenessProbe]
+///   waivers:
+///   - service: legacy-app
+///     expires: 2020-06-01
+///     reason: probe endpoint being written
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Policy {
+    /// Unique name of the policy
+    pub name: String,
+    /// What the policy is for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// How violations are treated
+    #[serde(default)]
+    pub severity: Severity,
+    /// Regions the policy applies to
+    ///
+    /// Applies to all regions if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<String>,
+    /// Environments the policy applies to
+    ///
+    /// Applies to all environments if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<Environment>,
+    /// Labels exempting services that have them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exemptLabels: Vec<String>,
+    /// The check to perform
+    pub rule: Rule,
+    /// Services exempt from the policy for a limited time
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub waivers: Vec<Waiver>,
+}
+
+/// A manifest failing a policy
+#[derive(Serialize, Clone, Debug)]
+pub struct Violation {
+    pub policy: String,
+    pub severity: Severity,
+    pub message: String,
+    /// Unexpired waiver for the violation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waiver: Option<Waiver>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.policy, self.severity_str(), self.message)?;
+        if let Some(w) = &self.waiver {
+            write!(f, " - waived until {}: {}", w.expires, w.reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl Violation {
+    fn severity_str(&self) -> &'static str {
+        match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    /// Whether the violation should fail validation
+    pub fn is_fatal(&self) -> bool {
+        self.severity == Severity::Error && self.waiver.is_none()
+    }
+}
+
+/// Name, resources and tag of every container in a manifest
+fn containers(mf: &Manifest) -> Vec<(&str, Option<&ResourceRequirements<String>>, Option<&str>)> {
+    let mut res = vec![(mf.name.as_str(), mf.resources.as_ref(), mf.version.as_deref())];
+    let sidecars = mf
+        .workers
+        .iter()
+        .map(|w| &w.container)
+        .chain(mf.sidecars.iter())
+        .chain(mf.initContainers.iter())
+        .chain(mf.cronJobs.iter().map(|c| &c.container));
+    res.extend(sidecars.map(|c: &Container| (c.name.as_str(), c.resources.as_ref(), c.version.as_deref())));
+    res
+}
+
+fn is_set(mf: &Value, path: &str) -> bool {
+    let mut v = mf;
+    for key in path.split('.') {
+        v = &v[key];
+    }
+    match v {
+        Value::Null => false,
+        Value::Array(xs) => !xs.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+        _ => true,
+    }
+}
+
+impl Rule {
+    fn verify(&self) -> Result<()> {
+        match self {
+            Rule::Require(paths) if paths.is_empty() => bail!("require rule needs properties"),
+            Rule::ForbidTags(tags) if tags.is_empty() => bail!("forbidTags rule needs tags"),
+            Rule::MaxMemory(m) => parse_memory(m).map(|_| ())?,
+            Rule::MaxCpu(c) => parse_cpu(c).map(|_| ())?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Describe every way a manifest breaks the rule
+    fn check(&self, mf: &Manifest) -> Result<Vec<String>> {
+        let mut res = vec![];
+        match self {
+            Rule::Require(paths) => {
+                let data = serde_json::to_value(mf)?;
+                for p in paths {
+                    if !is_set(&data, p) {
+                        res.push(format!("{} must be set", p));
+                    }
+                }
+            }
+            Rule::MaxMemory(max) => {
+                let limit = parse_memory(max)?;
+                for (name, rr, _) in containers(mf) {
+                    if let Some(rr) = rr {
+                        if parse_memory(&rr.limits.memory)? > limit {
+                            res.push(format!(
+                                "{} memory limit {} exceeds {}",
+                                name, rr.limits.memory, max
+                            ));
+                        }
+                    }
+                }
+            }
+            Rule::MaxCpu(max) => {
+                let limit = parse_cpu(max)?;
+                for (name, rr, _) in containers(mf) {
+                    if let Some(rr) = rr {
+                        if parse_cpu(&rr.limits.cpu)? > limit {
+                            res.push(format!("{} cpu limit {} exceeds {}", name, rr.limits.cpu, max));
+                        }
+                    }
+                }
+            }
+            Rule::ForbidTags(tags) => {
+                for (name, _, tag) in containers(mf) {
+                    if let Some(t) = tag {
+                        if tags.iter().any(|x| x == t) {
+                            res.push(format!("{} uses forbidden tag {}", name, t));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl Policy {
+    pub fn verify(&self) -> Result<()> {
+        if self.name.is_empty() {
+            bail!("Policies need a name");
+        }
+        if let Err(e) = self.rule.verify() {
+            bail!("Policy {} has an invalid rule: {}", self.name, e);
+        }
+        for w in &self.waivers {
+            if w.reason.is_empty() {
+                bail!(
+                    "Waiver for {} from policy {} needs a reason",
+                    w.service,
+                    self.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the policy applies to a manifest in a region
+    pub fn applies(&self, mf: &Manifest, reg: &Region) -> bool {
+        (self.regions.is_empty() || self.regions.contains(&reg.name))
+            && (self.environments.is_empty() || self.environments.contains(&reg.environment))
+            && !self.exemptLabels.iter().any(|l| mf.labels.contains_key(l))
+    }
+
+    /// Evaluate the policy against a manifest as of a given day
+    pub fn evaluate(&self, mf: &Manifest, reg: &Region, today: NaiveDate) -> Result<Vec<Violation>> {
+        if !self.applies(mf, reg) {
+            return Ok(vec![]);
+        }
+        let waiver = self.waivers.iter().find(|w| w.service == mf.name);
+        let waiver = match waiver {
+            Some(w) if w.expires < today => {
+                warn!(
+                    "Waiver for {} from policy {} expired on {}",
+                    mf.name, self.name, w.expires
+                );
+                None
+            }
+            w => w.cloned(),
+        };
+        let res = self
+            .rule
+            .check(mf)?
+            .into_iter()
+            .map(|message| Violation {
+                policy: self.name.clone(),
+                severity: self.severity,
+                message,
+                waiver: waiver.clone(),
+            })
+            .collect();
+        Ok(res)
+    }
+}
+
+/// Evaluate policies against a manifest today
+pub fn evaluate(policies: &[Policy], mf: &Manifest, reg: &Region) -> Result<Vec<Violation>> {
+    let today = Utc::now().naive_utc().date();
+    let mut res = vec![];
+    for p in policies {
+        res.extend(p.evaluate(mf, reg, today)?);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Policy, Severity};
+    use crate::{region::Environment, Manifest, Region};
+    use chrono::NaiveDate;
+
+    fn policy(yaml: &str) -> Policy {
+        let p: Policy = serde_yaml::from_str(yaml).unwrap();
+        p.verify().unwrap();
+        p
+    }
+
+    fn manifest() -> Manifest {
+        serde_yaml::from_str(
+            "
+name: fake-ask
+version: latest
+labels:
+  big-memory: 'true'
+resources:
+  requests: { cpu: 100m, memory: 1Gi }
+  limits: { cpu: 2, memory: 8Gi }
+sidecars:
+- name: redis
+  version: latest
+",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn policy_rules() {
+        let mf = manifest();
+        let reg = Region {
+            name: "prod-uk".into(),
+            environment: Environment::Prod,
+            ..Default::default()
+        };
+        let today = NaiveDate::from_ymd(2020, 3, 1);
+
+        let liveness = policy("{ name: liveness, environments: [prod], rule: { require: [livenessProbe] } }");
+        let vs = liveness.evaluate(&mf, &reg, today).unwrap();
+        assert_eq!(vs.len(), 1);
+        assert_eq!(vs[0].message, "livenessProbe must be set");
+        assert!(vs[0].is_fatal());
+        let dev = Region {
+            name: "dev-uk".into(),
+            environment: Environment::Dev,
+            ..Default::default()
+        };
+        assert!(liveness.evaluate(&mf, &dev, today).unwrap().is_empty());
+
+        let tags = policy("{ name: no-latest, severity: warning, rule: { forbidTags: [latest] } }");
+        let vs = tags.evaluate(&mf, &reg, today).unwrap();
+        assert_eq!(vs.len(), 2);
+        assert_eq!(vs[1].message, "redis uses forbidden tag latest");
+        assert_eq!(vs[0].severity, Severity::Warning);
+        assert!(!vs[0].is_fatal());
+
+        let memory = policy("{ name: memory, rule: { maxMemory: 4Gi } }");
+        let vs = memory.evaluate(&mf, &reg, today).unwrap();
+        assert_eq!(vs[0].message, "fake-ask memory limit 8Gi exceeds 4Gi");
+        let exempt = policy("{ name: memory, exemptLabels: [big-memory], rule: { maxMemory: 4Gi } }");
+        assert!(exempt.evaluate(&mf, &reg, today).unwrap().is_empty());
+        let cpu = policy("{ name: cpu, rule: { maxCpu: 2500m } }");
+        assert!(cpu.evaluate(&mf, &reg, today).unwrap().is_empty());
+    }
+
+    #[test]
+    fn policy_waivers() {
+        let mf = manifest();
+        let reg = Region::default();
+        let p = policy(
+            "
+name: liveness
+rule:
+  require: [livenessProbe]
+waivers:
+- service: fake-ask
+  expires: 2020-03-01
+  reason: probe being written
+",
+        );
+        let vs = p.evaluate(&mf, &reg, NaiveDate::from_ymd(2020, 3, 1)).unwrap();
+        assert!(vs[0].waiver.is_some());
+        assert!(!vs[0].is_fatal());
+        let vs = p.evaluate(&mf, &reg, NaiveDate::from_ymd(2020, 3, 2)).unwrap();
+        assert!(vs[0].waiver.is_none());
+        assert!(vs[0].is_fatal());
+
+        let invalid = "{ name: memory, rule: { maxMemory: 4Gb } }";
+        assert!(serde_yaml::from_str::<Policy>(invalid).unwrap().verify().is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::structs::kong::Kong;
+use chrono::{DateTime, Utc};
 use std::{collections::BTreeMap, env};
 
 use regex::Regex;
@@ -8,7 +9,11 @@ use semver::Version;
 use url::Url;
 use uuid::Uuid;
 
-#[allow(unused_imports)] use super::{BaseManifest, ConfigState, Result, Vault};
+#[allow(unused_imports)] use super::{BaseManifest, ConfigState, Result};
+use crate::{
+    freeze::FreezeWindow,
+    vault::{self, SecretStore},
+};
 
 use super::structs::Authorization;
 
@@ -16,7 +21,7 @@ use super::structs::Authorization;
 ///
 /// This is valdiated strictly using `shipcat validate` when versions are found in manifests.
 /// Otherwise, it's validated on upgrade time (via `shipcat apply`) when it's passed.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum VersionScheme {
     /// Version must be valid semver (no leading v)
     ///
@@ -57,22 +62,76 @@ impl VersionScheme {
     }
 }
 
+/// Where secrets for a region are stored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretBackend {
+    /// HashiCorp Vault's KV HTTP API at the configured url
+    Vault,
+    /// A local encrypted yaml file at the given path
+    ///
+    /// Values are decrypted with the key in `SHIPCAT_SECRET_KEY`.
+    File(String),
+    /// Environment variables prefixed with `SHIPCAT_SECRET__`
+    Env,
+}
+
+impl Default for SecretBackend {
+    fn default() -> Self {
+        SecretBackend::Vault
+    }
+}
+
+/// Version of vault's KV secret engine mounted at `secret/`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum KvVersion {
+    /// Unversioned secrets
+    V1,
+    /// Versioned secrets under `secret/data/` and `secret/metadata/`
+    V2,
+}
+
+impl Default for KvVersion {
+    fn default() -> Self {
+        KvVersion::V1
+    }
+}
+
 /// Vault configuration for a region
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct VaultConfig {
     /// Vault url up to and including port
+    ///
+    /// Only required for the vault backend.
+    #[serde(default)]
     pub url: String,
     /// Root folder under secret/
     ///
     /// Typically, the name of the region to disambiguate.
     pub folder: String,
+    /// Secret backend to use
+    ///
+    /// ```yaml
+    /// vault:
+    ///   folder: dev-uk
+    ///   backend:
+    ///     file: secrets/dev-uk.enc.yml
+    /// ```
+    #[serde(default)]
+    pub backend: SecretBackend,
+    /// KV secret engine version of the vault backend
+    ///
+    /// Secrets can only be pinned to versions with `v2`.
+    #[serde(default)]
+    pub kvVersion: KvVersion,
 }
 
 impl VaultConfig {
     pub fn verify(&self, region: &str) -> Result<()> {
-        if self.url == "" {
+        if self.backend == SecretBackend::Vault && self.url == "" {
             bail!("Need to set vault url for {}", region);
         }
         if self.folder == "" {
@@ -114,7 +173,7 @@ impl VaultConfig {
 //}
 
 /// Kafka configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KafkaConfig {
     /// Broker urls in "hostname:port" format.
@@ -134,7 +193,7 @@ pub struct KafkaConfig {
 }
 
 /// Webhook types that shipcat might trigger after actions
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(tag = "name", deny_unknown_fields, rename_all = "snake_case")]
 pub enum Webhook {
     /// Audit webhook details
@@ -142,17 +201,18 @@ pub enum Webhook {
 }
 
 /// Where / how to send audited events
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct AuditWebhook {
     /// Endpoint
+    #[schemars(with = "String")]
     pub url: Url,
     /// Credential
     pub token: String,
 }
 
 /// Configure how CRs will be deployed on a region
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct CRSettings {
     #[serde(rename = "config")]
@@ -162,7 +222,7 @@ pub struct CRSettings {
 // ----------------------------------------------------------------------------------
 
 /// Kong configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongConfig {
     /// Base URL to use (e.g. uk.dev.babylontech.co.uk)
@@ -182,7 +242,7 @@ pub struct KongConfig {
 }
 
 /// StatusCake configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct StatuscakeConfig {
     /// Contact Group that will be used if tests go down
@@ -194,7 +254,7 @@ pub struct StatuscakeConfig {
 }
 
 /// Logz.io configuration for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct LogzIoConfig {
     /// Base URL to use (e.g. https://app-eu.logz.io/#/dashboard/kibana/dashboard)
@@ -204,7 +264,7 @@ pub struct LogzIoConfig {
 }
 
 /// Grafana details for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct GrafanaConfig {
     /// Base URL to use (e.g. https://dev-grafana.ops.babylontech.co.uk)
@@ -214,13 +274,104 @@ pub struct GrafanaConfig {
 }
 
 /// Sentry details for a region
-#[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct SentryConfig {
     /// Base URL to use (e.g. https://dev-uk-sentry.ops.babylontech.co.uk)
     pub url: String,
 }
 
+/// Image verification for a region
+///
+/// `shipcat apply` resolves the version of a service to a digest through the
+/// Docker Registry v2 api, and refuses to apply images that do not exist.
+/// Sidecars, workers, cron jobs and init containers with an image of their own are checked too.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ImageVerification {
+    /// Pin the resolved digest into the manifest as `imageDigest`
+    #[serde(default)]
+    pub pinDigest: bool,
+
+    /// PEM encoded ECDSA P-256 public keys that images must be signed with
+    ///
+    /// Signatures are looked up the way `cosign` stores them, as a `sha256-<hex>.sig` tag
+    /// in the repository of the image. A valid signature from any of the keys is accepted.
+    /// Requiring signatures also pins the digest that was verified.
+    ///
+    /// Sidecars without an image of their own run an image from the chart that cannot be verified,
+    /// so they are refused while signatures are required.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub publicKeys: Vec<String>,
+}
+
+/// DER prefix of an ECDSA P-256 key in `SubjectPublicKeyInfo` form
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86,
+    0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+impl ImageVerification {
+    pub fn verify(&self) -> Result<()> {
+        self.public_keys()?;
+        Ok(())
+    }
+
+    /// Whether the resolved digest should be pinned into manifests
+    pub fn pins_digest(&self) -> bool {
+        self.pinDigest || !self.publicKeys.is_empty()
+    }
+
+    /// Uncompressed P-256 points of the `publicKeys`
+    pub fn public_keys(&self) -> Result<Vec<Vec<u8>>> {
+        self.publicKeys.iter().map(|pem| parse_public_key(pem)).collect()
+    }
+}
+
+fn parse_public_key(pem: &str) -> Result<Vec<u8>> {
+    let body = pem
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with("-----"))
+        .collect::<Vec<_>>()
+        .concat();
+    let der = match base64::decode(body.trim()) {
+        Ok(d) => d,
+        Err(_) => bail!("Image signing keys must be PEM encoded"),
+    };
+    if der.len() != P256_SPKI_PREFIX.len() + 65 || !der.starts_with(&P256_SPKI_PREFIX) {
+        bail!("Image signing keys must be ECDSA P-256 public keys");
+    }
+    Ok(der[P256_SPKI_PREFIX.len()..].to_vec())
+}
+
+#[cfg(test)]
+mod test_image_verification {
+    use super::ImageVerification;
+
+    #[test]
+    fn region_image_signing_keys() {
+        let key = "-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEYyBgEV/cbqTBJ/gsB8LbN923EchC
+nnV6EEA7zoXjEi6c9U7fS9veMDSh9w/yIhtwzn3t2t4owR8jFIrWhlNSLw==
+-----END PUBLIC KEY-----";
+        let mut iv = ImageVerification {
+            pinDigest: false,
+            publicKeys: vec![key.into()],
+        };
+        let keys = iv.public_keys().unwrap();
+        assert_eq!(keys[0].len(), 65);
+        assert_eq!(keys[0][0], 0x04); // uncompressed point
+        assert!(iv.pins_digest());
+
+        iv.publicKeys = vec!["-----BEGIN PUBLIC KEY-----\nnope\n-----END PUBLIC KEY-----".into()];
+        assert!(iv.verify().is_err());
+        // an rsa key is not accepted either
+        iv.publicKeys = vec![base64::encode(&[0x30, 0x82, 0x01, 0x22])];
+        assert!(iv.verify().is_err());
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongAnonymousConsumers {
@@ -235,14 +386,14 @@ pub struct KongOauthConsumer {
     pub username: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongJwtConsumer {
     pub kid: String,
     pub public_key: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongTcpLogConfig {
     pub enabled: bool,
@@ -258,14 +409,14 @@ impl KongConfig {
 
 /// Defaults for services in this region
 // TODO: This should be ManifestDefaults from shipcat_filebacked
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Clone, Debug, Default, JsonSchema)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DefaultConfig {
     pub kong: DefaultKongConfig,
 }
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DefaultKongConfig {
@@ -275,7 +426,7 @@ pub struct DefaultKongConfig {
 }
 
 impl Webhook {
-    async fn secrets(&mut self, vault: &Vault, region: &str) -> Result<()> {
+    async fn secrets(&mut self, vault: &dyn SecretStore, region: &str) -> Result<()> {
         match self {
             Webhook::Audit(h) => {
                 if h.token == "IN_VAULT" {
@@ -287,7 +438,7 @@ impl Webhook {
         Ok(())
     }
 
-    async fn verify_secrets_exist(&self, vault: &Vault, region: &str) -> Result<()> {
+    async fn verify_secrets_exist(&self, vault: &dyn SecretStore, region: &str) -> Result<()> {
         match self {
             Webhook::Audit(_h) => {
                 let vkey = format!("{}/shipcat/WEBHOOK_AUDIT_TOKEN", region);
@@ -391,7 +542,7 @@ mod test_webhooks {
 // ----------------------------------------------------------------------------------
 
 /// Environments are well defined strings
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Environment {
     /// Production environment
@@ -426,13 +577,19 @@ impl ToString for Environment {
 // ----------------------------------------------------------------------------------
 
 /// Environments are well defined strings
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum ReconciliationMode {
     /// Shipcat owned, CRD based decision
     ///
     /// Requires kubernetes 1.13 and above (default).
     /// If CRD was configured, kube apply chart with owner references
     CrdOwned,
+
+    /// Operator owned, CRD based decision
+    ///
+    /// `shipcat apply` and `cluster crd reconcile` only update the CRDs,
+    /// and `shipcat operator` rolls out the changes as it sees them.
+    Operator,
 }
 
 impl Default for ReconciliationMode {
@@ -447,7 +604,7 @@ impl Default for ReconciliationMode {
 ///
 /// Either it's a pure kubernetes context with a namespace and a cluster,
 /// or it's an abstract concept with many associated real kubernetes contexts.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Region {
@@ -463,6 +620,12 @@ pub struct Region {
     #[serde(default)]
     pub reconciliationMode: ReconciliationMode,
 
+    /// Automatically roll back services whose rollout failed
+    ///
+    /// Services can override this with `autoRollback` in their manifest.
+    #[serde(default)]
+    pub autoRollback: bool,
+
     /// Primary cluster serving this region
     ///
     /// Shipcat does not use this for to decide where a region gets deployed,
@@ -473,9 +636,29 @@ pub struct Region {
     /// Jobs that decide where to deploy a region to should use `get clusterinfo`
     /// with explicit cluster names and regions.
     pub cluster: String,
+
+    /// Clusters serving this region at the same time (active/active)
+    ///
+    /// When set, `shipcat apply` rolls out to every one of these clusters in parallel,
+    /// rather than to the current context. Restarts, deletes and crd reconciles cover them all. The kube context of each cluster is the one named
+    /// after the cluster, the region, or a `contextAliases` entry, that points at the cluster api.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub activeClusters: Vec<String>,
+
+    /// Periods during which changes to the region are refused
+    ///
+    /// `shipcat apply`, `restart` and `cluster crd reconcile` need an override with a reason
+    /// while any of these are in effect.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub freezeWindows: Vec<FreezeWindow>,
+
     /// Versioning scheme
     pub versioningScheme: VersionScheme,
 
+    /// Verification of images against their docker registry before applying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imageVerification: Option<ImageVerification>,
+
     /// Important base urls that can be templated in evars
     #[serde(default)]
     pub base_urls: BTreeMap<String, String>,
@@ -516,28 +699,39 @@ pub struct Region {
     pub defaults: DefaultConfig,
     /// The regular expression used to verify destination rules' regions
     #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_regex")]
+    #[schemars(with = "Option<String>", skip_serializing)]
     pub destinationRuleHostRegex: Option<Regex>,
 }
 
 impl Region {
     // Internal secret populator for Config::new
     pub async fn secrets(&mut self) -> Result<()> {
-        let v = Vault::regional(&self.vault)?;
+        let v = vault::regional_store(&self.vault)?;
         for wh in self.webhooks.iter_mut() {
-            wh.secrets(&v, &self.name).await?;
+            wh.secrets(v.as_ref(), &self.name).await?;
         }
         Ok(())
     }
 
     // Entry point for region verifier
     pub async fn verify_secrets_exist(&self) -> Result<()> {
-        let v = Vault::regional(&self.vault)?;
+        let v = vault::regional_store(&self.vault)?;
         for wh in &self.webhooks {
-            wh.verify_secrets_exist(&v, &self.name).await?;
+            wh.verify_secrets_exist(v.as_ref(), &self.name).await?;
         }
         Ok(())
     }
 
+    /// The first freeze window in effect at a given time
+    pub fn active_freeze(&self, now: DateTime<Utc>) -> Result<Option<&FreezeWindow>> {
+        for w in &self.freezeWindows {
+            if w.is_active(now)? {
+                return Ok(Some(w));
+            }
+        }
+        Ok(None)
+    }
+
     // Get the Vault URL for a given service in this region
     pub fn vault_url(&self, app: &str) -> String {
         let vault_url = self.vault.url.clone();
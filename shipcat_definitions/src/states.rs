@@ -1,7 +1,10 @@
-use super::{vault::Vault, Manifest, Region, Result};
+use super::{
+    vault::{self, SecretStore, Vault},
+    Manifest, Region, Result,
+};
 
 /// Type of primary workload that is associated with the Manifest
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum PrimaryWorkload {
     Deployment,
     Statefulset,
@@ -84,9 +87,9 @@ impl Manifest {
     /// Upgrade a `Base` manifest to either a Complete or a Stubbed one
     async fn upgrade(mut self, reg: &Region, state: ManifestState) -> Result<Self> {
         assert_eq!(self.state, ManifestState::Base); // sanity
-        let v = match state {
-            ManifestState::Completed => Vault::regional(&reg.vault)?,
-            ManifestState::Stubbed => Vault::mocked(&reg.vault)?,
+        let v: Box<dyn SecretStore> = match state {
+            ManifestState::Completed => vault::regional_store(&reg.vault)?,
+            ManifestState::Stubbed => Box::new(Vault::mocked(&reg.vault)?),
             _ => bail!("Can only upgrade a Base manifest to Completed or Stubbed"),
         };
         // replace one-off templates in evar strings with values
@@ -94,7 +97,7 @@ impl Manifest {
         // secrets may be injected at this step from the Region
         self.template_evars(reg)?;
         // secrets before configs (.j2 template files use raw secret values)
-        self.secrets(&v, &reg.vault).await?;
+        self.secrets(v.as_ref(), &reg.vault).await?;
 
         // templates last
         self.template_configs(reg)?;
@@ -122,7 +125,7 @@ impl Manifest {
 /// Various states a Config can exist in depending on resolution.
 ///
 /// Within shipcat, this is used to optimize speed of accessors.
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
 pub enum ConfigState {
     /// A filtered config for a specific region, with resolved secrets
     Filtered,
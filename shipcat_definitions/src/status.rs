@@ -1,5 +1,6 @@
 use super::Result;
 use chrono::{SecondsFormat, Utc};
+use std::collections::BTreeMap;
 
 pub fn make_date() -> String {
     // Format == `1996-12-19T16:39:57-08:00`, but we hardcode Utc herein.
@@ -18,6 +19,14 @@ pub struct ManifestStatus {
     /// A more easily readable summary of why the conditions are what they are
     #[serde(default)]
     pub summary: Option<ConditionSummary>,
+    /// The `.metadata.generation` last reconciled by `shipcat operator`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    /// Outcome of the last apply in every cluster of an active/active region
+    ///
+    /// Keyed by cluster name, and written to the crd in each cluster once all of them are done.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub clusters: BTreeMap<String, Condition>,
     /* TODO: vault secret hash
      * MAYBE: kong status?
      * MAYBE: canary status? */
@@ -53,6 +62,21 @@ pub struct Conditions {
     /// Best effort information given in message, but this won't replace DeploymentConditions
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub rolledout: Option<Condition>,
+
+    /// Automatic rollback after a failed rollout
+    ///
+    /// Only set when `autoRollback` is enabled. If rolledback.status is false,
+    /// the previous version could not be re-applied or failed to roll out itself.
+    /// The message contains the versions involved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rolledback: Option<Condition>,
+
+    /// Kubernetes objects match what the shipcatmanifest templates to
+    ///
+    /// Only set by `shipcat cluster drift`. If synced.status is false, objects have been
+    /// changed outside of shipcat (e.g. with `kubectl edit`), and the message lists them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced: Option<Condition>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -93,6 +117,16 @@ pub struct ConditionSummary {
     /// Last version that was successfully rolled out
     #[serde(default)]
     pub last_successful_rollout_version: Option<String>,
+
+    /// Secret versions used by the last successful rollout
+    ///
+    /// Only recorded for versioned secret stores, and used to pin secrets on rollback.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub last_successful_secret_versions: BTreeMap<String, u32>,
+
+    /// Date string (RFC3339) of when we last rolled back automatically
+    #[serde(default)]
+    last_rollback: Option<String>,
 }
 
 /// Condition
@@ -0,0 +1,21 @@
+/// Blue/green deployment parameters
+///
+/// Instead of a rolling update, a full second Deployment (the other colour) is brought up.
+/// Once it has rolled out, the Service selector is switched over to it,
+/// and the old colour is removed after the soak time.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct BlueGreen {
+    /// Seconds to keep the old colour around after switching traffic
+    #[serde(default = "soak_default")]
+    pub soak: u32,
+}
+
+fn soak_default() -> u32 {
+    300
+}
+
+impl Default for BlueGreen {
+    fn default() -> Self {
+        BlueGreen { soak: soak_default() }
+    }
+}
@@ -0,0 +1,116 @@
+use super::Result;
+
+/// A single step of a canary rollout
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct CanaryStep {
+    /// Percentage of replicas to run on the new version during this step
+    pub weight: u32,
+    /// Seconds to observe the canary at this weight before advancing
+    #[serde(default)]
+    pub pause: u32,
+}
+
+/// Canary rollout parameters
+///
+/// A parallel canary Deployment is scaled through the steps before the main Deployment
+/// is upgraded. The Service selects both, so traffic is split by replica count.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct Canary {
+    /// Steps to advance through, in increasing weight
+    pub steps: Vec<CanaryStep>,
+    /// Abort when canary containers restart more than this many times in total
+    #[serde(default)]
+    pub maxRestarts: u32,
+    /// Abort when more than this many canary pods are not ready
+    #[serde(default)]
+    pub maxUnready: u32,
+}
+
+impl Canary {
+    pub fn verify(&self) -> Result<()> {
+        if self.steps.is_empty() {
+            bail!("Need at least one step in canary");
+        }
+        let mut prev = 0;
+        for s in &self.steps {
+            if s.weight == 0 || s.weight >= 100 {
+                bail!("Canary step weight must be between 1 and 99, got {}", s.weight);
+            }
+            if s.weight <= prev {
+                bail!("Canary step weights must be increasing");
+            }
+            prev = s.weight;
+        }
+        Ok(())
+    }
+
+    /// Number of canary replicas needed for a step weight
+    ///
+    /// The main replicas keep running next to the canary, so the canary needs
+    /// `weight / (100 - weight)` pods per main replica to get `weight`% of the traffic.
+    /// Rounds to the nearest pod, but every step runs at least one canary pod.
+    pub fn replicas_for(&self, weight: u32, replicas: u32) -> u32 {
+        let rest = std::cmp::max(1, 100 - std::cmp::min(weight, 99));
+        let res = (f64::from(replicas) * f64::from(weight) / f64::from(rest)).round() as u32;
+        std::cmp::max(1, res)
+    }
+
+    /// Percentage of traffic a canary with `canary` pods gets next to `replicas` main pods
+    pub fn traffic_weight(canary: u32, replicas: u32) -> u32 {
+        let total = canary + replicas;
+        if total == 0 {
+            return 0;
+        }
+        (100 * canary + total / 2) / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Canary, CanaryStep};
+
+    fn canary(weights: &[u32]) -> Canary {
+        Canary {
+            steps: weights
+                .iter()
+                .map(|w| CanaryStep {
+                    weight: *w,
+                    pause: 60,
+                })
+                .collect(),
+            maxRestarts: 0,
+            maxUnready: 0,
+        }
+    }
+
+    #[test]
+    fn canary_verify() {
+        assert!(canary(&[10, 50]).verify().is_ok());
+        assert!(canary(&[]).verify().is_err());
+        assert!(canary(&[50, 10]).verify().is_err());
+        assert!(canary(&[10, 100]).verify().is_err());
+    }
+
+    #[test]
+    fn canary_replicas() {
+        let c = canary(&[10, 50]);
+        assert_eq!(c.replicas_for(10, 2), 1);
+        assert_eq!(c.replicas_for(10, 18), 2);
+        assert_eq!(c.replicas_for(50, 5), 5);
+        assert_eq!(c.replicas_for(25, 3), 1);
+        assert_eq!(c.replicas_for(75, 4), 12);
+    }
+
+    #[test]
+    fn canary_traffic_weight() {
+        let c = canary(&[10, 25, 50, 75]);
+        // the canary shares traffic with every main replica
+        for (weight, replicas) in &[(10, 18), (25, 3), (50, 5), (50, 1), (75, 4), (20, 40)] {
+            let pods = c.replicas_for(*weight, *replicas);
+            assert_eq!(Canary::traffic_weight(pods, *replicas), *weight);
+        }
+        // small deployments get as close as a single pod allows
+        assert_eq!(Canary::traffic_weight(c.replicas_for(10, 2), 2), 33);
+        assert_eq!(Canary::traffic_weight(0, 0), 0);
+    }
+}
@@ -1,6 +1,6 @@
 use super::{EnvVars, Port, Probe, ResourceRequirements, VolumeMount};
 
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
 #[serde(default, rename_all = "camelCase")]
 pub struct Container {
     /// Name of container
@@ -12,6 +12,14 @@ pub struct Container {
     /// Docker image tag
     #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<String>,
+    /// Digest of the image the version resolved to in its registry
+    ///
+    /// Pinned by `shipcat apply` for containers with an image of their own,
+    /// in regions with `imageVerification.pinDigest`.
+    ///
+    /// This is an internal property that is exposed as an output only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_digest: Option<String>,
 
     /// Resource Requirements
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -4,7 +4,7 @@ use std::path::Path;
 /// Supported dependency protocols
 ///
 /// Forces lowercase values of this enum to be used
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DependencyProtocol {
     /// HTTP REST dependency
@@ -25,7 +25,7 @@ impl Default for DependencyProtocol {
 }
 
 /// Dependency of a service
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Dependency {
     /// Name of service relied upon (used to goto dependent manifest)
@@ -1,4 +1,5 @@
 use super::Result;
+use crate::vault::VaultRef;
 use std::{
     collections::{BTreeMap, BTreeSet},
     mem,
@@ -8,6 +9,7 @@ use std::{
 ///
 /// These have a few special convenience behaviours:
 /// "IN_VAULT" values is replaced with value from vault/secret/folder/service/KEY
+/// An explicit `vault` field reads a named secret, optionally pinned to a version (see `VaultRef`)
 /// One off `tera` templates are calculated with a limited template context
 ///
 /// IN_VAULT secrets will all be put in a single kubernetes `Secret` object.
@@ -20,6 +22,10 @@ use std::{
 ///
 ///   # vault lookup:
 ///   DATABASE_URL: IN_VAULT
+///   # pinned vault lookup:
+///   API_KEY:
+///     vault: API_KEY
+///     version: 3
 ///
 ///   # templated evars:
 ///   INTERNAL_AUTH_URL: "{{ base_urls.services }}/auth/internal"
@@ -32,13 +38,17 @@ use std::{
 /// region, and replace them internally.
 ///
 /// The `as_secret` destinction only serves to put `AUTH_SECRET` into `Manifest::secrets`.
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 #[serde(default)]
 pub struct EnvVars {
     /// Plain text (non-secret) environment variables
     #[serde(skip_serializing_if = "BTreeMap::is_empty")]
     pub plain: BTreeMap<String, String>,
 
+    /// Environment variables read from explicit secret references
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub vault: BTreeMap<String, VaultRef>,
+
     /// Environment variable names stored in secrets
     ///
     /// This is an internal property that is exposed as an output only.
@@ -50,14 +60,11 @@ impl EnvVars {
     pub fn new(env: BTreeMap<String, String>) -> Self {
         EnvVars {
             plain: env,
+            vault: Default::default(),
             secrets: Default::default(),
         }
     }
 
-    fn is_vault_secret(value: &str) -> bool {
-        value == "IN_VAULT"
-    }
-
     fn template_secret_value(value: &str) -> Option<String> {
         let prefix = "SHIPCAT_SECRET::";
         if value.starts_with(prefix) {
@@ -68,30 +75,49 @@ impl EnvVars {
     }
 
     pub fn verify(&self) -> Result<()> {
-        for k in self.plain.keys() {
+        for k in self.plain.keys().chain(self.vault.keys()) {
             if k != &k.to_uppercase() {
                 bail!("Env vars need to be uppercase, found: {}", k);
             }
         }
+        for (k, vref) in &self.vault {
+            vref.verify(k)?;
+        }
         Ok(())
     }
 
-    // Remove variables with a value "IN_VAULT", mark them as a secret and return them.
-    pub fn vault_secrets(&mut self) -> BTreeSet<String> {
-        let mut plain = BTreeMap::new();
-        let mut vs = BTreeSet::new();
-        for (k, v) in self.plain.iter() {
-            if EnvVars::is_vault_secret(&v) {
-                vs.insert(k.to_string());
-                self.secrets.insert(k.to_string());
-            } else {
-                plain.insert(k.to_string(), v.to_string());
+    /// Secret references of all variables, including `IN_VAULT` ones
+    pub fn vault_refs(&self) -> BTreeMap<String, VaultRef> {
+        let mut res = self.vault.clone();
+        for (k, v) in &self.plain {
+            if let Some(vref) = VaultRef::in_vault(k, v) {
+                res.insert(k.to_string(), vref);
             }
         }
-        mem::replace(&mut self.plain, plain);
+        res
+    }
+
+    // Remove variables referencing vault, mark them as a secret and return them with their references.
+    pub fn vault_secrets(&mut self) -> BTreeMap<String, VaultRef> {
+        let vs = self.vault_refs();
+        self.plain.retain(|k, _| !vs.contains_key(k));
+        self.vault.clear();
+        self.secrets.extend(vs.keys().cloned());
         vs
     }
 
+    /// Pin unpinned secret references to the versions returned for their names
+    pub fn pin_secrets(&mut self, version: impl Fn(&str) -> Option<u32>) {
+        let mut refs = self.vault_refs();
+        for (k, vref) in refs.iter_mut() {
+            if vref.version.is_none() {
+                vref.version = version(k);
+            }
+        }
+        self.plain.retain(|k, _| !refs.contains_key(k));
+        self.vault = refs;
+    }
+
     // Remove secrets generated from templates from the plain variables, mark them as a secret and return them.
     pub fn template_secrets(&mut self) -> BTreeMap<String, String> {
         let mut plain = BTreeMap::new();
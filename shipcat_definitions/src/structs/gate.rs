@@ -4,7 +4,7 @@ use std::ops::Not;
 ///
 /// Gate is a babylon-specific, filtering entry-point for kong, as such, requires kong.
 /// Configuration for gate is expected to be picked up outside of shipcat for services using kong.
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Gate {
     /// Let external traffic in or not
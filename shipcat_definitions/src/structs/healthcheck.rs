@@ -5,7 +5,7 @@
 ///
 /// If we need complete control over these, consider writing a probes struct
 /// and making it only allowed if this is not present.
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct HealthCheck {
     /// Where the health check is located
@@ -3,7 +3,7 @@ use regex::Regex;
 
 // HostAlias support for all pods regardless of network configuration.
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 pub struct HostAlias {
     /// ip address string
     pub ip: String,
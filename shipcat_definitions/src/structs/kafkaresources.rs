@@ -2,7 +2,7 @@ use super::Result;
 use regex::Regex;
 use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct KafkaTopics {
     pub name: String,
 
@@ -19,7 +19,7 @@ pub struct KafkaTopics {
 /// Resource Types relating to a Kafka ACL to be applied onto a resource,
 /// values derived from the Strimzi Kafka User Custom Resource Definition
 /// [Strimzi Kafka User CRD ](https://github.com/strimzi/strimzi-kafka-operator/blob/master/install/user-operator/04-Crd-kafkauser.yaml)
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum KafkaUserResourceType {
     Topic,
@@ -31,7 +31,7 @@ pub enum KafkaUserResourceType {
 /// Operations relating to a Kafka ACL to be applied onto a resource,
 /// values derived from the Strimzi Kafka User Custom Resource Definition
 /// [Strimzi Kafka User CRD ](https://github.com/strimzi/strimzi-kafka-operator/blob/master/install/user-operator/04-Crd-kafkauser.yaml)
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "PascalCase")]
 pub enum KafkaUserOperation {
     Read,
@@ -47,14 +47,14 @@ pub enum KafkaUserOperation {
     All,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum KafkaUserPatternType {
     Literal,
     Prefix,
 }
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct AclDefinition {
     pub resource_name: String,
@@ -71,7 +71,7 @@ fn default_host() -> String {
 }
 
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct KafkaUsers {
     pub name: String,
@@ -79,7 +79,7 @@ pub struct KafkaUsers {
 }
 
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct KafkaResources {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
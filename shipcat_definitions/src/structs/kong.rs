@@ -4,7 +4,7 @@ use super::Authorization;
 use crate::deserializers::comma_separated_string;
 
 /// Kong setup for a service
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Kong {
@@ -120,7 +120,7 @@ fn preserve_host_default() -> bool {
 }
 
 /// Cors plugin data
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Cors {
     pub credentials: bool,
@@ -134,7 +134,7 @@ pub struct Cors {
 }
 
 /// Babylon Auth Header plugin data
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct BabylonAuthHeader {
     pub auth_service: String,
@@ -143,7 +143,7 @@ pub struct BabylonAuthHeader {
     pub http_timeout_msec: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum Authentication {
     None,
@@ -11,7 +11,7 @@ use crate::config::SlackParameters;
 /// Legacy contact data
 ///
 /// This property is being phased out in favour of .maintainer
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Contact {
     /// Free text name
     pub name: String,
@@ -49,7 +49,7 @@ impl Contact {
 }
 
 /// Slack channel verifier
-#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug, JsonSchema)]
 pub struct SlackChannel(String);
 impl SlackChannel {
     pub fn new(chan: &str) -> Self {
@@ -90,7 +90,7 @@ impl DerefMut for SlackChannel {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Language {
     Rust,
@@ -122,7 +122,7 @@ pub enum Language {
 /// context:
 ///   name: consultations
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Context {
     /// name of parent context
@@ -143,7 +143,7 @@ impl Context {
 }
 
 /// Metadata for a service
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[cfg_attr(test, derive(Default))]
 pub struct Metadata {
     /// Git repository
@@ -54,7 +54,7 @@ pub use self::env::EnvVars;
 // translations - these are typically inlined in templates as yaml
 /// Kubernetes resource structs
 pub mod resources;
-pub use self::resources::{parse_memory, ResourceRequirements};
+pub use self::resources::{parse_cpu, parse_memory, ResourceRequirements, Resources};
 /// Kubernetes volumes
 pub mod volume;
 pub use self::volume::{Volume, VolumeMount};
@@ -67,6 +67,12 @@ pub use self::probes::Probe;
 /// Kubernetes rolling-update settings
 pub mod rollingupdate;
 pub use self::rollingupdate::RollingUpdate;
+/// Blue/green deployment settings
+pub mod bluegreen;
+pub use self::bluegreen::BlueGreen;
+/// Canary rollout settings
+pub mod canary;
+pub use self::canary::{Canary, CanaryStep};
 /// Kubernetes horizontal pod autoscaler
 pub mod autoscaling;
 /// Kubernetes container lifecycle events
@@ -1,5 +1,5 @@
 /// Modes for slack upgrade notifications in this region
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub enum NotificationMode {
     /// Do not notify on upgrades in this region
     Silent,
@@ -6,7 +6,7 @@ use regex::Regex;
 ///
 /// This roughly corresponds to a Rule object in the Prometheus Operator API spec:
 /// https://github.com/coreos/prometheus-operator/blob/master/Documentation/api.md#rule
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct PrometheusAlert {
     /// Name of the alert
     ///
@@ -45,7 +45,7 @@ pub struct PrometheusAlert {
 ///
 /// Represents the set of alert severities we allow in our Prometheus alerts.
 #[serde(rename_all = "lowercase")]
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub enum PrometheusAlertSeverity {
     /// Warning severity
     ///
@@ -1,7 +1,7 @@
 use super::Result;
 
 // Untagged enum to get around the weird validation
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum AvailabilityPolicy {
     Percentage(String),
@@ -62,7 +62,7 @@ impl AvailabilityPolicy {
 }
 
 /// Configuration parameters for Deployment.spec.strategy.rollingUpdate
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct RollingUpdate {
     /// How many replicas or percentage of replicas that can be down during rolling-update
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -1,7 +1,7 @@
 /// Security context for ownership of volumes
 ///
 /// Verbatim from [kubernetes SecurityContext](https://kubernetes.io/docs/tasks/configure-pod-container/security-context/#configure-volume-permission-and-ownership-change-policy-for-pods)
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
 #[serde(default)]
 pub struct SecurityContext {
     #[serde(default, skip_serializing_if = "Option::is_none")]
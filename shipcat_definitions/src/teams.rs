@@ -6,7 +6,7 @@ use std::{
 };
 
 /// Information on one human
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Person {
     /// Name in "firstname.lastname" format (must match filename)
     pub name: String,
@@ -19,7 +19,7 @@ pub struct Person {
 }
 
 /// Information about a Squad of humans
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Squad {
     /// Dash-separated, lower-case name of the squad
     pub name: String,
@@ -35,7 +35,7 @@ pub struct Squad {
 }
 
 /// Information about a Tribe of squads
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Tribe {
     /// Dash-separated, lower-case name of the tribe
     pub name: String,
@@ -51,7 +51,7 @@ pub struct Tribe {
 ///
 /// Contains all data from all 4 folders in a EWOK_TEAMS_DIR
 /// All entries are sorted by filename (.name properties)
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, JsonSchema)]
 pub struct Owners {
     /// All people in people/{key}.toml
     pub people: BTreeMap<String, Person>,
@@ -88,7 +88,7 @@ impl Owners {
 ///
 /// If neither notifications or alerts have been specified, these will end up in
 /// your internal or support channel.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SlackSet {
     /// An internal slack channel for humans (no notifications)
     ///
@@ -110,7 +110,7 @@ pub struct SlackSet {
 }
 
 /// A set of github teams
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GithubTeams {
     /// Team name on github in lowercase, dash-separated form
     pub team: String,
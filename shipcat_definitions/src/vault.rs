@@ -1,7 +1,61 @@
-use std::{collections::BTreeMap, env};
+use async_trait::async_trait;
+use std::{
+    collections::BTreeMap,
+    env,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use super::{Error, ErrorKind, Result, ResultExt};
-use crate::region::VaultConfig;
+use crate::region::{KvVersion, SecretBackend, VaultConfig};
+
+/// A source of secrets
+///
+/// Keys are paths relative to vault's `secret/` mount, e.g. `dev-uk/myservice/MY_SECRET`,
+/// so that manifests resolve the same way regardless of backend.
+#[async_trait]
+pub trait SecretStore: Send + Sync {
+    /// Read the value of a single secret
+    async fn read(&self, key: &str) -> Result<String>;
+
+    /// List the secret names directly inside a folder
+    async fn list(&self, path: &str) -> Result<Vec<String>>;
+
+    /// Read a secret at an optional version, returning the version that was read
+    ///
+    /// Backends without versioning return no version, and cannot read pinned versions.
+    async fn read_version(&self, key: &str, version: Option<u32>) -> Result<(String, Option<u32>)> {
+        match version {
+            Some(v) => bail!(
+                "{} cannot read version {} of secret '{}'",
+                self.describe(),
+                v,
+                key
+            ),
+            None => Ok((self.read(key).await?, None)),
+        }
+    }
+
+    /// The name `list` reports for a secret named `name`
+    ///
+    /// Backends that store names in a normalised form override this.
+    fn listed_name(&self, name: &str) -> String {
+        name.to_string()
+    }
+
+    /// Short description of the backend for logging
+    fn describe(&self) -> String;
+}
+
+/// Create the secret store configured for a region
+pub fn regional_store(vc: &VaultConfig) -> Result<Box<dyn SecretStore>> {
+    Ok(match &vc.backend {
+        SecretBackend::Vault => Box::new(Vault::regional(vc)?),
+        SecretBackend::File(pth) => Box::new(EncryptedFile::open(pth)?),
+        SecretBackend::Env => Box::new(EnvStore::from_env()),
+    })
+}
 
 fn default_addr() -> Result<String> {
     env::var("VAULT_ADDR").map_err(|_| ErrorKind::MissingVaultAddr.into())
@@ -29,6 +83,153 @@ fn default_token() -> Result<String> {
         .chain_err(|| ErrorKind::MissingVaultToken)
 }
 
+/// Encrypt data with ChaCha20-Poly1305, prefixing the random nonce
+fn seal(key: &[u8], plain: &[u8]) -> Result<Vec<u8>> {
+    use ring::{
+        aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+        rand::{SecureRandom, SystemRandom},
+    };
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "failed to generate nonce")?;
+    let key = UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| "invalid encryption key")?;
+    let mut data = plain.to_vec();
+    LessSafeKey::new(key)
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| "failed to encrypt secret")?;
+    let mut res = nonce.to_vec();
+    res.append(&mut data);
+    Ok(res)
+}
+
+/// Decrypt data from `seal`; none if the key is wrong or the data was tampered with
+fn unseal(key: &[u8], mut data: Vec<u8>) -> Option<Vec<u8>> {
+    use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&data[..NONCE_LEN]);
+    let key = LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).ok()?);
+    let plain = key
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut data[NONCE_LEN..],
+        )
+        .ok()?;
+    Some(plain.to_vec())
+}
+
+fn sha256(data: &str) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, data.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+static CACHE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Let regional vault clients use the local secret cache
+///
+/// The cli turns it on unless `--no-cache` is passed. Long running processes should leave it off.
+pub fn enable_cache() {
+    CACHE_ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Longest time to serve a secret from the cache without asking vault
+///
+/// After this, the latest version of a secret is only reused if vault's metadata still has it as current.
+const CACHE_TTL: u64 = 60 * 60;
+
+/// A secret as stored in the cache
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedSecret {
+    value: String,
+    version: Option<u32>,
+    /// Unix timestamp after which vault must be asked again
+    expires: u64,
+}
+
+impl CachedSecret {
+    fn is_fresh(&self) -> bool {
+        self.expires > now()
+    }
+}
+
+/// Local cache of secrets read from vault
+///
+/// Every secret is stored in its own file named by a hash of the vault address and path,
+/// encrypted with a key derived from the vault token. Entries are unreadable to other tokens,
+/// and are used as is until the secret's lease duration, or `CACHE_TTL` if that is shorter.
+///
+/// Only KV v2 secrets are cached, as the current version of an expired entry can be checked
+/// before using it again.
+pub struct SecretCache {
+    dir: PathBuf,
+    key: Vec<u8>,
+}
+
+impl SecretCache {
+    pub fn new(dir: PathBuf, token: &str) -> SecretCache {
+        SecretCache {
+            dir,
+            key: sha256(&format!("shipcat-secret-cache:{}", token)),
+        }
+    }
+
+    /// The cache in the user's cache directory, if enabled
+    #[cfg(feature = "filesystem")]
+    fn in_user_dir(token: &str) -> Option<SecretCache> {
+        if !CACHE_ENABLED.load(Ordering::SeqCst) {
+            return None;
+        }
+        let dir = dirs::cache_dir()?.join("shipcat").join("secrets");
+        Some(SecretCache::new(dir, token))
+    }
+
+    #[cfg(not(feature = "filesystem"))]
+    fn in_user_dir(_token: &str) -> Option<SecretCache> {
+        None
+    }
+
+    fn file(&self, id: &str) -> PathBuf {
+        let name: String = sha256(id).iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(name)
+    }
+
+    /// Find a secret, expired or not
+    fn get(&self, id: &str) -> Option<CachedSecret> {
+        let data = std::fs::read(self.file(id)).ok()?;
+        let plain = unseal(&self.key, data)?;
+        serde_json::from_slice(&plain).ok()
+    }
+
+    /// Store a secret for `ttl` seconds
+    fn put(&self, id: &str, value: &str, version: Option<u32>, ttl: u64) -> Result<()> {
+        let entry = CachedSecret {
+            value: value.to_string(),
+            version,
+            expires: now() + ttl,
+        };
+        let data = seal(&self.key, &serde_json::to_vec(&entry)?)?;
+        std::fs::create_dir_all(&self.dir)?;
+        // write then rename so parallel readers never see partial entries
+        let file = self.file(id);
+        let tmp = file.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &file)?;
+        Ok(())
+    }
+}
+
 /// Secrets in vault values can be integers or strings
 ///
 /// If they are integers, we coerce them to strings
@@ -62,6 +263,114 @@ struct Secret {
     lease_duration: u64,
 }
 
+/// Secret data retrieved from a KV v2 engine, where data is nested next to metadata
+#[derive(Debug, Deserialize)]
+struct SecretV2 {
+    data: SecretV2Data,
+    #[serde(default)]
+    lease_duration: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretV2Data {
+    data: BTreeMap<String, SecretValue>,
+    metadata: SecretMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretMetadata {
+    version: u32,
+}
+
+/// Metadata of all versions of a secret in a KV v2 engine
+#[derive(Debug, Deserialize)]
+struct KeyMetadata {
+    data: KeyMetadataData,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyMetadataData {
+    current_version: u32,
+}
+
+/// A reference to a secret from a manifest value
+///
+/// `IN_VAULT` reads the secret named like the evar or secret file it is used in.
+/// Evars can read another secret from the same folder, or pin a version, with an explicit `vault` field.
+/// Secret files do this with `vault:NAME@3`, which cannot clash with their base64 values.
+///
+/// ```yaml
+/// env:
+///   DATABASE_URL: IN_VAULT
+///   API_KEY:
+///     vault: OTHER_KEY
+///     version: 3
+/// secretFiles:
+///   ssl-keystore: vault:keystore@2
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct VaultRef {
+    /// Secret name within the service folder
+    pub key: String,
+    /// Pinned version of the secret (latest if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
+
+impl VaultRef {
+    /// The reference in an `IN_VAULT` value of `name`, if it is one
+    pub fn in_vault(name: &str, value: &str) -> Option<VaultRef> {
+        if value == "IN_VAULT" {
+            Some(VaultRef {
+                key: name.to_string(),
+                version: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Parse the reference in the value of secret file `name`, if it is one
+    pub fn parse(name: &str, value: &str) -> Result<Option<VaultRef>> {
+        if let Some(vref) = VaultRef::in_vault(name, value) {
+            return Ok(Some(vref));
+        }
+        if !value.starts_with("vault:") {
+            return Ok(None);
+        }
+        let spec = &value["vault:".len()..];
+        let (key, version) = match spec.find('@') {
+            Some(i) => match spec[i + 1..].parse::<u32>() {
+                Ok(v) => (&spec[..i], Some(v)),
+                _ => bail!("Invalid secret version in {}: '{}'", name, value),
+            },
+            None => (spec, None),
+        };
+        let vref = VaultRef {
+            key: key.to_string(),
+            version,
+        };
+        vref.verify(name)?;
+        Ok(Some(vref))
+    }
+
+    /// Check the reference used by `name` points at a single secret version
+    pub fn verify(&self, name: &str) -> Result<()> {
+        if self.key.is_empty() || self.key.contains('/') {
+            bail!("Invalid secret name in {}: '{}'", name, self.key);
+        }
+        if self.version == Some(0) {
+            bail!("Invalid secret version in {}: versions start at 1", name);
+        }
+        Ok(())
+    }
+
+    /// The secret file value that pins this reference to a version
+    pub fn pinned(&self, version: u32) -> String {
+        format!("vault:{}@{}", self.key, version)
+    }
+}
+
 /// List data retrieved from Vault when listing available secrets
 #[derive(Debug, Deserialize)]
 struct ListSecrets {
@@ -78,6 +387,10 @@ pub struct Vault {
     token: String,
     /// Vault operation mode
     mode: Mode,
+    /// KV engine version
+    kv: KvVersion,
+    /// Local cache of read secrets
+    cache: Option<SecretCache>,
 }
 
 /// Vault usage mode
@@ -100,14 +413,32 @@ impl Vault {
         )
     }
 
+    /// Set the KV engine version to use
+    pub fn kv(mut self, kv: KvVersion) -> Self {
+        self.kv = kv;
+        self
+    }
+
+    /// Use a local cache for reads
+    pub fn cached(mut self, cache: SecretCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
     /// Initialize using VAULT_TOKEN evar + addr from the Region
+    ///
+    /// Reads go through the local secret cache if it has been enabled.
     pub fn regional(vc: &VaultConfig) -> Result<Vault> {
-        Vault::new(reqwest::Client::new(), &vc.url, default_token()?, Mode::Standard)
+        let token = default_token()?;
+        let mut v = Vault::new(reqwest::Client::new(), &vc.url, token.clone(), Mode::Standard)?;
+        v.cache = SecretCache::in_user_dir(&token);
+        Ok(v.kv(vc.kvVersion.clone()))
     }
 
     /// Initialize using dummy values and return garbage
     pub fn mocked(vc: &VaultConfig) -> Result<Vault> {
-        Vault::new(reqwest::Client::new(), &vc.url, default_token()?, Mode::Mocked)
+        let v = Vault::new(reqwest::Client::new(), &vc.url, default_token()?, Mode::Mocked)?;
+        Ok(v.kv(vc.kvVersion.clone()))
     }
 
     fn new<U, S>(client: reqwest::Client, addr: U, token: S, mode: Mode) -> Result<Vault>
@@ -121,6 +452,8 @@ impl Vault {
             addr,
             mode,
             token: token.into(),
+            kv: KvVersion::V1,
+            cache: None,
         })
     }
 
@@ -129,7 +462,7 @@ impl Vault {
     }
 
     // The actual HTTP GET logic
-    async fn get_secret(&self, path: &str) -> Result<Secret> {
+    async fn get_secret<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
         let url = self.addr.join(&format!("v1/{}", path))?;
         debug!("GET {}", url);
 
@@ -158,7 +491,11 @@ impl Vault {
     ///
     /// Does a HTTP LIST on the folder a service is in and returns the keys
     pub async fn list(&self, path: &str) -> Result<Vec<String>> {
-        let url = self.addr.join(&format!("v1/secret/{}?list=true", path))?;
+        let mount = match self.kv {
+            KvVersion::V1 => "secret",
+            KvVersion::V2 => "secret/metadata",
+        };
+        let url = self.addr.join(&format!("v1/{}/{}?list=true", mount, path))?;
         debug!("LIST {}", url);
 
         let mkerr = || ErrorKind::Url(url.clone());
@@ -196,34 +533,405 @@ impl Vault {
         Ok(res)
     }
 
-    /// Read secret from a Vault via an authenticated HTTP GET (or memory cache)
+    /// Read secret from a Vault via an authenticated HTTP GET (or local cache)
     pub async fn read(&self, key: &str) -> Result<String> {
-        let pth = format!("secret/{}", key);
+        Ok(self.read_version(key, None).await?.0)
+    }
+
+    /// Read a secret at an optional version along with the version that was read
+    ///
+    /// Versions are only available from KV v2 engines.
+    /// Only KV v2 reads are cached. Unexpired entries are used without asking vault,
+    /// and expired latest versions are renewed if vault's current version still matches.
+    pub async fn read_version(&self, key: &str, version: Option<u32>) -> Result<(String, Option<u32>)> {
         if self.mode == Mode::Mocked {
             // arbitrary base64 encoded value so it's compatible with everything
-            return Ok("aGVsbG8gd29ybGQ=".into());
+            return Ok(("aGVsbG8gd29ybGQ=".into(), version));
+        }
+        let cache = match (&self.cache, &self.kv) {
+            (Some(c), KvVersion::V2) => c,
+            _ => return Ok(self.fetch(key, version).await?.0),
+        };
+
+        let id = format!("{}{}@{}", self.addr, key, version.unwrap_or(0));
+        if let Some(hit) = cache.get(&id) {
+            if hit.is_fresh() {
+                debug!("Using cached secret {}", key);
+                return Ok((hit.value, hit.version));
+            }
+            if version.is_none() {
+                match self.get_secret::<KeyMetadata>(&format!("secret/metadata/{}", key)).await {
+                    Ok(meta) if hit.version == Some(meta.data.current_version) => {
+                        debug!("Renewing cached secret {}", key);
+                        if let Err(e) = cache.put(&id, &hit.value, hit.version, CACHE_TTL) {
+                            warn!("Failed to cache secret {}: {}", key, e);
+                        }
+                        return Ok((hit.value, hit.version));
+                    }
+                    Ok(_) => debug!("Cached secret {} is outdated", key),
+                    Err(e) => debug!("Not using cached secret {}: {}", key, e),
+                }
+            }
+        }
+        let ((value, found), lease) = self.fetch(key, version).await?;
+        let ttl = if lease > 0 {
+            std::cmp::min(lease, CACHE_TTL)
+        } else {
+            CACHE_TTL
+        };
+        if let Err(e) = cache.put(&id, &value, found, ttl) {
+            warn!("Failed to cache secret {}: {}", key, e);
         }
+        Ok((value, found))
+    }
 
-        let secret = self
-            .get_secret(&pth)
-            .await
-            .chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+    // Read a secret from vault along with its lease duration
+    async fn fetch(&self, key: &str, version: Option<u32>) -> Result<((String, Option<u32>), u64)> {
+        let (pth, data, found, lease) = match self.kv {
+            KvVersion::V1 => {
+                let pth = format!("secret/{}", key);
+                if let Some(v) = version {
+                    bail!("Cannot read version {} of {} from a kv v1 vault", v, pth);
+                }
+                let secret: Secret = self
+                    .get_secret(&pth)
+                    .await
+                    .chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+                (pth, secret.data, None, secret.lease_duration)
+            }
+            KvVersion::V2 => {
+                let pth = format!("secret/data/{}", key);
+                let query = version.map(|v| format!("?version={}", v)).unwrap_or_default();
+                let secret: SecretV2 = self
+                    .get_secret(&format!("{}{}", pth, query))
+                    .await
+                    .chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+                let version = Some(secret.data.metadata.version);
+                (pth, secret.data.data, version, secret.lease_duration)
+            }
+        };
 
         // NB: Currently assume each path in vault has a single `value`
         // Read the value key (which should exist)
-        secret
-            .data
-            .get("value")
+        data.get("value")
             .ok_or_else(|| ErrorKind::InvalidSecretForm(pth).into())
-            .map(|v| v.clone().into())
+            .map(|v| ((v.clone().into(), found), lease))
+    }
+}
+
+#[async_trait]
+impl SecretStore for Vault {
+    async fn read(&self, key: &str) -> Result<String> {
+        Vault::read(self, key).await
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<String>> {
+        Vault::list(self, path).await
+    }
+
+    async fn read_version(&self, key: &str, version: Option<u32>) -> Result<(String, Option<u32>)> {
+        Vault::read_version(self, key, version).await
+    }
+
+    fn describe(&self) -> String {
+        format!("vault {} ({:?}, kv {:?})", self.addr, self.mode, self.kv)
+    }
+}
+
+/// Secrets from a local encrypted yaml file
+///
+/// The file is a flat map from full secret keys to sealed values:
+///
+/// ```yaml
+/// dev-uk/myservice/MY_SECRET: ENC[bm9uY2UgYW5kIGNpcGhlcnRleHQ=]
+/// ```
+///
+/// A sealed value is `ENC[..]` around the base64 encoding of a random 12 byte nonce,
+/// followed by the ChaCha20-Poly1305 ciphertext and tag of the value (without associated data).
+/// The 256 bit key is read base64 encoded from `SHIPCAT_SECRET_KEY`.
+///
+/// Values are written with `shipcat secret seal`, rather than by hand.
+pub struct EncryptedFile {
+    path: String,
+    key: Vec<u8>,
+    secrets: BTreeMap<String, String>,
+}
+
+fn file_key() -> Result<Vec<u8>> {
+    let key = env::var("SHIPCAT_SECRET_KEY").map_err(|_| "SHIPCAT_SECRET_KEY not specified")?;
+    match base64::decode(key.trim()) {
+        Ok(k) if k.len() == 32 => Ok(k),
+        _ => bail!("SHIPCAT_SECRET_KEY must be a base64 encoded 256 bit key"),
+    }
+}
+
+impl EncryptedFile {
+    /// Load an encrypted secret file using the key in the environment
+    pub fn open(path: &str) -> Result<EncryptedFile> {
+        EncryptedFile::open_with_key(path, file_key()?)
+    }
+
+    /// Load an encrypted secret file with an explicit key
+    pub fn open_with_key(path: &str, key: Vec<u8>) -> Result<EncryptedFile> {
+        let data =
+            std::fs::read_to_string(path).chain_err(|| format!("failed to read secret file {}", path))?;
+        Ok(EncryptedFile {
+            path: path.into(),
+            key,
+            secrets: serde_yaml::from_str(&data)?,
+        })
+    }
+
+    /// Start a new encrypted secret file using the key in the environment
+    ///
+    /// Nothing is written until `save`.
+    pub fn create(path: &str) -> Result<EncryptedFile> {
+        Ok(EncryptedFile {
+            path: path.into(),
+            key: file_key()?,
+            secrets: BTreeMap::new(),
+        })
+    }
+
+    /// Seal a value and store it under a key, replacing any previous value
+    pub fn insert(&mut self, key: &str, value: &str) -> Result<()> {
+        let sealed = EncryptedFile::seal(&self.key, value)?;
+        self.secrets.insert(key.to_string(), sealed);
+        Ok(())
+    }
+
+    /// Write the sealed secrets back to the file
+    pub fn save(&self) -> Result<()> {
+        let data = serde_yaml::to_string(&self.secrets)?;
+        std::fs::write(&self.path, data).chain_err(|| format!("failed to write secret file {}", self.path))?;
+        Ok(())
+    }
+
+    /// Encrypt a value into the `ENC[..]` form stored in secret files
+    pub fn seal(key: &[u8], value: &str) -> Result<String> {
+        Ok(format!("ENC[{}]", base64::encode(&seal(key, value.as_bytes())?)))
+    }
+
+    fn open_value(&self, key: &str, sealed: &str) -> Result<String> {
+        let invalid = || ErrorKind::InvalidSecretForm(key.to_string());
+        if !sealed.starts_with("ENC[") || !sealed.ends_with(']') {
+            bail!(invalid());
+        }
+        let data = base64::decode(&sealed[4..sealed.len() - 1]).map_err(|_| invalid())?;
+        let plain = unseal(&self.key, data).ok_or_else(|| ErrorKind::SecretNotAccessible(key.to_string()))?;
+        Ok(String::from_utf8(plain).map_err(|_| invalid())?)
+    }
+}
+
+#[async_trait]
+impl SecretStore for EncryptedFile {
+    async fn read(&self, key: &str) -> Result<String> {
+        match self.secrets.get(key) {
+            Some(sealed) => self.open_value(key, sealed),
+            None => bail!(ErrorKind::SecretNotAccessible(key.to_string())),
+        }
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        let res = self
+            .secrets
+            .keys()
+            .filter(|k| k.starts_with(&prefix))
+            .map(|k| k[prefix.len()..].to_string())
+            .filter(|k| !k.contains('/')) // skip sub folders
+            .collect();
+        Ok(res)
     }
+
+    fn describe(&self) -> String {
+        format!("encrypted file {}", self.path)
+    }
+}
+
+/// Secrets from environment variables
+///
+/// A key like `dev-uk/myservice/my-secret` is read from `SHIPCAT_SECRET__DEV_UK__MYSERVICE__MY_SECRET`,
+/// i.e. slashes become double underscores, and every part is uppercased with anything
+/// that is not alphanumeric (like `-` or `.`) replaced by an underscore, so it can be set from a shell.
+/// Listed names are in this normalised form.
+/// Useful for CI, where secrets are already injected into the environment.
+pub struct EnvStore {
+    vars: BTreeMap<String, String>,
 }
 
+const ENV_PREFIX: &str = "SHIPCAT_SECRET__";
+
+impl EnvStore {
+    /// Secrets from the variables of the current process
+    pub fn from_env() -> EnvStore {
+        EnvStore::new(env::vars())
+    }
+
+    /// Secrets from an explicit set of environment variables
+    pub fn new(vars: impl IntoIterator<Item = (String, String)>) -> EnvStore {
+        EnvStore {
+            vars: vars.into_iter().filter(|(k, _)| k.starts_with(ENV_PREFIX)).collect(),
+        }
+    }
+
+    fn evar(key: &str) -> String {
+        let parts = key
+            .split('/')
+            .map(|p| {
+                p.chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        format!("{}{}", ENV_PREFIX, parts.join("__"))
+    }
+}
+
+#[async_trait]
+impl SecretStore for EnvStore {
+    async fn read(&self, key: &str) -> Result<String> {
+        let evar = EnvStore::evar(key);
+        match self.vars.get(&evar) {
+            Some(v) => Ok(v.clone()),
+            None => bail!(ErrorKind::SecretNotAccessible(evar)),
+        }
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}__", EnvStore::evar(path.trim_end_matches('/')));
+        let res = self
+            .vars
+            .keys()
+            .filter(|k| k.starts_with(&prefix))
+            .map(|k| k[prefix.len()..].to_string())
+            .filter(|k| !k.contains("__")) // skip sub folders
+            .collect();
+        Ok(res)
+    }
+
+    fn listed_name(&self, name: &str) -> String {
+        EnvStore::evar(name)[ENV_PREFIX.len()..].to_string()
+    }
+
+    fn describe(&self) -> String {
+        "environment variables".into()
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use super::Vault;
+    use super::{EncryptedFile, EnvStore, SecretCache, SecretStore, Vault, VaultRef};
     use base64;
+    use std::env;
+
+    #[tokio::test]
+    async fn encrypted_file_secrets() {
+        let key = [7u8; 32];
+        let data = format!(
+            "dev-uk/fake-ask/FAKE_SECRET: {}\ndev-uk/fake-ask/sub/NESTED: {}\n",
+            EncryptedFile::seal(&key, "hello").unwrap(),
+            EncryptedFile::seal(&key, "nested").unwrap()
+        );
+        let pth = env::temp_dir().join("shipcat-secrets-test.yml");
+        let pth = pth.to_str().unwrap();
+        std::fs::write(pth, data).unwrap();
+
+        let mut store = EncryptedFile::open_with_key(pth, key.to_vec()).unwrap();
+        assert_eq!(store.read("dev-uk/fake-ask/FAKE_SECRET").await.unwrap(), "hello");
+        assert!(store.read("dev-uk/fake-ask/MISSING").await.is_err());
+        assert_eq!(store.list("dev-uk/fake-ask").await.unwrap(), vec!["FAKE_SECRET"]);
+
+        // sealed values survive a round trip through the file
+        store.insert("dev-uk/fake-ask/OTHER", "sealed").unwrap();
+        store.save().unwrap();
+        let store = EncryptedFile::open_with_key(pth, key.to_vec()).unwrap();
+        assert_eq!(store.read("dev-uk/fake-ask/OTHER").await.unwrap(), "sealed");
+        assert_eq!(store.read("dev-uk/fake-ask/FAKE_SECRET").await.unwrap(), "hello");
+
+        // a different key cannot read it
+        let store = EncryptedFile::open_with_key(pth, vec![8u8; 32]).unwrap();
+        assert!(store.read("dev-uk/fake-ask/FAKE_SECRET").await.is_err());
+    }
+
+    #[test]
+    fn secret_cache() {
+        let dir = env::temp_dir().join(format!("shipcat-secret-cache-test-{}", std::process::id()));
+        let cache = SecretCache::new(dir.clone(), "token");
+        cache.put("dev-uk/fake-ask/A@0", "hello", Some(2), 60).unwrap();
+        let hit = cache.get("dev-uk/fake-ask/A@0").unwrap();
+        assert_eq!(hit.value, "hello");
+        assert_eq!(hit.version, Some(2));
+        assert!(cache.get("dev-uk/fake-ask/B@0").is_none());
+
+        assert!(hit.is_fresh());
+
+        // expired entries are kept for renewal, but not fresh
+        cache.put("dev-uk/fake-ask/C@0", "old", None, 0).unwrap();
+        assert!(!cache.get("dev-uk/fake-ask/C@0").unwrap().is_fresh());
+
+        // other tokens cannot read entries
+        let other = SecretCache::new(dir.clone(), "other-token");
+        assert!(other.get("dev-uk/fake-ask/A@0").is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn vault_refs() {
+        let r = VaultRef::parse("API_KEY", "IN_VAULT").unwrap().unwrap();
+        assert_eq!(r.key, "API_KEY");
+        assert_eq!(r.version, None);
+        assert_eq!(VaultRef::in_vault("API_KEY", "IN_VAULT"), Some(r));
+        assert_eq!(VaultRef::in_vault("API_KEY", "vault:API_KEY"), None);
+        let r = VaultRef::parse("API_KEY", "vault:OTHER_KEY@3").unwrap().unwrap();
+        assert_eq!(r.key, "OTHER_KEY");
+        assert_eq!(r.version, Some(3));
+        assert_eq!(r.pinned(4), "vault:OTHER_KEY@4");
+        assert_eq!(VaultRef::parse("A", "vault:A").unwrap().unwrap().version, None);
+        assert!(VaultRef::parse("A", "plain").unwrap().is_none());
+        assert!(VaultRef::parse("A", "vault:A@x").is_err());
+        assert!(VaultRef::parse("A", "vault:A@0").is_err());
+        assert!(VaultRef::parse("A", "vault:").is_err());
+        assert!(VaultRef::parse("A", "vault:../B").is_err());
+    }
+
+    #[tokio::test]
+    async fn env_secrets() {
+        let vars = vec![
+            ("SHIPCAT_SECRET__DEV_UK__FAKE_ENV__FAKE_FILE", "aGVsbG8="),
+            ("SHIPCAT_SECRET__DEV_UK__FAKE_ENV__SUB__NESTED", "no"),
+            ("SHIPCAT_SECRET__dev-uk__fake-env__RAW", "unnormalised"),
+            ("FAKE_FILE", "unprefixed"),
+        ];
+        let store = EnvStore::new(vars.into_iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        assert_eq!(store.read("dev-uk/fake-env/fake-file").await.unwrap(), "aGVsbG8=");
+        assert_eq!(store.read("dev-uk/fake-env/fake.file").await.unwrap(), "aGVsbG8=");
+        assert!(store.read("dev-uk/fake-env/MISSING").await.is_err());
+        assert!(store.read("dev-uk/fake-env/RAW").await.is_err());
+        assert_eq!(store.list("dev-uk/fake-env").await.unwrap(), vec!["FAKE_FILE"]);
+    }
+
+    #[tokio::test]
+    async fn env_secrets_exist() {
+        use crate::{Manifest, SecretBackend, VaultConfig};
+        let vars = vec![
+            ("SHIPCAT_SECRET__DEV_UK__FAKE_ENV__FAKE_FILE", "aGVsbG8="),
+            ("SHIPCAT_SECRET__DEV_UK__FAKE_ENV__SSL_KEY", "a2V5"),
+        ];
+        let store = EnvStore::new(vars.into_iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        let vc = VaultConfig {
+            folder: "dev-uk".into(),
+            backend: SecretBackend::Env,
+            ..Default::default()
+        };
+        let mut mf = Manifest::test("fake-env");
+        mf.secretFiles.insert("fake-file".into(), "IN_VAULT".into());
+        mf.secretFiles.insert("ssl.key".into(), "IN_VAULT".into());
+        mf.verify_secrets_in(&store, &vc).await.unwrap();
+
+        mf.secretFiles.insert("other-file".into(), "IN_VAULT".into());
+        assert!(mf.verify_secrets_in(&store, &vc).await.is_err());
+    }
 
     #[tokio::test]
     async fn get_dev_secret() {
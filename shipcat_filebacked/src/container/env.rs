@@ -1,17 +1,44 @@
 use merge::Merge;
 use std::collections::BTreeMap;
 
-use shipcat_definitions::{structs::EnvVars, Result};
+use shipcat_definitions::{structs::EnvVars, vault::VaultRef, Result};
 
 use crate::util::{Build, RelaxedString};
 
-#[derive(Deserialize, Clone, Default, Debug, PartialEq, Merge)]
-pub struct EnvVarsSource(BTreeMap<String, RelaxedString>);
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Merge, JsonSchema)]
+pub struct EnvVarsSource(BTreeMap<String, EnvValueSource>);
+
+/// An environment variable value, either plain or an explicit secret reference
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum EnvValueSource {
+    Secret(SecretRefSource),
+    Plain(RelaxedString),
+}
+
+/// A reference to a named secret in the service folder, optionally pinned to a version
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SecretRefSource {
+    pub vault: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
 
 impl Build<EnvVars, ()> for EnvVarsSource {
     fn build(self, params: &()) -> Result<EnvVars> {
-        let Self(plain) = self;
-        let env = EnvVars::new(plain.build(params)?);
+        let Self(values) = self;
+        let mut env = EnvVars::default();
+        for (k, v) in values {
+            match v {
+                EnvValueSource::Plain(s) => {
+                    env.plain.insert(k, s.build(params)?);
+                }
+                EnvValueSource::Secret(SecretRefSource { vault, version }) => {
+                    env.vault.insert(k, VaultRef { key: vault, version });
+                }
+            }
+        }
         // TODO: Inline
         env.verify()?;
         Ok(env)
@@ -22,8 +49,37 @@ impl<K: ToString, V: Into<RelaxedString>> From<BTreeMap<K, V>> for EnvVarsSource
     fn from(v: BTreeMap<K, V>) -> Self {
         let mut env = BTreeMap::new();
         for (k, v) in v {
-            env.insert(k.to_string(), v.into());
+            env.insert(k.to_string(), EnvValueSource::Plain(v.into()));
         }
         EnvVarsSource(env)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::EnvVarsSource;
+    use crate::util::Build;
+
+    #[test]
+    fn explicit_vault_refs() {
+        let source: EnvVarsSource = serde_yaml::from_str(
+            "
+PLAIN: vault:LOOKS_LIKE_A_REF
+SECRET: IN_VAULT
+API_KEY:
+  vault: OTHER_KEY
+  version: 3
+",
+        )
+        .unwrap();
+        let env = source.build(&()).unwrap();
+        assert_eq!(env.plain["PLAIN"], "vault:LOOKS_LIKE_A_REF");
+        assert_eq!(env.vault["API_KEY"].key, "OTHER_KEY");
+        assert_eq!(env.vault["API_KEY"].version, Some(3));
+
+        let mut env = env;
+        let refs = env.vault_secrets();
+        assert_eq!(refs.keys().collect::<Vec<_>>(), vec!["API_KEY", "SECRET"]);
+        assert_eq!(env.plain.keys().collect::<Vec<_>>(), vec!["PLAIN"]);
+    }
+}
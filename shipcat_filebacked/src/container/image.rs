@@ -4,7 +4,7 @@ use shipcat_definitions::Result;
 
 use crate::util::Build;
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ImageNameSource(String);
 
 impl Build<String, ()> for ImageNameSource {
@@ -26,7 +26,7 @@ impl Build<String, ()> for ImageNameSource {
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ImageTagSource(String);
 
 impl Build<String, ()> for ImageTagSource {
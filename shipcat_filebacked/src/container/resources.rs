@@ -5,7 +5,7 @@ use shipcat_definitions::{
 
 use crate::util::{Build, RelaxedString, Require};
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct ResourceRequirementsSource {
     pub requests: ResourcesSource,
@@ -23,7 +23,7 @@ impl Build<ResourceRequirements<String>, ()> for ResourceRequirementsSource {
     }
 }
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct ResourcesSource {
     pub cpu: Option<RelaxedString>,
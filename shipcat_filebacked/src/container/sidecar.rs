@@ -3,7 +3,7 @@ use shipcat_definitions::{structs::Container, Result};
 use super::source::{ContainerBuildParams, ContainerSource};
 use crate::util::Build;
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 pub struct SidecarSource(ContainerSource);
 
 impl Build<Container, ContainerBuildParams> for SidecarSource {
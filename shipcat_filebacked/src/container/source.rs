@@ -15,7 +15,7 @@ use super::{
     EnvVarsSource,
 };
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
 pub struct ContainerName(String);
 
 impl Build<String, ()> for ContainerName {
@@ -30,7 +30,7 @@ impl Build<String, ()> for ContainerName {
 }
 
 /// Source configuration for a K8s container, deserialized from a service manifest.
-#[derive(Deserialize, Merge, Clone, Default)]
+#[derive(Serialize, Deserialize, Merge, Clone, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct ContainerSource {
     pub name: Option<ContainerName>,
@@ -74,6 +74,7 @@ impl Build<Container, ContainerBuildParams> for ContainerSource {
             name: self.name.require("name")?.build(&())?,
             image: self.image.build(&())?,
             version: self.version.build(&())?,
+            image_digest: None,
 
             resources: self.resources.build(&())?,
 
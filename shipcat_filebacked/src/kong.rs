@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 
 use shipcat_definitions::{
     structs::{Authentication, Authorization, BabylonAuthHeader, Cors, Kong},
-    KongConfig, Region, Result,
+    ErrorKind, KongConfig, Region, Result,
 };
 
 use super::{
@@ -11,7 +11,7 @@ use super::{
     util::{Build, Enabled, EnabledMap},
 };
 
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Serialize, Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default)]
 pub struct KongApisSource {
     /// Default values to merge into every API
@@ -39,7 +39,7 @@ impl Build<Vec<Kong>, KongApisBuildParams> for KongApisSource {
         if let Some(k) = KongApisSource::build_single_api(&defaults, params)? {
             debug!("Using single Kong API for {}", params.service);
             if !self.apis.is_empty() {
-                bail!(".kong and .kong_apis properties are mutually exclusive")
+                bail!(ErrorKind::ConflictingManifestKeys("kong".into(), "kongApis".into()))
             }
             return Ok(vec![k]);
         }
@@ -53,6 +53,7 @@ impl Build<Vec<Kong>, KongApisBuildParams> for KongApisSource {
             }
             let merged = defaults.clone().merge(k);
             let maybe = merged.build(&KongBuildParams {
+                key: format!("kongApis.{}", name),
                 name,
                 service: params.service.clone(),
                 region: params.region.clone(),
@@ -85,6 +86,7 @@ impl KongApisSource {
         }
 
         Ok(Some(merged.build(&KongBuildParams {
+            key: "kong".into(),
             name: params.service.clone(),
             service: params.service.clone(),
             region: params.region.clone(),
@@ -93,7 +95,7 @@ impl KongApisSource {
     }
 }
 
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Serialize, Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct KongSource {
     pub upstream_url: Option<String>,
@@ -118,6 +120,8 @@ pub struct KongSource {
 }
 
 struct KongBuildParams {
+    /// Key path of the API in the manifest
+    pub key: String,
     pub name: String,
     pub service: String,
     pub region: Region,
@@ -128,6 +132,7 @@ impl Build<Kong, KongBuildParams> for KongSource {
     /// Build a Kong from a KongSource, validating and mutating properties.
     fn build(self, params: &KongBuildParams) -> Result<Kong> {
         let KongBuildParams {
+            key,
             region,
             service,
             name,
@@ -141,7 +146,7 @@ impl Build<Kong, KongBuildParams> for KongSource {
         }
 
         let upstream_url = self.build_upstream_url(&service, &region.namespace);
-        let (auth, authorization) = KongSource::build_auth(self.auth, self.authorization)?;
+        let (auth, authorization) = KongSource::build_auth(key, self.auth, self.authorization)?;
 
         let preserve_host = self.preserve_host.unwrap_or(true);
 
@@ -183,11 +188,15 @@ impl KongSource {
     }
 
     fn build_auth(
+        key: &str,
         auth: Option<Authentication>,
         authz: Enabled<AuthorizationSource>,
     ) -> Result<(Option<Authentication>, Option<Authorization>)> {
         Ok(match (auth, authz.build(&())?) {
-            (Some(_), Some(_)) => bail!("auth and authorization.enabled are mutually exclusive"),
+            (Some(_), Some(_)) => bail!(ErrorKind::ConflictingManifestKeys(
+                format!("{}.auth", key),
+                format!("{}.authorization.enabled", key)
+            )),
             (Some(Authentication::None), None) => (None, None),
             x => x,
         })
@@ -0,0 +1,633 @@
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+use shipcat_definitions::{Error, ErrorKind, Result};
+
+/// Where a part of a manifest comes from, in the order they are merged
+#[derive(Clone, Debug, PartialEq)]
+pub enum Layer {
+    /// Defaults from `shipcat.conf`
+    Global,
+    /// Defaults from the region in `shipcat.conf`
+    Regional,
+    /// The service's `manifest.yml`
+    Manifest,
+    /// Overrides for an environment like `dev.yml`
+    Environment(String),
+    /// Overrides for a region like `dev-uk.yml`
+    Region(String),
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layer::Global => write!(f, "global defaults"),
+            Layer::Regional => write!(f, "regional defaults"),
+            Layer::Manifest => write!(f, "manifest"),
+            Layer::Environment(e) => write!(f, "{} environment overrides", e),
+            Layer::Region(r) => write!(f, "{} region overrides", r),
+        }
+    }
+}
+
+/// A step in a key path like `resources.limits.cpu` or `cronJobs[0]`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn key_path(path: &[Segment]) -> String {
+    let mut res = String::new();
+    for s in path {
+        match s {
+            Segment::Key(k) if res.is_empty() => res += k,
+            Segment::Key(k) => res += &format!(".{}", k),
+            Segment::Index(i) => res += &format!("[{}]", i),
+        }
+    }
+    res
+}
+
+/// A manifest file and the layer it is merged in as
+pub struct LayerFile {
+    pub layer: Layer,
+    pub path: PathBuf,
+    value: Value,
+    /// Positions of every key path in the file
+    marks: Vec<(Vec<Segment>, Marker)>,
+}
+
+impl LayerFile {
+    /// Read a file as YAML, without deserializing it yet
+    pub async fn read(layer: Layer, path: &Path) -> Result<Self> {
+        use tokio::fs;
+        trace!("Reading manifest in {}", path.display());
+        if !path.exists() {
+            bail!("Manifest file {} does not exist", path.display())
+        }
+        let data = fs::read_to_string(&path).await?;
+        if data.is_empty() {
+            bail!("Manifest file {} is empty", path.display());
+        }
+        LayerFile::from_str(layer, path, &data)
+    }
+
+    /// Parse YAML as a file, recording the positions of its keys
+    fn from_str(layer: Layer, path: &Path, data: &str) -> Result<Self> {
+        let mut file = LayerFile {
+            layer,
+            path: path.to_path_buf(),
+            value: Value::Null,
+            marks: vec![],
+        };
+        match (serde_yaml::from_str(data), locate(data)) {
+            (Ok(v), Ok(marks)) => {
+                file.value = v;
+                file.marks = marks;
+            }
+            (Err(e), Err(m)) => {
+                let suffix = format!(" at line {} column {}", m.line(), m.col() + 1);
+                bail!(ErrorKind::InvalidManifestFile(
+                    file.position(&[], Some(m)),
+                    format!("did not parse as YAML: {}", e.to_string().trim_end_matches(&suffix))
+                ))
+            }
+            (Err(e), Ok(_)) => bail!(ErrorKind::InvalidManifestFile(
+                file.position(&[], None),
+                format!("did not parse as YAML: {}", e)
+            )),
+            (Ok(v), Err(_)) => file.value = v,
+        }
+        Ok(file)
+    }
+
+    /// Defaults from `shipcat.conf` as a layer
+    pub fn defaults(layer: Layer, value: Value) -> Self {
+        LayerFile {
+            layer,
+            path: PathBuf::from("shipcat.conf"),
+            value,
+            marks: vec![],
+        }
+    }
+
+    /// Deserialize the file, pointing to the offending key on failure
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T> {
+        let reason = match serde_yaml::from_value(self.value.clone()) {
+            Ok(d) => return Ok(d),
+            Err(e) => e.to_string(),
+        };
+        let path = self.culprit::<T>(&reason).unwrap_or_default();
+        bail!(ErrorKind::InvalidManifestFile(
+            self.position(&path, self.mark(&path)),
+            reason
+        ))
+    }
+
+    /// The deepest key path that causes a deserialization error
+    ///
+    /// Serde loses track of the position within flattened structs, so the file is deserialized
+    /// again without each key instead. A key causes the error if it goes away without it,
+    /// unless removing the key just leaves a required field missing.
+    fn culprit<T: DeserializeOwned>(&self, reason: &str) -> Option<Vec<Segment>> {
+        let causes = |path: &[Segment]| {
+            let mut value = self.value.clone();
+            if !remove(&mut value, path) {
+                return false;
+            }
+            match serde_yaml::from_value::<T>(value) {
+                Ok(_) => true,
+                Err(e) => {
+                    let r = e.to_string();
+                    r != reason && !r.starts_with("missing field")
+                }
+            }
+        };
+        self.marks
+            .iter()
+            .map(|(p, _)| p)
+            .filter(|p| causes(p))
+            .rev() // the first in the file among the deepest
+            .max_by_key(|p| p.len())
+            .cloned()
+    }
+
+    /// Where a key is set in this file as `file:line:column (layer)`
+    fn origin(&self, path: &[Segment]) -> String {
+        self.position(&[], self.mark(path))
+    }
+
+    fn mark(&self, path: &[Segment]) -> Option<Marker> {
+        self.marks.iter().find(|(p, _)| p == path).map(|(_, m)| *m)
+    }
+
+    /// Where in the file something is as `file:line:column (layer)`, with a key path if known
+    fn position(&self, path: &[Segment], mark: Option<Marker>) -> String {
+        let mut res = self.path.display().to_string();
+        if let Some(m) = mark {
+            res += &format!(":{}:{}", m.line(), m.col() + 1);
+        }
+        if !path.is_empty() {
+            res += &format!(" at `{}`", key_path(path));
+        }
+        format!("{} ({})", res, self.layer)
+    }
+}
+
+/// Remove the value at a key path from a document, returning whether it was there
+fn remove(value: &mut Value, path: &[Segment]) -> bool {
+    let (last, parents) = match path.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    let mut parent = value;
+    for s in parents {
+        let child = match (s, parent) {
+            (Segment::Key(k), Value::Mapping(m)) => m.get_mut(&Value::String(k.clone())),
+            (Segment::Index(i), Value::Sequence(xs)) => xs.get_mut(*i),
+            _ => None,
+        };
+        parent = match child {
+            Some(c) => c,
+            None => return false,
+        };
+    }
+    match (last, parent) {
+        (Segment::Key(k), Value::Mapping(m)) => m.remove(&Value::String(k.clone())).is_some(),
+        (Segment::Index(i), Value::Sequence(xs)) if *i < xs.len() => {
+            xs.remove(*i);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Records the positions of all key paths while parsing YAML
+struct Locator {
+    path: Vec<Segment>,
+    stack: Vec<Frame>,
+    marks: Vec<(Vec<Segment>, Marker)>,
+}
+
+enum Frame {
+    Mapping { at_key: bool },
+    Sequence { next: usize },
+}
+
+impl Locator {
+    fn begin(&mut self, scalar: Option<String>, mark: Marker) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { at_key: true }) => self.path.push(Segment::Key(scalar.unwrap_or_default())),
+            Some(Frame::Sequence { next }) => {
+                self.path.push(Segment::Index(*next));
+                *next += 1;
+            }
+            _ => return,
+        }
+        self.marks.push((self.path.clone(), mark));
+    }
+
+    fn end(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { at_key }) if *at_key => *at_key = false,
+            Some(Frame::Mapping { at_key }) => {
+                *at_key = true;
+                self.path.pop();
+            }
+            Some(Frame::Sequence { .. }) => {
+                self.path.pop();
+            }
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for Locator {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::MappingStart(_) => {
+                self.begin(None, mark);
+                self.stack.push(Frame::Mapping { at_key: true });
+            }
+            Event::SequenceStart(_) => {
+                self.begin(None, mark);
+                self.stack.push(Frame::Sequence { next: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.end();
+            }
+            Event::Scalar(v, ..) => {
+                self.begin(Some(v), mark);
+                self.end();
+            }
+            Event::Alias(_) => {
+                self.begin(None, mark);
+                self.end();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Find the positions of all key paths in a YAML document
+///
+/// Fails with the position of the syntax error if the document does not parse.
+fn locate(data: &str) -> std::result::Result<Vec<(Vec<Segment>, Marker)>, Marker> {
+    let mut locator = Locator {
+        path: vec![],
+        stack: vec![],
+        marks: vec![],
+    };
+    let mut parser = Parser::new(data.chars());
+    match parser.load(&mut locator, false) {
+        Ok(_) => Ok(locator.marks),
+        Err(e) => Err(*e.marker()),
+    }
+}
+
+/// Where the values of a merged manifest were set
+///
+/// Tracked while merging, so keys set by later layers take the origin of the last layer setting them.
+#[derive(Default)]
+pub struct Origins {
+    /// Descriptions of the merged layers, in merge order
+    layers: Vec<String>,
+    /// Leaf key paths, with the index of the layer that last set them and where
+    leaves: Vec<(Vec<Segment>, usize, String)>,
+}
+
+impl Origins {
+    /// Record the keys set by a layer merged on top of the previous ones
+    pub fn track(&mut self, file: &LayerFile) {
+        let layer = self.layers.len();
+        self.layers.push(file.position(&[], None));
+        let mut values = vec![];
+        leaves(&file.value, &mut vec![], &mut values);
+        for (path, _) in values {
+            let origin = file.origin(&path);
+            match self.leaves.iter_mut().find(|(p, ..)| *p == path) {
+                Some(leaf) => *leaf = (path, layer, origin),
+                None => self.leaves.push((path, layer, origin)),
+            }
+        }
+    }
+
+    /// Where a key, or any key within it, was last set
+    fn origin(&self, path: &[Segment]) -> Option<&str> {
+        self.leaves
+            .iter()
+            .filter(|(p, ..)| p.starts_with(path) || path.starts_with(p))
+            .max_by_key(|(_, layer, _)| *layer)
+            .map(|(.., origin)| origin.as_str())
+    }
+
+    /// Describe a dotted key path along with where it was set
+    fn describe(&self, key: &str) -> String {
+        let path = key.split('.').map(|k| Segment::Key(k.into())).collect::<Vec<_>>();
+        match self.origin(&path) {
+            Some(origin) => format!("`{}` in {}", key, origin),
+            None => format!("`{}`", key),
+        }
+    }
+
+    /// Point an error from building the merged manifest at the layers involved
+    ///
+    /// Conflicting keys are located in the layers that set them,
+    /// other errors are wrapped with the layers that were merged.
+    pub fn locate(&self, e: Error) -> Error {
+        match e.kind() {
+            ErrorKind::ConflictingManifestKeys(a, b) => {
+                ErrorKind::ConflictingManifestKeys(self.describe(a), self.describe(b)).into()
+            }
+            _ => Error::with_chain(e, ErrorKind::MergedManifestLayers(self.layers.join(", "))),
+        }
+    }
+}
+
+/// Where a resolved manifest value was set
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Provenance {
+    /// Key path in the manifest
+    pub key: String,
+    /// Resolved value
+    pub value: Value,
+    /// The layer and position that set it, or `default`
+    pub source: String,
+}
+
+/// Collect the scalar values of a document along with their key paths
+///
+/// Sequences of scalars are kept whole.
+fn leaves(value: &Value, path: &mut Vec<Segment>, res: &mut Vec<(Vec<Segment>, Value)>) {
+    match value {
+        Value::Null => {}
+        Value::Mapping(m) => {
+            for (k, v) in m {
+                if let Some(k) = k.as_str() {
+                    path.push(Segment::Key(k.into()));
+                    leaves(v, path, res);
+                    path.pop();
+                }
+            }
+        }
+        Value::Sequence(xs) if xs.is_empty() => {}
+        Value::Sequence(xs)
+            if xs
+                .iter()
+                .any(|x| x.as_mapping().is_some() || x.as_sequence().is_some()) =>
+        {
+            for (i, x) in xs.iter().enumerate() {
+                path.push(Segment::Index(i));
+                leaves(x, path, res);
+                path.pop();
+            }
+        }
+        _ => res.push((path.clone(), value.clone())),
+    }
+}
+
+/// The key path of a built manifest value in the manifest files
+///
+/// Only environment variables are restructured; `env.plain.FOO` is set as `env.FOO`,
+/// and `env.vault.FOO.key` as `env.FOO.vault`.
+fn source_path(path: &[Segment]) -> Vec<Segment> {
+    let key = |k: &str| Segment::Key(k.into());
+    let mut res: Vec<Segment> = vec![];
+    for (i, s) in path.iter().enumerate() {
+        if (*s == key("plain") || *s == key("vault")) && i > 0 && path[i - 1] == key("env") {
+            continue;
+        }
+        if *s == key("key") && i > 2 && path[i - 2] == key("vault") && path[i - 3] == key("env") {
+            res.push(key("vault"));
+            continue;
+        }
+        res.push(s.clone());
+    }
+    res
+}
+
+/// Where a built manifest value filled in from teams.yml was set
+///
+/// The squad and tribe always come from the team, while its slack channels
+/// only fill in channels the manifest layers left unset.
+fn team_origin<'a>(path: &[Segment], origins: &'a Origins) -> Option<&'a str> {
+    let key = |k: &str| Segment::Key(k.into());
+    if path.len() != 2 || path[0] != key("metadata") {
+        return None;
+    }
+    if path[1] == key("squad") || path[1] == key("tribe") {
+        Some("teams.yml")
+    } else if path[1] == key("support") || path[1] == key("notifications") {
+        origins.origin(path).or(Some("teams.yml"))
+    } else {
+        None
+    }
+}
+
+/// Attribute every value of a built manifest to the last layer that set it
+///
+/// Merging lets later layers override earlier ones, so the last layer setting a key is the one
+/// that took effect. Team metadata is attributed to teams.yml, and values no layer sets come
+/// from defaults in shipcat itself.
+pub fn explain(manifest: &Value, origins: &Origins) -> Vec<Provenance> {
+    let mut values = vec![];
+    leaves(manifest, &mut vec![], &mut values);
+    values
+        .into_iter()
+        .map(|(path, value)| {
+            let source = team_origin(&path, origins)
+                .or_else(|| origins.origin(&source_path(&path)))
+                .map_or_else(|| "default".to_string(), String::from);
+            Provenance {
+                key: key_path(&path),
+                value,
+                source,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, locate, Layer, LayerFile, Origins, Segment};
+    use crate::manifest::{ManifestOverrides, ManifestSource};
+    use shipcat_definitions::ErrorKind;
+    use std::path::Path;
+
+    fn file(layer: Layer, name: &str, data: &str) -> LayerFile {
+        LayerFile::from_str(layer, Path::new(name), data).unwrap()
+    }
+
+    #[test]
+    fn layer_locate() {
+        let data = "name: fake-ask\nresources:\n  limits:\n    cpu: 1\ncronJobs:\n- name: a\n- name: b\n";
+        let key = |k: &str| Segment::Key(k.into());
+        let marks = locate(data).unwrap();
+        let find = |path: &[Segment]| {
+            marks
+                .iter()
+                .find(|(p, _)| p.as_slice() == path)
+                .map(|(_, m)| (m.line(), m.col() + 1))
+        };
+        assert_eq!(find(&[key("resources"), key("limits"), key("cpu")]), Some((4, 5)));
+        assert_eq!(find(&[key("cronJobs"), Segment::Index(1), key("name")]), Some((7, 3)));
+        assert_eq!(find(&[key("missing")]), None);
+        let m = locate("name: a\n  - b: [\n").unwrap_err();
+        assert_eq!(m.line(), 2);
+    }
+
+    #[test]
+    fn layer_parse_errors() {
+        let f = file(
+            Layer::Manifest,
+            "manifest.yml",
+            "image: foo\nresources:\n  limits:\n    cpu: [1]\n",
+        );
+        let err = f.parse::<ManifestSource>().err().unwrap().to_string();
+        assert!(err.starts_with("manifest.yml:4:5 at `resources.limits.cpu` (manifest): invalid type"));
+
+        // including within flattened structs
+        let f = file(
+            Layer::Manifest,
+            "manifest.yml",
+            "image: foo\ncronJobs:\n- name: a\n  schedule: '* * * * *'\n  command: 1\n",
+        );
+        let err = f.parse::<ManifestSource>().err().unwrap().to_string();
+        assert!(err.starts_with("manifest.yml:5:3 at `cronJobs[0].command` (manifest): invalid type"));
+
+        let f = file(
+            Layer::Region("dev-uk".into()),
+            "dev-uk.yml",
+            "image: foo\nregions: [dev-uk]\n",
+        );
+        let err = f.parse::<ManifestOverrides>().err().unwrap().to_string();
+        assert!(err.starts_with("dev-uk.yml:2:1 at `regions` (dev-uk region overrides): unknown field"));
+
+        let err = LayerFile::from_str(Layer::Manifest, Path::new("manifest.yml"), "name: a\n  - b: [\n")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.starts_with("manifest.yml:2:"));
+    }
+
+    #[test]
+    fn layer_conflicts() {
+        let mut origins = Origins::default();
+        origins.track(&file(Layer::Manifest, "manifest.yml", "kong:\n  uris: /fake-ask\n"));
+        origins.track(&file(
+            Layer::Environment("dev".into()),
+            "dev.yml",
+            "kongApis:\n  defaults: {}\n  fake-ask-admin:\n    uris: /admin\n    auth: jwt\n",
+        ));
+        let conflict = |a: &str, b: &str| ErrorKind::ConflictingManifestKeys(a.into(), b.into()).into();
+        assert_eq!(
+            origins.locate(conflict("kong", "kongApis")).to_string(),
+            "`kong` in manifest.yml:2:3 (manifest) conflicts with `kongApis` in dev.yml:5:5 (dev environment overrides)"
+        );
+        assert_eq!(
+            origins
+                .locate(conflict(
+                    "kongApis.fake-ask-admin.auth",
+                    "kongApis.fake-ask-admin.authorization.enabled"
+                ))
+                .to_string(),
+            "`kongApis.fake-ask-admin.auth` in dev.yml:5:5 (dev environment overrides) conflicts with `kongApis.fake-ask-admin.authorization.enabled`"
+        );
+
+        // other errors are wrapped with the merged layers
+        let err = origins.locate("invalid".into());
+        assert_eq!(
+            err.to_string(),
+            "manifest merged from manifest.yml (manifest), dev.yml (dev environment overrides)"
+        );
+        assert_eq!(err.iter().nth(1).unwrap().to_string(), "invalid");
+    }
+
+    #[test]
+    fn layer_explain() {
+        let global = LayerFile::defaults(
+            Layer::Global,
+            serde_yaml::from_str(
+                "replicaCount: 2
+chart: base",
+            )
+            .unwrap(),
+        );
+        let mf = file(
+            Layer::Manifest,
+            "manifest.yml",
+            "replicaCount: 3
+metadata:
+  team: x
+  support: \"#x-help\"
+env:
+  A: a
+  B: b
+  C:
+    vault: OTHER
+",
+        );
+        let reg = file(
+            Layer::Region("prod-uk".into()),
+            "prod-uk.yml",
+            "replicaCount: 6
+env:
+  B: c
+",
+        );
+        let built = serde_yaml::from_str(
+            "name: x
+chart: base
+replicaCount: 6
+metadata:
+  team: x
+  squad: x
+  tribe: y
+  support: \"#x-help\"
+  notifications: \"#x-alerts\"
+env:
+  plain: {A: a, B: c}
+  vault:
+    C: {key: OTHER}
+",
+        )
+        .unwrap();
+        let mut origins = Origins::default();
+        for l in &[global, mf, reg] {
+            origins.track(l);
+        }
+        let res = explain(&built, &origins)
+            .into_iter()
+            .map(|p| (p.key, p.source))
+            .collect::<Vec<_>>();
+        assert_eq!(res, vec![
+            ("name".into(), "default".into()),
+            ("chart".into(), "shipcat.conf (global defaults)".into()),
+            (
+                "replicaCount".into(),
+                "prod-uk.yml:1:1 (prod-uk region overrides)".into()
+            ),
+            ("metadata.team".into(), "manifest.yml:3:3 (manifest)".into()),
+            ("metadata.squad".into(), "teams.yml".into()),
+            ("metadata.tribe".into(), "teams.yml".into()),
+            ("metadata.support".into(), "manifest.yml:4:3 (manifest)".into()),
+            ("metadata.notifications".into(), "teams.yml".into()),
+            ("env.plain.A".into(), "manifest.yml:6:3 (manifest)".into()),
+            (
+                "env.plain.B".into(),
+                "prod-uk.yml:3:3 (prod-uk region overrides)".into()
+            ),
+            ("env.vault.C.key".into(), "manifest.yml:9:5 (manifest)".into()),
+        ]);
+    }
+}
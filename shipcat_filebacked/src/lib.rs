@@ -1,5 +1,6 @@
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate merge_derive;
+#[macro_use] extern crate schemars;
 #[macro_use] extern crate log;
 #[macro_use] extern crate error_chain;
 
@@ -13,9 +14,14 @@ mod simple;
 pub use crate::simple::SimpleManifest;
 mod kong;
 
+mod layer;
+pub use crate::layer::Provenance;
 mod load;
 mod util;
 
+mod schema;
+pub use crate::schema::{manifest_schema, overrides_schema};
+
 use manifest::ManifestSource;
 use shipcat_definitions::{BaseManifest, Config, Manifest, Region, Result};
 
@@ -34,3 +40,7 @@ pub async fn all(conf: &Config) -> Result<Vec<BaseManifest>> {
 pub async fn available(conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
     ManifestSource::available(conf, reg).await
 }
+
+pub async fn explain(service: &str, conf: &Config, reg: &Region) -> Result<Vec<Provenance>> {
+    ManifestSource::explain(service, conf, reg).await
+}
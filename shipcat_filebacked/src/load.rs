@@ -1,33 +1,48 @@
 use std::path::{Path, PathBuf};
 
 use merge::Merge;
-use serde::de::DeserializeOwned;
 use shipcat_definitions::{Config, ErrorKind, Manifest, Region, Result, ResultExt};
 use walkdir::WalkDir;
 
 use super::{authorization::AuthorizationSource, util::Enabled, BaseManifest, SimpleManifest};
-use crate::manifest::{ManifestDefaults, ManifestOverrides, ManifestSource};
+use crate::{
+    layer::{explain, Layer, LayerFile, Origins, Provenance},
+    manifest::{ManifestDefaults, ManifestOverrides, ManifestSource},
+};
 
 impl ManifestSource {
     pub async fn load_manifest(service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
         let reg_name = reg.name.clone();
         let service_name = service.to_string();
 
-        let merged = ManifestSource::load_merged(service, conf, reg)
+        let (merged, origins) = ManifestSource::load_layers(service, conf, reg)
             .await
             .chain_err(|| ErrorKind::FailedToBuildManifest(service_name.clone(), reg_name.clone()))?;
         merged
             .build(&(conf.clone(), reg.clone()))
             .await
+            .map_err(|e| origins.locate(e))
             .chain_err(|| ErrorKind::FailedToBuildManifest(service_name.clone(), reg_name.clone()))
     }
 
     pub async fn load_metadata(service: &str, conf: &Config, reg: &Region) -> Result<SimpleManifest> {
-        let manifest = ManifestSource::load_merged(service, conf, reg).await?;
-        manifest.build_simple(&conf, &reg)
+        let (manifest, origins) = ManifestSource::load_layers(service, conf, reg).await?;
+        manifest.build_simple(conf, reg).map_err(|e| origins.locate(e))
     }
 
-    async fn load_merged(service: &str, conf: &Config, reg: &Region) -> Result<Self> {
+    /// Explain which layer each property of a manifest comes from
+    pub async fn explain(service: &str, conf: &Config, reg: &Region) -> Result<Vec<Provenance>> {
+        let (merged, origins) = ManifestSource::load_layers(service, conf, reg).await?;
+        let manifest = merged
+            .build(&(conf.clone(), reg.clone()))
+            .await
+            .map_err(|e| origins.locate(e))
+            .chain_err(|| ErrorKind::FailedToBuildManifest(service.to_string(), reg.name.clone()))?;
+        Ok(explain(&serde_yaml::to_value(&manifest)?, &origins))
+    }
+
+    /// Merge all layers of a manifest, tracking where each value came from
+    async fn load_layers(service: &str, conf: &Config, reg: &Region) -> Result<(Self, Origins)> {
         let dir = Self::services_dir().join(service);
 
         if !dir.exists() {
@@ -36,28 +51,44 @@ impl ManifestSource {
 
         let global_defaults = ManifestDefaults::from_global(conf)?;
         let regional_defaults = ManifestDefaults::from_region(reg)?;
+        let mut origins = Origins::default();
+        origins.track(&LayerFile::defaults(
+            Layer::Global,
+            serde_yaml::to_value(&global_defaults)?,
+        ));
+        origins.track(&LayerFile::defaults(
+            Layer::Regional,
+            serde_yaml::to_value(&regional_defaults)?,
+        ));
         let defaults = global_defaults.merge(regional_defaults);
 
-        let source_path = Self::services_dir().join(service).join("manifest.yml");
+        let source_path = dir.join("manifest.yml");
         debug!("Loading service manifest from {:?}", source_path);
-        let source: ManifestSource = read_from(&source_path).await?;
+        let source_file = LayerFile::read(Layer::Manifest, &source_path).await?;
+        let source: ManifestSource = source_file.parse()?;
         let mut manifest = defaults.merge_source(source);
+        origins.track(&source_file);
 
         let env_path = dir.join(format!("{}.yml", reg.environment.to_string()));
         if env_path.is_file() {
             debug!("Loading service overrides from {:?}", env_path);
-            let env: ManifestOverrides = read_from(&env_path).await?;
+            let env_file =
+                LayerFile::read(Layer::Environment(reg.environment.to_string()), &env_path).await?;
+            let env: ManifestOverrides = env_file.parse()?;
             manifest = manifest.merge_overrides(env);
+            origins.track(&env_file);
         }
 
         let region_path = dir.join(format!("{}.yml", reg.name));
         if region_path.is_file() {
             debug!("Loading service overrides from {:?}", region_path);
-            let region: ManifestOverrides = read_from(&region_path).await?;
+            let region_file = LayerFile::read(Layer::Region(reg.name.clone()), &region_path).await?;
+            let region: ManifestOverrides = region_file.parse()?;
             manifest = manifest.merge_overrides(region);
+            origins.track(&region_file);
         }
 
-        Ok(manifest)
+        Ok((manifest, origins))
     }
 
     fn all_names() -> Vec<String> {
@@ -85,8 +116,9 @@ impl ManifestSource {
         for service in Self::all_names() {
             let source_path = Self::services_dir().join(&service).join("manifest.yml");
             debug!("Loading service manifest from {:?}", source_path);
-            let source: ManifestSource = read_from(&source_path)
+            let source: ManifestSource = LayerFile::read(Layer::Manifest, &source_path)
                 .await
+                .and_then(|f| f.parse())
                 .chain_err(|| ErrorKind::InvalidManifest(service.clone()))?;
             let manifest = source
                 .build_base(conf)
@@ -151,23 +183,6 @@ impl ManifestDefaults {
     }
 }
 
-async fn read_from<T: DeserializeOwned>(path: &PathBuf) -> Result<T> {
-    use tokio::fs;
-    trace!("Reading manifest in {}", path.display());
-    if !path.exists() {
-        bail!("Manifest file {} does not exist", path.display())
-    }
-    let data = fs::read_to_string(&path).await?;
-    if data.is_empty() {
-        bail!("Manifest file {} is empty", path.display());
-    }
-    match serde_yaml::from_str(&data) {
-        Err(e) => bail!("Manifest file {} did not parse as YAML: {}", path.display(), e),
-        Ok(d) => Ok(d),
-    }
-}
-
-
 #[cfg(test)]
 mod tests {
     use std::{env, fs, path::Path};
@@ -4,9 +4,9 @@ use std::collections::BTreeMap;
 use shipcat_definitions::{
     structs::{
         autoscaling::AutoScaling, security::DataHandling, tolerations::Tolerations, volume::Volume,
-        ConfigMap, Dependency, DestinationRule, EventStream, Gate, HealthCheck, HostAlias, Kafka,
-        KafkaResources, LifeCycle, Metadata, NotificationMode, PersistentVolume, Probe, PrometheusAlert,
-        Rbac, RollingUpdate, SecurityContext, VaultOpts, VolumeMount,
+        BlueGreen, Canary, ConfigMap, Dependency, DestinationRule, EventStream, Gate, HealthCheck, HostAlias,
+        Kafka, KafkaResources, LifeCycle, Metadata, NotificationMode, PersistentVolume, Probe,
+        PrometheusAlert, Rbac, RollingUpdate, SecurityContext, VaultOpts, VolumeMount,
     },
     BaseManifest, Config, Manifest, PrimaryWorkload, Region, Result,
 };
@@ -24,7 +24,7 @@ use super::{
 };
 
 /// Main manifest, deserialized from `manifest.yml`
-#[derive(Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
 #[serde(default, rename_all = "camelCase")]
 pub struct ManifestSource {
     pub name: Option<String>,
@@ -38,7 +38,7 @@ pub struct ManifestSource {
 }
 
 /// Manifest overrides, deserialized from `dev-uk.yml`/`prod.yml` etc.
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Serialize, Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default, deny_unknown_fields, rename_all = "camelCase")]
 pub struct ManifestOverrides {
     pub workload: Option<PrimaryWorkload>,
@@ -66,6 +66,9 @@ pub struct ManifestOverrides {
     pub liveness_probe: Option<Probe>,
     pub lifecycle: Option<LifeCycle>,
     pub rolling_update: Option<RollingUpdate>,
+    pub blue_green: Option<BlueGreen>,
+    pub auto_rollback: Option<bool>,
+    pub canary: Option<Canary>,
     pub auto_scaling: Option<AutoScaling>,
     pub tolerations: Option<Vec<Tolerations>>,
     pub host_aliases: Option<Vec<HostAlias>>,
@@ -95,7 +98,7 @@ pub struct ManifestOverrides {
 }
 
 /// Global/regional manifest defaults, deserialized from `shipcat.conf` etc.
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Serialize, Deserialize, Default, Merge, Clone, JsonSchema)]
 #[serde(default, deny_unknown_fields, rename_all = "camelCase")]
 pub struct ManifestDefaults {
     pub image_prefix: Option<String>,
@@ -148,6 +151,8 @@ impl ManifestSource {
             imageSize: overrides.image_size.or(Some(512)),
             image: simple.image,
             version: simple.version,
+            imageDigest: Default::default(),
+            freezeOverride: Default::default(),
             command: overrides.command.unwrap_or_default(),
             securityContext: overrides.security_context,
             dataHandling: data_handling,
@@ -175,6 +180,9 @@ impl ManifestSource {
             livenessProbe: overrides.liveness_probe,
             lifecycle: overrides.lifecycle,
             rollingUpdate: overrides.rolling_update,
+            blueGreen: overrides.blue_green,
+            autoRollback: overrides.auto_rollback,
+            canary: overrides.canary,
             autoScaling: overrides.auto_scaling,
             tolerations: overrides.tolerations.unwrap_or_default(),
             hostAliases: overrides.host_aliases.unwrap_or_default(),
@@ -210,6 +218,7 @@ impl ManifestSource {
             namespace: region.namespace.clone(),
             uid: Default::default(),
             secrets: Default::default(),
+            secretVersions: Default::default(),
             state: Default::default(),
             workload: overrides.workload.unwrap_or_default(),
             prometheusAlerts: overrides.prometheus_alerts.unwrap_or_default(),
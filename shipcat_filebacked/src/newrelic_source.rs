@@ -28,7 +28,7 @@ use crate::util::Build;
 ///         duration: 60
 ///         threshold: 0.5
 /// ```
-#[derive(Debug, Default, Clone, Deserialize, Merge)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Merge, JsonSchema)]
 #[cfg_attr(test, derive(PartialEq))]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct NewrelicSource {
@@ -80,7 +80,7 @@ impl Build<Option<Newrelic>, SlackChannel> for NewrelicSource {
     }
 }
 
-#[derive(Debug, Default, Clone, Deserialize, Merge)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Merge, JsonSchema)]
 #[cfg_attr(test, derive(PartialEq))]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct NewrelicAlertSource {
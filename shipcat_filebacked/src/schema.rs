@@ -0,0 +1,137 @@
+use schemars::{
+    schema::{RootSchema, Schema, SchemaObject, SubschemaValidation},
+    schema_for, Map,
+};
+
+use shipcat_definitions::Manifest;
+
+use crate::manifest::{ManifestOverrides, ManifestSource};
+
+/// JSON Schema for `manifest.yml`
+pub fn manifest_schema() -> RootSchema {
+    let mut schema = schema_for!(ManifestSource);
+    describe(&mut schema);
+    schema
+}
+
+/// JSON Schema for regional and environment overrides like `dev-uk.yml`
+pub fn overrides_schema() -> RootSchema {
+    let mut schema = schema_for!(ManifestOverrides);
+    describe(&mut schema);
+    schema
+}
+
+fn description(schema: &Schema) -> Option<&String> {
+    match schema {
+        Schema::Object(o) => o.metadata.as_ref().and_then(|m| m.description.as_ref()),
+        _ => None,
+    }
+}
+
+/// Describe properties from the corresponding `Manifest` properties
+///
+/// The source structs are mostly undocumented, whereas every `Manifest` property is.
+fn describe(schema: &mut RootSchema) {
+    let docs = schema_for!(Manifest)
+        .schema
+        .object
+        .map(|o| o.properties)
+        .unwrap_or_default();
+    let props: &mut Map<String, Schema> = &mut schema.schema.object().properties;
+    for (name, prop) in props.iter_mut() {
+        if description(prop).is_some() {
+            continue;
+        }
+        let desc = match docs.get(name).and_then(description) {
+            Some(d) => d.clone(),
+            None => continue,
+        };
+        if let Schema::Object(o) = prop {
+            if o.is_ref() {
+                // siblings of a $ref are ignored, so describe a wrapper around it instead
+                let all_of = vec![Schema::Object(o.clone())];
+                *o = SchemaObject {
+                    subschemas: Some(Box::new(SubschemaValidation {
+                        all_of: Some(all_of),
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                };
+            }
+            o.metadata().description = Some(desc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{description, manifest_schema, overrides_schema};
+    use schemars::schema::{InstanceType, Schema, SchemaObject, SingleOrVec};
+
+    fn object(schema: &Schema) -> &SchemaObject {
+        match schema {
+            Schema::Object(o) => o,
+            _ => panic!("{:?} is not a schema object", schema),
+        }
+    }
+
+    fn types(schema: &Schema) -> Vec<InstanceType> {
+        match &object(schema).instance_type {
+            Some(SingleOrVec::Single(t)) => vec![**t],
+            Some(SingleOrVec::Vec(ts)) => ts.clone(),
+            None => vec![],
+        }
+    }
+
+    /// References in the `anyOf` of an optional property
+    fn any_of_refs(schema: &Schema) -> Vec<String> {
+        let subs = object(schema).subschemas.as_ref().unwrap();
+        subs.any_of
+            .iter()
+            .flatten()
+            .filter_map(|s| object(s).reference.clone())
+            .collect()
+    }
+
+    #[test]
+    fn schema_properties() {
+        let root = manifest_schema();
+        let schema = root.schema.object.unwrap();
+        assert_eq!(types(&schema.properties["name"]), vec![
+            InstanceType::String,
+            InstanceType::Null
+        ]);
+        let regions = &schema.properties["regions"];
+        assert_eq!(types(regions), vec![InstanceType::Array]);
+        match &object(regions).array.as_ref().unwrap().items {
+            Some(SingleOrVec::Single(item)) => assert_eq!(types(item), vec![InstanceType::String]),
+            items => panic!("unexpected regions items {:?}", items),
+        }
+        assert_eq!(types(&schema.properties["replicaCount"]), vec![
+            InstanceType::Integer,
+            InstanceType::Null
+        ]);
+        // flattened overrides
+        let probe = &schema.properties["readinessProbe"];
+        assert!(description(probe).unwrap().contains("readiness"));
+        assert_eq!(any_of_refs(probe), vec!["#/definitions/Probe"]);
+
+        // env values are plain or explicit secret references
+        let env = &root.definitions["EnvValueSource"];
+        assert_eq!(any_of_refs(env), vec![
+            "#/definitions/SecretRefSource",
+            "#/definitions/RelaxedString"
+        ]);
+        let secret_ref = object(&root.definitions["SecretRefSource"]).object.as_ref().unwrap();
+        assert!(secret_ref.required.contains("vault"));
+        assert_eq!(types(&secret_ref.properties["vault"]), vec![InstanceType::String]);
+
+        let overrides = overrides_schema().schema.object.unwrap();
+        assert_eq!(any_of_refs(&overrides.properties["livenessProbe"]), vec![
+            "#/definitions/Probe"
+        ]);
+        assert!(!overrides.properties.contains_key("regions"));
+        // deny_unknown_fields
+        assert_eq!(overrides.additional_properties, Some(Box::new(Schema::Bool(false))));
+    }
+}
@@ -1,6 +1,11 @@
 use std::collections::BTreeMap;
 
 use merge::Merge;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{Schema, SchemaObject},
+    JsonSchema,
+};
 use shipcat_definitions::Result;
 
 use super::Build;
@@ -23,7 +28,7 @@ use super::Build;
 ///     value: 3
 /// bar: ~
 /// ```
-#[derive(Deserialize, Default, Clone, PartialEq, Merge)]
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Merge)]
 #[cfg_attr(test, derive(Debug, Copy))]
 #[serde(default, deny_unknown_fields)]
 pub struct Enabled<T: Merge> {
@@ -33,6 +38,20 @@ pub struct Enabled<T: Merge> {
     pub item: T,
 }
 
+/// The schema of the inner struct with an extra `enabled` property
+impl<T: Merge + JsonSchema> JsonSchema for Enabled<T> {
+    fn schema_name() -> String {
+        format!("Enabled_{}", T::schema_name())
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::from(T::json_schema(gen));
+        let enabled = gen.subschema_for::<Option<bool>>();
+        schema.object().properties.insert("enabled".into(), enabled);
+        schema.into()
+    }
+}
+
 /// Builds the inner struct unless enabled is explicitly false.
 impl<S: Build<B, P> + Merge, B, P> Build<Option<B>, P> for Enabled<S> {
     fn build(self, params: &P) -> Result<Option<B>> {
@@ -46,7 +65,7 @@ impl<S: Build<B, P> + Merge, B, P> Build<Option<B>, P> for Enabled<S> {
 /// EnabledMap is a map where each value is wrapped in an Enabled.
 ///
 /// It can be built into a map which flattens the Enabled wrappers, so disabled values are excluded.
-#[derive(Deserialize, Default, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, JsonSchema)]
 #[cfg_attr(test, derive(Debug))]
 pub struct EnabledMap<K: Clone + std::hash::Hash + Ord, V: Clone + Default + Merge>(BTreeMap<K, Enabled<V>>);
 